@@ -0,0 +1,153 @@
+//! Streams a synthetic 5-million-line program through the simulator,
+//! reports how long it took and fails if the heap grew past
+//! [`PEAK_HEAP_LIMIT`] on the way.
+//!
+//! `cargo bench -p printctl-ui --bench large_program [-- <lines>]`
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use printctl_ui::features::profile::MachineProfile;
+use printctl_ui::features::program::GCodeProgram;
+use printctl_ui::features::source::GCodeSource;
+use printctl_ui::features::statistics::ProgramStatistics;
+
+const DEFAULT_LINES: usize = 5_000_000;
+
+/// Heap the whole run may use at once, whatever the number of lines. The
+/// file itself is mapped, not on the heap.
+const PEAK_HEAP_LIMIT: usize = 64 << 20;
+
+/// Keeps track of how much the heap holds and the most it ever held
+struct CountingAlloc {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl CountingAlloc {
+    fn grow(&self, bytes: usize) {
+        let now = self.current.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.peak.fetch_max(now, Ordering::Relaxed);
+    }
+
+    fn shrink(&self, bytes: usize) {
+        self.current.fetch_sub(bytes, Ordering::Relaxed);
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            self.grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        self.shrink(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            self.grow(new_size);
+            self.shrink(layout.size());
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static HEAP: CountingAlloc = CountingAlloc {
+    current: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+};
+
+/// Concentric squares, 0.2 mm layers, with the comments a slicer would leave
+fn write_program(path: &std::path::Path, lines: usize) -> std::io::Result<()> {
+    let mut out = BufWriter::new(std::fs::File::create(path)?);
+    writeln!(
+        out,
+        "M140 S60\nM104 S210\nG28\nM190 S60\nM109 S210\nG90\nM83"
+    )?;
+
+    let (mut written, mut layer) = (7, 0);
+    while written < lines {
+        writeln!(
+            out,
+            ";LAYER:{}\nG0 Z{:.1} F600\n;TYPE:FILL",
+            layer,
+            0.2 * (layer + 1) as f32
+        )?;
+        written += 3;
+        for i in 0..400.min(lines - written) {
+            let (x, y) = match i % 4 {
+                0 => (10.0, 10.0),
+                1 => (190.0, 10.0),
+                2 => (190.0, 190.0),
+                _ => (10.0, 190.0),
+            };
+            writeln!(
+                out,
+                "G1 X{:.3} Y{:.3} E0.8 F3000",
+                x + (i / 4) as f32 * 0.01,
+                y
+            )?;
+            written += 1;
+        }
+        layer += 1;
+    }
+
+    out.flush()
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let lines = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_LINES);
+
+    let path = std::env::temp_dir().join(format!("printctl-bench-{}.gcode", std::process::id()));
+    write_program(&path, lines)?;
+    let size = std::fs::metadata(&path)?.len();
+
+    let start = Instant::now();
+    let source = GCodeSource::open(&path)?;
+    let indexed = start.elapsed();
+    let program = GCodeProgram::open(Arc::new(source));
+    let stats = ProgramStatistics::from_program(&program, &MachineProfile::default());
+    let simulated = start.elapsed();
+    std::fs::remove_file(&path)?;
+
+    println!(
+        "{} lines, {:.1} MiB",
+        program.len(),
+        size as f64 / (1 << 20) as f64
+    );
+    println!(
+        "indexed in {:.2?}, simulated in {:.2?}",
+        indexed,
+        simulated - indexed
+    );
+    println!(
+        "{} layers, {:.1?} printing",
+        stats.layers.len(),
+        stats.total_time
+    );
+
+    let peak = HEAP.peak.load(Ordering::Relaxed);
+    println!("peak heap {:.1} MiB", peak as f64 / (1 << 20) as f64);
+    assert!(
+        peak < PEAK_HEAP_LIMIT,
+        "peak heap {} bytes is over the {} byte limit",
+        peak,
+        PEAK_HEAP_LIMIT
+    );
+
+    Ok(())
+}
@@ -2,4 +2,16 @@
 pub enum Error {
     #[error(transparent)]
     IO(#[from] std::io::Error),
+
+    #[error("could not decode thumbnail: {0}")]
+    Thumbnail(String),
+
+    #[error("invalid binary G-code: {0}")]
+    BinaryGCode(String),
+
+    #[error("invalid transform: {0}")]
+    Transform(String),
+
+    #[error("invalid lint option: {0}")]
+    LintRule(String),
 }
@@ -0,0 +1,139 @@
+//! Heatshrink (LZSS) codec as used by `.bgcode` G-code blocks.
+//!
+//! Every symbol starts with a tag bit: `1` is followed by an 8-bit literal,
+//! `0` by a back-reference of `window_bits` (offset - 1) and `lookahead_bits`
+//! (length - 1). Bits are packed MSB first.
+
+use std::collections::HashMap;
+
+use crate::prelude::*;
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    bit: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, count: u32) -> Option<u32> {
+        if self.bit + count as usize > self.data.len() * 8 {
+            return None;
+        }
+
+        let mut value = 0;
+        for _ in 0..count {
+            let byte = self.data[self.bit / 8];
+            let bit = (byte >> (7 - self.bit % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.bit += 1;
+        }
+        Some(value)
+    }
+}
+
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    bit: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, count: u32) {
+        for i in (0..count).rev() {
+            if self.bit.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.data.last_mut().unwrap() |= bit << (7 - self.bit % 8);
+            self.bit += 1;
+        }
+    }
+}
+
+pub fn decode(data: &[u8], window_bits: u32, lookahead_bits: u32) -> Result<Vec<u8>> {
+    let mut reader = BitReader { data, bit: 0 };
+    let mut out = Vec::with_capacity(data.len() * 2);
+
+    // trailing padding is always shorter than a full symbol
+    while let Some(tag) = reader.read(1) {
+        if tag == 1 {
+            let Some(byte) = reader.read(8) else { break };
+            out.push(byte as u8);
+            continue;
+        }
+
+        let (Some(index), Some(count)) = (reader.read(window_bits), reader.read(lookahead_bits))
+        else {
+            break;
+        };
+
+        let offset = index as usize + 1;
+        if offset > out.len() {
+            return Err(Error::BinaryGCode(
+                "heatshrink back-reference before start".into(),
+            ));
+        }
+        for _ in 0..=count {
+            out.push(out[out.len() - offset]);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Greedy encoder; matches shorter than two bytes cost more than literals
+pub fn encode(data: &[u8], window_bits: u32, lookahead_bits: u32) -> Vec<u8> {
+    const MIN_MATCH: usize = 2;
+    const MAX_CANDIDATES: usize = 64;
+
+    let window = 1usize << window_bits;
+    let max_len = 1usize << lookahead_bits;
+
+    let mut writer = BitWriter::default();
+    // positions where each two-byte prefix was seen, newest last
+    let mut seen: HashMap<[u8; 2], Vec<usize>> = HashMap::new();
+
+    let mut i = 0;
+    while i < data.len() {
+        let mut best = (0, 0);
+
+        if let Some(key) = data.get(i..i + MIN_MATCH) {
+            let candidates = seen.get(&[key[0], key[1]]).map(Vec::as_slice);
+            for &j in candidates
+                .unwrap_or_default()
+                .iter()
+                .rev()
+                .take(MAX_CANDIDATES)
+            {
+                if i - j > window {
+                    break;
+                }
+                let len = (0..max_len.min(data.len() - i))
+                    .take_while(|&k| data[j + k] == data[i + k])
+                    .count();
+                if len > best.1 {
+                    best = (i - j, len);
+                }
+            }
+        }
+
+        let step = if best.1 >= MIN_MATCH {
+            writer.write(0, 1);
+            writer.write((best.0 - 1) as u32, window_bits);
+            writer.write((best.1 - 1) as u32, lookahead_bits);
+            best.1
+        } else {
+            writer.write(1, 1);
+            writer.write(data[i] as u32, 8);
+            1
+        };
+
+        for pos in i..i + step {
+            if let Some(key) = data.get(pos..pos + MIN_MATCH) {
+                seen.entry([key[0], key[1]]).or_default().push(pos);
+            }
+        }
+        i += step;
+    }
+
+    writer.data
+}
@@ -0,0 +1,163 @@
+//! MeatPack codec as used by `.bgcode` G-code blocks.
+//!
+//! The most common G-code characters are packed two per byte as 4-bit
+//! codes; `0xF` marks a character that follows as a full byte. Control
+//! commands are sent as `0xFF 0xFF <cmd>`.
+
+use crate::prelude::*;
+
+const SIGNAL: u8 = 0xFF;
+const ENABLE_PACKING: u8 = 0xFB;
+const DISABLE_PACKING: u8 = 0xFA;
+const RESET_ALL: u8 = 0xF9;
+const ENABLE_NO_SPACES: u8 = 0xF7;
+const DISABLE_NO_SPACES: u8 = 0xF6;
+
+const FULL_CHAR: u8 = 0xF;
+
+fn unpack(code: u8, no_spaces: bool) -> Option<u8> {
+    Some(match code {
+        0..=9 => b'0' + code,
+        10 => b'.',
+        11 if no_spaces => b'E',
+        11 => b' ',
+        12 => b'\n',
+        13 => b'G',
+        14 => b'X',
+        _ => return None,
+    })
+}
+
+fn pack(byte: u8, no_spaces: bool) -> Option<u8> {
+    Some(match byte {
+        b'0'..=b'9' => byte - b'0',
+        b'.' => 10,
+        b'E' if no_spaces => 11,
+        b' ' if !no_spaces => 11,
+        b'\n' => 12,
+        b'G' => 13,
+        b'X' => 14,
+        _ => return None,
+    })
+}
+
+#[derive(Default)]
+struct Decoder {
+    out: Vec<u8>,
+    in_comment: bool,
+    no_spaces: bool,
+}
+
+impl Decoder {
+    fn push(&mut self, byte: u8) {
+        match byte {
+            b';' => self.in_comment = true,
+            b'\n' => self.in_comment = false,
+            _ => {}
+        }
+
+        // no-spaces mode drops the separator before every parameter letter
+        let separate = self.no_spaces
+            && !self.in_comment
+            && byte.is_ascii_alphabetic()
+            && self
+                .out
+                .last()
+                .is_some_and(|last| !matches!(last, b'\n' | b' '));
+        if separate {
+            self.out.push(b' ');
+        }
+        self.out.push(byte);
+    }
+}
+
+pub fn decode(data: &[u8]) -> Result<Vec<u8>> {
+    let mut decoder = Decoder::default();
+    let mut packing = false;
+    let mut i = 0;
+
+    let next = |i: &mut usize| {
+        let byte = data.get(*i).copied();
+        *i += 1;
+        byte.ok_or_else(|| Error::BinaryGCode("truncated meatpack stream".into()))
+    };
+
+    while i < data.len() {
+        let byte = next(&mut i)?;
+
+        if byte == SIGNAL && data.get(i) == Some(&SIGNAL) {
+            i += 1;
+            match next(&mut i)? {
+                ENABLE_PACKING => packing = true,
+                DISABLE_PACKING => packing = false,
+                ENABLE_NO_SPACES => decoder.no_spaces = true,
+                DISABLE_NO_SPACES => decoder.no_spaces = false,
+                RESET_ALL => {
+                    packing = false;
+                    decoder.no_spaces = false;
+                }
+                cmd => {
+                    return Err(Error::BinaryGCode(format!(
+                        "unknown meatpack command {:#04x}",
+                        cmd
+                    )));
+                }
+            }
+            continue;
+        }
+
+        if !packing {
+            decoder.push(byte);
+            continue;
+        }
+
+        // low nibble is the first character, full characters follow in order
+        let (lo, hi) = (byte & 0xF, byte >> 4);
+        for code in [lo, hi] {
+            let char = match unpack(code, decoder.no_spaces) {
+                Some(char) => char,
+                None if code == FULL_CHAR => next(&mut i)?,
+                None => unreachable!(),
+            };
+            decoder.push(char);
+        }
+    }
+
+    Ok(decoder.out)
+}
+
+/// Packs `data` with packing enabled; spaces are kept so comments survive
+pub fn encode(data: &[u8]) -> Vec<u8> {
+    pack_all(vec![SIGNAL, SIGNAL, ENABLE_PACKING], data, false)
+}
+
+/// Packs G-code written without spaces between words, which lets `E` take
+/// the code of the space. The decoder puts the spaces back.
+pub fn encode_minified(data: &[u8]) -> Vec<u8> {
+    let out = vec![
+        SIGNAL,
+        SIGNAL,
+        ENABLE_PACKING,
+        SIGNAL,
+        SIGNAL,
+        ENABLE_NO_SPACES,
+    ];
+    pack_all(out, data, true)
+}
+
+fn pack_all(mut out: Vec<u8>, data: &[u8], no_spaces: bool) -> Vec<u8> {
+    let mut pairs = data.chunks_exact(2);
+    for pair in &mut pairs {
+        let (lo, hi) = (pack(pair[0], no_spaces), pack(pair[1], no_spaces));
+        out.push(lo.unwrap_or(FULL_CHAR) | (hi.unwrap_or(FULL_CHAR) << 4));
+        out.extend(lo.is_none().then_some(pair[0]));
+        out.extend(hi.is_none().then_some(pair[1]));
+    }
+
+    // an odd trailing character cannot be packed on its own
+    if let [last] = pairs.remainder() {
+        out.extend([SIGNAL, SIGNAL, DISABLE_PACKING, *last]);
+    }
+
+    out
+}
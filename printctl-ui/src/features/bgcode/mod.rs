@@ -0,0 +1,557 @@
+//! Prusa binary G-code (`.bgcode`) container.
+//!
+//! A file is a small header followed by blocks, each made of a header,
+//! block specific parameters, the (optionally compressed) payload and a
+//! CRC32 of all three. Everything else in the crate works on ASCII G-code,
+//! so binary files are converted with [`to_ascii`] as soon as they are read.
+
+mod heatshrink;
+pub mod meatpack;
+
+use std::borrow::Cow;
+
+use base64::Engine;
+
+use super::thumbnail::{Thumbnail, ThumbnailFormat};
+use crate::prelude::*;
+
+pub const MAGIC: &[u8; 4] = b"GCDE";
+pub const VERSION: u32 = 1;
+
+// G-code blocks are split at line boundaries to stay below this size
+const MAX_GCODE_BLOCK: usize = 65536;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Checksum {
+    None,
+    Crc32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockType {
+    FileMetadata,
+    GCode,
+    SlicerMetadata,
+    PrinterMetadata,
+    PrintMetadata,
+    Thumbnail,
+}
+
+impl BlockType {
+    fn from_u16(value: u16) -> Result<Self> {
+        Ok(match value {
+            0 => Self::FileMetadata,
+            1 => Self::GCode,
+            2 => Self::SlicerMetadata,
+            3 => Self::PrinterMetadata,
+            4 => Self::PrintMetadata,
+            5 => Self::Thumbnail,
+            _ => return Err(invalid(format!("unknown block type {}", value))),
+        })
+    }
+
+    fn as_u16(self) -> u16 {
+        match self {
+            Self::FileMetadata => 0,
+            Self::GCode => 1,
+            Self::SlicerMetadata => 2,
+            Self::PrinterMetadata => 3,
+            Self::PrintMetadata => 4,
+            Self::Thumbnail => 5,
+        }
+    }
+
+    /// Size of the block parameters following the header
+    fn params_size(self) -> usize {
+        match self {
+            Self::Thumbnail => 6,
+            _ => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Deflate,
+    Heatshrink11,
+    Heatshrink12,
+}
+
+impl Compression {
+    fn from_u16(value: u16) -> Result<Self> {
+        Ok(match value {
+            0 => Self::None,
+            1 => Self::Deflate,
+            2 => Self::Heatshrink11,
+            3 => Self::Heatshrink12,
+            _ => return Err(invalid(format!("unknown compression {}", value))),
+        })
+    }
+
+    fn as_u16(self) -> u16 {
+        match self {
+            Self::None => 0,
+            Self::Deflate => 1,
+            Self::Heatshrink11 => 2,
+            Self::Heatshrink12 => 3,
+        }
+    }
+
+    fn decompress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Deflate => miniz_oxide::inflate::decompress_to_vec_zlib(data)
+                .map_err(|e| invalid(format!("deflate: {}", e))),
+            Self::Heatshrink11 => heatshrink::decode(data, 11, 4),
+            Self::Heatshrink12 => heatshrink::decode(data, 12, 4),
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::None => data.to_vec(),
+            Self::Deflate => miniz_oxide::deflate::compress_to_vec_zlib(data, 6),
+            Self::Heatshrink11 => heatshrink::encode(data, 11, 4),
+            Self::Heatshrink12 => heatshrink::encode(data, 12, 4),
+        }
+    }
+}
+
+// metadata blocks only define INI encoding (`key=value` lines)
+const ENCODING_INI: u16 = 0;
+
+const ENCODING_GCODE_NONE: u16 = 0;
+const ENCODING_GCODE_MEATPACK: u16 = 1;
+const ENCODING_GCODE_MEATPACK_COMMENTS: u16 = 2;
+
+fn invalid(reason: impl Into<String>) -> Error {
+    Error::BinaryGCode(reason.into())
+}
+
+/// Little-endian cursor over the raw file
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + count)
+            .ok_or_else(|| invalid("unexpected end of file"))?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+/// Decoded contents of a `.bgcode` file
+#[derive(Debug, Default, Clone)]
+pub struct BinaryGCode {
+    pub file_metadata: Vec<(String, String)>,
+    pub printer_metadata: Vec<(String, String)>,
+    pub print_metadata: Vec<(String, String)>,
+    pub slicer_metadata: Vec<(String, String)>,
+    pub thumbnails: Vec<Thumbnail>,
+    pub gcode: String,
+}
+
+pub fn is_bgcode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Returns the ASCII G-code of `bytes`, decoding it first if it is binary
+pub fn to_ascii(bytes: &[u8]) -> Result<Cow<'_, str>> {
+    if is_bgcode(bytes) {
+        Ok(Cow::Owned(BinaryGCode::decode(bytes)?.to_ascii()))
+    } else {
+        Ok(String::from_utf8_lossy(bytes))
+    }
+}
+
+impl BinaryGCode {
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader {
+            data: bytes,
+            pos: 0,
+        };
+
+        if reader.bytes(4)? != MAGIC {
+            return Err(invalid("missing GCDE magic"));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+        let checksum = match reader.u16()? {
+            0 => Checksum::None,
+            1 => Checksum::Crc32,
+            other => return Err(invalid(format!("unknown checksum type {}", other))),
+        };
+
+        let mut file = Self::default();
+        while !reader.is_empty() {
+            file.decode_block(&mut reader, checksum)?;
+        }
+
+        Ok(file)
+    }
+
+    fn decode_block(&mut self, reader: &mut Reader, checksum: Checksum) -> Result<()> {
+        let start = reader.pos;
+
+        let block_type = BlockType::from_u16(reader.u16()?)?;
+        let compression = Compression::from_u16(reader.u16()?)?;
+        let uncompressed_size = reader.u32()? as usize;
+        let data_size = match compression {
+            Compression::None => uncompressed_size,
+            _ => reader.u32()? as usize,
+        };
+        let params = reader.bytes(block_type.params_size())?;
+        let data = reader.bytes(data_size)?;
+
+        if checksum == Checksum::Crc32 {
+            let expected = crc32fast::hash(&reader.data[start..reader.pos]);
+            if reader.u32()? != expected {
+                return Err(invalid(format!(
+                    "checksum mismatch in {:?} block",
+                    block_type
+                )));
+            }
+        }
+
+        let data = compression.decompress(data)?;
+        if data.len() != uncompressed_size {
+            return Err(invalid(format!(
+                "{:?} block decompressed to {} bytes, expected {}",
+                block_type,
+                data.len(),
+                uncompressed_size
+            )));
+        }
+
+        let encoding = u16::from_le_bytes([params[0], params[1]]);
+        match block_type {
+            BlockType::GCode => {
+                let text = match encoding {
+                    ENCODING_GCODE_NONE => data,
+                    ENCODING_GCODE_MEATPACK | ENCODING_GCODE_MEATPACK_COMMENTS => {
+                        meatpack::decode(&data)?
+                    }
+                    _ => return Err(invalid(format!("unknown G-code encoding {}", encoding))),
+                };
+                self.gcode.push_str(&String::from_utf8_lossy(&text));
+            }
+            BlockType::Thumbnail => {
+                let format = match encoding {
+                    0 => ThumbnailFormat::Png,
+                    1 => ThumbnailFormat::Jpg,
+                    2 => ThumbnailFormat::Qoi,
+                    _ => return Err(invalid(format!("unknown thumbnail format {}", encoding))),
+                };
+                self.thumbnails.push(Thumbnail {
+                    width: u16::from_le_bytes([params[2], params[3]]) as u32,
+                    height: u16::from_le_bytes([params[4], params[5]]) as u32,
+                    format,
+                    data,
+                });
+            }
+            metadata => {
+                if encoding != ENCODING_INI {
+                    return Err(invalid(format!("unknown metadata encoding {}", encoding)));
+                }
+                let pairs = String::from_utf8_lossy(&data)
+                    .lines()
+                    .filter_map(|line| line.split_once('='))
+                    .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                    .collect();
+                match metadata {
+                    BlockType::FileMetadata => self.file_metadata = pairs,
+                    BlockType::PrinterMetadata => self.printer_metadata = pairs,
+                    BlockType::PrintMetadata => self.print_metadata = pairs,
+                    _ => self.slicer_metadata = pairs,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl BinaryGCode {
+    /// Wraps ASCII G-code, moving embedded thumbnails into their own blocks
+    pub fn from_ascii(src: &str) -> Self {
+        let thumbnails = Thumbnail::extract(src);
+
+        let mut gcode = String::with_capacity(src.len());
+        let mut in_thumbnail = false;
+        for line in src.split_inclusive('\n') {
+            let mut words = line.trim().trim_start_matches(';').split_whitespace();
+            match words.nth(1) {
+                Some("begin") if ThumbnailFormat::from_tag(Self::tag(line)).is_some() => {
+                    in_thumbnail = true;
+                }
+                Some("end") if in_thumbnail => in_thumbnail = false,
+                _ if in_thumbnail => {}
+                _ => gcode.push_str(line),
+            }
+        }
+
+        Self {
+            file_metadata: vec![("Producer".into(), "printctl".into())],
+            thumbnails,
+            gcode,
+            ..Self::default()
+        }
+    }
+
+    fn tag(line: &str) -> &str {
+        let comment = line.trim().trim_start_matches(';');
+        comment.split_whitespace().next().unwrap_or_default()
+    }
+
+    /// Renders the file as ASCII G-code, metadata and thumbnails as comments
+    /// the same way the slicers write them
+    pub fn to_ascii(&self) -> String {
+        let mut out = String::with_capacity(self.gcode.len() * 11 / 10);
+
+        let comments = |out: &mut String, pairs: &[(String, String)]| {
+            for (key, value) in pairs {
+                out.push_str(&format!("; {} = {}\n", key, value));
+            }
+        };
+
+        comments(&mut out, &self.file_metadata);
+        comments(&mut out, &self.printer_metadata);
+        out.push('\n');
+
+        let engine = base64::engine::general_purpose::STANDARD;
+        for thumbnail in &self.thumbnails {
+            let tag = thumbnail.format.tag();
+            let encoded = engine.encode(&thumbnail.data);
+            out.push_str(&format!(
+                "; {} begin {}x{} {}\n",
+                tag,
+                thumbnail.width,
+                thumbnail.height,
+                encoded.len()
+            ));
+            for chunk in encoded.as_bytes().chunks(78) {
+                out.push_str("; ");
+                out.push_str(std::str::from_utf8(chunk).unwrap());
+                out.push('\n');
+            }
+            out.push_str(&format!("; {} end\n;\n", tag));
+        }
+
+        out.push_str(&self.gcode);
+        if !self.gcode.ends_with('\n') {
+            out.push('\n');
+        }
+
+        comments(&mut out, &self.print_metadata);
+        if !self.slicer_metadata.is_empty() {
+            out.push_str("\n; prusaslicer_config = begin\n");
+            comments(&mut out, &self.slicer_metadata);
+            out.push_str("; prusaslicer_config = end\n");
+        }
+
+        out
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.gcode.len() / 2);
+        out.extend(MAGIC);
+        out.extend(VERSION.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+
+        let ini = |pairs: &[(String, String)]| {
+            let mut data = String::new();
+            for (key, value) in pairs {
+                data.push_str(&format!("{}={}\n", key, value));
+            }
+            data.into_bytes()
+        };
+
+        let encoding = ENCODING_INI.to_le_bytes();
+        if !self.file_metadata.is_empty() {
+            let data = ini(&self.file_metadata);
+            Self::encode_block(
+                &mut out,
+                BlockType::FileMetadata,
+                Compression::Deflate,
+                &encoding,
+                &data,
+            );
+        }
+        let data = ini(&self.printer_metadata);
+        Self::encode_block(
+            &mut out,
+            BlockType::PrinterMetadata,
+            Compression::Deflate,
+            &encoding,
+            &data,
+        );
+
+        for thumbnail in &self.thumbnails {
+            let format: u16 = match thumbnail.format {
+                ThumbnailFormat::Png => 0,
+                ThumbnailFormat::Jpg => 1,
+                ThumbnailFormat::Qoi => 2,
+            };
+            let mut params = format.to_le_bytes().to_vec();
+            params.extend((thumbnail.width as u16).to_le_bytes());
+            params.extend((thumbnail.height as u16).to_le_bytes());
+            Self::encode_block(
+                &mut out,
+                BlockType::Thumbnail,
+                Compression::None,
+                &params,
+                &thumbnail.data,
+            );
+        }
+
+        let data = ini(&self.print_metadata);
+        Self::encode_block(
+            &mut out,
+            BlockType::PrintMetadata,
+            Compression::Deflate,
+            &encoding,
+            &data,
+        );
+        let data = ini(&self.slicer_metadata);
+        Self::encode_block(
+            &mut out,
+            BlockType::SlicerMetadata,
+            Compression::Deflate,
+            &encoding,
+            &data,
+        );
+
+        let encoding = ENCODING_GCODE_MEATPACK_COMMENTS.to_le_bytes();
+        for chunk in Self::gcode_chunks(&self.gcode) {
+            let data = meatpack::encode(chunk.as_bytes());
+            Self::encode_block(
+                &mut out,
+                BlockType::GCode,
+                Compression::Heatshrink12,
+                &encoding,
+                &data,
+            );
+        }
+
+        out
+    }
+
+    fn encode_block(
+        out: &mut Vec<u8>,
+        block_type: BlockType,
+        compression: Compression,
+        params: &[u8],
+        data: &[u8],
+    ) {
+        let start = out.len();
+        let compressed = compression.compress(data);
+
+        out.extend(block_type.as_u16().to_le_bytes());
+        out.extend(compression.as_u16().to_le_bytes());
+        out.extend((data.len() as u32).to_le_bytes());
+        if compression != Compression::None {
+            out.extend((compressed.len() as u32).to_le_bytes());
+        }
+        out.extend(params);
+        out.extend(&compressed);
+
+        let checksum = crc32fast::hash(&out[start..]);
+        out.extend(checksum.to_le_bytes());
+    }
+
+    /// Splits `gcode` into chunks of whole lines no larger than a block
+    fn gcode_chunks(gcode: &str) -> Vec<&str> {
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        let mut end = 0;
+
+        for line in gcode.split_inclusive('\n') {
+            if end + line.len() - start > MAX_GCODE_BLOCK && end > start {
+                chunks.push(&gcode[start..end]);
+                start = end;
+            }
+            end += line.len();
+        }
+        if end > start {
+            chunks.push(&gcode[start..end]);
+        }
+
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE: &str = include_str!("../../../../printctl/fixtures/cube.gcode");
+
+    #[test]
+    fn heatshrink_round_trip() {
+        for (window, lookahead) in [(11, 4), (12, 4)] {
+            let encoded = heatshrink::encode(CUBE.as_bytes(), window, lookahead);
+            assert!(encoded.len() < CUBE.len());
+            let decoded = heatshrink::decode(&encoded, window, lookahead).unwrap();
+            assert_eq!(decoded, CUBE.as_bytes());
+        }
+    }
+
+    #[test]
+    fn meatpack_round_trip() {
+        for src in [CUBE, "G1 X1", "M104 S210 ; heat\n;comment\n"] {
+            let decoded = meatpack::decode(&meatpack::encode(src.as_bytes())).unwrap();
+            assert_eq!(decoded, src.as_bytes());
+        }
+    }
+
+    #[test]
+    fn bgcode_round_trip() {
+        let mut file = BinaryGCode::from_ascii(CUBE);
+        file.print_metadata = vec![(
+            "estimated printing time (normal mode)".into(),
+            "4m 34s".into(),
+        )];
+        // repeat the program to span several G-code blocks
+        file.gcode = file.gcode.repeat(4);
+
+        let bytes = file.encode();
+        assert!(is_bgcode(&bytes));
+        assert!(bytes.len() < file.gcode.len());
+
+        let decoded = BinaryGCode::decode(&bytes).unwrap();
+        assert_eq!(decoded.gcode, file.gcode);
+        assert_eq!(decoded.file_metadata, file.file_metadata);
+        assert_eq!(decoded.print_metadata, file.print_metadata);
+
+        let ascii = to_ascii(&bytes).unwrap();
+        assert!(ascii.contains("; estimated printing time (normal mode) = 4m 34s"));
+    }
+
+    #[test]
+    fn bgcode_detects_corruption() {
+        let mut bytes = BinaryGCode::from_ascii(CUBE).encode();
+        let last = bytes.len() - 10;
+        bytes[last] ^= 0xFF;
+        assert!(BinaryGCode::decode(&bytes).is_err());
+    }
+}
@@ -1,6 +1,3 @@
-use std::collections::HashMap;
-use std::ops::Range;
-
 use gcode::GCode;
 
 #[derive(Debug, Default, Clone)]
@@ -13,140 +10,167 @@ pub enum GCodeLine {
     },
 }
 
-#[derive(Debug, Clone)]
-pub struct ArgRange(Range<usize>, gcode::Word);
-
-impl ArgRange {
-    pub fn new(range: Range<usize>, argument: gcode::Word) -> Self {
-        Self(range, argument)
+impl GCodeLine {
+    pub fn gcodes(&self) -> &[GCode] {
+        match self {
+            GCodeLine::Empty => &[],
+            GCodeLine::Command { gcodes, .. } => gcodes,
+        }
     }
 
-    pub fn range(&self) -> &Range<usize> {
-        &self.0
+    /// Zero-based line in the source text this line was parsed from
+    pub fn source_line(&self) -> Option<usize> {
+        match self {
+            GCodeLine::Empty => None,
+            GCodeLine::Command { gcodes, .. } => gcodes.first().map(|gcode| gcode.span().line),
+        }
     }
 
-    pub fn argument(&self) -> &gcode::Word {
-        &self.1
+    /// Index of the line the comments of line `index` apply from. A marker
+    /// like `;LAYER:2` after a command belongs to the line after it.
+    pub fn comment_target(&self, index: usize) -> usize {
+        match self {
+            GCodeLine::Command { gcodes, .. } if !gcodes.is_empty() => index + 1,
+            _ => index,
+        }
     }
 }
 
-#[derive(Debug, Default, Clone)]
-pub struct ArgGroups([Box<[ArgRange]>; 26]);
-
-impl ArgGroups {
-    fn bucket_id(c: char) -> usize {
-        (c as u8 - b'A') as usize
-    }
-
-    pub fn new(lines: &[GCodeLine]) -> Self {
-        // tracks currently active argument groups by letter
-        let mut active: HashMap<char, (usize, gcode::Word)> = HashMap::new();
-
-        // 26 buckets for A-Z, each bucket will hold ArgRanges for that letter
-        // invariant: ranges are pushed in strictly increasing order of `start`
-        let mut buckets: [Vec<ArgRange>; 26] = std::array::from_fn(|_| Vec::new());
-
-        for (i, line) in lines.iter().enumerate() {
-            if let GCodeLine::Command { gcodes, .. } = line {
-                for gcode in gcodes.iter() {
-                    for arg in gcode.arguments() {
-                        let letter = arg.letter.to_ascii_uppercase();
-                        let idx = Self::bucket_id(letter);
-
-                        match active.get(&letter) {
-                            // case: same argument continues -> no action
-                            Some((_, active_arg)) if active_arg == arg => continue,
-
-                            // case: previous argument group ends -> close it and start new
-                            Some((start, active_arg)) => {
-                                let arg_range = ArgRange::new(*start..i, *active_arg);
-                                // push to bucket; start always > previous, so bucket remains sorted
-                                buckets[idx].push(arg_range);
-
-                                // open new active group starting at current line
-                                active.insert(letter, (i, *arg));
-                            }
-
-                            // case: first occurrence -> start new group
-                            None => {
-                                active.insert(letter, (i, *arg));
-                            }
-                        }
-                    }
+/// The parser reads `T` as a tool change of its own, so `M104 T1 S200`
+/// comes out as `M104` followed by `T1 S200`. Folds such words back into the
+/// M-code they belong to.
+pub fn merge_tool_words(gcodes: &[GCode]) -> Vec<GCode> {
+    let mut merged: Vec<GCode> = Vec::with_capacity(gcodes.len());
+
+    for gcode in gcodes {
+        let owner = merged.last_mut().filter(|prev| {
+            gcode.mnemonic() == gcode::Mnemonic::ToolChange
+                && prev.mnemonic() == gcode::Mnemonic::Miscellaneous
+                && prev.span().line == gcode.span().line
+        });
+
+        match owner {
+            Some(prev) => {
+                let tool = gcode::Word::new('T', gcode.major_number() as f32, gcode.span());
+                for word in std::iter::once(&tool).chain(gcode.arguments()) {
+                    // arguments beyond the buffer capacity are dropped
+                    let _ = prev.push_argument(*word);
                 }
             }
+            None => merged.push(gcode.clone()),
         }
+    }
 
-        // close any remaining active argument groups
-        let end = lines.len();
-        for (letter, (start, arg)) in active {
-            let idx = Self::bucket_id(letter);
-            // push at the end -> still preserves increasing start order
-            buckets[idx].push(ArgRange::new(start..end, arg));
-        }
+    merged
+}
 
-        let buckets: [Box<[ArgRange]>; 26] =
-            std::array::from_fn(|i| std::mem::take(&mut buckets[i]).into_boxed_slice());
+/// The parser sometimes repeats the command of a line, bare, at the end of
+/// the line before it; true if `gcode` is such a copy of `next`
+pub fn is_phantom(gcode: &GCode, next: Option<&GCode>) -> bool {
+    gcode.arguments().is_empty()
+        && next.is_some_and(|next| {
+            next.span().line == gcode.span().line
+                && next.mnemonic() == gcode.mnemonic()
+                && next.major_number() == gcode.major_number()
+                && next.minor_number() == gcode.minor_number()
+        })
+}
 
-        Self(buckets)
-    }
+/// Parses `src` into one line per source line, blank lines included, so
+/// writing them back out keeps the layout of the file
+pub fn parse_lines(src: &str) -> Vec<GCodeLine> {
+    parse_lines_at(src, 0, 0)
 }
 
-impl ArgGroups {
-    fn buckets(&self) -> &[Box<[ArgRange]>; 26] {
-        &self.0
+/// Like [`parse_lines`] for text found at byte `offset`, line `first_line`
+/// of a larger file, with spans pointing into that file
+pub fn parse_lines_at(src: &str, first_line: usize, offset: usize) -> Vec<GCodeLine> {
+    let mut gcodes = Vec::new();
+    let mut comments = Vec::new();
+    for line in gcode::full_parse_with_callbacks(src, gcode::Nop) {
+        gcodes.extend_from_slice(line.gcodes());
+        comments.extend(
+            line.comments()
+                .iter()
+                .map(|comment| (comment.span.line, comment.value.to_string())),
+        );
     }
 
-    /// Returns all argument groups active at the given line number
-    pub fn get(&self, line_number: usize) -> Vec<&ArgRange> {
-        let mut active_args = Vec::new();
+    // a tool word may land in another parsed line than its M-code
+    let gcodes = merge_tool_words(&gcodes);
 
-        // iterate over each letter's bucket (A-Z) each bucket is sorted by range start.
-        for bucket in self.buckets().iter() {
-            if bucket.is_empty() {
-                // skip empty buckets
-                continue;
+    let line_count = src.lines().count();
+    let mut lines: Vec<(Vec<GCode>, Vec<String>)> = vec![Default::default(); line_count];
+    for (i, gcode) in gcodes.iter().enumerate() {
+        if !is_phantom(gcode, gcodes.get(i + 1)) {
+            if let Some((gcodes, _)) = lines.get_mut(gcode.span().line) {
+                gcodes.push(shift(gcode, first_line, offset));
             }
+        }
+    }
+    for (line, comment) in comments {
+        if let Some((_, comments)) = lines.get_mut(line) {
+            comments.push(comment);
+        }
+    }
 
-            // perform a binary search to find the first range whose start is greater than line number
-            // this tells us the position where line number would be inserted to maintain sorted order
-            let idx = bucket.binary_search_by(|arg| {
-                if arg.range().start > line_number {
-                    std::cmp::Ordering::Greater
-                } else {
-                    std::cmp::Ordering::Less
-                }
-            });
-
-            // after the search:
-            // - Ok(i) means line number exactly matches the start of bucket[i]
-            // - Err(i) means line number would be inserted at index i to keep the array sorted
-            // in both cases, the candidate range that could contain line number is at index i-1
-            let check_idx = match idx {
-                Ok(i) | Err(i) => i.checked_sub(1),
-            };
-
-            if let Some(i) = check_idx {
-                let arg = &bucket[i];
-                // only add if line number actually falls within the range
-                if arg.range().contains(&line_number) {
-                    active_args.push(arg);
+    lines
+        .into_iter()
+        .map(|(gcodes, comments)| {
+            if gcodes.is_empty() && comments.is_empty() {
+                GCodeLine::Empty
+            } else {
+                GCodeLine::Command {
+                    gcodes: gcodes.into(),
+                    comments: comments.into(),
                 }
             }
-        }
+        })
+        .collect()
+}
 
-        active_args
-    }
+fn shift_span(span: gcode::Span, lines: usize, bytes: usize) -> gcode::Span {
+    gcode::Span::new(span.start + bytes, span.end + bytes, span.line + lines)
 }
 
-impl From<&[GCodeLine]> for ArgGroups {
-    fn from(lines: &[GCodeLine]) -> Self {
-        Self::new(lines)
+/// Moves `gcode` and its words `lines` lines and `bytes` bytes down
+fn shift(gcode: &GCode, lines: usize, bytes: usize) -> GCode {
+    if lines == 0 && bytes == 0 {
+        return gcode.clone();
+    }
+
+    let number = gcode.major_number() as f32 + gcode.minor_number() as f32 / 10.0;
+    let span = shift_span(gcode.span(), lines, bytes);
+    let mut shifted = GCode::new(gcode.mnemonic(), number, span);
+    for word in gcode.arguments() {
+        let span = shift_span(word.span, lines, bytes);
+        // same capacity as the original, so nothing is dropped
+        let _ = shifted.push_argument(gcode::Word::new(word.letter, word.value, span));
     }
+    shifted
 }
 
-impl From<&Box<[GCodeLine]>> for ArgGroups {
-    fn from(lines: &Box<[GCodeLine]>) -> Self {
-        Self::new(lines)
+/// Last value given for each argument letter, the modal words in effect
+/// after some line
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ArgState([Option<gcode::Word>; 26]);
+
+impl ArgState {
+    fn bucket_id(c: char) -> Option<usize> {
+        let c = c.to_ascii_uppercase();
+        c.is_ascii_uppercase().then(|| (c as u8 - b'A') as usize)
+    }
+
+    pub fn push(&mut self, line: &GCodeLine) {
+        for arg in line.gcodes().iter().flat_map(GCode::arguments) {
+            if let Some(idx) = Self::bucket_id(arg.letter) {
+                self.0[idx] = Some(*arg);
+            }
+        }
+    }
+
+    /// Words in effect, in letter order
+    pub fn words(&self) -> impl Iterator<Item = &gcode::Word> {
+        self.0.iter().flatten()
     }
 }
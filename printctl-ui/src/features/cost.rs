@@ -0,0 +1,198 @@
+use std::fmt;
+use std::ops::AddAssign;
+
+use super::statistics::ProgramStatistics;
+
+/// Filament as bought
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    /// Matched against the slicer's material name, ignoring case
+    pub name: String,
+    pub diameter_mm: f32,
+    /// g/cm³
+    pub density: f32,
+    pub price_per_kg: f32,
+}
+
+impl Material {
+    pub fn new(name: &str, density: f32, price_per_kg: f32) -> Self {
+        Self {
+            name: name.to_string(),
+            diameter_mm: 1.75,
+            density,
+            price_per_kg,
+        }
+    }
+
+    /// Grams in `length_mm` of filament
+    pub fn weight_g(&self, length_mm: f64) -> f64 {
+        let radius = self.diameter_mm as f64 / 2.0;
+        let volume_cm3 = std::f64::consts::PI * radius * radius * length_mm / 1000.0;
+        volume_cm3 * self.density as f64
+    }
+}
+
+/// Materials table and electricity rate jobs are billed with
+#[derive(Debug, Clone)]
+pub struct Pricing {
+    /// The first entry is used for files that name no known material
+    pub materials: Vec<Material>,
+    pub price_per_kwh: f32,
+}
+
+impl Default for Pricing {
+    fn default() -> Self {
+        Self {
+            materials: vec![
+                Material::new("PLA", 1.24, 20.0),
+                Material::new("PETG", 1.27, 22.0),
+                Material::new("ABS", 1.04, 22.0),
+                Material::new("ASA", 1.07, 28.0),
+                Material::new("TPU", 1.21, 35.0),
+                Material::new("PA", 1.14, 60.0),
+                Material::new("PC", 1.20, 45.0),
+            ],
+            price_per_kwh: 0.30,
+        }
+    }
+}
+
+impl Pricing {
+    pub fn material(&self, name: Option<&str>) -> Option<&Material> {
+        name.and_then(|name| {
+            self.materials
+                .iter()
+                .find(|material| material.name.eq_ignore_ascii_case(name.trim()))
+        })
+        .or(self.materials.first())
+    }
+
+    /// Bills a simulated program printed in `material`
+    pub fn estimate(&self, stats: &ProgramStatistics, material: Option<&str>) -> JobCost {
+        let material = self.material(material);
+        let filament_mm = stats.extrusion.net_mm().max(0.0);
+        let filament_g = material.map_or(0.0, |m| m.weight_g(filament_mm));
+        let energy_kwh = stats.energy.total_kwh();
+
+        JobCost {
+            filament_m: filament_mm / 1000.0,
+            filament_g,
+            filament_cost: material.map_or(0.0, |m| filament_g / 1000.0 * m.price_per_kg as f64),
+            energy_kwh,
+            energy_cost: energy_kwh * self.price_per_kwh as f64,
+        }
+    }
+}
+
+/// What a print uses up, summable over jobs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobCost {
+    pub filament_m: f64,
+    pub filament_g: f64,
+    pub filament_cost: f64,
+    pub energy_kwh: f64,
+    pub energy_cost: f64,
+}
+
+impl JobCost {
+    pub fn total(&self) -> f64 {
+        self.filament_cost + self.energy_cost
+    }
+}
+
+impl AddAssign<&JobCost> for JobCost {
+    fn add_assign(&mut self, other: &JobCost) {
+        self.filament_m += other.filament_m;
+        self.filament_g += other.filament_g;
+        self.filament_cost += other.filament_cost;
+        self.energy_kwh += other.energy_kwh;
+        self.energy_cost += other.energy_cost;
+    }
+}
+
+impl fmt::Display for JobCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2} m / {:.1} g filament ({:.2}), {:.3} kWh ({:.2}), total {:.2}",
+            self.filament_m,
+            self.filament_g,
+            self.filament_cost,
+            self.energy_kwh,
+            self.energy_cost,
+            self.total()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(net_mm: f64, heaters_j: f64) -> ProgramStatistics {
+        let mut stats = ProgramStatistics::default();
+        stats.extrusion.per_tool_extruded_mm = vec![net_mm];
+        stats.energy.heaters_j = heaters_j;
+        stats
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6 * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn bills_filament_and_energy() {
+        // a metre of 1.75 mm PLA is 2.405 cm³, and 3.6 MJ a kWh
+        let cost = Pricing::default().estimate(&stats(1000.0, 3.6e6), Some("PLA"));
+        let grams = std::f64::consts::PI * 0.875 * 0.875 * 1.24;
+        assert!(close(cost.filament_m, 1.0));
+        assert!(close(cost.filament_g, grams));
+        assert!(close(cost.filament_cost, grams / 1000.0 * 20.0));
+        assert!(close(cost.energy_kwh, 1.0));
+        assert!(close(cost.energy_cost, 0.30));
+        assert!(close(cost.total(), cost.filament_cost + 0.30));
+    }
+
+    #[test]
+    fn matches_materials_by_name() {
+        let pricing = Pricing::default();
+        let petg = pricing.estimate(&stats(1000.0, 0.0), Some(" petg "));
+        let pla = pricing.estimate(&stats(1000.0, 0.0), Some("PLA"));
+        assert!(petg.filament_g > pla.filament_g);
+        assert!(petg.filament_cost > pla.filament_cost);
+
+        // unknown or missing materials bill as the first one
+        for material in [Some("Unobtainium"), None] {
+            assert_eq!(pricing.estimate(&stats(1000.0, 0.0), material), pla);
+        }
+    }
+
+    #[test]
+    fn without_materials_only_length_and_energy_count() {
+        let pricing = Pricing {
+            materials: Vec::new(),
+            price_per_kwh: 0.5,
+        };
+        let cost = pricing.estimate(&stats(2000.0, 7.2e6), Some("PLA"));
+        assert!(close(cost.filament_m, 2.0));
+        assert_eq!((cost.filament_g, cost.filament_cost), (0.0, 0.0));
+        assert!(close(cost.energy_cost, 1.0));
+    }
+
+    #[test]
+    fn more_retracted_than_extruded_costs_nothing() {
+        let cost = Pricing::default().estimate(&stats(-5.0, 0.0), None);
+        assert_eq!(cost, JobCost::default());
+    }
+
+    #[test]
+    fn costs_add_up() {
+        let pricing = Pricing::default();
+        let one = pricing.estimate(&stats(1000.0, 3.6e6), None);
+        let mut total = JobCost::default();
+        total += &one;
+        total += &one;
+        assert!(close(total.filament_g, 2.0 * one.filament_g));
+        assert!(close(total.total(), 2.0 * one.total()));
+    }
+}
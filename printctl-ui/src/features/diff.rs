@@ -0,0 +1,687 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+
+use gcode::Mnemonic;
+
+use super::feature_type::FeatureType;
+use super::profile::MachineProfile;
+use super::program::{cmds, GCodeProgram, CHUNK_LINES};
+use super::progress::format_duration;
+use super::simulator::GCodeSimulator;
+use super::statistics::{FeatureMetrics, LayerMetrics, ProgramStatistics};
+
+// beyond this many edits in one section both sides are shown whole
+const MAX_EDITS: usize = 2000;
+
+/// A command as compared, comments and formatting left out
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffLine {
+    /// Zero-based source line
+    pub line: usize,
+    pub text: String,
+    // index into `GCodeProgram::lines`
+    program_line: usize,
+}
+
+/// Commands removed and added in one place, with the modal words
+/// (`F`, `S`, `Z`, ...) in effect where it starts in each program
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    /// Zero-based, `None` before the first layer
+    pub layer: Option<usize>,
+    pub feature: Option<FeatureType>,
+    pub removed: Vec<DiffLine>,
+    pub added: Vec<DiffLine>,
+    pub context_before: Vec<String>,
+    pub context_after: Vec<String>,
+}
+
+/// One layer of both programs, matched by number
+#[derive(Debug, Clone)]
+pub struct LayerDiff {
+    pub before: Option<LayerMetrics>,
+    pub after: Option<LayerMetrics>,
+    pub removed: usize,
+    pub added: usize,
+}
+
+impl LayerDiff {
+    pub fn is_changed(&self) -> bool {
+        self.removed + self.added > 0 || self.before.is_none() || self.after.is_none()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FeatureDiff {
+    pub feature: FeatureType,
+    pub before: FeatureMetrics,
+    pub after: FeatureMetrics,
+}
+
+/// Temperatures a program sets, in the order it first sets them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Temperatures {
+    pub hotend: Vec<f32>,
+    pub bed: Vec<f32>,
+}
+
+impl Temperatures {
+    fn new(program: &GCodeProgram) -> Self {
+        let mut temps = Self::default();
+        for chunk in program.chunks(CHUNK_LINES) {
+            for (_, gcode) in chunk.gcodes() {
+                if gcode.mnemonic() != Mnemonic::Miscellaneous {
+                    continue;
+                }
+                let targets = match gcode.major_number() {
+                    cmds::mcode::SET_HOTEND_TEMP | cmds::mcode::WAIT_FOR_HOTEND_TEMP => {
+                        &mut temps.hotend
+                    }
+                    cmds::mcode::SET_BED_TEMP | cmds::mcode::WAIT_FOR_BED_TEMP => &mut temps.bed,
+                    _ => continue,
+                };
+                let temp = gcode.value_for('S').or_else(|| gcode.value_for('R'));
+                if let Some(temp) = temp.filter(|&temp| temp > 0.0 && !targets.contains(&temp)) {
+                    targets.push(temp);
+                }
+            }
+        }
+        temps
+    }
+}
+
+/// What changed between two programs: simulated totals, each layer and
+/// feature type, and the commands themselves, compared layer by layer and
+/// within a layer by feature so reordered features still line up
+#[derive(Debug, Clone)]
+pub struct ProgramDiff {
+    pub before: ProgramStatistics,
+    pub after: ProgramStatistics,
+    pub temps_before: Temperatures,
+    pub temps_after: Temperatures,
+    pub layers: Vec<LayerDiff>,
+    pub features: Vec<FeatureDiff>,
+    pub hunks: Vec<Hunk>,
+}
+
+// layer (0 before the first) and feature of a run of commands, with how
+// many runs of that feature came before it in the layer
+type SectionKey = (usize, Option<FeatureType>, usize);
+
+/// Commands of a program with phantoms dropped, split into sections
+struct Sections {
+    commands: Vec<DiffLine>,
+    sections: BTreeMap<SectionKey, std::ops::Range<usize>>,
+    // sections in program order
+    order: Vec<SectionKey>,
+}
+
+impl Sections {
+    fn new(program: &GCodeProgram) -> Self {
+        let mut commands = Vec::new();
+        let mut keys = Vec::new();
+        for chunk in program.chunks(CHUNK_LINES) {
+            for (i, gcode) in chunk.gcodes() {
+                let layer = program.layers().layer_at(i).map_or(0, |layer| layer + 1);
+                keys.push((layer, program.features().feature_at(i)));
+                commands.push(DiffLine {
+                    line: gcode.span().line,
+                    text: gcode.to_string(),
+                    program_line: i,
+                });
+            }
+        }
+
+        let mut sections = BTreeMap::new();
+        let mut order = Vec::new();
+        let mut runs: BTreeMap<(usize, Option<FeatureType>), usize> = BTreeMap::new();
+        let mut start = 0;
+        for i in 1..=keys.len() {
+            if i < keys.len() && keys[i] == keys[start] {
+                continue;
+            }
+            let (layer, feature) = keys[start];
+            let run = runs.entry((layer, feature)).or_default();
+            let key = (layer, feature, *run);
+            *run += 1;
+            sections.insert(key, start..i);
+            order.push(key);
+            start = i;
+        }
+
+        Self {
+            commands,
+            sections,
+            order,
+        }
+    }
+
+    fn section(&self, key: &SectionKey) -> &[DiffLine] {
+        self.sections
+            .get(key)
+            .map_or(&[][..], |range| &self.commands[range.clone()])
+    }
+
+    /// Index into `commands` where `key` starts, or would
+    fn start(&self, key: &SectionKey) -> usize {
+        match self.sections.get(key) {
+            Some(range) => range.start,
+            None => self
+                .sections
+                .range(..key)
+                .next_back()
+                .map_or(0, |(_, range)| range.end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Edit {
+    Keep,
+    Remove,
+    Add,
+}
+
+/// Shortest edit script from `a` to `b` (Myers), `None` if it takes more
+/// than [`MAX_EDITS`] edits
+fn edit_script<T: PartialEq>(a: &[T], b: &[T]) -> Option<Vec<Edit>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    // furthest x on each diagonal k, kept around for every d to backtrack
+    let offset = (n + m + 1) as usize;
+    let mut v = vec![0isize; 2 * offset + 1];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=(n + m) {
+        if d as usize > MAX_EDITS {
+            return None;
+        }
+        // diagonals -d - 1..=d + 1, what backtracking from d reads
+        let window = offset - d as usize - 1..=offset + d as usize + 1;
+        trace.push(v[window].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let at = |k: isize| (k + offset as isize) as usize;
+            let mut x = if k == -d || k != d && v[at(k - 1)] < v[at(k + 1)] {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let (mut x, mut y) = (n, m);
+    let mut edits = Vec::new();
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let at = |k: isize| (k + d + 1) as usize;
+        let k = x - y;
+        let prev_k = if k == -d || k != d && v[at(k - 1)] < v[at(k + 1)] {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[at(prev_k)];
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x { Edit::Add } else { Edit::Remove });
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+
+    edits.reverse();
+    Some(edits)
+}
+
+/// Modal words in effect after the command before `index`
+fn context(program: &GCodeProgram, commands: &[DiffLine], index: usize) -> Vec<String> {
+    let Some(previous) = index.checked_sub(1).and_then(|i| commands.get(i)) else {
+        return Vec::new();
+    };
+    program
+        .args_at(previous.program_line)
+        .words()
+        .map(|word| format!("{}{}", word.letter, word.value))
+        .collect()
+}
+
+impl ProgramDiff {
+    pub fn new(before: &GCodeProgram, after: &GCodeProgram, profile: &MachineProfile) -> Self {
+        let simulator = GCodeSimulator::new(profile.clone());
+        let (stats_before, _) = simulator.simulate(before, profile.snapshot_builder());
+        let (stats_after, _) = simulator.simulate(after, profile.snapshot_builder());
+
+        let (a, b) = (Sections::new(before), Sections::new(after));
+        // in the order of the new program, sections only the old one has
+        // after the others of their layer
+        let mut keys = b.order.clone();
+        for key in &a.order {
+            if !b.sections.contains_key(key) {
+                let at = keys
+                    .iter()
+                    .rposition(|other| other.0 <= key.0)
+                    .map_or(0, |i| i + 1);
+                keys.insert(at, *key);
+            }
+        }
+
+        let mut hunks = Vec::new();
+        for key in &keys {
+            let (old, new) = (a.section(key), b.section(key));
+            let texts =
+                |lines: &[DiffLine]| lines.iter().map(|l| l.text.clone()).collect::<Vec<_>>();
+            let edits = edit_script(&texts(old), &texts(new)).unwrap_or_else(|| {
+                let mut edits = vec![Edit::Remove; old.len()];
+                edits.resize(old.len() + new.len(), Edit::Add);
+                edits
+            });
+
+            let (mut i, mut j) = (0, 0);
+            let mut edits = edits.into_iter().peekable();
+            while let Some(edit) = edits.next() {
+                if edit == Edit::Keep {
+                    (i, j) = (i + 1, j + 1);
+                    continue;
+                }
+                let (start_a, start_b) = (a.start(key) + i, b.start(key) + j);
+                let mut hunk = Hunk {
+                    layer: key.0.checked_sub(1),
+                    feature: key.1,
+                    removed: Vec::new(),
+                    added: Vec::new(),
+                    context_before: context(before, &a.commands, start_a),
+                    context_after: context(after, &b.commands, start_b),
+                };
+                let mut edit = Some(edit);
+                while let Some(change) = edit.filter(|&edit| edit != Edit::Keep) {
+                    match change {
+                        Edit::Remove => {
+                            hunk.removed.push(old[i].clone());
+                            i += 1;
+                        }
+                        _ => {
+                            hunk.added.push(new[j].clone());
+                            j += 1;
+                        }
+                    }
+                    edit = edits.next_if(|&edit| edit != Edit::Keep);
+                }
+                hunks.push(hunk);
+            }
+        }
+
+        let layer_count = stats_before.layers.len().max(stats_after.layers.len());
+        let mut layers = (0..layer_count)
+            .map(|i| LayerDiff {
+                before: stats_before.layers.get(i).cloned(),
+                after: stats_after.layers.get(i).cloned(),
+                removed: 0,
+                added: 0,
+            })
+            .collect::<Vec<_>>();
+        for hunk in &hunks {
+            if let Some(layer) = hunk.layer.and_then(|i| layers.get_mut(i)) {
+                layer.removed += hunk.removed.len();
+                layer.added += hunk.added.len();
+            }
+        }
+
+        let mut features = BTreeMap::<FeatureType, FeatureDiff>::new();
+        for (feature, metrics, is_after) in stats_before
+            .features
+            .iter()
+            .map(|(feature, metrics)| (feature, metrics, false))
+            .chain(stats_after.features.iter().map(|(f, m)| (f, m, true)))
+        {
+            let diff = features.entry(*feature).or_insert_with(|| FeatureDiff {
+                feature: *feature,
+                before: FeatureMetrics::default(),
+                after: FeatureMetrics::default(),
+            });
+            match is_after {
+                false => diff.before = metrics.clone(),
+                true => diff.after = metrics.clone(),
+            }
+        }
+
+        Self {
+            temps_before: Temperatures::new(before),
+            temps_after: Temperatures::new(after),
+            before: stats_before,
+            after: stats_after,
+            layers,
+            features: features.into_values().collect(),
+            hunks,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hunks.is_empty()
+    }
+
+    /// Rows of label, old value, new value and change, for the totals
+    pub fn summary(&self) -> Vec<[String; 4]> {
+        let (a, b) = (&self.before, &self.after);
+        let mut rows = vec![
+            duration_row("Time", a.total_time, b.total_time),
+            number_row(
+                "Extruded",
+                a.extrusion.extruded_mm,
+                b.extrusion.extruded_mm,
+                "mm",
+            ),
+            count_row("Print moves", a.print_moves, b.print_moves),
+            count_row("Travel moves", a.travel_moves, b.travel_moves),
+            count_row(
+                "Retractions",
+                a.extrusion.retractions,
+                b.extrusion.retractions,
+            ),
+            count_row("Layers", a.layers.len(), b.layers.len()),
+            number_row(
+                "Print speed",
+                a.xy_motion.print.average_speed_mm_per_s(),
+                b.xy_motion.print.average_speed_mm_per_s(),
+                "mm/s",
+            ),
+            number_row(
+                "Travel speed",
+                a.xy_motion.travel.average_speed_mm_per_s(),
+                b.xy_motion.travel.average_speed_mm_per_s(),
+                "mm/s",
+            ),
+        ];
+        for (label, old, new) in [
+            (
+                "Hotend",
+                &self.temps_before.hotend,
+                &self.temps_after.hotend,
+            ),
+            ("Bed", &self.temps_before.bed, &self.temps_after.bed),
+        ] {
+            let temps = |temps: &[f32]| {
+                let temps = temps.iter().map(f32::to_string).collect::<Vec<_>>();
+                format!("{}°C", temps.join("/"))
+            };
+            let change = if old == new { "" } else { "changed" };
+            rows.push([
+                label.to_string(),
+                temps(old),
+                temps(new),
+                change.to_string(),
+            ]);
+        }
+        rows
+    }
+}
+
+fn change(before: f64, after: f64, unit: &str) -> String {
+    let delta = after - before;
+    if delta.abs() < 0.05 {
+        return String::new();
+    }
+    let percent = match before {
+        0.0 => String::new(),
+        _ => format!(" ({:+.1}%)", delta / before * 100.0),
+    };
+    format!("{:+.1}{}{}", delta, unit, percent)
+}
+
+fn number_row(label: &str, before: f64, after: f64, unit: &str) -> [String; 4] {
+    [
+        label.to_string(),
+        format!("{:.1} {}", before, unit),
+        format!("{:.1} {}", after, unit),
+        change(before, after, &format!(" {}", unit)),
+    ]
+}
+
+fn count_row(label: &str, before: usize, after: usize) -> [String; 4] {
+    let delta = after as i64 - before as i64;
+    let change = match delta {
+        0 => String::new(),
+        _ => format!("{:+}", delta),
+    };
+    [
+        label.to_string(),
+        before.to_string(),
+        after.to_string(),
+        change,
+    ]
+}
+
+fn duration_row(label: &str, before: Duration, after: Duration) -> [String; 4] {
+    let change = match after.cmp(&before) {
+        std::cmp::Ordering::Equal => String::new(),
+        std::cmp::Ordering::Greater => format!("+{}", format_duration(after - before)),
+        std::cmp::Ordering::Less => format!("-{}", format_duration(before - after)),
+    };
+    [
+        label.to_string(),
+        format_duration(before),
+        format_duration(after),
+        change,
+    ]
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let layer = match self.layer {
+            Some(layer) => format!("layer {}", layer + 1),
+            None => "start".to_string(),
+        };
+        let feature = self
+            .feature
+            .map(|feature| format!(", {}", feature))
+            .unwrap_or_default();
+        writeln!(
+            f,
+            "@@ {}{} @@ {} | {}",
+            layer,
+            feature,
+            self.context_before.join(" "),
+            self.context_after.join(" ")
+        )?;
+        for line in &self.removed {
+            writeln!(f, "-{:>6}  {}", line.line + 1, line.text)?;
+        }
+        for line in &self.added {
+            writeln!(f, "+{:>6}  {}", line.line + 1, line.text)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ProgramDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<14}{:>14}{:>14}  Change", "", "Before", "After")?;
+        for [label, before, after, change] in self.summary() {
+            writeln!(f, "{:<14}{:>14}{:>14}  {}", label, before, after, change)?;
+        }
+
+        if !self.features.is_empty() {
+            writeln!(f, "Features:")?;
+            for diff in &self.features {
+                let (a, b) = (&diff.before, &diff.after);
+                writeln!(
+                    f,
+                    "  {:<18} {} -> {}  {:.1} -> {:.1} mm  {} -> {} moves",
+                    diff.feature.name(),
+                    format_duration(a.time),
+                    format_duration(b.time),
+                    a.extruded_mm,
+                    b.extruded_mm,
+                    a.moves,
+                    b.moves
+                )?;
+            }
+        }
+
+        let changed = self
+            .layers
+            .iter()
+            .enumerate()
+            .filter(|(_, layer)| layer.is_changed())
+            .collect::<Vec<_>>();
+        if !changed.is_empty() {
+            writeln!(f, "Changed layers:")?;
+        }
+        for (i, layer) in changed {
+            let z = |layer: &Option<LayerMetrics>| {
+                layer
+                    .as_ref()
+                    .and_then(|layer| layer.z)
+                    .map(|z| format!("{:.2}", z))
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            let time = |layer: &Option<LayerMetrics>| {
+                layer
+                    .as_ref()
+                    .map(|layer| format_duration(layer.time))
+                    .unwrap_or_else(|| "-".to_string())
+            };
+            writeln!(
+                f,
+                "  {:>5}  Z {} -> {} mm  {} -> {}  -{} +{} commands",
+                i + 1,
+                z(&layer.before),
+                z(&layer.after),
+                time(&layer.before),
+                time(&layer.after),
+                layer.removed,
+                layer.added
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: &str = "M104 S200\nM109 S200\nG28\nG90\nM83\nG1 F1800\n";
+
+    fn layer(n: usize, fill_x: u32) -> String {
+        format!(
+            ";LAYER:{}\nG1 Z{}\n;TYPE:WALL-OUTER\nG1 X10 Y0 E0.5\nG1 X10 Y10 E0.5\n\
+             ;TYPE:FILL\nG1 X0 Y10 E0.5\nG1 X{} Y0 E0.5\n",
+            n,
+            0.2 * (n + 1) as f32,
+            fill_x
+        )
+    }
+
+    fn program(layers: &[String]) -> GCodeProgram {
+        GCodeProgram::new(&format!("{}{}", START, layers.concat()))
+    }
+
+    fn diff(before: &[String], after: &[String]) -> ProgramDiff {
+        ProgramDiff::new(
+            &program(before),
+            &program(after),
+            &MachineProfile::default(),
+        )
+    }
+
+    /// Applies `edits` to `a`, taking added items from `b`
+    fn replay<T: Clone + PartialEq>(a: &[T], b: &[T], edits: &[Edit]) -> Vec<T> {
+        let (mut i, mut j, mut out) = (0, 0, Vec::new());
+        for edit in edits {
+            match edit {
+                Edit::Keep => {
+                    assert!(a[i] == b[j]);
+                    out.push(a[i].clone());
+                    (i, j) = (i + 1, j + 1);
+                }
+                Edit::Remove => i += 1,
+                Edit::Add => {
+                    out.push(b[j].clone());
+                    j += 1;
+                }
+            }
+        }
+        assert_eq!((i, j), (a.len(), b.len()));
+        out
+    }
+
+    #[test]
+    fn edit_script_is_shortest() {
+        for (a, b, changes) in [
+            ("", "", 0),
+            ("abc", "abc", 0),
+            ("", "abc", 3),
+            ("abc", "", 3),
+            ("abcabba", "cbabac", 5),
+            ("axc", "ayc", 2),
+            ("abcd", "abxcd", 1),
+        ] {
+            let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+            let edits = edit_script(&a, &b).unwrap();
+            assert_eq!(replay(&a, &b, &edits), b);
+            let count = edits.iter().filter(|&&edit| edit != Edit::Keep).count();
+            assert_eq!(count, changes, "{:?} -> {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn edit_script_gives_up_on_large_rewrites() {
+        let a = vec![0; MAX_EDITS];
+        let b = vec![1; MAX_EDITS];
+        assert!(edit_script(&a, &b).is_none());
+    }
+
+    #[test]
+    fn identical_programs_have_no_hunks() {
+        let layers = [layer(0, 0), layer(1, 0)];
+        let diff = diff(&layers, &layers);
+        assert!(diff.is_empty());
+        assert!(diff.layers.iter().all(|layer| !layer.is_changed()));
+        assert_eq!(diff.temps_before, diff.temps_after);
+    }
+
+    #[test]
+    fn inserted_layer_is_only_added() {
+        let diff = diff(&[layer(0, 0)], &[layer(0, 0), layer(1, 0)]);
+        assert!(diff.hunks.iter().all(|hunk| hunk.layer == Some(1)));
+        assert!(diff.hunks.iter().all(|hunk| hunk.removed.is_empty()));
+        let added = diff
+            .hunks
+            .iter()
+            .map(|hunk| hunk.added.len())
+            .sum::<usize>();
+        assert_eq!(added, 5);
+
+        assert_eq!(diff.layers.len(), 2);
+        assert!(!diff.layers[0].is_changed());
+        assert!(diff.layers[1].before.is_none() && diff.layers[1].after.is_some());
+    }
+
+    #[test]
+    fn changed_argument_stays_in_its_feature() {
+        let diff = diff(&[layer(0, 0), layer(1, 0)], &[layer(0, 0), layer(1, 5)]);
+        assert_eq!(diff.hunks.len(), 1);
+        let hunk = &diff.hunks[0];
+        assert_eq!(hunk.layer, Some(1));
+        assert_eq!(hunk.feature, Some(FeatureType::Infill));
+        let texts = |lines: &[DiffLine]| lines.iter().map(|l| l.text.clone()).collect::<Vec<_>>();
+        assert_eq!(texts(&hunk.removed), ["G1 X0 Y0 E0.5"]);
+        assert_eq!(texts(&hunk.added), ["G1 X5 Y0 E0.5"]);
+        assert_eq!(hunk.removed[0].line, hunk.added[0].line);
+        assert!(hunk.context_before.contains(&"F1800".to_string()));
+        assert_eq!(diff.layers[1].removed + diff.layers[1].added, 2);
+    }
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+use std::ops::Range;
+
+use super::code::GCodeLine;
+
+/// What a slicer says a region of the program prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FeatureType {
+    Skirt,
+    Brim,
+    OuterWall,
+    InnerWall,
+    OverhangWall,
+    Infill,
+    SolidInfill,
+    TopSurface,
+    BottomSurface,
+    Bridge,
+    GapFill,
+    Ironing,
+    Support,
+    SupportInterface,
+    WipeTower,
+    Custom,
+    Unknown,
+}
+
+impl FeatureType {
+    /// Parses a feature comment: `;TYPE:FILL` from Cura, `;TYPE:External
+    /// perimeter` from PrusaSlicer and `;TYPE:Outer wall` or `;FEATURE: Outer
+    /// wall` from Orca
+    pub fn from_comment(comment: &str) -> Option<Self> {
+        let comment = comment.trim_start_matches(';').trim();
+        let name = comment
+            .strip_prefix("TYPE:")
+            .or_else(|| comment.strip_prefix("FEATURE:"))?;
+        Some(Self::from_name(name))
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        let name = name.trim().to_ascii_lowercase().replace(['-', '_'], " ");
+        match name.as_str() {
+            "skirt" | "skirt/brim" => Self::Skirt,
+            "brim" => Self::Brim,
+            "wall outer" | "external perimeter" | "outer wall" => Self::OuterWall,
+            "wall inner" | "perimeter" | "inner wall" => Self::InnerWall,
+            "overhang perimeter" | "overhang wall" => Self::OverhangWall,
+            "fill" | "internal infill" | "sparse infill" => Self::Infill,
+            "skin" | "solid infill" | "internal solid infill" => Self::SolidInfill,
+            "top solid infill" | "top surface" => Self::TopSurface,
+            "bottom surface" => Self::BottomSurface,
+            "bridge" | "bridge infill" | "internal bridge" | "internal bridge infill" => {
+                Self::Bridge
+            }
+            "gap fill" | "gap infill" => Self::GapFill,
+            "ironing" => Self::Ironing,
+            "support" | "support material" | "support transition" => Self::Support,
+            "support interface" | "support material interface" => Self::SupportInterface,
+            "prime tower" | "wipe tower" => Self::WipeTower,
+            "custom" => Self::Custom,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Skirt => "Skirt",
+            Self::Brim => "Brim",
+            Self::OuterWall => "Outer wall",
+            Self::InnerWall => "Inner wall",
+            Self::OverhangWall => "Overhang wall",
+            Self::Infill => "Infill",
+            Self::SolidInfill => "Solid infill",
+            Self::TopSurface => "Top surface",
+            Self::BottomSurface => "Bottom surface",
+            Self::Bridge => "Bridge",
+            Self::GapFill => "Gap fill",
+            Self::Ironing => "Ironing",
+            Self::Support => "Support",
+            Self::SupportInterface => "Support interface",
+            Self::WipeTower => "Wipe tower",
+            Self::Custom => "Custom",
+            Self::Unknown => "Unknown",
+        }
+    }
+}
+
+impl fmt::Display for FeatureType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Program lines printing one feature
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureSegment {
+    pub feature: FeatureType,
+    /// Indices into [`GCodeProgram::lines`](super::program::GCodeProgram::lines)
+    pub lines: Range<usize>,
+}
+
+/// Feature segments of a program, in order, from slicer comments
+#[derive(Debug, Clone, Default)]
+pub struct FeatureIndex(Box<[FeatureSegment]>);
+
+impl FeatureIndex {
+    pub fn new(lines: &[GCodeLine]) -> Self {
+        let mut builder = FeatureIndexBuilder::default();
+        builder.push(lines);
+        builder.finish()
+    }
+}
+
+/// Builds a [`FeatureIndex`] from program lines pushed in order
+#[derive(Default)]
+pub struct FeatureIndexBuilder {
+    segments: Vec<FeatureSegment>,
+    // lines pushed so far
+    len: usize,
+}
+
+impl FeatureIndexBuilder {
+    pub fn push(&mut self, lines: &[GCodeLine]) {
+        for line in lines {
+            let i = self.len;
+            self.len += 1;
+
+            let GCodeLine::Command { comments, .. } = line else {
+                continue;
+            };
+            let Some(feature) = comments
+                .iter()
+                .find_map(|comment| FeatureType::from_comment(comment))
+            else {
+                continue;
+            };
+
+            let start = line.comment_target(i);
+            if let Some(last) = self.segments.last_mut() {
+                // repeated comments (every layer) carry on the segment
+                if last.feature == feature {
+                    continue;
+                }
+                last.lines.end = start;
+            }
+            self.segments.push(FeatureSegment {
+                feature,
+                lines: start..start,
+            });
+        }
+    }
+
+    pub fn feature_at(&self, line: usize) -> Option<FeatureType> {
+        feature_at(&self.segments, line)
+    }
+
+    pub fn finish(mut self) -> FeatureIndex {
+        if let Some(last) = self.segments.last_mut() {
+            last.lines.end = self.len.max(last.lines.start);
+        }
+        // markers on the same line leave empty segments behind
+        self.segments.retain(|segment| !segment.lines.is_empty());
+
+        FeatureIndex(self.segments.into_boxed_slice())
+    }
+}
+
+fn feature_at(segments: &[FeatureSegment], line: usize) -> Option<FeatureType> {
+    match segments.partition_point(|segment| segment.lines.start <= line) {
+        0 => None,
+        n => Some(segments[n - 1].feature),
+    }
+}
+
+impl FeatureIndex {
+    pub fn segments(&self) -> &[FeatureSegment] {
+        &self.0
+    }
+
+    /// Feature printed at program line `line`, none before the first comment
+    pub fn feature_at(&self, line: usize) -> Option<FeatureType> {
+        feature_at(&self.0, line)
+    }
+}
+
+impl From<&[GCodeLine]> for FeatureIndex {
+    fn from(lines: &[GCodeLine]) -> Self {
+        Self::new(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::program::GCodeProgram;
+    use super::*;
+
+    #[test]
+    fn parses_slicer_comments() {
+        for (comment, feature) in [
+            (";TYPE:WALL-OUTER", FeatureType::OuterWall),
+            (";TYPE:FILL", FeatureType::Infill),
+            (";TYPE:SKIN", FeatureType::SolidInfill),
+            (";TYPE:External perimeter", FeatureType::OuterWall),
+            (";TYPE:Top solid infill", FeatureType::TopSurface),
+            (";TYPE:Overhang perimeter", FeatureType::OverhangWall),
+            (";TYPE:Outer wall", FeatureType::OuterWall),
+            (";FEATURE: Sparse infill", FeatureType::Infill),
+            (";FEATURE: Internal Bridge", FeatureType::Bridge),
+            (";TYPE:Something new", FeatureType::Unknown),
+        ] {
+            assert_eq!(
+                FeatureType::from_comment(comment),
+                Some(feature),
+                "{}",
+                comment
+            );
+        }
+        for comment in [";LAYER:3", ";Z:0.2", ";WIDTH:0.45", "; TYPE is FILL"] {
+            assert_eq!(FeatureType::from_comment(comment), None, "{}", comment);
+        }
+    }
+
+    #[test]
+    fn segments_run_until_the_next_comment() {
+        let src = "G28\n;TYPE:SKIRT\nG1 X1 E1\n;TYPE:WALL-OUTER\nG1 X2 E2\nG1 X3 E3\n\
+                   ;TYPE:FILL\n;TYPE:FILL\nG1 X4 E4\n";
+        let program = GCodeProgram::new(src);
+        let features = program.features();
+        let kinds = features
+            .segments()
+            .iter()
+            .map(|segment| segment.feature)
+            .collect::<Vec<_>>();
+        // the repeated comment leaves no empty segment behind
+        assert_eq!(
+            kinds,
+            [
+                FeatureType::Skirt,
+                FeatureType::OuterWall,
+                FeatureType::Infill
+            ]
+        );
+
+        assert_eq!(features.feature_at(0), None);
+        assert_eq!(features.feature_at(2), Some(FeatureType::Skirt));
+        assert_eq!(features.feature_at(5), Some(FeatureType::OuterWall));
+        assert_eq!(features.feature_at(8), Some(FeatureType::Infill));
+        let last = features.segments().last().unwrap();
+        assert_eq!(last.lines.end, program.len());
+    }
+
+    #[test]
+    fn unlabelled_programs_have_no_segments() {
+        let program = GCodeProgram::new("G28\nG1 X10 E1\n;LAYER:1\nG1 X0 E2\n");
+        assert!(program.features().segments().is_empty());
+        assert_eq!(program.features().feature_at(1), None);
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt::Write;
+
+use gcode::GCode;
+
+use super::bgcode::meatpack;
+
+pub use super::code::parse_lines;
+use super::code::GCodeLine;
+
+/// How lines are written back out as G-code. Values are written as parsed,
+/// so a file formatted with the defaults reads back the same.
+#[derive(Debug, Clone, Default)]
+pub struct Formatter {
+    /// Decimals to round numbers to, trailing zeros dropped
+    pub precision: Option<usize>,
+    pub strip_comments: bool,
+    /// Drops comments, blank lines and the spaces between words
+    pub minify: bool,
+}
+
+impl Formatter {
+    /// As small as text gets, for streaming over serial
+    pub fn minified() -> Self {
+        Self {
+            minify: true,
+            ..Default::default()
+        }
+    }
+
+    /// Reformats a whole file
+    pub fn format(&self, src: &str) -> String {
+        self.format_lines(&parse_lines(src))
+    }
+
+    /// One command per line, with the comments after the last one
+    pub fn format_lines(&self, lines: &[GCodeLine]) -> String {
+        let strip_comments = self.strip_comments || self.minify;
+        let mut out = String::new();
+
+        for line in lines {
+            let (gcodes, comments) = match line {
+                GCodeLine::Command { gcodes, comments } => (&gcodes[..], &comments[..]),
+                GCodeLine::Empty => (&[][..], &[][..]),
+            };
+            let comments = if strip_comments { &[][..] } else { comments };
+            // lines that held only comments go with them
+            let dropped = matches!(line, GCodeLine::Command { .. }) && strip_comments;
+            if gcodes.is_empty() && comments.is_empty() && (self.minify || dropped) {
+                continue;
+            }
+
+            for (i, gcode) in gcodes.iter().enumerate() {
+                if i > 0 {
+                    out.push('\n');
+                }
+                self.write_gcode(&mut out, gcode);
+            }
+            for comment in comments {
+                if !out.is_empty() && !out.ends_with('\n') {
+                    out.push(' ');
+                }
+                out.push_str(comment);
+            }
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// Minified and MeatPack-encoded, for firmware that unpacks MeatPack
+    pub fn meatpack(&self, lines: &[GCodeLine]) -> Vec<u8> {
+        let text = Self {
+            minify: true,
+            ..self.clone()
+        }
+        .format_lines(lines);
+
+        meatpack::encode_minified(text.as_bytes())
+    }
+
+    fn write_gcode(&self, out: &mut String, gcode: &GCode) {
+        let _ = write!(out, "{}{}", gcode.mnemonic(), gcode.major_number());
+        if gcode.minor_number() != 0 {
+            let _ = write!(out, ".{}", gcode.minor_number());
+        }
+
+        for arg in gcode.arguments() {
+            if !self.minify {
+                out.push(' ');
+            }
+            out.push(arg.letter);
+            self.write_number(out, arg.value);
+        }
+    }
+
+    fn write_number(&self, out: &mut String, value: f32) {
+        let Some(precision) = self.precision else {
+            let _ = write!(out, "{}", value);
+            return;
+        };
+
+        let rounded = format!("{:.*}", precision, value);
+        let trimmed = match rounded.contains('.') {
+            true => rounded.trim_end_matches('0').trim_end_matches('.'),
+            false => &rounded,
+        };
+        out.push_str(if trimmed == "-0" { "0" } else { trimmed });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUBE: &str = include_str!("../../../printctl/fixtures/cube.gcode");
+
+    fn gcodes(lines: &[GCodeLine]) -> Vec<String> {
+        lines
+            .iter()
+            .flat_map(|line| match line {
+                GCodeLine::Command { gcodes, .. } => gcodes.iter().map(GCode::to_string).collect(),
+                GCodeLine::Empty => Vec::new(),
+            })
+            .collect()
+    }
+
+    fn comments(lines: &[GCodeLine]) -> Vec<String> {
+        lines
+            .iter()
+            .flat_map(|line| match line {
+                GCodeLine::Command { comments, .. } => comments.to_vec(),
+                GCodeLine::Empty => Vec::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_keeps_commands_and_comments() {
+        let original = parse_lines(CUBE);
+        let formatted = Formatter::default().format(CUBE);
+        let reparsed = parse_lines(&formatted);
+
+        assert_eq!(reparsed.len(), CUBE.lines().count());
+        assert_eq!(gcodes(&reparsed), gcodes(&original));
+        assert_eq!(comments(&reparsed), comments(&original));
+        assert!(comments(&original).iter().any(|c| c.starts_with(";LAYER:")));
+
+        // formatting is idempotent
+        assert_eq!(Formatter::default().format(&formatted), formatted);
+    }
+
+    #[test]
+    fn precision_rounds_and_trims() {
+        let formatter = Formatter {
+            precision: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            formatter.format("G1 X91.000 Y-0.001 E0.89802 F1800\n"),
+            "G1 X91 Y0 E0.9 F1800\n"
+        );
+
+        let formatted = formatter.format(CUBE);
+        assert_eq!(
+            gcodes(&parse_lines(&formatted)).len(),
+            gcodes(&parse_lines(CUBE)).len()
+        );
+    }
+
+    #[test]
+    fn strip_comments_keeps_commands() {
+        let formatter = Formatter {
+            strip_comments: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            formatter.format("M104 S200 ; heat\n;LAYER:0\n\nG28\n"),
+            "M104 S200\n\nG28\n"
+        );
+
+        let stripped = parse_lines(&formatter.format(CUBE));
+        assert!(comments(&stripped).is_empty());
+        assert_eq!(gcodes(&stripped), gcodes(&parse_lines(CUBE)));
+    }
+
+    #[test]
+    fn minified_meatpack_round_trip() {
+        let lines = parse_lines(CUBE);
+        let minified = Formatter::minified().format_lines(&lines);
+        assert!(!minified.contains(' ') && !minified.contains(';'));
+        assert_eq!(gcodes(&parse_lines(&minified)), gcodes(&lines));
+
+        let packed = Formatter::default().meatpack(&lines);
+        assert!(packed.len() < minified.len());
+        let unpacked = meatpack::decode(&packed).unwrap();
+        let unpacked = std::str::from_utf8(&unpacked).unwrap();
+        assert_eq!(gcodes(&parse_lines(unpacked)), gcodes(&lines));
+    }
+}
@@ -0,0 +1,318 @@
+use std::ops::Range;
+
+use gcode::{GCode, Mnemonic};
+
+use super::code::GCodeLine;
+use super::program::cmds;
+
+/// Range of program lines printing one layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    /// Height of the first extruding move, once the layer has one
+    pub z: Option<f32>,
+    /// Indices into [`GCodeProgram::lines`](super::program::GCodeProgram::lines)
+    pub lines: Range<usize>,
+}
+
+/// Layers of a program, from slicer comments or Z changes without them
+#[derive(Debug, Clone, Default)]
+pub struct LayerIndex(Box<[Layer]>);
+
+/// Cura writes `;LAYER:n`, PrusaSlicer and Orca `;LAYER_CHANGE`
+fn is_layer_comment(comment: &str) -> bool {
+    let comment = comment.trim_start_matches(';').trim();
+    comment == "LAYER_CHANGE"
+        || comment
+            .strip_prefix("LAYER:")
+            .is_some_and(|n| n.trim().parse::<i32>().is_ok())
+}
+
+/// Tracks just enough modal state to tell extruding moves apart
+#[derive(Default)]
+struct Scanner {
+    z: f32,
+    e: f32,
+    relative_e: bool,
+}
+
+impl Scanner {
+    /// Returns whether `gcode` extrudes while moving in XY
+    fn scan(&mut self, gcode: &GCode) -> bool {
+        match (gcode.mnemonic(), gcode.major_number()) {
+            (
+                Mnemonic::General,
+                cmds::gcode::TRAVEL_MOVE
+                | cmds::gcode::PRINT_MOVE
+                | cmds::gcode::PRINT_ARC_CW
+                | cmds::gcode::PRINT_ARC_CCW,
+            ) => {
+                if let Some(z) = gcode.value_for('Z') {
+                    self.z = z;
+                }
+                let planar = gcode.value_for('X').is_some() || gcode.value_for('Y').is_some();
+                match gcode.value_for('E') {
+                    Some(e) if self.relative_e => planar && e > 0.0,
+                    Some(e) => {
+                        let extruded = e > self.e;
+                        self.e = e;
+                        planar && extruded
+                    }
+                    None => false,
+                }
+            }
+            (Mnemonic::General, cmds::gcode::SET_POSITION) => {
+                if let Some(e) = gcode.value_for('E') {
+                    self.e = e;
+                }
+                false
+            }
+            (Mnemonic::Miscellaneous, cmds::mcode::EXTRUDE_ABSOLUTE_POSITIONING) => {
+                self.relative_e = false;
+                false
+            }
+            (Mnemonic::Miscellaneous, cmds::mcode::EXTRUDE_RELATIVE_POSITIONING) => {
+                self.relative_e = true;
+                false
+            }
+            _ => false,
+        }
+    }
+}
+
+impl LayerIndex {
+    pub fn new(lines: &[GCodeLine]) -> Self {
+        let mut builder = LayerIndexBuilder::default();
+        builder.push(lines);
+        builder.finish()
+    }
+}
+
+/// Builds a [`LayerIndex`] from program lines pushed in order, so large
+/// programs can be indexed a chunk at a time
+#[derive(Default)]
+pub struct LayerIndexBuilder {
+    layers: Vec<Layer>,
+    scanner: Scanner,
+    // where the move to the next layer height was issued
+    z_change: Option<usize>,
+    // whether the slicer marks layers with comments
+    commented: bool,
+    // lines pushed so far
+    len: usize,
+}
+
+impl LayerIndexBuilder {
+    pub fn push(&mut self, lines: &[GCodeLine]) {
+        for line in lines {
+            self.push_line(line);
+        }
+    }
+
+    fn push_line(&mut self, line: &GCodeLine) {
+        let i = self.len;
+        self.len += 1;
+
+        let GCodeLine::Command { gcodes, comments } = line else {
+            return;
+        };
+
+        for gcode in gcodes.iter() {
+            let z = self.scanner.z;
+            let extruding = self.scanner.scan(gcode);
+            if self.scanner.z != z {
+                self.z_change = Some(i);
+            }
+            if !extruding {
+                continue;
+            }
+
+            let layer_z = self.layers.last().and_then(|layer| layer.z);
+            match self.layers.last_mut() {
+                Some(layer) if layer.z.is_none() => layer.z = Some(self.scanner.z),
+                // without comments, printing above the last layer starts a new one
+                _ if !self.commented && layer_z.is_none_or(|z| self.scanner.z > z) => {
+                    let start = self.z_change.unwrap_or(i).min(i);
+                    self.layers.push(Layer {
+                        z: Some(self.scanner.z),
+                        lines: start..start,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        if comments.iter().any(|comment| is_layer_comment(comment)) {
+            if !self.commented {
+                // whatever the heuristic found so far was start G-code
+                self.commented = true;
+                self.layers.clear();
+            }
+            let start = line.comment_target(i);
+            self.layers.push(Layer {
+                z: None,
+                lines: start..start,
+            });
+        }
+    }
+
+    /// Lines pushed so far
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Layers found so far; only their starts are final
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn layer_at(&self, line: usize) -> Option<usize> {
+        layer_at(&self.layers, line)
+    }
+
+    pub fn finish(mut self) -> LayerIndex {
+        // each layer runs up to the next one, the last one to the end
+        let ends = self
+            .layers
+            .iter()
+            .skip(1)
+            .map(|layer| layer.lines.start)
+            .chain(std::iter::once(self.len))
+            .collect::<Vec<_>>();
+        for (layer, end) in self.layers.iter_mut().zip(ends) {
+            layer.lines.end = end;
+        }
+
+        LayerIndex(self.layers.into_boxed_slice())
+    }
+}
+
+fn layer_at(layers: &[Layer], line: usize) -> Option<usize> {
+    match layers.partition_point(|layer| layer.lines.start <= line) {
+        0 => None,
+        n => Some(n - 1),
+    }
+}
+
+impl LayerIndex {
+    pub fn layers(&self) -> &[Layer] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Zero-based layer containing program line `line`, none before the first
+    pub fn layer_at(&self, line: usize) -> Option<usize> {
+        layer_at(&self.0, line)
+    }
+
+    /// First line of the layer after the one containing `line`
+    pub fn next_start(&self, line: usize) -> Option<usize> {
+        let next = self.layer_at(line).map_or(0, |layer| layer + 1);
+        self.0.get(next).map(|layer| layer.lines.start)
+    }
+
+    /// First line of the layer containing `line`, or of the one before when
+    /// already there
+    pub fn previous_start(&self, line: usize) -> Option<usize> {
+        let layer = self.layer_at(line)?;
+        match self.0[layer].lines.start {
+            start if start < line => Some(start),
+            _ => layer
+                .checked_sub(1)
+                .map(|previous| self.0[previous].lines.start),
+        }
+    }
+}
+
+impl From<&[GCodeLine]> for LayerIndex {
+    fn from(lines: &[GCodeLine]) -> Self {
+        Self::new(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::program::GCodeProgram;
+    use super::*;
+
+    /// Zero-based source line of each layer's first command, and its height
+    fn layers(src: &str) -> Vec<(Option<usize>, Option<f32>)> {
+        let program = GCodeProgram::new(src);
+        program
+            .layers()
+            .layers()
+            .iter()
+            .map(|layer| {
+                let lines = program.lines(layer.lines.clone());
+                (lines.iter().find_map(GCodeLine::source_line), layer.z)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cura_comments_start_layers() {
+        let src = "G28\nG1 Z0.2\n;LAYER:0\nG1 X10 E1\n;LAYER:1\nG1 Z0.4\nG1 X0 E2\n";
+        assert_eq!(layers(src), [(Some(3), Some(0.2)), (Some(5), Some(0.4))]);
+    }
+
+    #[test]
+    fn prusa_comments_start_layers() {
+        let src =
+            "G28\n;LAYER_CHANGE\n;Z:0.2\nG1 Z0.2\nG1 X10 E1\n;LAYER_CHANGE\nG1 Z0.4\nG1 X0 E2\n";
+        assert_eq!(layers(src), [(Some(3), Some(0.2)), (Some(6), Some(0.4))]);
+    }
+
+    #[test]
+    fn comments_discard_the_heuristic_layers_before_them() {
+        // the purge line prints before the slicer's first layer
+        let src = "G1 Z0.3\nG1 X100 E10\n;LAYER:0\nG1 Z0.2\nG1 X10 E11\n";
+        assert_eq!(layers(src), [(Some(3), Some(0.2))]);
+    }
+
+    #[test]
+    fn z_changes_start_layers_without_comments() {
+        let src = "G28\nG1 Z5\nG1 Z0.2\nG1 X10 E1\nG1 Z0.4\nG1 X0 E2\nG1 Z0.6\n";
+        assert_eq!(layers(src), [(Some(2), Some(0.2)), (Some(4), Some(0.4))]);
+    }
+
+    #[test]
+    fn hops_retractions_and_travel_are_not_layers() {
+        let src = "G1 Z0.2\nG1 X10 E1\nG1 E0.2\nG1 Z0.6\nG1 X50\nG1 Z0.2\nG1 E1\nG1 X60 E2\n";
+        assert_eq!(layers(src), [(Some(0), Some(0.2))]);
+    }
+
+    #[test]
+    fn relative_extrusion_is_tracked() {
+        let src = "M83\nG1 Z0.2\nG1 X10 E1\nG1 Z0.4\nG1 X0 E1\nG1 Z0.6\nG1 X10 E-1\n";
+        assert_eq!(layers(src), [(Some(1), Some(0.2)), (Some(3), Some(0.4))]);
+    }
+
+    #[test]
+    fn navigates_between_layers() {
+        let index = LayerIndex(
+            [2..3, 3..6, 6..9]
+                .into_iter()
+                .map(|lines| Layer { z: None, lines })
+                .collect(),
+        );
+        assert_eq!(index.layer_at(0), None);
+        assert_eq!(index.layer_at(2), Some(0));
+        assert_eq!(index.layer_at(8), Some(2));
+        assert_eq!(index.next_start(0), Some(2));
+        assert_eq!(index.next_start(4), Some(6));
+        assert_eq!(index.next_start(7), None);
+        assert_eq!(index.previous_start(4), Some(3));
+        assert_eq!(index.previous_start(3), Some(2));
+        assert_eq!(index.previous_start(2), None);
+    }
+}
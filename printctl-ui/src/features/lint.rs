@@ -0,0 +1,622 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use gcode::{GCode, Mnemonic};
+
+use crate::prelude::*;
+
+use super::feature_type::FeatureType;
+use super::machine::MachineState;
+use super::profile::MachineProfile;
+use super::program::{cmds, GCodeProgram, CHUNK_LINES};
+
+/// Marlin's stock `EXTRUDE_MINTEMP`, °C
+pub const MIN_EXTRUDE_TEMP: f32 = 170.0;
+
+// more filament than path per move only happens when E is read in the wrong mode
+const MAX_FILAMENT_PER_MM: f32 = 1.0;
+// longer than any retraction
+const MAX_RETRACT_MM: f32 = 20.0;
+
+/// Something a program can get wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Rule {
+    /// Not implemented by the target firmware
+    UnsupportedCommand,
+    /// Coordinates that only make sense in the other positioning mode
+    ModeConfusion,
+    /// Extrusion while no hotend temperature is set
+    NoTemperature,
+    /// Extrusion below the minimum temperature or before waiting for it
+    ColdExtrusion,
+    /// Unretracted travel across what the layer already printed
+    TravelWithoutRetraction,
+    /// Axis speeds above the machine limits
+    FeedrateLimit,
+    /// Motion after `M84`/`M18` turned the motors off, without homing again
+    AfterMotorsOff,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::UnsupportedCommand,
+        Rule::ModeConfusion,
+        Rule::NoTemperature,
+        Rule::ColdExtrusion,
+        Rule::TravelWithoutRetraction,
+        Rule::FeedrateLimit,
+        Rule::AfterMotorsOff,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Rule::UnsupportedCommand => "unsupported-command",
+            Rule::ModeConfusion => "mode-confusion",
+            Rule::NoTemperature => "no-temperature",
+            Rule::ColdExtrusion => "cold-extrusion",
+            Rule::TravelWithoutRetraction => "travel-without-retraction",
+            Rule::FeedrateLimit => "feedrate-limit",
+            Rule::AfterMotorsOff => "after-motors-off",
+        }
+    }
+
+    /// Errors ruin the print or the machine, warnings might not
+    pub fn severity(self) -> Severity {
+        match self {
+            Rule::NoTemperature | Rule::ColdExtrusion | Rule::AfterMotorsOff => Severity::Error,
+            _ => Severity::Warning,
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Rule::ALL
+            .into_iter()
+            .find(|rule| rule.name() == s.trim())
+            .ok_or_else(|| Error::LintRule(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// One finding of a rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    /// Zero-based source line
+    pub line: usize,
+    pub rule: Rule,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        self.rule.severity()
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}: {} [{}] {}",
+            self.line + 1,
+            self.severity(),
+            self.rule,
+            self.message
+        )
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// JSON array of diagnostics with one-based line numbers
+pub fn to_json(diagnostics: &[Diagnostic]) -> String {
+    let entries = diagnostics
+        .iter()
+        .map(|d| {
+            format!(
+                "{{\"line\":{},\"rule\":{},\"severity\":{},\"message\":{}}}",
+                d.line + 1,
+                json_string(d.rule.name()),
+                json_string(&d.severity().to_string()),
+                json_string(&d.message)
+            )
+        })
+        .collect::<Vec<_>>();
+
+    format!("[{}]", entries.join(","))
+}
+
+/// Firmware whose command set programs are checked against
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Firmware {
+    #[default]
+    Marlin,
+    /// Built-in commands only, macros from the printer config are unknown
+    Klipper,
+}
+
+#[rustfmt::skip]
+const MARLIN_G: &[u32] = &[
+    0, 1, 2, 3, 4, 5, 6, 10, 11, 12, 17, 18, 19, 20, 21, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35,
+    38, 42, 53, 54, 55, 56, 57, 58, 59, 60, 61, 76, 80, 90, 91, 92, 425,
+];
+
+#[rustfmt::skip]
+const MARLIN_M: &[u32] = &[
+    0, 1, 3, 4, 5, 7, 8, 9, 10, 11, 16, 17, 18, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+    32, 33, 34, 42, 43, 48, 73, 75, 76, 77, 78, 80, 81, 82, 83, 84, 85, 86, 87, 92, 100, 102, 104,
+    105, 106, 107, 108, 109, 110, 111, 112, 113, 114, 115, 117, 118, 119, 120, 121, 122, 123, 125,
+    126, 127, 128, 129, 140, 141, 143, 145, 149, 150, 154, 155, 163, 164, 165, 166, 190, 191, 192,
+    193, 200, 201, 203, 204, 205, 206, 207, 208, 209, 211, 217, 218, 220, 221, 226, 240, 250, 255,
+    256, 260, 261, 280, 281, 282, 290, 300, 301, 302, 303, 304, 305, 306, 350, 351, 355, 360, 361,
+    362, 363, 364, 380, 381, 400, 401, 402, 403, 404, 405, 406, 407, 410, 412, 413, 420, 421, 422,
+    423, 425, 428, 430, 486, 493, 500, 501, 502, 503, 504, 510, 511, 512, 524, 540, 569, 575, 592,
+    593, 600, 603, 605, 665, 666, 672, 701, 702, 710, 808, 810, 811, 851, 852, 860, 861, 862, 863,
+    864, 865, 866, 867, 868, 869, 871, 876, 900, 906, 907, 908, 909, 910, 911, 912, 913, 914, 915,
+    916, 917, 918, 919, 928, 951, 993, 994, 995, 997, 999, 7219,
+];
+
+const KLIPPER_G: &[u32] = &[0, 1, 2, 3, 4, 10, 11, 17, 18, 19, 28, 90, 91, 92];
+
+#[rustfmt::skip]
+const KLIPPER_M: &[u32] = &[
+    18, 73, 82, 83, 84, 104, 105, 106, 107, 109, 110, 112, 114, 115, 117, 118, 140, 190, 204, 220,
+    221, 400, 486,
+];
+
+impl Firmware {
+    pub fn supports(self, gcode: &GCode) -> bool {
+        let (g, m) = match self {
+            Firmware::Marlin => (MARLIN_G, MARLIN_M),
+            Firmware::Klipper => (KLIPPER_G, KLIPPER_M),
+        };
+        match gcode.mnemonic() {
+            Mnemonic::General => g.contains(&gcode.major_number()),
+            Mnemonic::Miscellaneous => m.contains(&gcode.major_number()),
+            Mnemonic::ToolChange => true,
+            Mnemonic::ProgramNumber => false,
+        }
+    }
+}
+
+impl FromStr for Firmware {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "marlin" => Ok(Firmware::Marlin),
+            "klipper" => Ok(Firmware::Klipper),
+            _ => Err(Error::LintRule(format!("unknown firmware {}", s))),
+        }
+    }
+}
+
+/// Which rules run and what they check against
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    pub rules: BTreeSet<Rule>,
+    pub firmware: Firmware,
+    /// °C, below it extruding is cold
+    pub min_extrude_temp: f32,
+    /// Shorter travels may cross the part without retracting, mm
+    pub min_retract_travel_mm: f32,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            rules: Rule::ALL.into_iter().collect(),
+            firmware: Firmware::default(),
+            min_extrude_temp: MIN_EXTRUDE_TEMP,
+            min_retract_travel_mm: 2.0,
+        }
+    }
+}
+
+type Segment = [[f32; 2]; 2];
+
+fn orientation(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+/// Proper crossing, segments that only touch do not count
+fn crosses(p: &Segment, q: &Segment) -> bool {
+    orientation(p[0], p[1], q[0]) * orientation(p[0], p[1], q[1]) < 0.0
+        && orientation(q[0], q[1], p[0]) * orientation(q[0], q[1], p[1]) < 0.0
+}
+
+/// Runs through a program once, keeping the state the rules look at
+struct Linter<'a> {
+    config: &'a LintConfig,
+    diagnostics: Vec<Diagnostic>,
+    state: MachineState,
+    // whether each tool has been waited on since its target last changed
+    heated: Vec<bool>,
+    // rules that stay quiet until the state they complained about changes
+    temp_reported: bool,
+    mode_reported: bool,
+    // as the firmware sees it, which boots absolute
+    relative: bool,
+    relative_e: bool,
+    feed_reported: Option<f32>,
+    retracted: bool,
+    motors_off: Option<usize>,
+    // extruding moves of the current layer, only outer walls when the slicer
+    // labels features since combing crosses the inside on purpose
+    printed: Vec<Segment>,
+    on_outer_wall: Option<bool>,
+}
+
+impl Linter<'_> {
+    fn report(&mut self, rule: Rule, line: usize, message: String) {
+        if self.config.rules.contains(&rule) {
+            self.diagnostics.push(Diagnostic {
+                line,
+                rule,
+                message,
+            });
+        }
+    }
+
+    fn check(&mut self, gcode: &GCode) {
+        let line = gcode.span().line;
+        if !self.config.firmware.supports(gcode) {
+            let message = format!("{} is not supported by {:?}", gcode, self.config.firmware);
+            self.report(Rule::UnsupportedCommand, line, message);
+        }
+
+        let (next, _) = self.state.execute(gcode);
+        let general = gcode.mnemonic() == Mnemonic::General;
+        let misc = gcode.mnemonic() == Mnemonic::Miscellaneous;
+        match gcode.major_number() {
+            cmds::gcode::TRAVEL_MOVE
+            | cmds::gcode::PRINT_MOVE
+            | cmds::gcode::PRINT_ARC_CW
+            | cmds::gcode::PRINT_ARC_CCW
+                if general =>
+            {
+                self.check_move(gcode, &next)
+            }
+            cmds::gcode::AUTO_HOME if general => self.motors_off = None,
+            cmds::gcode::ABSOLUTE_POSITIONING | cmds::gcode::RELATIVE_POSITIONING if general => {
+                self.relative = gcode.major_number() == cmds::gcode::RELATIVE_POSITIONING;
+                self.relative_e = self.relative;
+                self.mode_reported = false;
+            }
+            cmds::gcode::SET_POSITION if general => self.mode_reported = false,
+            cmds::mcode::EXTRUDE_ABSOLUTE_POSITIONING
+            | cmds::mcode::EXTRUDE_RELATIVE_POSITIONING
+                if misc =>
+            {
+                self.relative_e = gcode.major_number() == cmds::mcode::EXTRUDE_RELATIVE_POSITIONING;
+                self.mode_reported = false;
+            }
+            // `M84 S` only sets the idle timeout
+            cmds::mcode::DISABLE_STEPPERS | cmds::mcode::STOP_IDLE_HOLD
+                if misc && gcode.value_for('S').is_none() =>
+            {
+                self.motors_off.get_or_insert(line);
+            }
+            cmds::mcode::SET_HOTEND_TEMP | cmds::mcode::WAIT_FOR_HOTEND_TEMP if misc => {
+                let tool = gcode
+                    .value_for('T')
+                    .map_or(self.state.active_tool(), |t| t as usize);
+                if let Some(heated) = self.heated.get_mut(tool) {
+                    *heated = gcode.major_number() == cmds::mcode::WAIT_FOR_HOTEND_TEMP;
+                }
+                self.temp_reported = false;
+            }
+            _ => {}
+        }
+
+        self.state = next;
+    }
+
+    fn check_move(&mut self, gcode: &GCode, next: &MachineState) {
+        let line = gcode.span().line;
+        let (from, to) = (self.state.position(), next.position());
+        let [dx, dy, dz] = [
+            (to.x() - from.x()).as_mm(),
+            (to.y() - from.y()).as_mm(),
+            (to.z() - from.z()).as_mm(),
+        ];
+        let de =
+            next.current_tool().extrusion().as_mm() - self.state.current_tool().extrusion().as_mm();
+        let xy = dx.hypot(dy);
+
+        if let Some(off) = self.motors_off {
+            if xy > 0.0 || dz != 0.0 || de != 0.0 {
+                let message = format!(
+                    "moves after the motors were turned off on line {} without homing",
+                    off + 1
+                );
+                self.report(Rule::AfterMotorsOff, line, message);
+                self.motors_off = None;
+            }
+        }
+
+        if de > 0.0 && !self.temp_reported {
+            let tool = next.active_tool();
+            let target = next.current_tool().heater_state().target_temp();
+            let issue = match target {
+                None => Some((
+                    Rule::NoTemperature,
+                    "extrudes with no hotend temperature set".to_string(),
+                )),
+                Some(temp) if temp < self.config.min_extrude_temp => Some((
+                    Rule::ColdExtrusion,
+                    format!(
+                        "extrudes with the hotend set to {}°C, below {}°C",
+                        temp, self.config.min_extrude_temp
+                    ),
+                )),
+                Some(_) if !self.heated.get(tool).copied().unwrap_or(true) => Some((
+                    Rule::ColdExtrusion,
+                    "extrudes before waiting for the hotend to heat (M109)".to_string(),
+                )),
+                Some(_) => None,
+            };
+            if let Some((rule, message)) = issue {
+                self.report(rule, line, message);
+                self.temp_reported = true;
+            }
+        }
+
+        self.check_modes(line, xy, de);
+        self.check_feedrate(line, next, [dx, dy, dz, de]);
+
+        let segment = [
+            [from.x().as_mm(), from.y().as_mm()],
+            [to.x().as_mm(), to.y().as_mm()],
+        ];
+        if de > 0.0 && xy > 0.0 {
+            if self.on_outer_wall != Some(false) {
+                self.printed.push(segment);
+            }
+        } else if de == 0.0
+            && xy >= self.config.min_retract_travel_mm
+            && !self.retracted
+            && !next.retracted()
+            && self
+                .printed
+                .iter()
+                .any(|printed| crosses(&segment, printed))
+        {
+            let message = format!("travels {:.1} mm across the part without retracting", xy);
+            self.report(Rule::TravelWithoutRetraction, line, message);
+        }
+
+        if de < 0.0 {
+            self.retracted = true;
+        } else if de > 0.0 {
+            self.retracted = false;
+        }
+    }
+
+    fn check_modes(&mut self, line: usize, xy: f32, de: f32) {
+        if self.mode_reported {
+            return;
+        }
+
+        let relative_e = self.relative_e;
+        let message = if self.relative && de > 0.0 && xy > 0.0 {
+            "prints in relative positioning (G91), coordinates look absolute".to_string()
+        } else if relative_e && xy > 0.0 && de > xy * MAX_FILAMENT_PER_MM {
+            format!(
+                "extrudes {:.1} mm over {:.1} mm, E looks absolute in relative extrusion mode (M83)",
+                de, xy
+            )
+        } else if !relative_e && de < -MAX_RETRACT_MM {
+            format!(
+                "E jumps back {:.1} mm, E looks relative in absolute extrusion mode (M82)",
+                -de
+            )
+        } else {
+            return;
+        };
+
+        self.report(Rule::ModeConfusion, line, message);
+        self.mode_reported = true;
+    }
+
+    fn check_feedrate(&mut self, line: usize, next: &MachineState, deltas: [f32; 4]) {
+        let feedrate = next.feedrate().as_mm_per_s();
+        let length = deltas[..3].iter().map(|d| d * d).sum::<f32>().sqrt();
+        if self.feed_reported == Some(feedrate) {
+            return;
+        }
+
+        // each axis moves at its share of the feedrate, E alone at all of it
+        let length = if length > 0.0 {
+            length
+        } else {
+            deltas[3].abs()
+        };
+        if length == 0.0 {
+            return;
+        }
+        let limits = next.limits().max_feedrate;
+        let over =
+            (0..4).find(|&axis| feedrate * deltas[axis].abs() / length > limits[axis] * 1.001);
+        if let Some(axis) = over {
+            let message = format!(
+                "{} moves at {:.0} mm/s, above the {} mm/s limit",
+                ['X', 'Y', 'Z', 'E'][axis],
+                feedrate * deltas[axis].abs() / length,
+                limits[axis]
+            );
+            self.report(Rule::FeedrateLimit, line, message);
+            self.feed_reported = Some(feedrate);
+        }
+    }
+}
+
+/// Runs the configured rules over `program`, in program order
+pub fn lint(
+    program: &GCodeProgram,
+    profile: &MachineProfile,
+    config: &LintConfig,
+) -> Vec<Diagnostic> {
+    let state = MachineState::from_profile(profile);
+    let mut linter = Linter {
+        config,
+        diagnostics: Vec::new(),
+        heated: vec![false; state.tools().len()],
+        state,
+        temp_reported: false,
+        mode_reported: false,
+        relative: false,
+        relative_e: false,
+        feed_reported: None,
+        retracted: false,
+        motors_off: None,
+        printed: Vec::new(),
+        on_outer_wall: None,
+    };
+
+    let layers = program.layers();
+    let features = program.features();
+    let labelled = !features.segments().is_empty();
+    let mut layer = None;
+    for chunk in program.chunks(CHUNK_LINES) {
+        for (i, gcode) in chunk.gcodes() {
+            if layers.layer_at(i) != layer {
+                layer = layers.layer_at(i);
+                linter.printed.clear();
+            }
+            linter.on_outer_wall = labelled.then(|| {
+                matches!(
+                    features.feature_at(i),
+                    Some(FeatureType::OuterWall | FeatureType::OverhangWall)
+                )
+            });
+            linter.check(gcode);
+        }
+    }
+
+    linter.diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEATED: &str = "M104 S200\nM109 S200\nG28\nG90\nM82\n";
+
+    /// One-based lines `rule` flags in `src`, with only that rule enabled
+    fn flagged(rule: Rule, firmware: Firmware, src: &str) -> Vec<usize> {
+        let config = LintConfig {
+            rules: BTreeSet::from([rule]),
+            firmware,
+            ..Default::default()
+        };
+        let program = GCodeProgram::new(src);
+        lint(&program, &MachineProfile::default(), &config)
+            .into_iter()
+            .map(|d| d.line + 1)
+            .collect()
+    }
+
+    fn heated(rule: Rule, moves: &str) -> Vec<usize> {
+        flagged(rule, Firmware::Marlin, &format!("{}{}", HEATED, moves))
+    }
+
+    #[test]
+    fn unsupported_command() {
+        let src = "M600\nG1 X10\n";
+        assert_eq!(
+            flagged(Rule::UnsupportedCommand, Firmware::Klipper, src),
+            [1]
+        );
+        assert!(flagged(Rule::UnsupportedCommand, Firmware::Marlin, src).is_empty());
+    }
+
+    #[test]
+    fn mode_confusion() {
+        assert_eq!(heated(Rule::ModeConfusion, "G91\nG1 X10 Y10 E1\n"), [7]);
+        assert!(heated(Rule::ModeConfusion, "G1 X10 Y10 E1\n").is_empty());
+        assert_eq!(heated(Rule::ModeConfusion, "M83\nG1 X1 E5\n"), [7]);
+        assert!(heated(Rule::ModeConfusion, "M83\nG1 X10 E0.5\n").is_empty());
+    }
+
+    #[test]
+    fn no_temperature() {
+        let src = "G28\nG90\nG1 X10 E1\n";
+        assert_eq!(flagged(Rule::NoTemperature, Firmware::Marlin, src), [3]);
+        assert!(heated(Rule::NoTemperature, "G1 X10 E1\n").is_empty());
+    }
+
+    #[test]
+    fn cold_extrusion() {
+        let too_cold = "M109 S150\nG90\nG1 X10 E1\n";
+        assert_eq!(
+            flagged(Rule::ColdExtrusion, Firmware::Marlin, too_cold),
+            [3]
+        );
+        let not_waited = "M104 S200\nG90\nG1 X10 E1\n";
+        assert_eq!(
+            flagged(Rule::ColdExtrusion, Firmware::Marlin, not_waited),
+            [3]
+        );
+        assert!(heated(Rule::ColdExtrusion, "G1 X10 E1\n").is_empty());
+    }
+
+    #[test]
+    fn travel_without_retraction() {
+        let line = "G1 X0 Y5 F3000\nG1 X10 Y5 E1\nG1 X5 Y0\n";
+        let crossing = format!("{}G1 X5 Y10\n", line);
+        assert_eq!(heated(Rule::TravelWithoutRetraction, &crossing), [9]);
+        let retracted = format!("{}G1 E0.2\nG1 X5 Y10\n", line);
+        assert!(heated(Rule::TravelWithoutRetraction, &retracted).is_empty());
+    }
+
+    #[test]
+    fn feedrate_limit() {
+        assert_eq!(heated(Rule::FeedrateLimit, "G1 X100 F60000\n"), [6]);
+        assert!(heated(Rule::FeedrateLimit, "G1 X100 F6000\n").is_empty());
+    }
+
+    #[test]
+    fn after_motors_off() {
+        assert_eq!(heated(Rule::AfterMotorsOff, "M84\nG1 X10\n"), [7]);
+        assert!(heated(Rule::AfterMotorsOff, "M84\nG28\nG1 X10\n").is_empty());
+        assert!(heated(Rule::AfterMotorsOff, "M84 S60\nG1 X10\n").is_empty());
+    }
+}
@@ -3,7 +3,8 @@ use std::time::Duration;
 use gcode::GCode;
 
 use super::metric::{ActivePlane, Distance, Position, PositionMode, Speed, Units};
-use super::motion::MotionProfile;
+use super::motion::{ArcPath, MotionPath, MotionProfile};
+use super::profile::{MachineProfile, MotionLimits, RetractionSettings};
 
 #[derive(Debug, Default, Clone)]
 pub struct HomedAxes {
@@ -23,10 +24,33 @@ impl HeaterState {
     pub fn target_temp(&self) -> Option<f32> {
         self.1
     }
+
+    /// `S0` turns the heater off
+    fn set_target(&mut self, target: f32) {
+        self.1 = (target > 0.0).then_some(target);
+    }
 }
 
-#[derive(Debug, Clone)]
-pub struct ToolState(Distance, HeaterState);
+/// Heater a `M109`/`M190` blocks on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heater {
+    Bed,
+    Tool(usize),
+}
+
+/// Time the command itself holds up the queue for, besides motion
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Wait {
+    #[default]
+    None,
+    Dwell(Duration),
+    /// Also waits for the heater to cool down when set
+    Heater(Heater, bool),
+    ToolChange(Duration),
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ToolState(Distance, HeaterState, Position);
 
 impl ToolState {
     pub fn extrusion(&self) -> &Distance {
@@ -37,6 +61,15 @@ impl ToolState {
         &self.1
     }
 
+    fn heater_state_mut(&mut self) -> &mut HeaterState {
+        &mut self.1
+    }
+
+    /// Nozzle offset from the first tool
+    pub fn offset(&self) -> Position {
+        self.2
+    }
+
     fn extrude(&mut self, dist: Distance, mode: &PositionMode) {
         match mode {
             PositionMode::Absolute => self.0 = dist,
@@ -53,12 +86,16 @@ impl FanState {
         self.0
     }
 
+    fn set_speed(&mut self, speed: u8) {
+        self.0 = speed;
+    }
+
     fn stop(&mut self) {
         self.0 = 0;
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct MachineState {
     units: Units,
     axes: Position,
@@ -71,10 +108,81 @@ pub struct MachineState {
 
     active_tool: u8,
     tools: Vec<ToolState>,
+    tool_change_time: Duration,
 
     fans: Vec<FanState>,
     cooling_fan: FanState,
     bed_temp: HeaterState,
+
+    limits: MotionLimits,
+    retraction: RetractionSettings,
+    // whether `G10` retracted and `G11` has yet to recover
+    retracted: bool,
+    // geometry of the move that produced this state
+    path: MotionPath,
+    // what the command that produced this state waits for
+    wait: Wait,
+}
+
+impl Default for MachineState {
+    fn default() -> Self {
+        Self {
+            units: Units::default(),
+            axes: Position::default(),
+            feedrate: Speed::default(),
+            homed: HomedAxes::default(),
+            active_plane: ActivePlane::default(),
+            positioning: PositionMode::default(),
+            extrusion_positioning: PositionMode::default(),
+            // single extruder until a machine profile says otherwise
+            active_tool: 0,
+            tools: vec![ToolState::default()],
+            tool_change_time: Duration::ZERO,
+            fans: Vec::new(),
+            cooling_fan: FanState::default(),
+            bed_temp: HeaterState::default(),
+            limits: MotionLimits::default(),
+            retraction: RetractionSettings::default(),
+            retracted: false,
+            path: MotionPath::default(),
+            wait: Wait::default(),
+        }
+    }
+}
+
+impl MachineState {
+    pub fn from_profile(profile: &MachineProfile) -> Self {
+        let ambient = HeaterState(profile.ambient_temp, None);
+        let mut tools = profile
+            .tools
+            .iter()
+            .map(|tool| ToolState(Distance::ZERO, ambient.clone(), tool.offset))
+            .collect::<Vec<_>>();
+        if tools.is_empty() {
+            tools.push(ToolState(Distance::ZERO, ambient.clone(), Position::ORIGIN));
+        }
+
+        Self {
+            limits: profile.limits.clone(),
+            retraction: profile.retraction.clone(),
+            tools,
+            tool_change_time: profile.tool_change_time,
+            bed_temp: ambient,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn set_wait(&mut self, wait: Wait) {
+        self.wait = wait;
+    }
+
+    /// Overwrites the current heater temperatures with simulated ones
+    pub(crate) fn set_temperatures(&mut self, bed: f32, tools: &[f32]) {
+        self.bed_temp.0 = bed;
+        for (tool, temp) in self.tools.iter_mut().zip(tools) {
+            tool.1 .0 = *temp;
+        }
+    }
 }
 
 impl MachineState {
@@ -90,14 +198,58 @@ impl MachineState {
         &self.bed_temp
     }
 
+    pub fn path(&self) -> MotionPath {
+        self.path
+    }
+
+    pub fn wait(&self) -> Wait {
+        self.wait
+    }
+
+    pub fn cooling_fan(&self) -> &FanState {
+        &self.cooling_fan
+    }
+
+    pub fn fans(&self) -> &[FanState] {
+        &self.fans
+    }
+
+    pub fn limits(&self) -> &MotionLimits {
+        &self.limits
+    }
+
     pub fn tools(&self) -> &Vec<ToolState> {
         &self.tools
     }
 
+    pub fn retracted(&self) -> bool {
+        self.retracted
+    }
+
+    pub fn active_tool(&self) -> usize {
+        self.active_tool as usize
+    }
+
     pub fn current_tool(&self) -> &ToolState {
         &self.tools[self.active_tool as usize]
     }
 
+    pub fn feedrate(&self) -> Speed {
+        self.feedrate
+    }
+
+    pub fn units(&self) -> Units {
+        self.units
+    }
+
+    pub fn positioning(&self) -> PositionMode {
+        self.positioning
+    }
+
+    pub fn extrusion_positioning(&self) -> PositionMode {
+        self.extrusion_positioning
+    }
+
     fn set_feedrate(&mut self, feedrate: Speed) {
         self.feedrate = feedrate;
     }
@@ -113,6 +265,8 @@ impl MachineState {
     pub fn execute(&self, gcode: &GCode) -> (Self, Option<MotionProfile>) {
         let mut next = self.clone();
         let mut motion = None;
+        next.path = MotionPath::Linear;
+        next.wait = Wait::None;
 
         match (gcode.mnemonic(), gcode.major_number()) {
             (gcode::Mnemonic::General, cmds::gcode::USE_IMPERIAL_UNITS) => {
@@ -123,12 +277,15 @@ impl MachineState {
                 next.units = Units::Millimeters;
             }
 
+            // G90/G91 switch the extruder too, M82/M83 override it afterwards
             (gcode::Mnemonic::General, cmds::gcode::ABSOLUTE_POSITIONING) => {
                 next.positioning = PositionMode::Absolute;
+                next.extrusion_positioning = PositionMode::Absolute;
             }
 
             (gcode::Mnemonic::General, cmds::gcode::RELATIVE_POSITIONING) => {
                 next.positioning = PositionMode::Relative;
+                next.extrusion_positioning = PositionMode::Relative;
             }
 
             (gcode::Mnemonic::Miscellaneous, cmds::mcode::EXTRUDE_ABSOLUTE_POSITIONING) => {
@@ -138,6 +295,22 @@ impl MachineState {
             (gcode::Mnemonic::Miscellaneous, cmds::mcode::EXTRUDE_RELATIVE_POSITIONING) => {
                 next.extrusion_positioning = PositionMode::Relative;
             }
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::SET_MAX_ACCELERATION) => {
+                next.limits.set_max_acceleration(gcode);
+            }
+
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::SET_MAX_FEEDRATE) => {
+                next.limits.set_max_feedrate(gcode);
+            }
+
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::SET_STARTING_ACCELERATION) => {
+                next.limits.set_acceleration(gcode);
+            }
+
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::SET_ADVANCED_SETTINGS) => {
+                next.limits.set_advanced(gcode);
+            }
+
             (gcode::Mnemonic::General, cmds::gcode::AUTO_HOME) => {
                 next.axes = Position::default();
                 next.homed = HomedAxes {
@@ -145,33 +318,159 @@ impl MachineState {
                     y: true,
                     z: true,
                 };
+                motion.replace(MotionProfile::Instant);
+            }
+            (gcode::Mnemonic::General, cmds::gcode::DWELL) => {
+                // `P` is milliseconds, `S` seconds
+                let secs = gcode
+                    .value_for('S')
+                    .or_else(|| gcode.value_for('P').map(|ms| ms / 1000.0))
+                    .unwrap_or(0.0);
+                next.wait = Wait::Dwell(Duration::from_secs_f32(secs.max(0.0)));
+            }
+
+            // `G10 L` sets workspace offsets instead; repeated `G10`/`G11` do nothing
+            (gcode::Mnemonic::General, cmds::gcode::FIRMWARE_RETRACT)
+                if gcode.value_for('L').is_none() && !self.retracted =>
+            {
+                let retraction = &self.retraction;
+                next.retracted = true;
+                next.current_tool_mut().extrude(
+                    Distance::from_mm(-retraction.length),
+                    &PositionMode::Relative,
+                );
+                next.axes.translate_z(
+                    Distance::from_mm(retraction.z_lift),
+                    &PositionMode::Relative,
+                );
+                motion.replace(MotionProfile::ConstantVelocity(Speed::from_mm_per_s(
+                    retraction.feedrate,
+                )));
+            }
+
+            (gcode::Mnemonic::General, cmds::gcode::FIRMWARE_RECOVER) if self.retracted => {
+                let retraction = &self.retraction;
+                next.retracted = false;
+                next.current_tool_mut().extrude(
+                    Distance::from_mm(retraction.length + retraction.recover_extra),
+                    &PositionMode::Relative,
+                );
+                next.axes.translate_z(
+                    Distance::from_mm(-retraction.z_lift),
+                    &PositionMode::Relative,
+                );
+                motion.replace(MotionProfile::ConstantVelocity(Speed::from_mm_per_s(
+                    retraction.recover_feedrate,
+                )));
+            }
+
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::SET_FIRMWARE_RETRACTION) => {
+                next.retraction.set_retract(gcode);
+            }
+
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::SET_FIRMWARE_RECOVER) => {
+                next.retraction.set_recover(gcode);
             }
-            (gcode::Mnemonic::General, cmds::gcode::TRAVEL_MOVE | cmds::gcode::PRINT_MOVE) => {
-                if let Some(e) = gcode.value_for('E') {
-                    let dist = Distance::from_mm(e);
-                    next.current_tool_mut().extrude(dist, &self.positioning);
-                }
 
-                if let Some(f) = gcode.value_for('F') {
-                    let dist = Distance::new(f, &self.units);
-                    next.feedrate = Speed::from_distance_time(dist, Duration::from_secs(60));
+            (gcode::Mnemonic::ToolChange, tool) => {
+                let tool = tool as usize;
+                if tool < self.tools.len() && tool != self.active_tool() {
+                    next.active_tool = tool as u8;
+
+                    // the carriage shifts so the new nozzle lands on the same spot
+                    let shift = self.tools[tool]
+                        .offset()
+                        .distance(&self.current_tool().offset());
+                    let travel = shift / Speed::from_mm_per_s(self.limits.max_feedrate[0]);
+                    next.wait = Wait::ToolChange(self.tool_change_time + travel);
                 }
+            }
 
-                if let Some(x) = gcode.value_for('X') {
-                    let dx = Distance::new(x, &self.units);
-                    next.axes.translate_x(dx, &self.positioning);
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::SET_HOTEND_OFFSET) => {
+                let tool = gcode.value_for('T').map(|t| t as usize).unwrap_or(1);
+                if let Some(state) = next.tools.get_mut(tool) {
+                    let offset = state.offset();
+                    let axis = |letter, current: Distance| {
+                        gcode
+                            .value_for(letter)
+                            .map(|v| Distance::new(v, &self.units).as_mm())
+                            .unwrap_or(current.as_mm())
+                    };
+                    state.2 = Position::from_mm(
+                        axis('X', offset.x()),
+                        axis('Y', offset.y()),
+                        axis('Z', offset.z()),
+                    );
                 }
+            }
 
-                if let Some(y) = gcode.value_for('Y') {
-                    let dy = Distance::new(y, &self.units);
-                    next.axes.translate_y(dy, &self.positioning);
+            (gcode::Mnemonic::General, cmds::gcode::SET_POSITION) => {
+                self.set_position(&mut next, gcode);
+                motion.replace(MotionProfile::Instant);
+            }
+
+            (
+                gcode::Mnemonic::Miscellaneous,
+                cmds::mcode::SET_HOTEND_TEMP | cmds::mcode::WAIT_FOR_HOTEND_TEMP,
+            ) => {
+                let tool = gcode
+                    .value_for('T')
+                    .map(|t| t as usize)
+                    .unwrap_or(self.active_tool as usize);
+                // `R` waits for cooling as well as heating
+                let cooling = gcode.value_for('S').is_none();
+                let target = gcode.value_for('S').or_else(|| gcode.value_for('R'));
+
+                if let (Some(state), Some(target)) = (next.tools.get_mut(tool), target) {
+                    state.heater_state_mut().set_target(target);
+                    if gcode.major_number() == cmds::mcode::WAIT_FOR_HOTEND_TEMP {
+                        next.wait = Wait::Heater(Heater::Tool(tool), cooling);
+                    }
                 }
+            }
 
-                if let Some(z) = gcode.value_for('Z') {
-                    let dz = Distance::new(z, &self.units);
-                    next.axes.translate_z(dz, &self.positioning);
+            (
+                gcode::Mnemonic::Miscellaneous,
+                cmds::mcode::SET_BED_TEMP | cmds::mcode::WAIT_FOR_BED_TEMP,
+            ) => {
+                let cooling = gcode.value_for('S').is_none();
+                if let Some(target) = gcode.value_for('S').or_else(|| gcode.value_for('R')) {
+                    next.bed_temp.set_target(target);
+                    if gcode.major_number() == cmds::mcode::WAIT_FOR_BED_TEMP {
+                        next.wait = Wait::Heater(Heater::Bed, cooling);
+                    }
                 }
+            }
+
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::SET_FAN_SPEED) => {
+                let speed = gcode.value_for('S').unwrap_or(255.0).clamp(0.0, 255.0) as u8;
+                next.fan_mut(gcode).set_speed(speed);
+            }
+
+            (gcode::Mnemonic::Miscellaneous, cmds::mcode::FAN_OFF) => {
+                next.fan_mut(gcode).stop();
+            }
+
+            (gcode::Mnemonic::General, cmds::gcode::USE_XY_PLANE) => {
+                next.active_plane = ActivePlane::XY;
+            }
+
+            (gcode::Mnemonic::General, cmds::gcode::USE_ZX_PLANE) => {
+                next.active_plane = ActivePlane::XZ;
+            }
+
+            (gcode::Mnemonic::General, cmds::gcode::USE_YZ_PLANE) => {
+                next.active_plane = ActivePlane::YZ;
+            }
+
+            (gcode::Mnemonic::General, cmds::gcode::TRAVEL_MOVE | cmds::gcode::PRINT_MOVE) => {
+                self.apply_move(&mut next, gcode);
+                motion.replace(MotionProfile::ConstantVelocity(next.feedrate));
+            }
 
+            (gcode::Mnemonic::General, cmds::gcode::PRINT_ARC_CW | cmds::gcode::PRINT_ARC_CCW) => {
+                self.apply_move(&mut next, gcode);
+                next.path = self.arc_path(&next, gcode);
                 motion.replace(MotionProfile::ConstantVelocity(next.feedrate));
             }
             _ => {}
@@ -179,4 +478,363 @@ impl MachineState {
 
         (next, motion)
     }
+
+    /// `G92` with no axes resets all of them to zero
+    fn set_position(&self, next: &mut Self, gcode: &GCode) {
+        let all = !['X', 'Y', 'Z', 'E']
+            .iter()
+            .any(|axis| gcode.value_for(*axis).is_some());
+        let value = |axis| gcode.value_for(axis).or(all.then_some(0.0));
+
+        if let Some(e) = value('E') {
+            next.current_tool_mut()
+                .extrude(Distance::from_mm(e), &PositionMode::Absolute);
+        }
+        if let Some(x) = value('X') {
+            let x = Distance::new(x, &self.units);
+            next.axes.translate_x(x, &PositionMode::Absolute);
+        }
+        if let Some(y) = value('Y') {
+            let y = Distance::new(y, &self.units);
+            next.axes.translate_y(y, &PositionMode::Absolute);
+        }
+        if let Some(z) = value('Z') {
+            let z = Distance::new(z, &self.units);
+            next.axes.translate_z(z, &PositionMode::Absolute);
+        }
+    }
+
+    /// `P` selects an auxiliary fan, otherwise the part cooling fan
+    fn fan_mut(&mut self, gcode: &GCode) -> &mut FanState {
+        match gcode.value_for('P').map(|p| p as usize) {
+            Some(index) => {
+                if self.fans.len() <= index {
+                    self.fans.resize(index + 1, FanState::default());
+                }
+                &mut self.fans[index]
+            }
+            None => &mut self.cooling_fan,
+        }
+    }
+
+    /// Feedrate, extrusion and axis words shared by linear and arc moves
+    fn apply_move(&self, next: &mut Self, gcode: &GCode) {
+        if let Some(e) = gcode.value_for('E') {
+            let dist = Distance::from_mm(e);
+            next.current_tool_mut()
+                .extrude(dist, &self.extrusion_positioning);
+        }
+
+        if let Some(f) = gcode.value_for('F') {
+            let dist = Distance::new(f, &self.units);
+            next.feedrate = Speed::from_distance_time(dist, Duration::from_secs(60));
+        }
+
+        if let Some(x) = gcode.value_for('X') {
+            let dx = Distance::new(x, &self.units);
+            next.axes.translate_x(dx, &self.positioning);
+        }
+
+        if let Some(y) = gcode.value_for('Y') {
+            let dy = Distance::new(y, &self.units);
+            next.axes.translate_y(dy, &self.positioning);
+        }
+
+        if let Some(z) = gcode.value_for('Z') {
+            let dz = Distance::new(z, &self.units);
+            next.axes.translate_z(dz, &self.positioning);
+        }
+    }
+
+    /// Arc from this state to `next`; malformed arcs fall back to a straight line
+    fn arc_path(&self, next: &Self, gcode: &GCode) -> MotionPath {
+        let clockwise = gcode.major_number() == cmds::gcode::PRINT_ARC_CW;
+        let plane = self.active_plane;
+        let word = |letter| {
+            gcode
+                .value_for(letter)
+                .map(|value| Distance::new(value, &self.units).as_mm())
+        };
+
+        let arc = match word('R') {
+            Some(radius) => ArcPath::from_radius(self.axes, next.axes, radius, plane, clockwise),
+            None => {
+                // centre offsets relative to the start, in plane axis order
+                let (first, second) = match plane {
+                    ActivePlane::XY => ('I', 'J'),
+                    ActivePlane::XZ => ('K', 'I'),
+                    ActivePlane::YZ => ('J', 'K'),
+                };
+                let offset = [word(first).unwrap_or(0.0), word(second).unwrap_or(0.0)];
+                ArcPath::from_offset(self.axes, next.axes, offset, plane, clockwise)
+            }
+        };
+
+        arc.map(MotionPath::Arc).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::code::{is_phantom, merge_tool_words};
+    use super::super::profile::ToolProfile;
+    use super::*;
+
+    fn run(profile: &MachineProfile, src: &str) -> MachineState {
+        let gcodes = gcode::parse(src).collect::<Vec<_>>();
+        let mut state = profile.initial_state();
+        for (i, gcode) in gcodes.iter().enumerate() {
+            if !is_phantom(gcode, gcodes.get(i + 1)) {
+                state = state.execute(gcode).0;
+            }
+        }
+        state
+    }
+
+    fn close(a: Distance, mm: f32) -> bool {
+        (a.as_mm() - mm).abs() < 1e-4
+    }
+
+    #[test]
+    fn firmware_retraction_uses_the_stock_settings() {
+        let profile = MachineProfile::default();
+        let retracted = run(&profile, "G1 Z1\nG10\n");
+        assert!(retracted.retracted());
+        assert!(close(*retracted.current_tool().extrusion(), -3.0));
+
+        // a second G10 does nothing until G11 recovers
+        let again = run(&profile, "G1 Z1\nG10\nG10\n");
+        assert!(close(*again.current_tool().extrusion(), -3.0));
+        let recovered = run(&profile, "G1 Z1\nG10\nG11\nG11\n");
+        assert!(!recovered.retracted());
+        assert!(close(*recovered.current_tool().extrusion(), 0.0));
+    }
+
+    #[test]
+    fn m207_and_m208_change_retraction() {
+        let profile = MachineProfile::default();
+        let retracted = run(
+            &profile,
+            "G90\nG1 Z1\nM207 S5 F1800 Z0.4\nM208 S0.2 F600\nG10\n",
+        );
+        assert!(close(*retracted.current_tool().extrusion(), -5.0));
+        assert!(close(retracted.position().z(), 1.4));
+        assert_eq!(retracted.retraction.feedrate, 30.0);
+        assert_eq!(retracted.retraction.recover_feedrate, 10.0);
+
+        let (recovered, motion) = retracted.execute(&gcode::parse("G11").next().unwrap());
+        assert!(close(*recovered.current_tool().extrusion(), 0.2));
+        assert!(close(recovered.position().z(), 1.0));
+        assert!(matches!(
+            motion,
+            Some(MotionProfile::ConstantVelocity(speed)) if speed.as_mm_per_s() == 10.0
+        ));
+    }
+
+    #[test]
+    fn g10_with_l_sets_offsets_instead() {
+        let state = run(&MachineProfile::default(), "G10 L2 P1 X10\n");
+        assert!(!state.retracted());
+        assert!(close(*state.current_tool().extrusion(), 0.0));
+    }
+
+    #[test]
+    fn tool_changes_wait_for_the_carriage() {
+        let profile = MachineProfile {
+            tools: vec![
+                ToolProfile::default(),
+                ToolProfile {
+                    offset: Position::from_mm(30.0, 0.0, 0.0),
+                    thermal: None,
+                },
+            ],
+            tool_change_time: Duration::from_secs(2),
+            ..MachineProfile::default()
+        };
+        let state = profile.initial_state();
+
+        let (changed, _) = state.execute(&gcode::parse("T1").next().unwrap());
+        assert_eq!(changed.active_tool(), 1);
+        // 30 mm at the 300 mm/s X limit on top of the fixed time
+        let Wait::ToolChange(wait) = changed.wait() else {
+            panic!("{:?}", changed.wait());
+        };
+        assert!((wait.as_secs_f32() - 2.1).abs() < 1e-3);
+
+        // the active tool and tools that do not exist change nothing
+        for src in ["T0", "T5"] {
+            let (same, _) = state.execute(&gcode::parse(src).next().unwrap());
+            assert_eq!(same.active_tool(), 0);
+            assert_eq!(same.wait(), Wait::None);
+        }
+
+        let extruded = run(&profile, "T1\nM83\nG1 E2\nT0\nG1 E1\n");
+        assert!(close(*extruded.tools()[0].extrusion(), 1.0));
+        assert!(close(*extruded.tools()[1].extrusion(), 2.0));
+    }
+
+    /// Runs the single command in `src`, tool words folded in as programs do
+    fn step(state: &MachineState, src: &str) -> (MachineState, Option<MotionProfile>) {
+        let gcodes = merge_tool_words(&gcode::parse(src).collect::<Vec<_>>());
+        assert_eq!(gcodes.len(), 1, "{}", src);
+        state.execute(&gcodes[0])
+    }
+
+    #[test]
+    fn g4_dwells_for_p_milliseconds_or_s_seconds() {
+        let state = MachineState::default();
+        for (src, wait) in [
+            ("G4 P250", Duration::from_millis(250)),
+            ("G4 S2", Duration::from_secs(2)),
+            ("G4 S1 P500", Duration::from_secs(1)),
+            ("G4", Duration::ZERO),
+            ("G4 S-1", Duration::ZERO),
+        ] {
+            let (dwelling, motion) = step(&state, src);
+            assert_eq!(dwelling.wait(), Wait::Dwell(wait), "{}", src);
+            assert!(motion.is_none());
+        }
+
+        // the wait holds for that command only
+        assert_eq!(
+            run(
+                &MachineProfile::default(),
+                "G4 S2
+M83
+"
+            )
+            .wait(),
+            Wait::None
+        );
+    }
+
+    #[test]
+    fn g92_renames_positions_without_moving() {
+        let profile = MachineProfile::default();
+        let moved = run(&profile, "G90\nM82\nG1 X10 Y20 Z5 E7\n");
+
+        let (reset, motion) = step(&moved, "G92 E0");
+        assert!(close(*reset.current_tool().extrusion(), 0.0));
+        assert!(close(reset.position().x(), 10.0));
+        assert!(matches!(motion, Some(MotionProfile::Instant)));
+
+        let (shifted, _) = step(&moved, "G92 X1 Z0.2");
+        assert!(close(shifted.position().x(), 1.0));
+        assert!(close(shifted.position().y(), 20.0));
+        assert!(close(shifted.position().z(), 0.2));
+        assert!(close(*shifted.current_tool().extrusion(), 7.0));
+
+        // no axes resets all of them
+        let (zeroed, _) = step(&moved, "G92");
+        let position = zeroed.position();
+        assert!(close(position.x(), 0.0) && close(position.y(), 0.0) && close(position.z(), 0.0));
+        assert!(close(*zeroed.current_tool().extrusion(), 0.0));
+
+        // absolute moves continue from the new origin
+        let continued = run(&profile, "G90\nM82\nG1 X10 E7\nG92 X0 E0\nG1 X5 E1\n");
+        assert!(close(continued.position().x(), 5.0));
+        assert!(close(*continued.current_tool().extrusion(), 1.0));
+    }
+
+    #[test]
+    fn m104_and_m140_set_targets_without_waiting() {
+        let profile = MachineProfile::with_tools(2);
+        let state = profile.initial_state();
+
+        let (hotend, _) = step(&state, "M104 S210");
+        assert_eq!(
+            hotend.current_tool().heater_state().target_temp(),
+            Some(210.0)
+        );
+        assert_eq!(hotend.wait(), Wait::None);
+
+        let (other_tool, _) = step(&state, "M104 T1 S180");
+        assert_eq!(other_tool.tools()[0].heater_state().target_temp(), None);
+        assert_eq!(
+            other_tool.tools()[1].heater_state().target_temp(),
+            Some(180.0)
+        );
+
+        let (bed, _) = step(&state, "M140 S60");
+        assert_eq!(bed.bed_heater().target_temp(), Some(60.0));
+        assert_eq!(bed.wait(), Wait::None);
+
+        // S0 turns heaters off, a missing target leaves them be
+        let (off, _) = step(&hotend, "M104 S0");
+        assert_eq!(off.current_tool().heater_state().target_temp(), None);
+        let (kept, _) = step(&hotend, "M104");
+        assert_eq!(
+            kept.current_tool().heater_state().target_temp(),
+            Some(210.0)
+        );
+        let (bed_off, _) = step(&bed, "M140 S0");
+        assert_eq!(bed_off.bed_heater().target_temp(), None);
+    }
+
+    #[test]
+    fn m109_and_m190_wait_and_r_waits_for_cooling_too() {
+        let state = MachineProfile::with_tools(2).initial_state();
+
+        for (src, wait, target) in [
+            ("M109 S200", Wait::Heater(Heater::Tool(0), false), 200.0),
+            ("M109 R150", Wait::Heater(Heater::Tool(0), true), 150.0),
+            ("M109 T1 S220", Wait::Heater(Heater::Tool(1), false), 220.0),
+        ] {
+            let (waiting, _) = step(&state, src);
+            assert_eq!(waiting.wait(), wait, "{}", src);
+            let Wait::Heater(Heater::Tool(tool), _) = wait else {
+                unreachable!();
+            };
+            let heater = waiting.tools()[tool].heater_state();
+            assert_eq!(heater.target_temp(), Some(target), "{}", src);
+        }
+
+        let (bed, _) = step(&state, "M190 S60");
+        assert_eq!(bed.wait(), Wait::Heater(Heater::Bed, false));
+        assert_eq!(bed.bed_heater().target_temp(), Some(60.0));
+        let (cooling, _) = step(&bed, "M190 R40");
+        assert_eq!(cooling.wait(), Wait::Heater(Heater::Bed, true));
+        assert_eq!(cooling.bed_heater().target_temp(), Some(40.0));
+
+        // nothing to wait for without a target
+        assert_eq!(step(&state, "M109").0.wait(), Wait::None);
+        assert_eq!(step(&state, "M190").0.wait(), Wait::None);
+    }
+
+    #[test]
+    fn m106_and_m107_set_fans() {
+        let state = MachineState::default();
+
+        assert_eq!(step(&state, "M106").0.cooling_fan().speed(), 255);
+        assert_eq!(step(&state, "M106 S300").0.cooling_fan().speed(), 255);
+        let (half, _) = step(&state, "M106 S128");
+        assert_eq!(half.cooling_fan().speed(), 128);
+        assert_eq!(step(&half, "M107").0.cooling_fan().speed(), 0);
+
+        // P picks an auxiliary fan and leaves the part cooling fan alone
+        let (aux, _) = step(&half, "M106 P1 S100");
+        assert_eq!(aux.fans().len(), 2);
+        assert_eq!(aux.fans()[1].speed(), 100);
+        assert_eq!(aux.cooling_fan().speed(), 128);
+        let (aux_off, _) = step(&aux, "M107 P1");
+        assert_eq!(aux_off.fans()[1].speed(), 0);
+        assert_eq!(aux_off.cooling_fan().speed(), 128);
+    }
+
+    #[test]
+    fn g17_to_g19_select_the_arc_plane() {
+        let state = MachineState::default();
+        assert!(matches!(state.plane(), ActivePlane::XY));
+
+        let (xz, _) = step(&state, "G18");
+        assert!(matches!(xz.plane(), ActivePlane::XZ));
+        let (yz, _) = step(&xz, "G19");
+        assert!(matches!(yz.plane(), ActivePlane::YZ));
+        let (xy, _) = step(&yz, "G17");
+        assert!(matches!(xy.plane(), ActivePlane::XY));
+
+        // the plane is modal and outlives moves
+        let moved = run(&MachineProfile::default(), "G18\nG1 X10 Z1\n");
+        assert!(matches!(moved.plane(), ActivePlane::XZ));
+    }
 }
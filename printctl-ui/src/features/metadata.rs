@@ -0,0 +1,192 @@
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slicer {
+    Cura,
+    PrusaSlicer,
+    OrcaSlicer,
+}
+
+/// Print settings and estimates left by the slicer in header/footer comments
+#[derive(Debug, Default, Clone)]
+pub struct SlicerMetadata {
+    pub slicer: Option<Slicer>,
+    pub estimated_time: Option<Duration>,
+    pub filament_length_mm: Option<f32>,
+    pub filament_weight_g: Option<f32>,
+    pub layer_height_mm: Option<f32>,
+    pub material: Option<String>,
+}
+
+impl SlicerMetadata {
+    pub fn parse(src: &str) -> Self {
+        let mut meta = Self::default();
+
+        for line in src.lines() {
+            let Some(comment) = line.trim().strip_prefix(';') else {
+                continue;
+            };
+            let comment = comment.trim();
+
+            if meta.slicer.is_none() {
+                meta.slicer = Self::detect_slicer(comment);
+            }
+
+            // Cura uses `KEY:value`, Prusa/Orca use `key = value` (Orca headers `key : value`)
+            let Some((key, value)) = comment.split_once('=').or_else(|| comment.split_once(':'))
+            else {
+                continue;
+            };
+            meta.apply(&key.trim().to_ascii_lowercase(), value.trim());
+        }
+
+        meta
+    }
+
+    fn detect_slicer(comment: &str) -> Option<Slicer> {
+        let comment = comment.to_ascii_lowercase();
+        if comment.starts_with("generated with cura") {
+            Some(Slicer::Cura)
+        } else if comment.starts_with("generated by prusaslicer") {
+            Some(Slicer::PrusaSlicer)
+        } else if comment.starts_with("generated by orcaslicer") {
+            Some(Slicer::OrcaSlicer)
+        } else {
+            None
+        }
+    }
+
+    fn apply(&mut self, key: &str, value: &str) {
+        match key {
+            // Cura
+            "time" | "print.time" => {
+                self.estimated_time = value.parse().ok().map(Duration::from_secs);
+            }
+            "filament used" => {
+                // Cura reports meters (`1.2345m`)
+                let meters = value.replace('m', "");
+                self.filament_length_mm = parse_total(&meters).map(|m| m * 1000.0);
+            }
+            "layer height" => self.layer_height_mm = parse_number(value),
+            "material" | "filament_type" | "extruder_train.0.material.name" => {
+                let material = value.split(';').next().unwrap_or_default().trim();
+                if !material.is_empty() {
+                    self.material = Some(material.to_string());
+                }
+            }
+
+            // PrusaSlicer / OrcaSlicer, `producer` comes from binary G-code metadata
+            "producer" if self.slicer.is_none() => {
+                self.slicer = Self::detect_slicer(&format!("generated by {}", value));
+            }
+            "estimated printing time (normal mode)" | "total estimated time" => {
+                self.estimated_time = parse_duration(value).or(self.estimated_time);
+            }
+            "model printing time" => {
+                // Orca packs both estimates on one line: `1h 2m; total estimated time: 1h 5m`
+                if let Some((_, total)) = value.split_once("total estimated time:") {
+                    self.estimated_time = parse_duration(total.trim());
+                }
+            }
+            "filament used [mm]" | "total filament length [mm]" => {
+                self.filament_length_mm = parse_total(value);
+            }
+            "filament used [g]" | "total filament weight [g]" => {
+                self.filament_weight_g = parse_total(value);
+            }
+            "layer_height" => self.layer_height_mm = parse_number(value),
+
+            _ => {}
+        }
+    }
+}
+
+fn parse_number(value: &str) -> Option<f32> {
+    value.trim().parse().ok()
+}
+
+/// Multi-extruder values are comma separated; sum them up
+fn parse_total(value: &str) -> Option<f32> {
+    value.split(',').map(parse_number).sum::<Option<f32>>()
+}
+
+/// Parses slicer durations such as `1d 2h 3m 4s`
+fn parse_duration(value: &str) -> Option<Duration> {
+    let mut secs = 0;
+    for part in value.split_whitespace() {
+        let (number, unit) = part.split_at(part.len().checked_sub(1)?);
+        let number: u64 = number.parse().ok()?;
+        secs += match unit {
+            "d" => number * 86_400,
+            "h" => number * 3_600,
+            "m" => number * 60,
+            "s" => number,
+            _ => return None,
+        };
+    }
+    Some(Duration::from_secs(secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_cura_header() {
+        let meta = SlicerMetadata::parse(
+            ";FLAVOR:Marlin\n;TIME:6666\n;Filament used: 1.5m\n;Layer height: 0.2\n\
+             ;EXTRUDER_TRAIN.0.MATERIAL.NAME:PLA\n;Generated with Cura_SteamEngine 5.4.0\nG28\n",
+        );
+
+        assert_eq!(meta.slicer, Some(Slicer::Cura));
+        assert_eq!(meta.estimated_time, Some(Duration::from_secs(6666)));
+        assert_eq!(meta.filament_length_mm, Some(1500.0));
+        assert_eq!(meta.layer_height_mm, Some(0.2));
+        assert_eq!(meta.material.as_deref(), Some("PLA"));
+    }
+
+    #[test]
+    fn parses_prusaslicer_footer() {
+        let meta = SlicerMetadata::parse(
+            "; generated by PrusaSlicer 2.6.0+linux-x64-GTK3 on 2023-07-12 at 10:00:00 UTC\nG28\n\
+             ; filament used [mm] = 1234.5\n; filament used [g] = 3.7\n\
+             ; estimated printing time (normal mode) = 1h 2m 3s\n\
+             ; layer_height = 0.15\n; filament_type = PETG\n",
+        );
+
+        assert_eq!(meta.slicer, Some(Slicer::PrusaSlicer));
+        assert_eq!(meta.estimated_time, Some(Duration::from_secs(3723)));
+        assert_eq!(meta.filament_length_mm, Some(1234.5));
+        assert_eq!(meta.filament_weight_g, Some(3.7));
+        assert_eq!(meta.layer_height_mm, Some(0.15));
+        assert_eq!(meta.material.as_deref(), Some("PETG"));
+    }
+
+    #[test]
+    fn parses_orcaslicer_header() {
+        let meta = SlicerMetadata::parse(
+            "; generated by OrcaSlicer 1.8.0 on 2023-11-02 at 09:00:00\n\
+             ; model printing time: 1h 2m; total estimated time: 1h 5m 10s\n\
+             ; total filament length [mm] : 1000.0,250.5\n\
+             ; total filament weight [g] : 3.0,0.5\n\
+             ; layer_height = 0.2\n; filament_type = PLA;PETG\nG28\n",
+        );
+
+        assert_eq!(meta.slicer, Some(Slicer::OrcaSlicer));
+        assert_eq!(meta.estimated_time, Some(Duration::from_secs(3910)));
+        // multi-material totals are summed
+        assert_eq!(meta.filament_length_mm, Some(1250.5));
+        assert_eq!(meta.filament_weight_g, Some(3.5));
+        assert_eq!(meta.layer_height_mm, Some(0.2));
+        assert_eq!(meta.material.as_deref(), Some("PLA"));
+    }
+
+    #[test]
+    fn ignores_unknown_comments() {
+        let meta = SlicerMetadata::parse("; hello = world\n;LAYER:0\nG1 X1 ; move: fast\n");
+
+        assert_eq!(meta.slicer, None);
+        assert_eq!(meta.estimated_time, None);
+        assert_eq!(meta.material, None);
+    }
+}
@@ -109,7 +109,7 @@ impl Div<f32> for Distance {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
 pub struct Position {
     x_mm: f32,
     y_mm: f32,
@@ -123,6 +123,32 @@ impl Position {
         z_mm: 0.0,
     };
 
+    #[inline]
+    pub fn from_mm(x_mm: f32, y_mm: f32, z_mm: f32) -> Self {
+        Self { x_mm, y_mm, z_mm }
+    }
+
+    /// Coordinates as `[first, second, normal]` axes of `plane`, so arcs can
+    /// be computed the same way in every plane
+    #[inline]
+    pub fn to_plane(&self, plane: &ActivePlane) -> [f32; 3] {
+        match plane {
+            ActivePlane::XY => [self.x_mm, self.y_mm, self.z_mm],
+            ActivePlane::XZ => [self.z_mm, self.x_mm, self.y_mm],
+            ActivePlane::YZ => [self.y_mm, self.z_mm, self.x_mm],
+        }
+    }
+
+    #[inline]
+    pub fn from_plane(coords: [f32; 3], plane: &ActivePlane) -> Self {
+        let [a, b, n] = coords;
+        match plane {
+            ActivePlane::XY => Self::from_mm(a, b, n),
+            ActivePlane::XZ => Self::from_mm(b, n, a),
+            ActivePlane::YZ => Self::from_mm(n, a, b),
+        }
+    }
+
     #[inline]
     pub fn x(&self) -> Distance {
         Distance::from_mm(self.x_mm)
@@ -305,7 +331,7 @@ impl Div<Speed> for Distance {
         if speed.mm_per_s <= 0.0 {
             Duration::ZERO
         } else {
-            Duration::from_secs_f32(self.mm / speed.mm_per_s)
+            Duration::try_from_secs_f32(self.mm / speed.mm_per_s).unwrap_or_default()
         }
     }
 }
@@ -1,9 +1,25 @@
+pub mod bgcode;
 pub mod code;
+pub mod cost;
+pub mod diff;
+pub mod feature_type;
+pub mod format;
+pub mod layer;
+pub mod lint;
 pub mod machine;
+pub mod metadata;
 pub mod metric;
 pub mod motion;
+pub mod planner;
+pub mod profile;
 pub mod program;
+pub mod progress;
+pub mod resume;
 pub mod simulator;
 pub mod snapshot;
+pub mod source;
 pub mod statistics;
 pub mod thermal;
+pub mod thumbnail;
+pub mod timeline;
+pub mod transform;
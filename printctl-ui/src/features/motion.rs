@@ -1,22 +1,253 @@
+use std::f32::consts::TAU;
 use std::time::Duration;
 
 use super::machine::MachineState;
 use super::metric::{ActivePlane, Distance, Position, Speed};
 use super::snapshot::Transition;
 
+/// Marlin's `MINIMUM_PLANNER_SPEED`, mm/s
+pub const MIN_PLANNER_SPEED: f32 = 0.05;
+
+/// Floor for `M204 S0` and the like so planned moves always take time, mm/s²
+pub const MIN_ACCELERATION: f32 = 1.0;
+
 #[derive(Debug, Default)]
 pub enum MotionProfile {
     ConstantVelocity(Speed),
+    Trapezoidal(Trapezoid),
     #[default]
     Instant, // G92, homing completion, etc
 }
 
+/// Geometry of the move that led to a machine state
+#[derive(Debug, Clone, Copy, Default)]
+pub enum MotionPath {
+    #[default]
+    Linear,
+    Arc(ArcPath),
+}
+
+/// Circular (or helical) `G2`/`G3` move in an [`ActivePlane`]
+#[derive(Debug, Clone, Copy)]
+pub struct ArcPath {
+    plane: ActivePlane,
+    center: [f32; 2],
+    radius: f32,
+    start_angle: f32,
+    // signed, negative for clockwise
+    sweep: f32,
+}
+
+impl ArcPath {
+    /// Arc around `start + offset` (`I`/`J`/`K` in plane order)
+    pub fn from_offset(
+        start: Position,
+        end: Position,
+        offset: [f32; 2],
+        plane: ActivePlane,
+        clockwise: bool,
+    ) -> Option<Self> {
+        let [sa, sb, _] = start.to_plane(&plane);
+        let [ea, eb, _] = end.to_plane(&plane);
+        let center = [sa + offset[0], sb + offset[1]];
+        let radius = offset[0].hypot(offset[1]);
+        if radius <= f32::EPSILON {
+            return None;
+        }
+
+        let start_angle = (sb - center[1]).atan2(sa - center[0]);
+        let end_angle = (eb - center[1]).atan2(ea - center[0]);
+        let mut sweep = end_angle - start_angle;
+
+        // same start and end point is a full circle
+        let full_circle = (ea - sa).hypot(eb - sb) <= 1e-4;
+        if clockwise && (sweep >= 0.0 || full_circle) {
+            sweep -= TAU;
+        } else if !clockwise && (sweep <= 0.0 || full_circle) {
+            sweep += TAU;
+        }
+        sweep = sweep.clamp(-TAU, TAU);
+
+        Some(Self {
+            plane,
+            center,
+            radius,
+            start_angle,
+            sweep,
+        })
+    }
+
+    /// Arc of radius `R`; negative radii select the longer of the two arcs
+    pub fn from_radius(
+        start: Position,
+        end: Position,
+        radius: f32,
+        plane: ActivePlane,
+        clockwise: bool,
+    ) -> Option<Self> {
+        let [sa, sb, _] = start.to_plane(&plane);
+        let [ea, eb, _] = end.to_plane(&plane);
+        let (da, db) = (ea - sa, eb - sb);
+        let chord = da.hypot(db);
+        if chord <= f32::EPSILON {
+            return None;
+        }
+
+        // centre sits on the chord bisector, on the side the direction picks
+        let r = radius.abs();
+        let h = (r * r - chord * chord / 4.0).max(0.0).sqrt();
+        let side = if clockwise ^ (radius < 0.0) {
+            -1.0
+        } else {
+            1.0
+        };
+        let center = [
+            (sa + ea) / 2.0 - side * h * db / chord,
+            (sb + eb) / 2.0 + side * h * da / chord,
+        ];
+
+        Self::from_offset(
+            start,
+            end,
+            [center[0] - sa, center[1] - sb],
+            plane,
+            clockwise,
+        )
+    }
+
+    pub fn plane(&self) -> ActivePlane {
+        self.plane
+    }
+
+    /// Length of the arc projected on its plane
+    pub fn planar_length(&self) -> Distance {
+        Distance::from_mm(self.radius * self.sweep.abs())
+    }
+
+    fn point(&self, fraction: f32) -> [f32; 2] {
+        let angle = self.start_angle + self.sweep * fraction;
+        [
+            self.center[0] + self.radius * angle.cos(),
+            self.center[1] + self.radius * angle.sin(),
+        ]
+    }
+
+    // derivative of the in-plane position with respect to the swept fraction
+    fn velocity(&self, fraction: f32) -> [f32; 2] {
+        let angle = self.start_angle + self.sweep * fraction;
+        let speed = self.radius * self.sweep;
+        [-speed * angle.sin(), speed * angle.cos()]
+    }
+}
+
+/// Velocity profile of a planned move: accelerate, cruise, decelerate
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Trapezoid {
+    pub entry: Speed,
+    pub cruise: Speed,
+    pub exit: Speed,
+    /// mm/s²
+    pub acceleration: f32,
+
+    pub accelerate_distance: Distance,
+    pub cruise_distance: Distance,
+    pub decelerate_distance: Distance,
+}
+
+impl Trapezoid {
+    /// Fastest profile over `length` that respects the entry/exit speeds
+    pub fn new(length: Distance, entry: Speed, nominal: Speed, exit: Speed, accel: f32) -> Self {
+        let (length, v0, v1) = (length.as_mm(), entry.as_mm_per_s(), exit.as_mm_per_s());
+        // `f32::max` also drops NaN
+        let accel = accel.max(MIN_ACCELERATION);
+        let mut vc = nominal.as_mm_per_s().max(MIN_PLANNER_SPEED).max(v0).max(v1);
+
+        let mut accelerate = (vc * vc - v0 * v0) / (2.0 * accel);
+        let mut decelerate = (vc * vc - v1 * v1) / (2.0 * accel);
+
+        // too short to reach nominal speed -> triangle profile
+        if accelerate + decelerate > length {
+            let peak = ((2.0 * accel * length + v0 * v0 + v1 * v1) / 2.0).sqrt();
+            vc = peak.max(v0).max(v1);
+            accelerate = ((vc * vc - v0 * v0) / (2.0 * accel)).clamp(0.0, length);
+            decelerate = length - accelerate;
+        }
+
+        Self {
+            entry,
+            cruise: Speed::from_mm_per_s(vc),
+            exit,
+            acceleration: accel,
+            accelerate_distance: Distance::from_mm(accelerate),
+            cruise_distance: Distance::from_mm((length - accelerate - decelerate).max(0.0)),
+            decelerate_distance: Distance::from_mm(decelerate),
+        }
+    }
+
+    fn ramp_time(&self, from: Speed, to: Speed) -> Duration {
+        let dv = (to.as_mm_per_s() - from.as_mm_per_s()).abs();
+        if self.acceleration > 0.0 {
+            Duration::try_from_secs_f32(dv / self.acceleration).unwrap_or_default()
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    pub fn accelerate_time(&self) -> Duration {
+        self.ramp_time(self.entry, self.cruise)
+    }
+
+    pub fn cruise_time(&self) -> Duration {
+        self.cruise_distance / self.cruise
+    }
+
+    pub fn decelerate_time(&self) -> Duration {
+        self.ramp_time(self.cruise, self.exit)
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.accelerate_time() + self.cruise_time() + self.decelerate_time()
+    }
+
+    pub fn length(&self) -> Distance {
+        self.accelerate_distance + self.cruise_distance + self.decelerate_distance
+    }
+
+    /// Distance covered `t` into the move
+    pub fn distance_at(&self, t: Duration) -> Distance {
+        let (a, v0, vc) = (
+            self.acceleration,
+            self.entry.as_mm_per_s(),
+            self.cruise.as_mm_per_s(),
+        );
+        let (t_acc, t_cruise) = (self.accelerate_time(), self.cruise_time());
+
+        let mm = if t <= t_acc {
+            let t = t.as_secs_f32();
+            v0 * t + 0.5 * a * t * t
+        } else if t <= t_acc + t_cruise {
+            let t = (t - t_acc).as_secs_f32();
+            self.accelerate_distance.as_mm() + vc * t
+        } else {
+            let t = (t - t_acc - t_cruise)
+                .min(self.decelerate_time())
+                .as_secs_f32();
+            (self.accelerate_distance + self.cruise_distance).as_mm() + vc * t - 0.5 * a * t * t
+        };
+
+        Distance::from_mm(mm.clamp(0.0, self.length().as_mm()))
+    }
+}
+
 #[derive(Debug)]
 pub struct MotionTransition {
     start: Position,
     end: Position,
     plane: ActivePlane,
     motion: MotionProfile,
+    path: MotionPath,
+    // signed filament length fed by the active tool during the move
+    extrusion: Distance,
 }
 
 impl MotionTransition {
@@ -26,34 +257,135 @@ impl MotionTransition {
             end,
             plane,
             motion,
+            path: MotionPath::Linear,
+            extrusion: Distance::ZERO,
         }
     }
 
+    pub fn start(&self) -> Position {
+        self.start
+    }
+
+    pub fn end(&self) -> Position {
+        self.end
+    }
+
+    pub fn profile(&self) -> &MotionProfile {
+        &self.motion
+    }
+
+    pub fn path(&self) -> &MotionPath {
+        &self.path
+    }
+
+    pub fn extrusion(&self) -> Distance {
+        self.extrusion
+    }
+
+    /// Length travelled by the toolhead, following arcs and helices
     pub fn distance(&self) -> Distance {
-        self.start.planar_distance(&self.end, &self.plane)
+        match &self.path {
+            MotionPath::Linear => self.start.distance(&self.end),
+            MotionPath::Arc(arc) => {
+                let normal = self.end.to_plane(&arc.plane)[2] - self.start.to_plane(&arc.plane)[2];
+                Distance::from_mm(arc.planar_length().as_mm().hypot(normal))
+            }
+        }
     }
-}
 
-impl Transition for MotionTransition {
-    type Output = Position;
+    pub fn planar_distance(&self) -> Distance {
+        match &self.path {
+            MotionPath::Linear => self.start.planar_distance(&self.end, &self.plane),
+            MotionPath::Arc(arc) => arc.planar_length(),
+        }
+    }
 
-    fn interpolate(&self, tau: f32) -> Position {
+    /// Share of the path covered at normalized time τ
+    pub fn progress(&self, tau: f32) -> f32 {
         let t = tau.clamp(0.0, 1.0);
 
         match self.motion {
-            MotionProfile::Instant => self.end,
+            MotionProfile::Instant => 1.0,
 
-            MotionProfile::ConstantVelocity(_) => {
-                let delta = self.end - self.start;
-                self.start + delta * t
+            MotionProfile::ConstantVelocity(_) => t,
+
+            MotionProfile::Trapezoidal(trapezoid) => {
+                let length = trapezoid.length();
+                if length.is_zero() {
+                    return 1.0;
+                }
+                let covered = trapezoid.distance_at(trapezoid.duration().mul_f32(t));
+                covered.as_mm() / length.as_mm()
+            }
+        }
+    }
+
+    /// Toolhead position after covering `fraction` of the path
+    pub fn position_at(&self, fraction: f32) -> Position {
+        let fraction = fraction.clamp(0.0, 1.0);
+        match &self.path {
+            MotionPath::Linear => self.start + (self.end - self.start) * fraction,
+            MotionPath::Arc(arc) => {
+                let (start, end) = (
+                    self.start.to_plane(&arc.plane),
+                    self.end.to_plane(&arc.plane),
+                );
+                let [a, b] = if fraction >= 1.0 {
+                    [end[0], end[1]]
+                } else {
+                    arc.point(fraction)
+                };
+                let normal = start[2] + (end[2] - start[2]) * fraction;
+                Position::from_plane([a, b, normal], &arc.plane)
+            }
+        }
+    }
+
+    /// Unit direction of travel in `X`, `Y`, `Z` after covering `fraction` of the path
+    pub fn direction_at(&self, fraction: f32) -> [f32; 3] {
+        let delta = match &self.path {
+            MotionPath::Linear => self.end - self.start,
+            MotionPath::Arc(arc) => {
+                let [da, db] = arc.velocity(fraction);
+                let normal = self.end.to_plane(&arc.plane)[2] - self.start.to_plane(&arc.plane)[2];
+                Position::from_plane([da, db, normal], &arc.plane)
             }
+        };
+
+        let length = Position::ORIGIN.distance(&delta).as_mm();
+        if length <= f32::EPSILON {
+            return [0.0; 3];
+        }
+        [
+            delta.x().as_mm() / length,
+            delta.y().as_mm() / length,
+            delta.z().as_mm() / length,
+        ]
+    }
+
+    /// Length the feedrate applies to; extruder-only moves are timed on E
+    pub fn path_length(&self) -> Distance {
+        let distance = self.distance();
+        if distance.is_zero() {
+            Distance::from_mm(self.extrusion.as_mm().abs())
+        } else {
+            distance
         }
     }
+}
+
+impl Transition for MotionTransition {
+    type Output = Position;
+
+    fn interpolate(&self, tau: f32) -> Position {
+        self.position_at(self.progress(tau))
+    }
 
     fn duration(&self) -> Duration {
         match self.motion {
             MotionProfile::Instant => Duration::ZERO,
-            MotionProfile::ConstantVelocity(speed) => self.distance() / speed,
+            MotionProfile::ConstantVelocity(speed) => self.path_length() / speed,
+            MotionProfile::Trapezoidal(trapezoid) => trapezoid.duration(),
         }
     }
 }
@@ -64,6 +396,9 @@ pub struct MotionTransitionBuilder {
     end: Position,
     plane: ActivePlane,
     motion: MotionProfile,
+    path: MotionPath,
+    start_extrusion: Distance,
+    end_extrusion: Distance,
 }
 
 impl From<MotionProfile> for MotionTransitionBuilder {
@@ -79,18 +414,18 @@ impl MotionTransitionBuilder {
     pub fn start(self, state: &MachineState) -> Self {
         Self {
             start: state.position(),
-            end: self.end,
-            plane: self.plane,
-            motion: self.motion,
+            start_extrusion: *state.current_tool().extrusion(),
+            ..self
         }
     }
 
     pub fn end(self, state: &MachineState) -> Self {
         Self {
-            start: self.start,
             end: state.position(),
+            end_extrusion: *state.current_tool().extrusion(),
             plane: state.plane(),
-            motion: self.motion,
+            path: state.path(),
+            ..self
         }
     }
 
@@ -100,6 +435,104 @@ impl MotionTransitionBuilder {
             end: self.end,
             plane: self.plane,
             motion: self.motion,
+            path: self.path,
+            extrusion: self.end_extrusion - self.start_extrusion,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn at(x: f32, y: f32) -> Position {
+        Position::from_mm(x, y, 0.0)
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn offset_arcs_sweep_in_their_direction() {
+        // quarter circle around the origin from (10, 0) to (0, 10)
+        let (start, end) = (at(10.0, 0.0), at(0.0, 10.0));
+        let ccw = ArcPath::from_offset(start, end, [-10.0, 0.0], ActivePlane::XY, false).unwrap();
+        assert!(close(ccw.sweep, PI / 2.0));
+        assert!(close(ccw.planar_length().as_mm(), 5.0 * PI));
+        let [x, y] = ccw.point(0.5);
+        assert!(close(x, 10.0 * (PI / 4.0).cos()) && close(y, 10.0 * (PI / 4.0).sin()));
+
+        // clockwise takes the long way round
+        let cw = ArcPath::from_offset(start, end, [-10.0, 0.0], ActivePlane::XY, true).unwrap();
+        assert!(close(cw.sweep, -1.5 * PI));
+        assert!(close(cw.planar_length().as_mm(), 15.0 * PI));
+    }
+
+    #[test]
+    fn same_start_and_end_is_a_full_circle() {
+        let start = at(10.0, 0.0);
+        let cw = ArcPath::from_offset(start, start, [-10.0, 0.0], ActivePlane::XY, true).unwrap();
+        assert!(close(cw.sweep, -TAU));
+        let ccw = ArcPath::from_offset(start, start, [-10.0, 0.0], ActivePlane::XY, false).unwrap();
+        assert!(close(ccw.sweep, TAU));
+    }
+
+    #[test]
+    fn radius_picks_the_short_or_long_arc() {
+        let (start, end) = (at(10.0, 0.0), at(0.0, 10.0));
+        let short = ArcPath::from_radius(start, end, 10.0, ActivePlane::XY, false).unwrap();
+        assert!(close(short.center[0], 0.0) && close(short.center[1], 0.0));
+        assert!(close(short.sweep, PI / 2.0));
+
+        let long = ArcPath::from_radius(start, end, -10.0, ActivePlane::XY, false).unwrap();
+        assert!(close(long.center[0], 10.0) && close(long.center[1], 10.0));
+        assert!(close(long.sweep, 1.5 * PI));
+
+        let short_cw = ArcPath::from_radius(start, end, 10.0, ActivePlane::XY, true).unwrap();
+        assert!(close(short_cw.center[0], 10.0) && close(short_cw.center[1], 10.0));
+        assert!(close(short_cw.sweep, -PI / 2.0));
+    }
+
+    #[test]
+    fn too_small_radius_becomes_a_half_circle() {
+        let (start, end) = (at(0.0, 0.0), at(10.0, 0.0));
+        let arc = ArcPath::from_radius(start, end, 1.0, ActivePlane::XY, true).unwrap();
+        assert!(close(arc.radius, 5.0));
+        assert!(close(arc.sweep.abs(), PI));
+    }
+
+    #[test]
+    fn degenerate_arcs_are_rejected() {
+        let start = at(10.0, 0.0);
+        assert!(
+            ArcPath::from_offset(start, at(0.0, 10.0), [0.0, 0.0], ActivePlane::XY, true).is_none()
+        );
+        assert!(ArcPath::from_radius(start, start, 5.0, ActivePlane::XY, true).is_none());
+    }
+
+    #[test]
+    fn helical_arcs_follow_their_plane() {
+        // XZ in plane order is (Z, X): quarter circle from Z=10 to X=10 while
+        // Y, the normal, rises linearly
+        let (start, end) = (
+            Position::from_mm(0.0, 5.0, 10.0),
+            Position::from_mm(10.0, 7.0, 0.0),
+        );
+        let arc = ArcPath::from_offset(start, end, [-10.0, 0.0], ActivePlane::XZ, false).unwrap();
+        assert!(close(arc.planar_length().as_mm(), 5.0 * PI));
+
+        let transition = MotionTransition {
+            path: MotionPath::Arc(arc),
+            ..MotionTransition::new(start, end, ActivePlane::XZ, MotionProfile::Instant)
+        };
+        let middle = transition.position_at(0.5);
+        let side = 10.0 * (PI / 4.0).sin();
+        assert!(close(middle.x().as_mm(), side) && close(middle.z().as_mm(), side));
+        assert!(close(middle.y().as_mm(), 6.0));
+
+        let last = transition.position_at(1.0);
+        assert!(close(last.x().as_mm(), 10.0) && close(last.z().as_mm(), 0.0));
+    }
+}
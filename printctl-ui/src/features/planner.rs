@@ -0,0 +1,257 @@
+use super::machine::{MachineState, Wait};
+use super::metric::{Distance, Speed};
+use super::motion::{
+    MotionProfile, MotionTransitionBuilder, Trapezoid, MIN_ACCELERATION, MIN_PLANNER_SPEED,
+};
+use super::profile::MotionLimits;
+
+/// Marlin's default `BLOCK_BUFFER_SIZE`
+pub const DEFAULT_LOOKAHEAD: usize = 16;
+
+/// Marlin's feedrate until the first `F` word, mm/s
+pub const DEFAULT_FEEDRATE: f32 = 25.0;
+
+/// A simulated command as seen by the planner
+pub type PlannerStep = (MachineState, MachineState, Option<MotionProfile>);
+
+#[derive(Debug, Clone, Copy)]
+struct Block {
+    // unit direction in X, Y, Z, E when entering and leaving the block
+    entry_unit: [f32; 4],
+    exit_unit: [f32; 4],
+    length: f32,
+    nominal: f32,
+    acceleration: f32,
+    // max speed when entering this block from the previous one
+    max_junction: f32,
+}
+
+impl Block {
+    fn new(before: &MachineState, after: &MachineState, feedrate: Speed) -> Option<Self> {
+        let transition = MotionTransitionBuilder::from(MotionProfile::Instant)
+            .start(before)
+            .end(after)
+            .build();
+
+        // extruder-only moves are planned along E
+        let distance = transition.distance().as_mm();
+        let extrusion = transition.extrusion().as_mm();
+        let length = transition.path_length().as_mm();
+        if length <= f32::EPSILON {
+            return None;
+        }
+
+        let unit = |fraction| {
+            let [x, y, z] = transition
+                .direction_at(fraction)
+                .map(|d| d * distance / length);
+            [x, y, z, extrusion / length]
+        };
+        let (entry_unit, exit_unit) = (unit(0.0), unit(1.0));
+
+        let limits = after.limits();
+        let feedrate = match feedrate.is_zero() {
+            true => Speed::from_mm_per_s(DEFAULT_FEEDRATE),
+            false => feedrate,
+        };
+        let (mut nominal, mut acceleration) = if distance <= f32::EPSILON {
+            (feedrate.as_mm_per_s(), limits.retract_acceleration)
+        } else if extrusion > 0.0 {
+            (
+                feedrate.as_mm_per_s().max(limits.min_feedrate),
+                limits.print_acceleration,
+            )
+        } else {
+            (
+                feedrate.as_mm_per_s().max(limits.min_travel_feedrate),
+                limits.travel_acceleration,
+            )
+        };
+
+        // the slowest axis involved caps speed and acceleration of the whole move
+        for i in 0..4 {
+            let component = entry_unit[i].abs().max(exit_unit[i].abs());
+            if component > f32::EPSILON {
+                nominal = nominal.min(limits.max_feedrate[i] / component);
+                acceleration = acceleration.min(limits.max_acceleration[i] / component);
+            }
+        }
+        let nominal = nominal.max(MIN_PLANNER_SPEED);
+        let acceleration = acceleration.max(MIN_ACCELERATION);
+
+        Some(Self {
+            entry_unit,
+            exit_unit,
+            length,
+            nominal,
+            acceleration,
+            max_junction: 0.0,
+        })
+    }
+
+    /// Highest speed to pass from `prev` into `self` without exceeding the
+    /// cornering limits
+    fn junction_speed(&self, prev: &Block, limits: &MotionLimits) -> f32 {
+        let max = self.nominal.min(prev.nominal);
+
+        match limits.junction_deviation {
+            Some(deviation) => {
+                let cos_theta = -(0..3)
+                    .map(|i| prev.exit_unit[i] * self.entry_unit[i])
+                    .sum::<f32>();
+                if cos_theta > 0.999_999 {
+                    // full reversal
+                    return 0.0;
+                }
+                if cos_theta < -0.999_999 {
+                    // straight line
+                    return max;
+                }
+                let sin_theta_d2 = (0.5 * (1.0 - cos_theta)).sqrt();
+                let radius = deviation * sin_theta_d2 / (1.0 - sin_theta_d2);
+                (self.acceleration * radius).sqrt().min(max)
+            }
+            None => {
+                // scale down until no axis changes speed by more than its jerk
+                let mut speed = max;
+                for (i, jerk) in limits.jerk.iter().enumerate() {
+                    let change = (self.entry_unit[i] - prev.exit_unit[i]).abs() * speed;
+                    if change > *jerk {
+                        speed *= jerk / change;
+                    }
+                }
+                speed
+            }
+        }
+    }
+
+    /// Fastest speed at the start of the block that can still reach `exit`
+    fn max_entry(&self, exit: f32) -> f32 {
+        (exit * exit + 2.0 * self.acceleration * self.length)
+            .sqrt()
+            .min(self.nominal)
+            .min(self.max_junction)
+    }
+}
+
+/// Look-ahead planner turning constant-velocity moves into trapezoids.
+///
+/// Like Marlin, every move must be able to stop within the look-ahead
+/// window, so short segments cannot build up full speed.
+#[derive(Debug, Clone)]
+pub struct MotionPlanner {
+    lookahead: usize,
+}
+
+impl Default for MotionPlanner {
+    fn default() -> Self {
+        Self::new(DEFAULT_LOOKAHEAD)
+    }
+}
+
+impl MotionPlanner {
+    pub fn new(lookahead: usize) -> Self {
+        Self {
+            lookahead: lookahead.max(1),
+        }
+    }
+
+    pub fn plan(&self, steps: &mut [PlannerStep]) {
+        // runs of consecutive moves; homing, G92 and anything that waits
+        // empties the planner buffer
+        let mut chain: Vec<(usize, Block)> = Vec::new();
+
+        for i in 0..steps.len() {
+            let (before, after, motion) = &steps[i];
+            match motion {
+                Some(MotionProfile::ConstantVelocity(feedrate)) => {
+                    let Some(mut block) = Block::new(before, after, *feedrate) else {
+                        continue;
+                    };
+                    if let Some((_, prev)) = chain.last() {
+                        block.max_junction = block.junction_speed(prev, after.limits());
+                    }
+                    chain.push((i, block));
+                }
+                // `G92 E0` only renames the extruder position and keeps the buffer
+                Some(_) if before.position() == after.position() => {}
+                Some(_) => self.flush(&mut chain, steps),
+                None if after.wait() != Wait::None => self.flush(&mut chain, steps),
+                None => {}
+            }
+        }
+        self.flush(&mut chain, steps);
+    }
+
+    fn flush(&self, chain: &mut Vec<(usize, Block)>, steps: &mut [PlannerStep]) {
+        let mut entry = 0.0;
+
+        for n in 0..chain.len() {
+            let block = chain[n].1;
+
+            // stop at the end of the window unless the chain ends sooner
+            let window = &chain[n + 1..chain.len().min(n + 1 + self.lookahead)];
+            let exit = window
+                .iter()
+                .rev()
+                .fold(0.0, |exit, (_, next)| next.max_entry(exit));
+
+            // cannot exit faster than accelerating over the whole block allows
+            let exit = exit.min((entry * entry + 2.0 * block.acceleration * block.length).sqrt());
+
+            let trapezoid = Trapezoid::new(
+                Distance::from_mm(block.length),
+                Speed::from_mm_per_s(entry),
+                Speed::from_mm_per_s(block.nominal),
+                Speed::from_mm_per_s(exit),
+                block.acceleration,
+            );
+            steps[chain[n].0].2 = Some(MotionProfile::Trapezoidal(trapezoid));
+            entry = exit;
+        }
+
+        chain.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::profile::MachineProfile;
+    use super::super::statistics::ProgramStatistics;
+
+    fn total_time(src: &str) -> Duration {
+        ProgramStatistics::from_source(src, &MachineProfile::default()).total_time
+    }
+
+    #[test]
+    fn move_before_first_feedrate_uses_default() {
+        // 10 mm at 25 mm/s, then 10 mm at 10 mm/s
+        let time = total_time("G90\nG1 X10\nG1 X20 F600\n").as_secs_f32();
+        assert!(time > 1.3 && time < 2.0, "{time}");
+    }
+
+    #[test]
+    fn zero_acceleration_is_ignored() {
+        let time = total_time("G90\nM204 S0\nG1 X10 F600\n").as_secs_f32();
+        assert!(time > 0.9 && time < 1.5, "{time}");
+    }
+
+    #[test]
+    fn zero_axis_limits_are_ignored() {
+        let time = total_time("G90\nM201 X0 Y0\nM203 X0\nG1 X10 Y5 F600\n").as_secs_f32();
+        assert!(time > 1.0 && time < 1.7, "{time}");
+    }
+
+    #[test]
+    fn planned_moves_are_never_instant() {
+        // zero limits that bypass the M-code setters still plan finite moves
+        let mut profile = MachineProfile::default();
+        profile.limits.print_acceleration = 0.0;
+        profile.limits.travel_acceleration = 0.0;
+        profile.limits.max_feedrate = [0.0; 4];
+        let stats = ProgramStatistics::from_source("G90\nG1 X10\nG1 X20 E1\n", &profile);
+        assert!(stats.total_time > Duration::ZERO);
+    }
+}
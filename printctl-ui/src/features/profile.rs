@@ -0,0 +1,251 @@
+use std::time::Duration;
+
+use gcode::GCode;
+
+use super::machine::MachineState;
+use super::metric::Position;
+use super::snapshot::SnapshotBuilder;
+use super::thermal::{HeaterModel, LumpedThermalModel};
+
+/// Per-axis values in `X`, `Y`, `Z`, `E` order
+pub type AxisLimits = [f32; 4];
+
+const AXES: [char; 4] = ['X', 'Y', 'Z', 'E'];
+
+/// Firmware motion limits, set by `M201`/`M203`/`M204`/`M205`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MotionLimits {
+    /// `M201`, mm/s²
+    pub max_acceleration: AxisLimits,
+    /// `M203`, mm/s
+    pub max_feedrate: AxisLimits,
+
+    /// `M204 P`, mm/s² for extruding moves
+    pub print_acceleration: f32,
+    /// `M204 R`, mm/s² for extruder-only moves
+    pub retract_acceleration: f32,
+    /// `M204 T`, mm/s² for travel moves
+    pub travel_acceleration: f32,
+
+    /// `M205 J`, mm; replaces classic jerk when set
+    pub junction_deviation: Option<f32>,
+    /// `M205 X Y Z E`, mm/s
+    pub jerk: AxisLimits,
+    /// `M205 S`, mm/s
+    pub min_feedrate: f32,
+    /// `M205 T`, mm/s
+    pub min_travel_feedrate: f32,
+}
+
+impl Default for MotionLimits {
+    /// Marlin's stock configuration
+    fn default() -> Self {
+        Self {
+            max_acceleration: [3000.0, 3000.0, 100.0, 10000.0],
+            max_feedrate: [300.0, 300.0, 5.0, 25.0],
+            print_acceleration: 3000.0,
+            retract_acceleration: 3000.0,
+            travel_acceleration: 3000.0,
+            junction_deviation: Some(0.013),
+            jerk: [10.0, 10.0, 0.3, 5.0],
+            min_feedrate: 0.0,
+            min_travel_feedrate: 0.0,
+        }
+    }
+}
+
+impl MotionLimits {
+    pub fn set_max_acceleration(&mut self, gcode: &GCode) {
+        Self::set_axes(&mut self.max_acceleration, gcode);
+    }
+
+    pub fn set_max_feedrate(&mut self, gcode: &GCode) {
+        Self::set_axes(&mut self.max_feedrate, gcode);
+    }
+
+    pub fn set_acceleration(&mut self, gcode: &GCode) {
+        // legacy `M204 S` sets both print and travel acceleration, Marlin
+        // ignores zero
+        if let Some(s) = gcode.value_for('S').filter(|s| *s > 0.0) {
+            self.print_acceleration = s;
+            self.travel_acceleration = s;
+        }
+        if let Some(p) = gcode.value_for('P').filter(|p| *p > 0.0) {
+            self.print_acceleration = p;
+        }
+        if let Some(r) = gcode.value_for('R').filter(|r| *r > 0.0) {
+            self.retract_acceleration = r;
+        }
+        if let Some(t) = gcode.value_for('T').filter(|t| *t > 0.0) {
+            self.travel_acceleration = t;
+        }
+    }
+
+    pub fn set_advanced(&mut self, gcode: &GCode) {
+        if let Some(j) = gcode.value_for('J') {
+            self.junction_deviation = Some(j);
+        }
+        if AXES.iter().any(|axis| gcode.value_for(*axis).is_some()) {
+            // slicers only emit axis jerk for firmware planning with classic jerk
+            Self::set_axes(&mut self.jerk, gcode);
+            self.junction_deviation = None;
+        }
+        if let Some(s) = gcode.value_for('S') {
+            self.min_feedrate = s;
+        }
+        if let Some(t) = gcode.value_for('T') {
+            self.min_travel_feedrate = t;
+        }
+    }
+
+    fn set_axes(limits: &mut AxisLimits, gcode: &GCode) {
+        for (limit, axis) in limits.iter_mut().zip(AXES) {
+            if let Some(value) = gcode.value_for(axis).filter(|v| *v > 0.0) {
+                *limit = value;
+            }
+        }
+    }
+}
+
+/// Firmware retraction used by `G10`/`G11`, set by `M207`/`M208`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetractionSettings {
+    /// `M207 S`, mm
+    pub length: f32,
+    /// `M207 F`, mm/s
+    pub feedrate: f32,
+    /// `M207 Z`, mm the nozzle lifts while retracted
+    pub z_lift: f32,
+    /// `M208 S`, mm primed on top of the retracted length
+    pub recover_extra: f32,
+    /// `M208 F`, mm/s
+    pub recover_feedrate: f32,
+}
+
+impl Default for RetractionSettings {
+    /// Marlin's stock configuration
+    fn default() -> Self {
+        Self {
+            length: 3.0,
+            feedrate: 45.0,
+            z_lift: 0.0,
+            recover_extra: 0.0,
+            recover_feedrate: 8.0,
+        }
+    }
+}
+
+impl RetractionSettings {
+    pub fn set_retract(&mut self, gcode: &GCode) {
+        if let Some(s) = gcode.value_for('S') {
+            self.length = s;
+        }
+        if let Some(f) = gcode.value_for('F') {
+            self.feedrate = f / 60.0;
+        }
+        if let Some(z) = gcode.value_for('Z') {
+            self.z_lift = z;
+        }
+    }
+
+    pub fn set_recover(&mut self, gcode: &GCode) {
+        if let Some(s) = gcode.value_for('S') {
+            self.recover_extra = s;
+        }
+        if let Some(f) = gcode.value_for('F') {
+            self.recover_feedrate = f / 60.0;
+        }
+    }
+}
+
+/// Electrical draw besides the heaters, for energy estimates
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerProfile {
+    /// W for the board, fans and display whenever the printer is on
+    pub idle_w: f32,
+    /// W the steppers add while moving
+    pub motors_w: f32,
+}
+
+impl Default for PowerProfile {
+    /// Typical 24V hobby printer
+    fn default() -> Self {
+        Self {
+            idle_w: 10.0,
+            motors_w: 20.0,
+        }
+    }
+}
+
+/// Extruder (IDEX carriage, MMU slot, ...) selectable with `T<n>`
+#[derive(Debug, Clone, Default)]
+pub struct ToolProfile {
+    /// Nozzle offset from the first tool, also settable with `M218`
+    pub offset: Position,
+    /// Heater model if this tool's differs from `hotend_thermal`
+    pub thermal: Option<HeaterModel>,
+}
+
+/// Static description of a printer the simulator starts from
+#[derive(Debug, Clone)]
+pub struct MachineProfile {
+    pub limits: MotionLimits,
+    pub retraction: RetractionSettings,
+    /// °C the heaters start at
+    pub ambient_temp: f32,
+    /// Fitted with `calibrate-thermal`, stock guesses otherwise
+    pub bed_thermal: HeaterModel,
+    pub hotend_thermal: HeaterModel,
+    pub power: PowerProfile,
+
+    pub tools: Vec<ToolProfile>,
+    /// Fixed time a tool change takes on top of moving between nozzle offsets
+    pub tool_change_time: Duration,
+    /// Run after every tool change, like a firmware `tool_change` macro
+    pub tool_change_gcode: Option<String>,
+}
+
+impl Default for MachineProfile {
+    fn default() -> Self {
+        Self {
+            limits: MotionLimits::default(),
+            retraction: RetractionSettings::default(),
+            ambient_temp: 25.0,
+            bed_thermal: LumpedThermalModel::bed().into(),
+            hotend_thermal: LumpedThermalModel::hotend().into(),
+            power: PowerProfile::default(),
+            tools: vec![ToolProfile::default()],
+            tool_change_time: Duration::ZERO,
+            tool_change_gcode: None,
+        }
+    }
+}
+
+impl MachineProfile {
+    /// Profile with `count` tools sharing the same nozzle position
+    pub fn with_tools(count: usize) -> Self {
+        Self {
+            tools: vec![ToolProfile::default(); count.max(1)],
+            ..Self::default()
+        }
+    }
+
+    pub fn initial_state(&self) -> MachineState {
+        MachineState::from_profile(self)
+    }
+
+    /// Snapshot builder with this profile's heater models
+    pub fn snapshot_builder(&self) -> SnapshotBuilder<HeaterModel, HeaterModel> {
+        let builder = SnapshotBuilder::default()
+            .bed_thermal_model(self.bed_thermal.clone())
+            .tools_thermal_model(self.hotend_thermal.clone());
+
+        self.tools
+            .iter()
+            .enumerate()
+            .filter_map(|(i, tool)| Some((i, tool.thermal.clone()?)))
+            .fold(builder, |builder, (i, model)| {
+                builder.tool_thermal_model(i, model)
+            })
+    }
+}
@@ -1,35 +1,137 @@
+use std::collections::VecDeque;
 use std::ops::Range;
+use std::sync::{Arc, Mutex, OnceLock};
 
-use gcode::GCode;
+use super::code::{parse_lines_at, ArgState, GCodeLine};
+use super::feature_type::{FeatureIndex, FeatureIndexBuilder};
+use super::layer::{LayerIndex, LayerIndexBuilder};
+use super::source::{GCodeSource, SourceChunk};
 
-use super::code::{ArgGroups, ArgRange, GCodeLine};
+/// Source lines parsed at a time when browsing a program
+pub const CHUNK_LINES: usize = 4096;
+// parsed chunks kept around, most recently used first
+const CACHED_CHUNKS: usize = 4;
 
+/// Consecutive lines of a program, parsed
+#[derive(Debug, Clone, Default)]
+pub struct ProgramChunk {
+    first_line: usize,
+    lines: Arc<[GCodeLine]>,
+}
+
+impl ProgramChunk {
+    fn parse(chunk: SourceChunk) -> Self {
+        Self {
+            first_line: chunk.first_line,
+            lines: parse_lines_at(chunk.text, chunk.first_line, chunk.offset).into(),
+        }
+    }
+
+    /// Zero-based program line the chunk starts at
+    pub fn first_line(&self) -> usize {
+        self.first_line
+    }
+
+    pub fn lines(&self) -> &[GCodeLine] {
+        &self.lines
+    }
+
+    pub fn line_range(&self) -> Range<usize> {
+        self.first_line..self.first_line + self.lines.len()
+    }
+
+    /// Every command with the program line it is on
+    pub fn gcodes(&self) -> impl Iterator<Item = (usize, &gcode::GCode)> {
+        self.lines.iter().enumerate().flat_map(move |(i, line)| {
+            line.gcodes()
+                .iter()
+                .map(move |gcode| (self.first_line + i, gcode))
+        })
+    }
+}
+
+/// Built in one pass over the whole program
+#[derive(Debug, Default)]
+struct ProgramIndex {
+    layers: LayerIndex,
+    features: FeatureIndex,
+    // words in effect before each chunk of `CHUNK_LINES`
+    args: Box<[ArgState]>,
+}
+
+/// Program over a source, one line per source line. Lines are parsed a
+/// chunk at a time when asked for and only the last few chunks are kept, so
+/// memory does not grow with the file; the indices are built on first use.
 #[derive(Debug, Default, Clone)]
 pub struct GCodeProgram {
-    stack: Box<[GCode]>,
-    arg_groups: ArgGroups,
-    lines: Box<[GCodeLine]>,
+    source: Arc<GCodeSource>,
+    // clones share the cache and indices, so a background thread can fill
+    // them in
+    cache: Arc<Mutex<VecDeque<ProgramChunk>>>,
+    index: Arc<OnceLock<ProgramIndex>>,
     selection: Range<usize>,
 }
 
 impl GCodeProgram {
     pub fn new(src: &str) -> Self {
-        let stack = gcode::full_parse_with_callbacks(src, gcode::Nop)
-            .flat_map(|line| line.gcodes().to_vec())
-            .collect();
-        let lines = gcode::full_parse_with_callbacks(src, gcode::Nop)
-            .map(|line| GCodeLine::from(line))
-            .collect::<Vec<GCodeLine>>()
-            .into_boxed_slice();
-        let arg_groups = ArgGroups::from(&lines);
+        Self::open(Arc::new(GCodeSource::new(src.to_string())))
+    }
 
+    pub fn open(source: Arc<GCodeSource>) -> Self {
         Self {
-            stack,
-            lines,
-            arg_groups,
+            source,
             ..Default::default()
         }
     }
+
+    /// Builds the indices on another thread, so opening a large program
+    /// does not wait for them
+    pub fn build_indices_in_background(&self) {
+        let program = self.clone();
+        std::thread::spawn(move || {
+            program.index();
+        });
+    }
+
+    fn index(&self) -> &ProgramIndex {
+        self.index.get_or_init(|| {
+            let mut layers = LayerIndexBuilder::default();
+            let mut features = FeatureIndexBuilder::default();
+            let (mut args, mut state) = (Vec::new(), ArgState::default());
+            for chunk in self.chunks(CHUNK_LINES) {
+                args.push(state);
+                layers.push(chunk.lines());
+                features.push(chunk.lines());
+                chunk.lines().iter().for_each(|line| state.push(line));
+            }
+
+            ProgramIndex {
+                layers: layers.finish(),
+                features: features.finish(),
+                args: args.into_boxed_slice(),
+            }
+        })
+    }
+
+    /// Parses the chunk holding `line`, or finds it in the cache
+    fn chunk_at(&self, line: usize) -> ProgramChunk {
+        let first_line = line - line % CHUNK_LINES;
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(i) = cache.iter().position(|c| c.first_line == first_line) {
+            let chunk = cache.remove(i).unwrap_or_default();
+            cache.push_front(chunk.clone());
+            return chunk;
+        }
+
+        let chunk = ProgramChunk::parse(SourceChunk {
+            first_line,
+            offset: self.source.line_offset(first_line),
+            text: self.source.lines(first_line..first_line + CHUNK_LINES),
+        });
+        cache.push_front(chunk.clone());
+        cache.truncate(CACHED_CHUNKS);
+        chunk
+    }
 }
 
 impl GCodeProgram {
@@ -37,36 +139,94 @@ impl GCodeProgram {
         self.selection.end
     }
 
-    pub fn stack(&self) -> &[GCode] {
-        &self.stack
+    pub fn source(&self) -> &GCodeSource {
+        &self.source
     }
 
-    pub fn lines(&self) -> &[GCodeLine] {
-        &self.lines
+    /// Number of lines, the same as in the source
+    pub fn len(&self) -> usize {
+        self.source.line_count()
     }
 
-    pub fn selection(&self) -> &Range<usize> {
-        &self.selection
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    pub fn arg_groups(&self) -> &ArgGroups {
-        &self.arg_groups
+    /// Parses the whole program in order, `lines_per_chunk` lines at a
+    /// time, without keeping any of it
+    pub fn chunks(&self, lines_per_chunk: usize) -> impl Iterator<Item = ProgramChunk> + '_ {
+        self.source.chunks(lines_per_chunk).map(ProgramChunk::parse)
     }
 
-    pub fn current_line(&self) -> &GCodeLine {
-        self.lines().get(self.cursor()).unwrap_or(&GCodeLine::Empty)
+    pub fn line(&self, line: usize) -> GCodeLine {
+        if line >= self.len() {
+            return GCodeLine::Empty;
+        }
+        let chunk = self.chunk_at(line);
+        chunk.lines()[line - chunk.first_line].clone()
     }
 
-    pub fn current_arg_group(&self) -> Vec<&ArgRange> {
-        self.arg_groups.get(self.cursor())
+    /// Lines in `range` that the program has
+    pub fn lines(&self, range: Range<usize>) -> Vec<GCodeLine> {
+        let mut lines = Vec::with_capacity(range.len());
+        let mut line = range.start;
+        while line < range.end.min(self.len()) {
+            let chunk = self.chunk_at(line);
+            let end = range.end.min(chunk.line_range().end);
+            lines
+                .extend_from_slice(&chunk.lines()[line - chunk.first_line..end - chunk.first_line]);
+            line = end;
+        }
+        lines
     }
 
-    pub fn executed(&self) -> &[GCode] {
-        &self.stack()[..self.cursor()]
+    /// Whether the indices are built, so asking for them will not block
+    pub fn is_indexed(&self) -> bool {
+        self.index.get().is_some()
     }
 
-    pub fn remaining(&self) -> &[GCode] {
-        &self.stack()[self.cursor()..]
+    pub fn layers(&self) -> &LayerIndex {
+        &self.index().layers
+    }
+
+    pub fn features(&self) -> &FeatureIndex {
+        &self.index().features
+    }
+
+    pub fn selection(&self) -> &Range<usize> {
+        &self.selection
+    }
+
+    /// Modal words in effect once `line` has run
+    pub fn args_at(&self, line: usize) -> ArgState {
+        Self::args_from(self.index(), &self.chunk_at(line), line)
+    }
+
+    fn args_from(index: &ProgramIndex, chunk: &ProgramChunk, line: usize) -> ArgState {
+        let mut state = index
+            .args
+            .get(chunk.first_line / CHUNK_LINES)
+            .copied()
+            .unwrap_or_default();
+        let end = (line + 1).min(chunk.line_range().end);
+        for line in &chunk.lines()[..end.saturating_sub(chunk.first_line)] {
+            state.push(line);
+        }
+        state
+    }
+
+    pub fn current_line(&self) -> GCodeLine {
+        self.line(self.cursor())
+    }
+
+    /// Empty while the indices are still being built
+    pub fn current_args(&self) -> ArgState {
+        match self.index.get() {
+            Some(index) if self.cursor() < self.len() => {
+                Self::args_from(index, &self.chunk_at(self.cursor()), self.cursor())
+            }
+            _ => ArgState::default(),
+        }
     }
 
     pub fn selection_mut(&mut self) -> &mut Range<usize> {
@@ -74,7 +234,7 @@ impl GCodeProgram {
     }
 
     pub fn advance(&mut self) -> Option<usize> {
-        if self.selection.end < self.stack.len() {
+        if self.selection.end < self.len() {
             self.selection.end += 1;
             Some(self.selection.end)
         } else {
@@ -82,6 +242,12 @@ impl GCodeProgram {
         }
     }
 
+    /// Moves the cursor straight to `line`
+    pub fn seek(&mut self, line: usize) -> usize {
+        self.selection.end = line.clamp(self.selection.start, self.len());
+        self.selection.end
+    }
+
     pub fn rewind(&mut self) -> Option<usize> {
         if self.selection.end > self.selection.start {
             self.selection.end -= 1;
@@ -97,6 +263,8 @@ pub mod cmds {
         // non-modal modes
         pub const DWELL: u32 = 4;
         pub const SET_COORD_SYS: u32 = 10;
+        pub const FIRMWARE_RETRACT: u32 = 10;
+        pub const FIRMWARE_RECOVER: u32 = 11;
         pub const USE_XY_PLANE: u32 = 17;
         pub const USE_ZX_PLANE: u32 = 18;
         pub const USE_YZ_PLANE: u32 = 19;
@@ -123,11 +291,29 @@ pub mod cmds {
         pub const EXTRUDE_ABSOLUTE_POSITIONING: u32 = 82;
         pub const EXTRUDE_RELATIVE_POSITIONING: u32 = 83;
         pub const SET_HOTEND_TEMP: u32 = 104;
+        pub const WAIT_FOR_HOTEND_TEMP: u32 = 109;
+
+        // steppers
+        pub const DISABLE_STEPPERS: u32 = 18;
+        pub const STOP_IDLE_HOLD: u32 = 84;
+
+        // firmware retraction
+        pub const SET_FIRMWARE_RETRACTION: u32 = 207;
+        pub const SET_FIRMWARE_RECOVER: u32 = 208;
+
+        // tools
+        pub const SET_HOTEND_OFFSET: u32 = 218;
 
         // fan
         pub const SET_FAN_SPEED: u32 = 106;
         pub const FAN_OFF: u32 = 107;
 
+        // motion limits
+        pub const SET_MAX_ACCELERATION: u32 = 201;
+        pub const SET_MAX_FEEDRATE: u32 = 203;
+        pub const SET_STARTING_ACCELERATION: u32 = 204;
+        pub const SET_ADVANCED_SETTINGS: u32 = 205;
+
         // temp
         pub const SET_BED_TEMP: u32 = 140;
         pub const SET_CHAMBER_TEMP: u32 = 141;
@@ -137,3 +323,57 @@ pub mod cmds {
         pub const WAIT_FOR_PROBE_TEMP: u32 = 192;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_source_lines_across_chunks() {
+        let src: String = (0..CHUNK_LINES + 10)
+            .map(|i| match i % 3 {
+                0 => format!("G1 X{}\n", i),
+                1 => "; note\n".to_string(),
+                _ => "\n".to_string(),
+            })
+            .collect();
+        let program = GCodeProgram::new(&src);
+        assert_eq!(program.len(), CHUNK_LINES + 10);
+
+        let at = CHUNK_LINES + 2;
+        let line = program.line(at);
+        assert_eq!(line.source_line(), Some(at));
+        let gcode = &line.gcodes()[0];
+        assert_eq!(gcode.value_for('X'), Some(at as f32));
+        let span = gcode.span();
+        assert!(src[span.start..span.end].starts_with(&format!("G1 X{}", at)));
+
+        let lines = program.lines(CHUNK_LINES - 3..CHUNK_LINES + 3);
+        assert_eq!(lines.len(), 6);
+        assert!(matches!(lines[1], GCodeLine::Empty));
+        assert_eq!(lines[2].source_line(), Some(CHUNK_LINES - 1));
+        assert!(matches!(program.line(program.len()), GCodeLine::Empty));
+
+        let streamed = program.chunks(1000).map(|c| c.lines().len()).sum::<usize>();
+        assert_eq!(streamed, program.len());
+    }
+
+    #[test]
+    fn modal_words_carry_across_chunks() {
+        let src = format!("G1 F1200\n{}", "G1 X1\n".repeat(CHUNK_LINES + 5));
+        let mut program = GCodeProgram::new(&src);
+
+        // not indexed yet, the view shows nothing rather than wait
+        assert_eq!(program.current_args().words().count(), 0);
+        let words = program
+            .args_at(CHUNK_LINES + 2)
+            .words()
+            .map(|word| (word.letter, word.value))
+            .collect::<Vec<_>>();
+        assert_eq!(words, [('F', 1200.0), ('X', 1.0)]);
+
+        program.seek(0);
+        let words = program.current_args().words().count();
+        assert_eq!(words, 1);
+    }
+}
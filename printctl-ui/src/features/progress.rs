@@ -0,0 +1,309 @@
+use std::fmt;
+use std::time::Duration;
+
+use super::profile::MachineProfile;
+use super::program::GCodeProgram;
+use super::simulator::{GCodeSimulator, SnapshotEntry};
+use super::snapshot::SnapshotBuilder;
+use super::statistics::ProgramStatistics;
+use super::thermal::ThermalModel;
+
+/// Simulated timeline of a program, indexed by source line
+#[derive(Debug, Clone, Default)]
+pub struct ProgressPlan {
+    // cumulative bytes at the end of each source line
+    line_bytes: Box<[usize]>,
+    // cumulative simulated time at the end of each source line
+    line_times: Box<[Duration]>,
+    // layer being printed at each source line (0 before the first layer)
+    line_layers: Box<[usize]>,
+    layer_count: usize,
+}
+
+impl ProgressPlan {
+    /// Simulates `program`, handing every snapshot on to `visit` as well so
+    /// callers can build more from the same run
+    pub fn simulate<B, T>(
+        program: &GCodeProgram,
+        simulator: &GCodeSimulator,
+        snapshot_builder: SnapshotBuilder<B, T>,
+        mut visit: impl FnMut(usize, &SnapshotEntry<B, T>),
+    ) -> (Self, ProgramStatistics)
+    where
+        B: ThermalModel,
+        T: ThermalModel,
+    {
+        let line_bytes = program
+            .source()
+            .as_str()
+            .split_inclusive('\n')
+            .scan(0, |total, line| {
+                *total += line.len();
+                Some(*total)
+            })
+            .collect::<Vec<_>>();
+
+        let line_count = line_bytes.len();
+        let mut line_times = vec![Duration::ZERO; line_count];
+        let mut line_layers = vec![0; line_count];
+
+        let stats = simulator.replay(program, snapshot_builder, |line, entry| {
+            visit(line, &entry);
+            if line >= line_count {
+                return;
+            }
+
+            // layers count from 1 in progress, 0 is the start G-code
+            let layer = program.layers().layer_at(line).map_or(0, |layer| layer + 1);
+
+            line_times[line] = line_times[line].max(entry.end_time());
+            line_layers[line] = line_layers[line].max(layer);
+        });
+
+        // lines without motion (comments, blanks) inherit from the line before
+        for i in 1..line_count {
+            line_times[i] = line_times[i].max(line_times[i - 1]);
+            line_layers[i] = line_layers[i].max(line_layers[i - 1]);
+        }
+
+        let plan = Self {
+            line_bytes: line_bytes.into_boxed_slice(),
+            line_times: line_times.into_boxed_slice(),
+            line_layers: line_layers.into_boxed_slice(),
+            layer_count: program.layers().len(),
+        };
+        (plan, stats)
+    }
+
+    /// Parses and simulates `src` with the profile's thermal models
+    pub fn from_source(src: &str, profile: &MachineProfile) -> Self {
+        Self::with_statistics(src, profile).0
+    }
+
+    /// Like [`Self::from_source`], also keeping the statistics of the run
+    pub fn with_statistics(src: &str, profile: &MachineProfile) -> (Self, ProgramStatistics) {
+        let program = GCodeProgram::new(src);
+        let simulator = GCodeSimulator::new(profile.clone());
+
+        Self::simulate(&program, &simulator, profile.snapshot_builder(), |_, _| {})
+    }
+}
+
+impl ProgressPlan {
+    pub fn total_lines(&self) -> usize {
+        self.line_times.len()
+    }
+
+    pub fn total_bytes(&self) -> usize {
+        self.line_bytes.last().copied().unwrap_or(0)
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.line_times.last().copied().unwrap_or(Duration::ZERO)
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.layer_count
+    }
+
+    /// Simulated time once the first `lines_done` lines have executed
+    pub fn simulated_at(&self, lines_done: usize) -> Duration {
+        Self::at(&self.line_times, lines_done).unwrap_or(Duration::ZERO)
+    }
+
+    pub fn progress(&self, lines_done: usize, elapsed: Duration) -> JobProgress {
+        let lines_done = lines_done.min(self.total_lines());
+        let simulated_done = self.simulated_at(lines_done);
+        let simulated_total = self.total_time();
+
+        // scale the remaining simulated time by how far reality drifted from it so far
+        let ratio = if simulated_done.is_zero() || elapsed.is_zero() {
+            1.0
+        } else {
+            elapsed.as_secs_f64() / simulated_done.as_secs_f64()
+        };
+        let eta = (simulated_total - simulated_done).mul_f64(ratio);
+
+        JobProgress {
+            lines_done,
+            total_lines: self.total_lines(),
+            bytes_done: Self::at(&self.line_bytes, lines_done).unwrap_or(0),
+            total_bytes: self.total_bytes(),
+            simulated_done,
+            simulated_total,
+            layer: Self::at(&self.line_layers, lines_done).unwrap_or(0),
+            layer_count: self.layer_count,
+            elapsed,
+            eta,
+        }
+    }
+
+    fn at<V: Copy>(values: &[V], lines_done: usize) -> Option<V> {
+        lines_done
+            .checked_sub(1)
+            .and_then(|i| values.get(i))
+            .copied()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JobProgress {
+    pub lines_done: usize,
+    pub total_lines: usize,
+
+    pub bytes_done: usize,
+    pub total_bytes: usize,
+
+    pub simulated_done: Duration,
+    pub simulated_total: Duration,
+
+    pub layer: usize,
+    pub layer_count: usize,
+
+    pub elapsed: Duration,
+    pub eta: Duration,
+}
+
+impl JobProgress {
+    fn ratio(done: f64, total: f64) -> f32 {
+        if total > 0.0 {
+            (done / total).clamp(0.0, 1.0) as f32
+        } else {
+            0.0
+        }
+    }
+
+    pub fn percent_lines(&self) -> f32 {
+        Self::ratio(self.lines_done as f64, self.total_lines as f64) * 100.0
+    }
+
+    pub fn percent_bytes(&self) -> f32 {
+        Self::ratio(self.bytes_done as f64, self.total_bytes as f64) * 100.0
+    }
+
+    pub fn percent_time(&self) -> f32 {
+        let done = self.simulated_done.as_secs_f64();
+        let total = self.simulated_total.as_secs_f64();
+        Self::ratio(done, total) * 100.0
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+impl fmt::Display for JobProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1}% (lines {:.1}%, bytes {:.1}%) layer {}/{} elapsed {} eta {}",
+            self.percent_time(),
+            self.percent_lines(),
+            self.percent_bytes(),
+            self.layer,
+            self.layer_count,
+            format_duration(self.elapsed),
+            format_duration(self.eta),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // start G-code, then two layers of one extruding move each
+    const SRC: &str = "G90\nM83\nG1 Z0.2 F600\nG1 X10 E1\n; second layer\nG1 Z0.4\nG1 X0 E1\n";
+
+    fn plan() -> ProgressPlan {
+        ProgressPlan::from_source(SRC, &MachineProfile::default())
+    }
+
+    #[test]
+    fn plan_follows_source_lines() {
+        let plan = plan();
+        assert_eq!(plan.total_lines(), 7);
+        assert_eq!(plan.total_bytes(), SRC.len());
+        assert_eq!(plan.layer_count(), 2);
+
+        // modal commands take no time, moves add to it, comments carry it over
+        assert_eq!(plan.simulated_at(0), Duration::ZERO);
+        assert_eq!(plan.simulated_at(2), Duration::ZERO);
+        assert!(plan.simulated_at(3) > Duration::ZERO);
+        assert!(plan.simulated_at(4) > plan.simulated_at(3));
+        assert_eq!(plan.simulated_at(5), plan.simulated_at(4));
+        assert!(plan.simulated_at(7) > plan.simulated_at(6));
+        assert_eq!(plan.simulated_at(7), plan.total_time());
+    }
+
+    #[test]
+    fn progress_by_lines_bytes_and_layers() {
+        let plan = plan();
+        let start = plan.progress(0, Duration::ZERO);
+        assert_eq!((start.lines_done, start.bytes_done, start.layer), (0, 0, 0));
+
+        let first_layer = plan.progress(4, Duration::ZERO);
+        assert_eq!(
+            first_layer.bytes_done,
+            "G90\nM83\nG1 Z0.2 F600\nG1 X10 E1\n".len()
+        );
+        assert_eq!(first_layer.layer, 1);
+        assert_eq!(first_layer.layer_count, 2);
+
+        // past the end is clamped to the last line
+        let done = plan.progress(100, Duration::ZERO);
+        assert_eq!(done.lines_done, 7);
+        assert_eq!(done.layer, 2);
+        assert_eq!(done.percent_lines(), 100.0);
+        assert_eq!(done.percent_bytes(), 100.0);
+        assert_eq!(done.percent_time(), 100.0);
+        assert_eq!(done.eta, Duration::ZERO);
+    }
+
+    #[test]
+    fn eta_scales_by_actual_over_simulated() {
+        let plan = plan();
+        let done = plan.simulated_at(4);
+        let remaining = plan.total_time() - done;
+
+        // nothing to compare against yet, so the simulation is taken as is
+        assert_eq!(
+            plan.progress(0, Duration::from_secs(3)).eta,
+            plan.total_time()
+        );
+        assert_eq!(plan.progress(4, Duration::ZERO).eta, remaining);
+
+        // running twice as slow as simulated doubles what is left
+        let slow = plan.progress(4, done * 2);
+        assert_eq!(slow.elapsed, done * 2);
+        assert!(slow.eta.abs_diff(remaining * 2) < Duration::from_micros(1));
+        let fast = plan.progress(4, done / 2);
+        assert!(fast.eta.abs_diff(remaining / 2) < Duration::from_micros(1));
+    }
+
+    #[test]
+    fn percentages_clamp_and_handle_empty_totals() {
+        let progress = JobProgress {
+            lines_done: 25,
+            total_lines: 100,
+            bytes_done: 300,
+            total_bytes: 200,
+            simulated_done: Duration::from_secs(15),
+            simulated_total: Duration::from_secs(60),
+            ..Default::default()
+        };
+        assert_eq!(progress.percent_lines(), 25.0);
+        assert_eq!(progress.percent_bytes(), 100.0);
+        assert_eq!(progress.percent_time(), 25.0);
+
+        let empty = JobProgress::default();
+        assert_eq!(empty.percent_lines(), 0.0);
+        assert_eq!(empty.percent_bytes(), 0.0);
+        assert_eq!(empty.percent_time(), 0.0);
+    }
+}
@@ -0,0 +1,281 @@
+use std::fmt;
+
+use super::code::GCodeLine;
+use super::format::parse_lines;
+use super::layer::LayerIndex;
+use super::machine::MachineState;
+use super::metric::{PositionMode, Units};
+use super::profile::MachineProfile;
+
+// how far the nozzle lifts off the print before homing X/Y
+const CLEARANCE_MM: f32 = 5.0;
+const TRAVEL_MM_PER_MIN: f32 = 3000.0;
+const Z_MM_PER_MIN: f32 = 600.0;
+
+/// Where to pick a failed print back up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResumePoint {
+    /// Zero-based source line
+    Line(usize),
+    /// Start of the first layer printed at or above this height, mm
+    Z(f32),
+}
+
+impl fmt::Display for ResumePoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResumePoint::Line(line) => write!(f, "line {}", line + 1),
+            ResumePoint::Z(z) => write!(f, "Z {} mm", z),
+        }
+    }
+}
+
+/// Machine state the program is in at a line, and the G-code that brings a
+/// printer that lost it back there
+#[derive(Debug, Clone)]
+pub struct ResumePlan {
+    /// Zero-based source line to stream from, after the preamble
+    pub line: usize,
+    /// State the program prefix leaves the machine in
+    pub state: MachineState,
+    pub preamble: Vec<String>,
+}
+
+impl ResumePlan {
+    /// Replays everything before the resume point; `None` if the program
+    /// has no such line or layer
+    pub fn new(src: &str, point: ResumePoint, profile: &MachineProfile) -> Option<Self> {
+        let lines = parse_lines(src);
+        let line = match point {
+            ResumePoint::Line(line) => line,
+            ResumePoint::Z(z) => {
+                LayerIndex::new(&lines)
+                    .layers()
+                    .iter()
+                    .find(|layer| layer.z.is_some_and(|layer_z| layer_z >= z - 1e-3))?
+                    .lines
+                    .start
+            }
+        };
+        if line >= lines.len() {
+            return None;
+        }
+
+        let mut state = MachineState::from_profile(profile);
+        for prefix in &lines[..line] {
+            if let GCodeLine::Command { gcodes, .. } = prefix {
+                for gcode in gcodes.iter() {
+                    state = state.execute(gcode).0;
+                }
+            }
+        }
+
+        Some(Self {
+            line,
+            preamble: RestoreState::from(&state).preamble(line),
+            state,
+        })
+    }
+}
+
+/// What a printer has to be brought back to before a program can continue,
+/// in plain numbers so it can be saved and read back
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RestoreState {
+    /// Nozzle position, mm
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    /// Extruder position of the active tool, mm
+    pub e: f32,
+    pub feedrate_mm_per_min: f32,
+    pub active_tool: usize,
+    /// Hotend targets by tool, `0` for off
+    pub tool_temps: Vec<f32>,
+    pub bed_temp: f32,
+    pub fan_speed: u8,
+    /// Speeds of fans set with `M106 P`, by index
+    pub aux_fan_speeds: Vec<u8>,
+    pub relative: bool,
+    pub relative_e: bool,
+    pub inches: bool,
+}
+
+impl From<&MachineState> for RestoreState {
+    fn from(state: &MachineState) -> Self {
+        let position = state.position();
+        Self {
+            x: position.x().as_mm(),
+            y: position.y().as_mm(),
+            z: position.z().as_mm(),
+            e: state.current_tool().extrusion().as_mm(),
+            feedrate_mm_per_min: state.feedrate().as_mm_per_min(),
+            active_tool: state.active_tool(),
+            tool_temps: state
+                .tools()
+                .iter()
+                .map(|tool| tool.heater_state().target_temp().unwrap_or(0.0))
+                .collect(),
+            bed_temp: state.bed_heater().target_temp().unwrap_or(0.0),
+            fan_speed: state.cooling_fan().speed(),
+            aux_fan_speeds: state.fans().iter().map(|fan| fan.speed()).collect(),
+            relative: matches!(state.positioning(), PositionMode::Relative),
+            relative_e: matches!(state.extrusion_positioning(), PositionMode::Relative),
+            inches: matches!(state.units(), Units::Inches),
+        }
+    }
+}
+
+impl RestoreState {
+    /// Heats up, homes X/Y only since the print is in the way of the Z
+    /// endstop, and restores position and modal state. Assumes the nozzle
+    /// still sits at the height the print stopped at.
+    pub fn preamble(&self, line: usize) -> Vec<String> {
+        let heated_tools = self
+            .tool_temps
+            .iter()
+            .enumerate()
+            .filter(|(_, temp)| **temp > 0.0)
+            .collect::<Vec<_>>();
+        let bed = (self.bed_temp > 0.0).then_some(self.bed_temp);
+
+        let mut out = vec![format!("; resuming at line {}, Z {:.3}", line + 1, self.z)];
+        // start both heating before homing, wait for them after
+        out.extend(bed.map(|temp| format!("M140 S{}", temp)));
+        out.extend(
+            heated_tools
+                .iter()
+                .map(|(i, temp)| format!("M104 T{} S{}", i, temp)),
+        );
+
+        out.extend([
+            "G21".to_string(),
+            format!("G92 Z{:.3}", self.z),
+            "G91".to_string(),
+            format!("G1 Z{} F{}", CLEARANCE_MM, Z_MM_PER_MIN),
+            "G90".to_string(),
+            "G28 X Y".to_string(),
+        ]);
+
+        out.extend(bed.map(|temp| format!("M190 S{}", temp)));
+        out.extend(
+            heated_tools
+                .iter()
+                .map(|(i, temp)| format!("M109 T{} S{}", i, temp)),
+        );
+        if self.tool_temps.len() > 1 {
+            out.push(format!("T{}", self.active_tool));
+        }
+
+        out.push(format!("G92 E{:.5}", self.e));
+        out.push(format!("M106 S{}", self.fan_speed));
+        for (i, speed) in self.aux_fan_speeds.iter().enumerate() {
+            out.push(format!("M106 P{} S{}", i, speed));
+        }
+
+        out.push(format!(
+            "G1 X{:.3} Y{:.3} F{}",
+            self.x, self.y, TRAVEL_MM_PER_MIN
+        ));
+        out.push(format!("G1 Z{:.3} F{}", self.z, Z_MM_PER_MIN));
+        if self.feedrate_mm_per_min > 0.0 {
+            out.push(format!("G1 F{:.0}", self.feedrate_mm_per_min));
+        }
+
+        // G90/G91 switch the extruder too, so M82/M83 go after
+        if self.relative {
+            out.push("G91".to_string());
+        }
+        out.push(if self.relative_e { "M83" } else { "M82" }.to_string());
+        if self.inches {
+            out.push("G20".to_string());
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SRC: &str = "M140 S60\nM104 S210\nG28\nG90\nM83\n\
+                       ;LAYER:0\nG1 Z0.2 F600\nG1 X10 Y10 E1 F1800\n\
+                       ;LAYER:1\nG1 Z0.4\nG1 X20 E1\nM106 S255\n\
+                       ;LAYER:2\nG91\nG1 Z0.2\nG1 X-10 E1\n";
+
+    fn position(preamble: &[String], line: &str) -> usize {
+        preamble
+            .iter()
+            .position(|l| l == line)
+            .unwrap_or_else(|| panic!("{:?} not in {:?}", line, preamble))
+    }
+
+    #[test]
+    fn finds_layer_at_height() {
+        let profile = MachineProfile::default();
+
+        let plan = ResumePlan::new(SRC, ResumePoint::Z(0.3), &profile).unwrap();
+        assert_eq!(plan.line, 8);
+        assert_eq!(plan.state.position().z().as_mm(), 0.2);
+
+        let plan = ResumePlan::new(SRC, ResumePoint::Z(0.4), &profile).unwrap();
+        assert_eq!(plan.line, 8);
+
+        assert!(ResumePlan::new(SRC, ResumePoint::Z(10.0), &profile).is_none());
+        assert!(ResumePlan::new(SRC, ResumePoint::Line(100), &profile).is_none());
+    }
+
+    #[test]
+    fn replays_prefix_up_to_line() {
+        let plan = ResumePlan::new(SRC, ResumePoint::Line(15), &MachineProfile::default()).unwrap();
+        let state = RestoreState::from(&plan.state);
+
+        assert_eq!((state.x, state.y, state.z), (20.0, 10.0, 0.6));
+        assert_eq!(state.e, 2.0);
+        assert_eq!(state.feedrate_mm_per_min, 1800.0);
+        assert_eq!(state.tool_temps, [210.0]);
+        assert_eq!(state.bed_temp, 60.0);
+        assert_eq!(state.fan_speed, 255);
+        assert!(state.relative && state.relative_e);
+    }
+
+    #[test]
+    fn preamble_restores_modes_after_moving() {
+        let plan = ResumePlan::new(SRC, ResumePoint::Line(15), &MachineProfile::default()).unwrap();
+        let preamble = &plan.preamble;
+
+        // heats while homing X/Y only, then waits
+        let home = position(preamble, "G28 X Y");
+        assert!(position(preamble, "M140 S60") < home);
+        assert!(position(preamble, "M104 T0 S210") < home);
+        assert!(position(preamble, "M190 S60") > home);
+        assert!(position(preamble, "M109 T0 S210") > home);
+        assert!(!preamble.iter().any(|l| l == "G28" || l == "G28 Z"));
+
+        // absolute moves back to where the print stopped, modes restored last
+        let xy = position(preamble, "G1 X20.000 Y10.000 F3000");
+        let z = position(preamble, "G1 Z0.600 F600");
+        let relative = preamble.iter().rposition(|l| l == "G91").unwrap();
+        assert!(xy < z && z < relative);
+        assert!(relative < position(preamble, "M83"));
+        assert!(position(preamble, "G92 E2.00000") < xy);
+        assert!(preamble.contains(&"M106 S255".to_string()));
+        assert!(preamble.contains(&"G1 F1800".to_string()));
+    }
+
+    #[test]
+    fn preamble_skips_unset_feedrate_and_cold_heaters() {
+        let preamble = RestoreState {
+            tool_temps: vec![0.0],
+            ..Default::default()
+        }
+        .preamble(0);
+
+        for heater in ["M104", "M109", "M140", "M190"] {
+            assert!(!preamble.iter().any(|l| l.starts_with(heater)));
+        }
+        assert!(!preamble.iter().any(|l| l.starts_with("G1 F")));
+        assert!(preamble.ends_with(&["M82".to_string()]));
+    }
+}
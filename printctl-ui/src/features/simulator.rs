@@ -1,11 +1,19 @@
 use std::ops::Range;
 use std::time::Duration;
 
-use super::machine::MachineState;
-use super::program::GCodeProgram;
+use super::code::{parse_lines, GCodeLine};
+use super::machine::{MachineState, Wait};
+use super::motion::MotionTransitionBuilder;
+use super::planner::{MotionPlanner, PlannerStep};
+use super::profile::MachineProfile;
+use super::program::{GCodeProgram, ProgramChunk};
 use super::snapshot::{Snapshot, SnapshotBuilder, Transition};
+use super::statistics::ProgramStatistics;
 use super::thermal::ThermalModel;
 
+// lines planned at a time, the planner buffer is emptied between them
+const PLAN_CHUNK_LINES: usize = 16 * 1024;
+
 #[derive(Debug)]
 pub struct SnapshotEntry<B, T>(Range<Duration>, Snapshot<B, T>)
 where
@@ -37,36 +45,265 @@ where
     pub fn duration(&self) -> Duration {
         self.0.end - self.0.start
     }
+
+    pub fn snapshot(&self) -> &Snapshot<B, T> {
+        &self.1
+    }
+}
+
+/// Where a simulation stands between programs it is fed
+struct SimulationRun {
+    state: MachineState,
+    time_elapsed: Duration,
+    bed_temp: f32,
+    tool_temps: Vec<f32>,
+}
+
+impl SimulationRun {
+    fn new(profile: &MachineProfile) -> Self {
+        let state = profile.initial_state();
+        Self {
+            time_elapsed: Duration::ZERO,
+            bed_temp: state.bed_heater().current_temp(),
+            tool_temps: state
+                .tools()
+                .iter()
+                .map(|tool| tool.heater_state().current_temp())
+                .collect(),
+            state,
+        }
+    }
 }
 
 #[derive(Debug, Default)]
-pub struct GCodeSimulator;
+pub struct GCodeSimulator {
+    profile: MachineProfile,
+    planner: MotionPlanner,
+}
 
 impl GCodeSimulator {
+    pub fn new(profile: MachineProfile) -> Self {
+        Self {
+            profile,
+            planner: MotionPlanner::default(),
+        }
+    }
+
+    pub fn profile(&self) -> &MachineProfile {
+        &self.profile
+    }
+
     pub fn simulate<B, T>(
         &self,
         program: &GCodeProgram,
         snapshot_builder: SnapshotBuilder<B, T>,
-    ) -> (Duration, Vec<SnapshotEntry<B, T>>)
+    ) -> (ProgramStatistics, Vec<SnapshotEntry<B, T>>)
     where
         B: ThermalModel,
         T: ThermalModel,
     {
-        let mut time_elapsed = Duration::ZERO;
-        let mut state = MachineState::default();
         let mut snapshots = Vec::new();
+        let stats = self.replay(program, snapshot_builder, |_, entry| snapshots.push(entry));
+
+        (stats, snapshots)
+    }
+
+    /// Simulates `program` a chunk of lines at a time, handing every snapshot
+    /// to `visit` with the program line it came from instead of keeping it,
+    /// so memory stays bounded however large the program is. The planner
+    /// buffer is emptied between chunks.
+    pub fn replay<B, T>(
+        &self,
+        program: &GCodeProgram,
+        snapshot_builder: SnapshotBuilder<B, T>,
+        mut visit: impl FnMut(usize, SnapshotEntry<B, T>),
+    ) -> ProgramStatistics
+    where
+        B: ThermalModel,
+        T: ThermalModel,
+    {
+        let mut run = SimulationRun::new(&self.profile);
+        let mut stats = ProgramStatistics {
+            number_of_lines: program.len(),
+            ..Default::default()
+        };
+        stats.sync_layers(program.layers().layers());
+
+        for chunk in program.chunks(PLAN_CHUNK_LINES) {
+            self.run(&chunk, &snapshot_builder, &mut run, |line, entry| {
+                stats.record(entry.snapshot());
+                if let Some(layer) = program.layers().layer_at(line) {
+                    stats.record_layer(layer, entry.snapshot());
+                }
+                if let Some(feature) = program.features().feature_at(line) {
+                    stats.record_feature(feature, entry.snapshot());
+                }
+                visit(line, entry);
+            });
+        }
+        stats.record_power(&self.profile.power);
+
+        stats
+    }
+
+    /// Executes, plans and times `chunk` from where `run` left off, handing
+    /// every snapshot to `visit` with the program line it came from
+    fn run<B, T>(
+        &self,
+        chunk: &ProgramChunk,
+        snapshot_builder: &SnapshotBuilder<B, T>,
+        run: &mut SimulationRun,
+        mut visit: impl FnMut(usize, SnapshotEntry<B, T>),
+    ) where
+        B: ThermalModel,
+        T: ThermalModel,
+    {
+        // execute everything first so the planner can look ahead
+        let mut steps: Vec<PlannerStep> = Vec::new();
+        let mut lines = Vec::new();
+        for (line, gcode) in chunk.gcodes() {
+            let (mut next, motion) = run.state.execute(gcode);
+            if next.active_tool() != run.state.active_tool() {
+                next = self.run_tool_change(next);
+            }
+            let before = std::mem::replace(&mut run.state, next.clone());
+            steps.push((before, next, motion));
+            lines.push(line);
+        }
+        self.planner.plan(&mut steps);
+
+        // heaters evolve with simulated time, which is only known once planned
+        for ((mut before, mut after, motion), line) in steps.into_iter().zip(lines) {
+            before.set_temperatures(run.bed_temp, &run.tool_temps);
+            after.set_temperatures(run.bed_temp, &run.tool_temps);
 
-        for gcode in program.stack() {
             let snapshot = snapshot_builder.clone();
-            let (next, motion) = state.execute(gcode);
-            let snapshot = snapshot.build(state.clone(), next.clone(), motion);
-            let entry = SnapshotEntry::new(time_elapsed, snapshot);
+            let snapshot = snapshot.build(before, after, motion);
+            let (_, thermal) = snapshot.interpolate(1.0);
+            run.bed_temp = thermal.bed_temp();
+            run.tool_temps = thermal.tool_temps().to_vec();
 
-            time_elapsed = entry.end_time();
-            snapshots.push(entry);
+            let entry = SnapshotEntry::new(run.time_elapsed, snapshot);
+            run.time_elapsed = entry.end_time();
+            visit(line, entry);
+        }
+    }
+
+    /// Runs the profile's tool-change G-code on top of a `T<n>` and folds
+    /// its duration into the tool change so steps still match the program
+    fn run_tool_change(&self, tool_change: MachineState) -> MachineState {
+        let Some(src) = &self.profile.tool_change_gcode else {
+            return tool_change;
+        };
+
+        let mut state = tool_change.clone();
+        let mut steps: Vec<PlannerStep> = Vec::new();
+        for gcode in parse_lines(src).iter().flat_map(GCodeLine::gcodes) {
+            let (next, motion) = state.execute(gcode);
+            steps.push((state, next.clone(), motion));
             state = next;
         }
+        self.planner.plan(&mut steps);
+
+        let extra = steps
+            .into_iter()
+            .map(|(before, after, motion)| {
+                let moving = motion.map(|profile| {
+                    MotionTransitionBuilder::from(profile)
+                        .start(&before)
+                        .end(&after)
+                        .build()
+                        .duration()
+                });
+                let dwell = match after.wait() {
+                    Wait::Dwell(dwell) | Wait::ToolChange(dwell) => dwell,
+                    _ => Duration::ZERO,
+                };
+                moving.unwrap_or(Duration::ZERO).max(dwell)
+            })
+            .sum();
+
+        // the macro runs as part of the tool change
+        if let Wait::ToolChange(time) = tool_change.wait() {
+            state.set_wait(Wait::ToolChange(time + extra));
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simulate(
+        src: &str,
+    ) -> (
+        ProgramStatistics,
+        Vec<SnapshotEntry<impl ThermalModel, impl ThermalModel>>,
+    ) {
+        let profile = MachineProfile::default();
+        let simulator = GCodeSimulator::new(profile.clone());
+        simulator.simulate(&GCodeProgram::new(src), profile.snapshot_builder())
+    }
+
+    #[test]
+    fn snapshots_follow_each_other() {
+        // travel, print, retract, hop, travel, lower and prime
+        let src = "G90\nM83\nG1 X10 F3000\nG1 X20 E0.5 F1200\nG1 E-0.8 F2400\nG1 Z0.4\n\
+                   G1 X30\nG1 Z0\nG1 E0.8\n";
+        let (stats, entries) = simulate(src);
+
+        assert_eq!(entries.len(), 9);
+        assert_eq!(entries[0].start_time(), Duration::ZERO);
+        for (before, after) in entries.iter().zip(&entries[1..]) {
+            assert_eq!(before.end_time(), after.start_time());
+        }
+        assert_eq!(entries.last().unwrap().end_time(), stats.total_time);
+
+        // modal commands take no time
+        assert!(entries[..2].iter().all(|entry| entry.duration().is_zero()));
+        let print = entries[3].snapshot();
+        assert_eq!(print.after().position().x().as_mm(), 20.0);
+        assert!(entries[3].duration() >= Duration::from_millis(500));
+
+        // retracts and primes take time without moving an axis
+        for retract in [&entries[4], &entries[8]] {
+            let motion = retract.snapshot().motion().unwrap();
+            assert_eq!(motion.distance().as_mm(), 0.0);
+            assert!(retract.duration() > Duration::ZERO);
+        }
+        let hop = entries[5].snapshot();
+        assert_eq!(hop.before().position().z().as_mm(), 0.0);
+        assert_eq!(hop.after().position().z().as_mm(), 0.4);
+        assert_eq!(hop.after().position().x().as_mm(), 20.0);
+    }
+
+    #[test]
+    fn waits_count_towards_total_time() {
+        let (stats, entries) = simulate("M104 S200\nM109 S200\nG1 X10 F600\n");
+
+        assert_eq!(entries.len(), 3);
+        assert!(entries[0].duration().is_zero());
+        let heating = &entries[1];
+        assert!(heating.snapshot().motion().is_none());
+        assert!(heating.duration() > Duration::from_secs(10));
+        assert!(stats.total_time > heating.duration() + Duration::from_secs(1));
+        assert!(stats.energy.heaters_j > 0.0);
+    }
+
+    #[test]
+    fn replay_visits_source_lines() {
+        let src = "; start\nG90\n\nG1 X10 F600 ; move\nG1 Y10\n";
+        let simulator = GCodeSimulator::new(MachineProfile::default());
+        let mut lines = Vec::new();
+        let stats = simulator.replay(
+            &GCodeProgram::new(src),
+            MachineProfile::default().snapshot_builder(),
+            |line, _| lines.push(line),
+        );
 
-        (time_elapsed, snapshots)
+        assert_eq!(lines, [1, 3, 4]);
+        assert_eq!(stats.number_of_lines, 5);
+        assert_eq!(stats.travel_moves, 2);
     }
 }
@@ -1,6 +1,7 @@
+use std::collections::BTreeMap;
 use std::time::Duration;
 
-use super::machine::MachineState;
+use super::machine::{MachineState, Wait};
 use super::metric::Position;
 use super::motion::{MotionProfile, MotionTransition, MotionTransitionBuilder};
 use super::thermal::{ThermalModel, ThermalSnapshot, ThermalTransition, ThermalTransitionBuilder};
@@ -41,7 +42,11 @@ where
         thermal: ThermalTransition<B, T>,
         motion: Option<MotionTransition>,
     ) -> Self {
-        let duration = thermal.duration().max(
+        let dwell = match after.wait() {
+            Wait::Dwell(dwell) | Wait::ToolChange(dwell) => dwell,
+            _ => Duration::ZERO,
+        };
+        let duration = thermal.duration().max(dwell).max(
             motion
                 .as_ref()
                 .map(|m| m.duration())
@@ -55,6 +60,23 @@ where
             thermal,
         }
     }
+
+    pub fn before(&self) -> &MachineState {
+        &self.before
+    }
+
+    pub fn after(&self) -> &MachineState {
+        &self.after
+    }
+
+    pub fn motion(&self) -> Option<&MotionTransition> {
+        self.motion.as_ref()
+    }
+
+    /// Joules the heaters draw while the command runs
+    pub fn heater_energy(&self) -> f32 {
+        self.thermal.energy(self.duration)
+    }
 }
 
 impl<B, T> Transition for Snapshot<B, T>
@@ -76,10 +98,7 @@ where
             None => self.after.position(),
         };
 
-        let thermal = {
-            let t = snapshot_secs / self.thermal.duration().as_secs_f32();
-            self.thermal.interpolate(t.clamp(0.0, 1.0))
-        };
+        let thermal = self.thermal.at(Duration::from_secs_f32(snapshot_secs));
 
         (position, thermal)
     }
@@ -93,6 +112,8 @@ where
 pub struct SnapshotBuilder<B, T> {
     bed_thermal_model: B,
     tools_thermal_model: T,
+    // per tool, instead of `tools_thermal_model`
+    tool_thermal_models: BTreeMap<usize, T>,
 }
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -106,6 +127,7 @@ impl<T> SnapshotBuilder<NoThermalModel, T> {
         SnapshotBuilder {
             bed_thermal_model: model,
             tools_thermal_model: self.tools_thermal_model,
+            tool_thermal_models: self.tool_thermal_models,
         }
     }
 }
@@ -118,6 +140,7 @@ impl<B> SnapshotBuilder<B, NoThermalModel> {
         SnapshotBuilder {
             bed_thermal_model: self.bed_thermal_model,
             tools_thermal_model: model,
+            tool_thermal_models: BTreeMap::new(),
         }
     }
 }
@@ -127,16 +150,25 @@ where
     B: ThermalModel,
     T: ThermalModel,
 {
+    /// Models one tool's heater differently from the rest
+    pub fn tool_thermal_model(mut self, tool: usize, model: T) -> Self {
+        self.tool_thermal_models.insert(tool, model);
+        self
+    }
+
     pub fn build(
         self,
         before: MachineState,
         after: MachineState,
         motion_profile: Option<MotionProfile>,
     ) -> Snapshot<B, T> {
-        let thermal = ThermalTransitionBuilder::default()
-            .bed_model(self.bed_thermal_model)
-            .tools_model(self.tools_thermal_model)
-            .build(&after);
+        let thermal = self.tool_thermal_models.into_iter().fold(
+            ThermalTransitionBuilder::default()
+                .bed_model(self.bed_thermal_model)
+                .tools_model(self.tools_thermal_model),
+            |builder, (tool, model)| builder.tool_model(tool, model),
+        );
+        let thermal = thermal.build(&after);
 
         let motion = motion_profile.map(|profile| {
             MotionTransitionBuilder::from(profile)
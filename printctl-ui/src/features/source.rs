@@ -0,0 +1,235 @@
+use std::ops::Range;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::prelude::*;
+
+use super::bgcode;
+
+// every n-th line start is indexed, the rest are found by scanning from there
+const INDEX_STRIDE: usize = 256;
+
+/// Where the text of a source lives
+#[derive(Debug)]
+enum Text {
+    Owned(String),
+    // plain text files are mapped, the OS pages them in and out as needed
+    Mapped(Mmap),
+}
+
+impl Default for Text {
+    fn default() -> Self {
+        Text::Owned(String::new())
+    }
+}
+
+/// G-code text with a sparse index of where lines start, so parts of it can
+/// be parsed without touching the rest
+#[derive(Default)]
+pub struct GCodeSource {
+    text: Text,
+    line_count: usize,
+    // start of every `INDEX_STRIDE`-th line
+    checkpoints: Box<[usize]>,
+}
+
+/// Consecutive source lines parsed as one program
+#[derive(Debug, Clone, Copy)]
+pub struct SourceChunk<'a> {
+    /// Zero-based source line the chunk starts at
+    pub first_line: usize,
+    /// Byte offset of the chunk in the source
+    pub offset: usize,
+    pub text: &'a str,
+}
+
+impl std::fmt::Debug for GCodeSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GCodeSource")
+            .field("len", &self.len())
+            .field("line_count", &self.line_count)
+            .field("mapped", &matches!(self.text, Text::Mapped(_)))
+            .finish()
+    }
+}
+
+impl GCodeSource {
+    pub fn new(src: String) -> Self {
+        Self::indexed(Text::Owned(src))
+    }
+
+    /// Maps plain G-code files without reading them in. Binary G-code is
+    /// decoded into memory, and invalid UTF-8 is replaced like on upload.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the map is read-only and owned by this source. Like any
+        // mapping it assumes nobody rewrites the file while it is open.
+        let map = unsafe { Mmap::map(&file)? };
+
+        if !bgcode::is_bgcode(&map) && std::str::from_utf8(&map).is_ok() {
+            return Ok(Self::indexed(Text::Mapped(map)));
+        }
+        Ok(Self::new(bgcode::to_ascii(&map)?.into_owned()))
+    }
+
+    fn indexed(text: Text) -> Self {
+        let mut source = Self {
+            text,
+            ..Default::default()
+        };
+        (source.line_count, source.checkpoints) = Self::index(source.as_str().as_bytes());
+        source
+    }
+
+    fn index(bytes: &[u8]) -> (usize, Box<[usize]>) {
+        if bytes.is_empty() {
+            return (0, Box::new([]));
+        }
+
+        let mut line_count: usize = 1;
+        let mut checkpoints = vec![0];
+        for (i, _) in bytes.iter().enumerate().filter(|(_, byte)| **byte == b'\n') {
+            // a trailing line break does not start another line
+            if i + 1 == bytes.len() {
+                break;
+            }
+            if line_count.is_multiple_of(INDEX_STRIDE) {
+                checkpoints.push(i + 1);
+            }
+            line_count += 1;
+        }
+
+        (line_count, checkpoints.into_boxed_slice())
+    }
+}
+
+impl GCodeSource {
+    pub fn as_str(&self) -> &str {
+        match &self.text {
+            Text::Owned(text) => text,
+            // SAFETY: checked to be UTF-8 in `open`, and the map is read-only
+            Text::Mapped(map) => unsafe { std::str::from_utf8_unchecked(map) },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_str().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_str().is_empty()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_count
+    }
+
+    /// Byte offset where source line `line` starts
+    pub fn line_offset(&self, line: usize) -> usize {
+        if line >= self.line_count {
+            return self.len();
+        }
+
+        let start = self.checkpoints[line / INDEX_STRIDE];
+        let skip = line % INDEX_STRIDE;
+        if skip == 0 {
+            return start;
+        }
+        self.as_str().as_bytes()[start..]
+            .iter()
+            .enumerate()
+            .filter(|(_, byte)| **byte == b'\n')
+            .nth(skip - 1)
+            .map(|(i, _)| start + i + 1)
+            .unwrap_or(self.len())
+    }
+
+    /// Text of source lines `lines`, including their line breaks
+    pub fn lines(&self, lines: Range<usize>) -> &str {
+        &self.as_str()[self.line_offset(lines.start)..self.line_offset(lines.end)]
+    }
+
+    pub fn line(&self, line: usize) -> &str {
+        self.lines(line..line + 1).trim_end_matches(['\r', '\n'])
+    }
+
+    /// Splits the source into chunks of `lines_per_chunk` lines
+    pub fn chunks(&self, lines_per_chunk: usize) -> impl Iterator<Item = SourceChunk<'_>> {
+        let lines_per_chunk = lines_per_chunk.max(1);
+        (0..self.line_count())
+            .step_by(lines_per_chunk)
+            .map(move |first_line| SourceChunk {
+                first_line,
+                offset: self.line_offset(first_line),
+                text: self.lines(first_line..first_line + lines_per_chunk),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_found_across_index_stride() {
+        let src: String = (0..1000).map(|i| format!("G1 X{}\n", i)).collect();
+        let source = GCodeSource::new(src);
+
+        assert_eq!(source.line_count(), 1000);
+        for line in [0, 1, 255, 256, 257, 511, 999] {
+            assert_eq!(source.line(line), format!("G1 X{}", line));
+        }
+        assert_eq!(source.lines(998..1000), "G1 X998\nG1 X999\n");
+        assert_eq!(source.line_offset(1000), source.len());
+
+        let chunks: Vec<_> = source.chunks(300).collect();
+        assert_eq!(
+            chunks.iter().map(|c| c.first_line).collect::<Vec<_>>(),
+            [0, 300, 600, 900]
+        );
+        assert_eq!(
+            chunks.iter().map(|c| c.text.len()).sum::<usize>(),
+            source.len()
+        );
+    }
+
+    #[test]
+    fn last_line_without_line_break() {
+        let source = GCodeSource::new("G28\r\nG1 X1".to_string());
+
+        assert_eq!(source.line_count(), 2);
+        assert_eq!(source.line(0), "G28");
+        assert_eq!(source.line(1), "G1 X1");
+        assert_eq!(GCodeSource::new(String::new()).line_count(), 0);
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let path =
+            std::env::temp_dir().join(format!("printctl-source-{}.gcode", std::process::id()));
+        std::fs::write(&path, b"G28 ; caf\xe9\nG1 X1\n").unwrap();
+        let source = GCodeSource::open(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let source = source.unwrap();
+        assert_eq!(source.line_count(), 2);
+        assert_eq!(source.line(0), "G28 ; caf\u{fffd}");
+        assert_eq!(source.line(1), "G1 X1");
+    }
+
+    #[test]
+    fn plain_files_are_mapped() {
+        let path =
+            std::env::temp_dir().join(format!("printctl-mapped-{}.gcode", std::process::id()));
+        std::fs::write(&path, "G28\nG1 X1\nG1 X2\n").unwrap();
+        let source = GCodeSource::open(&path).unwrap();
+
+        assert!(matches!(source.text, Text::Mapped(_)));
+        assert_eq!(source.line(2), "G1 X2");
+        let offsets = source.chunks(2).map(|c| c.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, [0, 10]);
+        drop(source);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
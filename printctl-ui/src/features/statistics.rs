@@ -1,5 +1,22 @@
+use std::collections::BTreeMap;
+use std::fmt;
 use std::time::Duration;
 
+use super::feature_type::FeatureType;
+use super::layer::Layer;
+use super::metric::ActivePlane;
+use super::motion::{MotionPath, MotionProfile, MotionTransition};
+use super::profile::{MachineProfile, PowerProfile};
+use super::program::GCodeProgram;
+use super::progress::format_duration;
+use super::simulator::GCodeSimulator;
+use super::snapshot::{Snapshot, Transition};
+use super::thermal::ThermalModel;
+
+/// Retractions in a single layer above which stringing fixes or retraction
+/// wear become a concern
+pub const MAX_RETRACTIONS_PER_LAYER: usize = 100;
+
 #[derive(Debug, Clone, Default)]
 pub struct MotionMetrics {
     pub distance_mm: f64,
@@ -15,6 +32,60 @@ pub struct MotionMetrics {
     pub decelerating_time: Duration,
 }
 
+impl MotionMetrics {
+    fn record(&mut self, distance_mm: f64, time: Duration, motion: &MotionProfile) {
+        self.distance_mm += distance_mm;
+        self.time += time;
+
+        match motion {
+            MotionProfile::ConstantVelocity(_) => {
+                self.cruising_distance_mm += distance_mm;
+                self.cruising_time += time;
+            }
+            MotionProfile::Trapezoidal(trapezoid) => {
+                // split this axis' share of the move by the phases of the whole move
+                let length = trapezoid.length().as_mm() as f64;
+                let total = trapezoid.duration().as_secs_f64();
+                if length <= 0.0 || total <= 0.0 {
+                    return;
+                }
+                let phases = [
+                    (trapezoid.accelerate_distance, trapezoid.accelerate_time()),
+                    (trapezoid.cruise_distance, trapezoid.cruise_time()),
+                    (trapezoid.decelerate_distance, trapezoid.decelerate_time()),
+                ];
+                let buckets = [
+                    (
+                        &mut self.accelerating_distance_mm,
+                        &mut self.accelerating_time,
+                    ),
+                    (&mut self.cruising_distance_mm, &mut self.cruising_time),
+                    (
+                        &mut self.decelerating_distance_mm,
+                        &mut self.decelerating_time,
+                    ),
+                ];
+                for ((distance, duration), (distance_mm_total, time_total)) in
+                    phases.into_iter().zip(buckets)
+                {
+                    *distance_mm_total += distance_mm * distance.as_mm() as f64 / length;
+                    *time_total += time.mul_f64(duration.as_secs_f64() / total);
+                }
+            }
+            MotionProfile::Instant => {}
+        }
+    }
+
+    /// Average speed over the whole distance
+    pub fn average_speed_mm_per_s(&self) -> f64 {
+        if self.time.is_zero() {
+            0.0
+        } else {
+            self.distance_mm / self.time.as_secs_f64()
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct MotionStatistics {
     pub print: MotionMetrics,
@@ -26,10 +97,61 @@ pub struct ExtrusionMetrics {
     pub extruded_mm: f64,
     pub retracted_mm: f64,
     pub primed_mm: f64,
+    pub retractions: usize,
 
     pub extrusion_time: Duration,
     pub retract_time: Duration,
     pub prime_time: Duration,
+
+    /// Net filament fed by each tool, indexed by tool number
+    pub per_tool_extruded_mm: Vec<f64>,
+}
+
+impl ExtrusionMetrics {
+    /// Filament fed by all tools, less what was retracted back
+    pub fn net_mm(&self) -> f64 {
+        self.per_tool_extruded_mm.iter().sum()
+    }
+
+    fn record_tool(&mut self, tool: usize, e_mm: f64) {
+        if self.per_tool_extruded_mm.len() <= tool {
+            self.per_tool_extruded_mm.resize(tool + 1, 0.0);
+        }
+        self.per_tool_extruded_mm[tool] += e_mm;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LayerMetrics {
+    pub z: Option<f32>,
+    pub time: Duration,
+    pub extruded_mm: f64,
+    pub print_moves: usize,
+    pub travel_moves: usize,
+    pub retractions: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct FeatureMetrics {
+    pub time: Duration,
+    pub extruded_mm: f64,
+    pub moves: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EnergyMetrics {
+    /// Time any axis or the extruder is moving
+    pub motion_time: Duration,
+
+    pub heaters_j: f64,
+    pub motors_j: f64,
+    pub idle_j: f64,
+}
+
+impl EnergyMetrics {
+    pub fn total_kwh(&self) -> f64 {
+        (self.heaters_j + self.motors_j + self.idle_j) / 3.6e6
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -46,6 +168,418 @@ pub struct ProgramStatistics {
     // extrusion
     pub extrusion: ExtrusionMetrics,
 
+    // per layer, in program order
+    pub layers: Vec<LayerMetrics>,
+    // per slicer feature type
+    pub features: BTreeMap<FeatureType, FeatureMetrics>,
+
+    // electricity
+    pub energy: EnergyMetrics,
+
     // timing
     pub total_time: Duration,
 }
+
+impl ProgramStatistics {
+    /// Parses and simulates `src` with the profile's thermal models
+    pub fn from_source(src: &str, profile: &MachineProfile) -> Self {
+        Self::from_program(&GCodeProgram::new(src), profile)
+    }
+
+    /// Simulates `program` a chunk at a time, keeping nothing but the
+    /// statistics, so files of any size can be analyzed
+    pub fn from_program(program: &GCodeProgram, profile: &MachineProfile) -> Self {
+        let builder = profile.snapshot_builder();
+        let simulator = GCodeSimulator::new(profile.clone());
+
+        simulator.replay(program, builder, |_, _| {})
+    }
+
+    /// Accounts for one simulated command
+    pub fn record<B, T>(&mut self, snapshot: &Snapshot<B, T>)
+    where
+        B: ThermalModel,
+        T: ThermalModel,
+    {
+        self.total_time += snapshot.duration();
+        self.energy.heaters_j += snapshot.heater_energy() as f64;
+
+        // instant transitions (homing, G92) only rename positions
+        if let Some(motion) = snapshot.motion() {
+            if !matches!(motion.profile(), MotionProfile::Instant) {
+                let tool = snapshot.after().active_tool();
+                self.energy.motion_time += motion.duration();
+                self.record_motion(motion, motion.duration(), tool);
+            }
+        }
+    }
+
+    /// Fills in what motors and electronics draw over the recorded times
+    pub fn record_power(&mut self, power: &PowerProfile) {
+        let energy = &mut self.energy;
+        energy.motors_j = power.motors_w as f64 * energy.motion_time.as_secs_f64();
+        energy.idle_j = power.idle_w as f64 * self.total_time.as_secs_f64();
+    }
+
+    /// Keeps one entry per layer found so far, in step with an index still
+    /// being built
+    pub(crate) fn sync_layers(&mut self, layers: &[Layer]) {
+        // slicer layer comments replace layers guessed from the start G-code
+        if layers.len() < self.layers.len() {
+            self.layers.clear();
+        }
+        self.layers.resize_with(layers.len(), LayerMetrics::default);
+        for (metrics, layer) in self.layers.iter_mut().zip(layers) {
+            metrics.z = layer.z;
+        }
+    }
+
+    /// Zero-based layers retracting more than `limit` times
+    pub fn excessive_retractions(
+        &self,
+        limit: usize,
+    ) -> impl Iterator<Item = (usize, &LayerMetrics)> {
+        self.layers
+            .iter()
+            .enumerate()
+            .filter(move |(_, layer)| layer.retractions > limit)
+    }
+
+    /// Accounts for one simulated command within `layer`
+    pub fn record_layer<B, T>(&mut self, layer: usize, snapshot: &Snapshot<B, T>)
+    where
+        B: ThermalModel,
+        T: ThermalModel,
+    {
+        let Some(metrics) = self.layers.get_mut(layer) else {
+            return;
+        };
+        metrics.time += snapshot.duration();
+
+        let Some(motion) = snapshot.motion() else {
+            return;
+        };
+        if matches!(motion.profile(), MotionProfile::Instant) {
+            return;
+        }
+        let e_mm = motion.extrusion().as_mm() as f64;
+        if e_mm < 0.0 {
+            metrics.retractions += 1;
+        }
+        if motion.distance().as_mm() <= f32::EPSILON {
+            return;
+        }
+        if e_mm > 0.0 && motion.planar_distance().as_mm() > f32::EPSILON {
+            metrics.print_moves += 1;
+            metrics.extruded_mm += e_mm;
+        } else {
+            metrics.travel_moves += 1;
+        }
+    }
+
+    /// Accounts for one simulated command printing `feature`
+    pub fn record_feature<B, T>(&mut self, feature: FeatureType, snapshot: &Snapshot<B, T>)
+    where
+        B: ThermalModel,
+        T: ThermalModel,
+    {
+        let metrics = self.features.entry(feature).or_default();
+        metrics.time += snapshot.duration();
+
+        if let Some(motion) = snapshot.motion() {
+            if motion.distance().as_mm() > f32::EPSILON {
+                metrics.moves += 1;
+                metrics.extruded_mm += (motion.extrusion().as_mm() as f64).max(0.0);
+            }
+        }
+    }
+
+    fn record_motion(&mut self, motion: &MotionTransition, time: Duration, tool: usize) {
+        let (start, end) = (motion.start(), motion.end());
+        let xy_mm = match motion.path() {
+            MotionPath::Arc(arc) if matches!(arc.plane(), ActivePlane::XY) => {
+                arc.planar_length().as_mm() as f64
+            }
+            _ => (end.x() - start.x())
+                .as_mm()
+                .hypot((end.y() - start.y()).as_mm()) as f64,
+        };
+        let z_mm = (end.z() - start.z()).as_mm().abs() as f64;
+        let path_mm = motion.distance().as_mm() as f64;
+        let e_mm = motion.extrusion().as_mm() as f64;
+
+        let moved = path_mm > f64::from(f32::EPSILON);
+        // primes and retracts may lift or lower the nozzle without printing
+        let printing = e_mm > 0.0 && xy_mm > f64::from(f32::EPSILON);
+
+        if moved {
+            let xy_motion = if printing {
+                self.print_moves += 1;
+                &mut self.xy_motion.print
+            } else {
+                self.travel_moves += 1;
+                &mut self.xy_motion.travel
+            };
+
+            // diagonal moves split their time by the share of each component
+            let share = |mm: f64| time.mul_f64(mm / path_mm);
+            if xy_mm > 0.0 {
+                xy_motion.record(xy_mm, share(xy_mm), motion.profile());
+            }
+            if z_mm > 0.0 {
+                self.z_motion.record(z_mm, share(z_mm), motion.profile());
+            }
+        }
+
+        let extrusion = &mut self.extrusion;
+        if e_mm != 0.0 {
+            extrusion.record_tool(tool, e_mm);
+        }
+        match (printing, e_mm > 0.0) {
+            (true, _) => {
+                extrusion.extruded_mm += e_mm;
+                extrusion.extrusion_time += time;
+            }
+            (false, true) => {
+                extrusion.primed_mm += e_mm;
+                extrusion.prime_time += time;
+            }
+            // retracts while travelling (wipe) count as retractions too
+            _ if e_mm < 0.0 => {
+                extrusion.retractions += 1;
+                extrusion.retracted_mm += -e_mm;
+                extrusion.retract_time += time;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for MotionMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.1} mm in {} ({:.1} mm/s avg; accel {:.1} mm, cruise {:.1} mm, decel {:.1} mm)",
+            self.distance_mm,
+            format_duration(self.time),
+            self.average_speed_mm_per_s(),
+            self.accelerating_distance_mm,
+            self.cruising_distance_mm,
+            self.decelerating_distance_mm,
+        )
+    }
+}
+
+impl fmt::Display for LayerMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let z = self.z.map(|z| format!("{:.2} mm", z)).unwrap_or_default();
+        write!(
+            f,
+            "{:>9} {:>9} {:>9.1} mm {:>5} print {:>5} travel {:>4} retractions",
+            z,
+            format_duration(self.time),
+            self.extruded_mm,
+            self.print_moves,
+            self.travel_moves,
+            self.retractions,
+        )
+    }
+}
+
+impl fmt::Display for ProgramStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let extrusion = &self.extrusion;
+
+        writeln!(f, "Lines:        {}", self.number_of_lines)?;
+        writeln!(
+            f,
+            "Moves:        {} print, {} travel",
+            self.print_moves, self.travel_moves
+        )?;
+        writeln!(f, "Layers:       {}", self.layers.len())?;
+        writeln!(f, "Print (XY):   {}", self.xy_motion.print)?;
+        writeln!(f, "Travel (XY):  {}", self.xy_motion.travel)?;
+        writeln!(f, "Z:            {}", self.z_motion)?;
+        writeln!(
+            f,
+            "Extruded:     {:.1} mm in {}",
+            extrusion.extruded_mm,
+            format_duration(extrusion.extrusion_time)
+        )?;
+        writeln!(
+            f,
+            "Retracted:    {:.1} mm in {} ({} retractions)",
+            extrusion.retracted_mm,
+            format_duration(extrusion.retract_time),
+            extrusion.retractions
+        )?;
+        writeln!(
+            f,
+            "Primed:       {:.1} mm in {}",
+            extrusion.primed_mm,
+            format_duration(extrusion.prime_time)
+        )?;
+        if !self.features.is_empty() {
+            writeln!(f, "Features:")?;
+            let total = self.total_time.as_secs_f64();
+            for (feature, metrics) in &self.features {
+                let share = if total > 0.0 {
+                    metrics.time.as_secs_f64() / total * 100.0
+                } else {
+                    0.0
+                };
+                writeln!(
+                    f,
+                    "  {:<18} {:>9} {:>5.1}% {:>9.1} mm",
+                    feature.name(),
+                    format_duration(metrics.time),
+                    share,
+                    metrics.extruded_mm,
+                )?;
+            }
+        }
+        // single-extruder printers have nothing to break down
+        if extrusion.per_tool_extruded_mm.len() > 1 {
+            for (tool, mm) in extrusion.per_tool_extruded_mm.iter().enumerate() {
+                writeln!(f, "  T{:<10} {:.1} mm", tool, mm)?;
+            }
+        }
+        writeln!(
+            f,
+            "Energy:       {:.3} kWh (heaters {:.3}, motors {:.3}, idle {:.3})",
+            self.energy.total_kwh(),
+            self.energy.heaters_j / 3.6e6,
+            self.energy.motors_j / 3.6e6,
+            self.energy.idle_j / 3.6e6,
+        )?;
+        write!(f, "Total time:   {}", format_duration(self.total_time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a travel and a print move, a retract and prime around a Z hop, then a
+    // second layer; Cura comments mark the layers and features
+    const SRC: &str = "\
+G90
+M83
+G1 Z0.2 F600
+;LAYER:0
+;TYPE:WALL-OUTER
+G1 X10 F3000
+G1 X20 E0.5 F1200
+G1 E-0.8 F2400
+G1 Z0.6
+G1 X30
+G1 Z0.2
+G1 E0.8
+;TYPE:FILL
+G1 X40 E0.5 F1200
+;LAYER:1
+G1 Z0.4 F600
+G1 X0 E2 F1200
+";
+
+    fn stats() -> ProgramStatistics {
+        ProgramStatistics::from_source(SRC, &MachineProfile::default())
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn splits_print_from_travel_and_xy_from_z() {
+        let stats = stats();
+        assert_eq!(stats.number_of_lines, 17);
+        assert_eq!((stats.print_moves, stats.travel_moves), (3, 6));
+
+        let motion = &stats.xy_motion;
+        assert!(close(motion.print.distance_mm, 60.0));
+        assert!(close(motion.travel.distance_mm, 20.0));
+        // the hop and the layer changes, not the print moves on the same plane
+        assert!(close(stats.z_motion.distance_mm, 1.2));
+
+        // nothing moves faster than it was asked to
+        assert!(motion.print.time >= Duration::from_secs(3));
+        assert!(motion.print.average_speed_mm_per_s() <= 20.0 + 1e-3);
+        let phases = &motion.print;
+        let split = phases.accelerating_distance_mm
+            + phases.cruising_distance_mm
+            + phases.decelerating_distance_mm;
+        assert!(close(split, phases.distance_mm));
+    }
+
+    #[test]
+    fn splits_extrusion_from_retraction_and_priming() {
+        let extrusion = stats().extrusion;
+        assert!(close(extrusion.extruded_mm, 3.0));
+        assert!(close(extrusion.retracted_mm, 0.8));
+        assert!(close(extrusion.primed_mm, 0.8));
+        assert_eq!(extrusion.retractions, 1);
+        assert!(close(extrusion.net_mm(), 3.0));
+        assert_eq!(extrusion.per_tool_extruded_mm.len(), 1);
+
+        // 0.8 mm at 40 mm/s each way
+        assert!(extrusion.retract_time >= Duration::from_millis(20));
+        assert!(extrusion.prime_time >= Duration::from_millis(20));
+        assert!(extrusion.extrusion_time >= Duration::from_secs(3));
+    }
+
+    #[test]
+    fn totals_add_up() {
+        let stats = stats();
+        let layers: Duration = stats.layers.iter().map(|layer| layer.time).sum();
+        let features: Duration = stats.features.values().map(|feature| feature.time).sum();
+        let extrusion = &stats.extrusion;
+        let moves = stats.xy_motion.print.time
+            + stats.xy_motion.travel.time
+            + stats.z_motion.time
+            + extrusion.retract_time
+            + extrusion.prime_time;
+
+        assert!(stats.total_time > Duration::ZERO);
+        // the first Z move is start G-code, outside any layer or feature, and
+        // moves are all there is to time without heaters to wait for
+        assert!(layers < stats.total_time && features < stats.total_time);
+        assert!(moves.abs_diff(stats.energy.motion_time) < Duration::from_micros(10));
+        assert_eq!(stats.energy.motion_time, stats.total_time);
+    }
+
+    #[test]
+    fn records_layers() {
+        let layers = stats().layers;
+        assert_eq!(layers.len(), 2);
+
+        let first = &layers[0];
+        assert_eq!(first.z, Some(0.2));
+        assert_eq!((first.print_moves, first.travel_moves), (2, 4));
+        assert_eq!(first.retractions, 1);
+        assert!(close(first.extruded_mm, 1.0));
+
+        let second = &layers[1];
+        assert_eq!(second.z, Some(0.4));
+        assert_eq!((second.print_moves, second.travel_moves), (1, 1));
+        assert_eq!(second.retractions, 0);
+        assert!(close(second.extruded_mm, 2.0));
+        assert!(second.time > Duration::ZERO);
+    }
+
+    #[test]
+    fn records_features() {
+        let features = stats().features;
+        assert_eq!(features.len(), 2);
+
+        // the retract and prime move no axis, so only count towards time
+        let wall = &features[&FeatureType::OuterWall];
+        assert_eq!(wall.moves, 5);
+        assert!(close(wall.extruded_mm, 0.5));
+
+        let infill = &features[&FeatureType::Infill];
+        assert_eq!(infill.moves, 3);
+        assert!(close(infill.extruded_mm, 2.5));
+        assert!(infill.time > wall.time);
+    }
+}
@@ -1,16 +1,26 @@
+use std::collections::BTreeMap;
 use std::time::Duration;
 
-use super::machine::{HeaterState, MachineState};
+use super::machine::{Heater, HeaterState, MachineState, Wait};
 use super::snapshot::Transition;
 
 pub trait ThermalModel: Clone {
-    /// Temperature at time `t` (seconds since transition start)
-    fn temperature(&self, initial: f32, t: Duration) -> f32;
+    /// Temperature at time `t` (seconds since transition start), with the
+    /// heater regulating towards `target` or off without one
+    fn temperature(&self, initial: f32, target: Option<f32>, t: Duration) -> f32;
 
-    /// How long until we reach target temperature
+    /// How long until a `M109`/`M190` waiting on `target` returns, heating
+    /// or cooling
     fn settle_time(&self, initial: f32, target: Option<f32>) -> Duration;
+
+    /// Joules the heater draws over `t`
+    fn energy(&self, initial: f32, target: Option<f32>, t: Duration) -> f32;
 }
 
+// a target the heater cannot reach counts as reached this close to where
+// the temperature levels off
+const UNREACHABLE_MARGIN: f32 = 1.0;
+
 #[derive(Debug, Clone)]
 pub struct LumpedThermalModel {
     pub ambient: f32,
@@ -19,14 +29,126 @@ pub struct LumpedThermalModel {
     pub heat_capacity: f32,
 }
 
-impl ThermalModel for LumpedThermalModel {
-    fn temperature(&self, initial: f32, t: Duration) -> f32 {
-        let t_sec = t.as_secs_f32();
+impl LumpedThermalModel {
+    /// Typical 40W cartridge heater in an aluminium block
+    pub fn hotend() -> Self {
+        Self {
+            ambient: 25.0,
+            power_w: 40.0,
+            loss_coeff: 0.1,
+            heat_capacity: 10.0,
+        }
+    }
+
+    /// Typical 200W heated bed
+    pub fn bed() -> Self {
+        Self {
+            ambient: 25.0,
+            power_w: 200.0,
+            loss_coeff: 1.5,
+            heat_capacity: 400.0,
+        }
+    }
+}
+
+/// One temperature reading taken while calibrating a heater
+#[derive(Debug, Clone, Copy)]
+pub struct ThermalSample {
+    /// Since the recording started
+    pub time: Duration,
+    pub temp: f32,
+    /// Whether the heater was driven at full power, rather than off
+    pub heating: bool,
+}
+
+impl LumpedThermalModel {
+    /// Least-squares fit of a recorded heat-up at full power followed by a
+    /// cool-down with the heater off.
+    ///
+    /// The curves only pin down `power_w / heat_capacity` and
+    /// `loss_coeff / heat_capacity`, so the heater's rated power sets the
+    /// scale. Returns `None` when the samples do not describe a heater.
+    pub fn fit(ambient: f32, power_w: f32, samples: &[ThermalSample]) -> Option<Self> {
+        // dT/dt = a·u - k·(T - ambient), with u = 1 while heating, solved
+        // for a = P/C and k = h/C over the finite differences
+        let (mut suu, mut sux, mut sxx, mut suy, mut sxy) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for pair in samples.windows(2) {
+            let dt = pair[1].time.saturating_sub(pair[0].time).as_secs_f64();
+            if dt <= 0.0 || pair[0].heating != pair[1].heating {
+                continue;
+            }
+            let u = if pair[0].heating { 1.0 } else { 0.0 };
+            let x = -((pair[0].temp + pair[1].temp) as f64 / 2.0 - ambient as f64);
+            let y = (pair[1].temp - pair[0].temp) as f64 / dt;
+
+            suu += u * u;
+            sux += u * x;
+            sxx += x * x;
+            suy += u * y;
+            sxy += x * y;
+        }
+
+        let det = suu * sxx - sux * sux;
+        if det.abs() <= f64::EPSILON {
+            return None;
+        }
+        let a = (suy * sxx - sxy * sux) / det;
+        let k = (suu * sxy - sux * suy) / det;
+        if !(a > 0.0 && k > 0.0) {
+            return None;
+        }
+
+        let heat_capacity = power_w as f64 / a;
+        Some(Self {
+            ambient,
+            power_w,
+            loss_coeff: (k * heat_capacity) as f32,
+            heat_capacity: heat_capacity as f32,
+        })
+    }
+}
+
+impl LumpedThermalModel {
+    fn steady_temp(&self, power: bool) -> f32 {
+        if power {
+            self.ambient + self.power_w / self.loss_coeff
+        } else {
+            self.ambient
+        }
+    }
+
+    fn k(&self) -> f32 {
+        self.loss_coeff / self.heat_capacity
+    }
 
-        let k = self.loss_coeff / self.heat_capacity;
-        let steady = self.ambient + self.power_w / self.loss_coeff;
+    /// Power that holds `target` against losses
+    fn holding_power(&self, target: f32) -> f32 {
+        (self.loss_coeff * (target - self.ambient)).clamp(0.0, self.power_w)
+    }
+
+    /// `target`, or where the temperature levels off short of it
+    fn reachable(&self, initial: f32, target: f32) -> f32 {
+        if initial < target {
+            target.min(self.steady_temp(true) - UNREACHABLE_MARGIN)
+        } else {
+            target.max(self.steady_temp(false) + UNREACHABLE_MARGIN)
+        }
+    }
+}
 
-        steady + (initial - steady) * (-k * t_sec).exp()
+/// Runs at full power below the target and off above it, then holds the
+/// target exactly
+impl ThermalModel for LumpedThermalModel {
+    fn temperature(&self, initial: f32, target: Option<f32>, t: Duration) -> f32 {
+        let heating = target.is_some_and(|target| initial < target);
+        let steady = self.steady_temp(heating);
+        let temp = steady + (initial - steady) * (-self.k() * t.as_secs_f32()).exp();
+
+        match target {
+            Some(target) if heating => temp.min(target),
+            Some(target) => temp.max(target),
+            None => temp,
+        }
     }
 
     fn settle_time(&self, initial: f32, target: Option<f32>) -> Duration {
@@ -34,17 +156,285 @@ impl ThermalModel for LumpedThermalModel {
             return Duration::ZERO;
         };
 
-        let steady = self.ambient + self.power_w / self.loss_coeff;
+        let steady = self.steady_temp(initial < target);
+        let target = self.reachable(initial, target);
+
+        // already past the (reachable) target
         let ratio = (target - steady) / (initial - steady);
+        if !(ratio > 0.0 && ratio < 1.0) {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f32(-ratio.ln() / self.k())
+    }
+
+    fn energy(&self, initial: f32, target: Option<f32>, t: Duration) -> f32 {
+        let Some(target) = target else {
+            return 0.0;
+        };
+
+        // full power while heating, nothing while cooling, then holding
+        let settled = self.settle_time(initial, Some(target)).min(t);
+        let full = if initial < target { self.power_w } else { 0.0 };
+        full * settled.as_secs_f32() + self.holding_power(target) * (t - settled).as_secs_f32()
+    }
+}
+
+// Marlin's PID_FUNCTIONAL_RANGE, further from the target the PID loop
+// hands over to bang-bang control
+const PID_FUNCTIONAL_RANGE: f32 = 10.0;
+const PWM_MAX: f32 = 255.0;
+
+/// Closed-loop heater control the firmware runs
+#[derive(Debug, Clone, Copy)]
+pub enum Controller {
+    /// Full power below `target - hysteresis`, off above `target + hysteresis`
+    BangBang { hysteresis: f32 },
+    /// Gains in PWM steps (0-255) like Marlin's `M301`, `ki` per second and
+    /// `kd` acting on the measured temperature
+    Pid { kp: f32, ki: f32, kd: f32 },
+}
 
-        if ratio <= f32::EPSILON {
+/// Heater plant driven by a simulated firmware control loop, numerically
+/// integrated so overshoot and settling show up.
+///
+/// Every transition restarts the loop; one starting near its target assumes
+/// the integrator had settled at the power that holds it there.
+#[derive(Debug, Clone)]
+pub struct ControlledThermalModel {
+    pub plant: LumpedThermalModel,
+    pub controller: Controller,
+    /// `M109`/`M190` start counting once within this many °C of the target
+    pub window: f32,
+    /// and restart if it drifts further than this
+    pub hysteresis: f32,
+    /// How long the temperature has to stay in the window
+    pub residency: Duration,
+    /// Integration step
+    pub step: Duration,
+    /// Longest a wait is simulated for before giving up
+    pub max_settle: Duration,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ControlState {
+    temp: f32,
+    previous_temp: f32,
+    integral: f32,
+    heating: bool,
+}
+
+impl ControlledThermalModel {
+    /// Marlin's stock `TEMP_WINDOW`/`TEMP_HYSTERESIS`/`TEMP_RESIDENCY_TIME`
+    pub fn new(plant: LumpedThermalModel, controller: Controller) -> Self {
+        Self {
+            plant,
+            controller,
+            window: 1.0,
+            hysteresis: 3.0,
+            residency: Duration::from_secs(10),
+            step: Duration::from_millis(100),
+            max_settle: Duration::from_secs(30 * 60),
+        }
+    }
+
+    /// Marlin's default hotend PID gains
+    pub fn hotend() -> Self {
+        Self::new(
+            LumpedThermalModel::hotend(),
+            Controller::Pid {
+                kp: 22.2,
+                ki: 1.08,
+                kd: 114.0,
+            },
+        )
+    }
+
+    /// Marlin's default bang-bang bed
+    pub fn bed() -> Self {
+        Self::new(
+            LumpedThermalModel::bed(),
+            Controller::BangBang { hysteresis: 2.0 },
+        )
+    }
+
+    fn start(&self, initial: f32, target: Option<f32>) -> ControlState {
+        // power that holds the target against losses, as a settled integrator
+        let integral = match (self.controller, target) {
+            (Controller::Pid { ki, .. }, Some(target)) if ki > 0.0 => {
+                self.plant.holding_power(target) / self.plant.power_w * PWM_MAX / ki
+            }
+            _ => 0.0,
+        };
+
+        ControlState {
+            temp: initial,
+            previous_temp: initial,
+            integral,
+            heating: target.is_some_and(|target| initial < target),
+        }
+    }
+
+    /// Heater duty cycle in 0..=1
+    fn output(&self, state: &mut ControlState, target: Option<f32>, dt: f32) -> f32 {
+        let Some(target) = target else {
+            return 0.0;
+        };
+        let error = target - state.temp;
+
+        match self.controller {
+            Controller::BangBang { hysteresis } => {
+                if error > hysteresis {
+                    state.heating = true;
+                } else if error < -hysteresis {
+                    state.heating = false;
+                }
+                if state.heating {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Controller::Pid { kp, ki, kd } => {
+                if error.abs() > PID_FUNCTIONAL_RANGE {
+                    state.integral = 0.0;
+                    return if error > 0.0 { 1.0 } else { 0.0 };
+                }
+
+                if ki > 0.0 {
+                    state.integral = (state.integral + error * dt).clamp(0.0, PWM_MAX / ki);
+                }
+                let derivative = (state.temp - state.previous_temp) / dt;
+                let pwm = kp * error + ki * state.integral - kd * derivative;
+                (pwm / PWM_MAX).clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    /// Steps the loop by `dt`, returning the energy the heater drew
+    fn advance(&self, state: &mut ControlState, target: Option<f32>, dt: f32) -> f32 {
+        let duty = self.output(state, target, dt);
+        let plant = &self.plant;
+        let flow = duty * plant.power_w - plant.loss_coeff * (state.temp - plant.ambient);
+
+        state.previous_temp = state.temp;
+        state.temp += flow / plant.heat_capacity * dt;
+        duty * plant.power_w * dt
+    }
+
+    /// Temperature after `t` and the energy drawn until then
+    fn integrate(&self, initial: f32, target: Option<f32>, t: Duration) -> (f32, f32) {
+        let mut state = self.start(initial, target);
+        let step = self.step.as_secs_f32();
+
+        let mut energy = 0.0;
+        let mut remaining = t.as_secs_f32();
+        while remaining > 0.0 {
+            let dt = remaining.min(step);
+            energy += self.advance(&mut state, target, dt);
+            remaining -= dt;
+        }
+
+        (state.temp, energy)
+    }
+}
+
+impl ThermalModel for ControlledThermalModel {
+    fn temperature(&self, initial: f32, target: Option<f32>, t: Duration) -> f32 {
+        self.integrate(initial, target, t).0
+    }
+
+    fn energy(&self, initial: f32, target: Option<f32>, t: Duration) -> f32 {
+        self.integrate(initial, target, t).1
+    }
+
+    fn settle_time(&self, initial: f32, target: Option<f32>) -> Duration {
+        let Some(target) = target else {
             return Duration::ZERO;
+        };
+
+        let mut state = self.start(initial, Some(target));
+        let reachable = self.plant.reachable(initial, target);
+        let mut elapsed = Duration::ZERO;
+        let mut residency_start = None;
+
+        while elapsed < self.max_settle {
+            let error = (state.temp - reachable).abs();
+            if error <= self.window {
+                let start = *residency_start.get_or_insert(elapsed);
+                if elapsed - start >= self.residency {
+                    return elapsed;
+                }
+            } else if error > self.hysteresis {
+                residency_start = None;
+            }
+
+            self.advance(&mut state, Some(target), self.step.as_secs_f32());
+            elapsed += self.step;
+        }
+
+        self.max_settle
+    }
+}
+
+/// Thermal model chosen per heater at runtime
+#[derive(Debug, Clone)]
+pub enum HeaterModel {
+    Lumped(LumpedThermalModel),
+    Controlled(ControlledThermalModel),
+}
+
+impl HeaterModel {
+    pub fn plant(&self) -> &LumpedThermalModel {
+        match self {
+            HeaterModel::Lumped(model) => model,
+            HeaterModel::Controlled(model) => &model.plant,
+        }
+    }
+
+    /// Same control, different heater physics, e.g. after calibration
+    pub fn with_plant(self, plant: LumpedThermalModel) -> Self {
+        match self {
+            HeaterModel::Lumped(_) => HeaterModel::Lumped(plant),
+            HeaterModel::Controlled(model) => {
+                HeaterModel::Controlled(ControlledThermalModel { plant, ..model })
+            }
         }
+    }
+}
 
-        let k = self.loss_coeff / self.heat_capacity;
-        let t = -ratio.abs().ln() / k;
+impl From<LumpedThermalModel> for HeaterModel {
+    fn from(model: LumpedThermalModel) -> Self {
+        HeaterModel::Lumped(model)
+    }
+}
 
-        Duration::from_secs_f32(t.max(0.0))
+impl From<ControlledThermalModel> for HeaterModel {
+    fn from(model: ControlledThermalModel) -> Self {
+        HeaterModel::Controlled(model)
+    }
+}
+
+impl ThermalModel for HeaterModel {
+    fn temperature(&self, initial: f32, target: Option<f32>, t: Duration) -> f32 {
+        match self {
+            HeaterModel::Lumped(model) => model.temperature(initial, target, t),
+            HeaterModel::Controlled(model) => model.temperature(initial, target, t),
+        }
+    }
+
+    fn settle_time(&self, initial: f32, target: Option<f32>) -> Duration {
+        match self {
+            HeaterModel::Lumped(model) => model.settle_time(initial, target),
+            HeaterModel::Controlled(model) => model.settle_time(initial, target),
+        }
+    }
+
+    fn energy(&self, initial: f32, target: Option<f32>, t: Duration) -> f32 {
+        match self {
+            HeaterModel::Lumped(model) => model.energy(initial, target, t),
+            HeaterModel::Controlled(model) => model.energy(initial, target, t),
+        }
     }
 }
 
@@ -55,6 +445,10 @@ where
 {
     thermal_model: M,
     heater: HeaterState,
+    // `M109`/`M190` hold the queue until the target is reached
+    wait: bool,
+    // `R` instead of `S` also waits for the heater to cool down
+    wait_cooling: bool,
 }
 
 impl<M> HeaterTransition<M>
@@ -65,8 +459,36 @@ where
         Self {
             heater,
             thermal_model,
+            wait: false,
+            wait_cooling: false,
+        }
+    }
+
+    pub fn waiting(self, wait: bool, wait_cooling: bool) -> Self {
+        Self {
+            wait,
+            wait_cooling,
+            ..self
         }
     }
+
+    /// Temperature `elapsed` into the transition
+    pub fn temperature_at(&self, elapsed: Duration) -> f32 {
+        self.thermal_model.temperature(
+            self.heater.current_temp(),
+            self.heater.target_temp(),
+            elapsed,
+        )
+    }
+
+    /// Joules drawn over the first `elapsed` of the transition
+    pub fn energy_at(&self, elapsed: Duration) -> f32 {
+        self.thermal_model.energy(
+            self.heater.current_temp(),
+            self.heater.target_temp(),
+            elapsed,
+        )
+    }
 }
 
 impl<M> Transition for HeaterTransition<M>
@@ -77,24 +499,54 @@ where
 
     fn interpolate(&self, tau: f32) -> f32 {
         let tau = tau.clamp(0.0, 1.0);
-        let elapsed = self.duration().mul_f32(tau);
-
-        self.thermal_model
-            .temperature(self.heater.current_temp(), elapsed)
+        self.temperature_at(self.duration().mul_f32(tau))
     }
 
     fn duration(&self) -> Duration {
+        let current = self.heater.current_temp();
+        let blocks = self
+            .heater
+            .target_temp()
+            .is_some_and(|target| current < target || (self.wait_cooling && current > target));
+        if !self.wait || !blocks {
+            return Duration::ZERO;
+        }
+
         self.thermal_model
             .settle_time(self.heater.current_temp(), self.heater.target_temp())
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct ThermalSnapshot {
     bed_temp: f32,
     tool_temps: Vec<f32>,
 }
 
+impl ThermalSnapshot {
+    pub fn bed_temp(&self) -> f32 {
+        self.bed_temp
+    }
+
+    pub fn tool_temps(&self) -> &[f32] {
+        &self.tool_temps
+    }
+
+    /// Temperatures `t` of the way from `self` to `other`
+    pub fn lerp(&self, other: &Self, t: f32) -> Self {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            bed_temp: lerp(self.bed_temp, other.bed_temp),
+            tool_temps: self
+                .tool_temps
+                .iter()
+                .zip(&other.tool_temps)
+                .map(|(&a, &b)| lerp(a, b))
+                .collect(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ThermalTransition<B, T>
 where
@@ -111,19 +563,37 @@ where
     T: ThermalModel,
 {
     pub fn new(machine: &MachineState, bed_model: B, tool_model: T) -> Self {
-        let bed_heater = machine.bed_heater();
-        let bed = HeaterTransition::new(bed_heater.clone(), bed_model);
+        ThermalTransitionBuilder {
+            bed_model,
+            tools_model: tool_model,
+            tool_models: BTreeMap::new(),
+        }
+        .build(machine)
+    }
+}
 
-        let tools = machine
-            .tools()
-            .iter()
-            .map(|tool| {
-                let heater = tool.heater_state();
-                HeaterTransition::new(heater.clone(), tool_model.clone())
-            })
-            .collect::<Vec<_>>();
+impl<B, T> ThermalTransition<B, T>
+where
+    B: ThermalModel,
+    T: ThermalModel,
+{
+    /// Temperatures `elapsed` into the transition, which may outlast the
+    /// transition's own duration while motion continues
+    pub fn at(&self, elapsed: Duration) -> ThermalSnapshot {
+        ThermalSnapshot {
+            bed_temp: self.bed.temperature_at(elapsed),
+            tool_temps: self
+                .tools
+                .iter()
+                .map(|t| t.temperature_at(elapsed))
+                .collect(),
+        }
+    }
 
-        Self { bed, tools }
+    /// Joules all heaters draw over the first `elapsed` of the transition
+    pub fn energy(&self, elapsed: Duration) -> f32 {
+        let tools = self.tools.iter().map(|t| t.energy_at(elapsed));
+        self.bed.energy_at(elapsed) + tools.sum::<f32>()
     }
 }
 
@@ -155,6 +625,8 @@ where
 pub struct ThermalTransitionBuilder<B, T> {
     bed_model: B,
     tools_model: T,
+    // per tool, instead of `tools_model`
+    tool_models: BTreeMap<usize, T>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -168,6 +640,7 @@ impl<T> ThermalTransitionBuilder<NoThermalModel, T> {
         ThermalTransitionBuilder {
             bed_model: model,
             tools_model: self.tools_model,
+            tool_models: self.tool_models,
         }
     }
 }
@@ -180,6 +653,7 @@ impl<B> ThermalTransitionBuilder<B, NoThermalModel> {
         ThermalTransitionBuilder {
             bed_model: self.bed_model,
             tools_model: model,
+            tool_models: BTreeMap::new(),
         }
     }
 }
@@ -189,19 +663,170 @@ where
     B: ThermalModel,
     T: ThermalModel,
 {
+    /// Overrides the tools model for one tool
+    pub fn tool_model(mut self, tool: usize, model: T) -> Self {
+        self.tool_models.insert(tool, model);
+        self
+    }
+
     pub fn build(self, state: &MachineState) -> ThermalTransition<B, T> {
+        let waits_for = |heater| match state.wait() {
+            Wait::Heater(waiting, cooling) if waiting == heater => (true, cooling),
+            _ => (false, false),
+        };
+
         let bed_heater = state.bed_heater();
-        let bed = HeaterTransition::new(bed_heater.clone(), self.bed_model);
+        let (wait, cooling) = waits_for(Heater::Bed);
+        let bed = HeaterTransition::new(bed_heater.clone(), self.bed_model).waiting(wait, cooling);
 
         let tools = state
             .tools()
             .iter()
-            .map(|tool| {
+            .enumerate()
+            .map(|(i, tool)| {
                 let heater = tool.heater_state();
-                HeaterTransition::new(heater.clone(), self.tools_model.clone())
+                let model = self.tool_models.get(&i).unwrap_or(&self.tools_model);
+                let (wait, cooling) = waits_for(Heater::Tool(i));
+                HeaterTransition::new(heater.clone(), model.clone()).waiting(wait, cooling)
             })
             .collect::<Vec<_>>();
 
         ThermalTransition { bed, tools }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Readings every second of `model` heating at full power from ambient,
+    /// then cooling with the heater off
+    fn recording(model: &LumpedThermalModel, heat: u64, cool: u64) -> Vec<ThermalSample> {
+        let full_power = Some(f32::MAX);
+        let peak = model.temperature(model.ambient, full_power, Duration::from_secs(heat));
+        let heating = (0..=heat).map(|s| ThermalSample {
+            time: Duration::from_secs(s),
+            temp: model.temperature(model.ambient, full_power, Duration::from_secs(s)),
+            heating: true,
+        });
+        let cooling = (1..=cool).map(|s| ThermalSample {
+            time: Duration::from_secs(heat + s),
+            temp: model.temperature(peak, None, Duration::from_secs(s)),
+            heating: false,
+        });
+        heating.chain(cooling).collect()
+    }
+
+    fn close(a: f32, b: f32, tolerance: f32) -> bool {
+        (a - b).abs() <= tolerance * b.abs()
+    }
+
+    #[test]
+    fn fit_recovers_the_model() {
+        for model in [LumpedThermalModel::hotend(), LumpedThermalModel::bed()] {
+            let samples = recording(&model, 120, 120);
+            let fitted = LumpedThermalModel::fit(model.ambient, model.power_w, &samples).unwrap();
+            assert!(
+                close(fitted.heat_capacity, model.heat_capacity, 0.02),
+                "{:?}",
+                fitted
+            );
+            assert!(
+                close(fitted.loss_coeff, model.loss_coeff, 0.02),
+                "{:?}",
+                fitted
+            );
+        }
+    }
+
+    #[test]
+    fn fit_scales_with_the_rated_power() {
+        let model = LumpedThermalModel::hotend();
+        let samples = recording(&model, 120, 120);
+        let fitted = LumpedThermalModel::fit(model.ambient, 2.0 * model.power_w, &samples).unwrap();
+        assert!(close(fitted.heat_capacity, 2.0 * model.heat_capacity, 0.02));
+        assert!(close(fitted.loss_coeff, 2.0 * model.loss_coeff, 0.02));
+    }
+
+    #[test]
+    fn fit_rejects_recordings_that_are_not_a_heater() {
+        let model = LumpedThermalModel::hotend();
+        assert!(LumpedThermalModel::fit(25.0, 40.0, &[]).is_none());
+
+        // cooling alone says nothing about the heater
+        let cooling = recording(&model, 60, 60).split_off(61);
+        assert!(LumpedThermalModel::fit(25.0, 40.0, &cooling).is_none());
+
+        // a heater that never warms up
+        let flat = (0..60)
+            .map(|s| ThermalSample {
+                time: Duration::from_secs(s),
+                temp: 25.0,
+                heating: s < 30,
+            })
+            .collect::<Vec<_>>();
+        assert!(LumpedThermalModel::fit(25.0, 40.0, &flat).is_none());
+    }
+
+    /// Temperatures every second for `secs` seconds
+    fn trace(model: &ControlledThermalModel, initial: f32, target: f32, secs: u64) -> Vec<f32> {
+        (0..=secs)
+            .map(|s| model.temperature(initial, Some(target), Duration::from_secs(s)))
+            .collect()
+    }
+
+    #[test]
+    fn pid_overshoots_then_settles() {
+        let model = ControlledThermalModel::hotend();
+        let temps = trace(&model, 25.0, 200.0, 600);
+        let peak = temps.iter().copied().fold(f32::MIN, f32::max);
+        assert!(peak > 200.5 && peak < 215.0, "peak {}", peak);
+        assert!((temps[600] - 200.0).abs() < 0.5, "end {}", temps[600]);
+
+        let settle = model.settle_time(25.0, Some(200.0));
+        assert!(settle > model.residency && settle < Duration::from_secs(600));
+    }
+
+    #[test]
+    fn pid_holds_a_reached_target() {
+        let model = ControlledThermalModel::hotend();
+        for temp in trace(&model, 200.0, 200.0, 120) {
+            assert!((temp - 200.0).abs() < 0.5, "{}", temp);
+        }
+        assert_eq!(model.settle_time(200.0, Some(200.0)), model.residency);
+    }
+
+    #[test]
+    fn bang_bang_cycles_around_the_target() {
+        let model = ControlledThermalModel::bed();
+        let temps = trace(&model, 25.0, 60.0, 3600);
+        let reached = temps.iter().position(|&temp| temp >= 60.0).unwrap();
+        let after = &temps[reached..];
+        let (low, high) = after
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &t| (lo.min(t), hi.max(t)));
+        assert!(low < 60.0 && low > 57.0, "low {}", low);
+        assert!(high > 60.0 && high < 63.0, "high {}", high);
+    }
+
+    #[test]
+    fn controlled_heaters_cool_down() {
+        let model = ControlledThermalModel::hotend();
+        let settle = model.settle_time(200.0, Some(150.0));
+        // the loop cannot cool faster than the heater switched off
+        let passive = model.plant.settle_time(200.0, Some(150.0));
+        assert!(settle >= passive && settle < passive + Duration::from_secs(60));
+
+        let temps = trace(&model, 200.0, 150.0, 600);
+        assert!(temps.windows(2).take(10).all(|pair| pair[1] < pair[0]));
+        assert!((temps[600] - 150.0).abs() < 0.5);
+        assert!(model.energy(200.0, None, Duration::from_secs(60)) == 0.0);
+    }
+
+    #[test]
+    fn unreachable_targets_still_settle() {
+        let model = ControlledThermalModel::hotend();
+        let settle = model.settle_time(25.0, Some(1000.0));
+        assert!(settle < model.max_settle);
+    }
+}
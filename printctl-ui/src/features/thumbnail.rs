@@ -0,0 +1,370 @@
+use base64::Engine;
+
+use crate::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbnailFormat {
+    Png,
+    Qoi,
+    Jpg,
+}
+
+impl ThumbnailFormat {
+    /// Maps the block tag (`thumbnail`, `thumbnail_QOI`, ...) to its image format
+    pub(crate) fn from_tag(tag: &str) -> Option<Self> {
+        match tag {
+            "thumbnail" | "thumbnail_PNG" => Some(Self::Png),
+            "thumbnail_QOI" => Some(Self::Qoi),
+            "thumbnail_JPG" => Some(Self::Jpg),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn tag(&self) -> &'static str {
+        match self {
+            Self::Png => "thumbnail",
+            Self::Qoi => "thumbnail_QOI",
+            Self::Jpg => "thumbnail_JPG",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Qoi => "image/qoi",
+            Self::Jpg => "image/jpeg",
+        }
+    }
+}
+
+/// Preview image embedded by the slicer in `; thumbnail begin ... ; thumbnail end` blocks
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub width: u32,
+    pub height: u32,
+    pub format: ThumbnailFormat,
+    pub data: Vec<u8>,
+}
+
+/// Decoded RGBA pixels, row by row
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Image {
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+impl Thumbnail {
+    pub fn extract(src: &str) -> Vec<Thumbnail> {
+        let mut thumbnails = Vec::new();
+        let mut block: Option<(ThumbnailFormat, u32, u32, String)> = None;
+
+        for line in src.lines() {
+            // blocks are made of comment lines only
+            let Some(comment) = line.trim().strip_prefix(';') else {
+                block = None;
+                continue;
+            };
+
+            let mut words = comment.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(tag), Some("begin")) => {
+                    // `; thumbnail begin 16x16 1234`
+                    let size = words.next().and_then(|size| size.split_once('x'));
+                    block = ThumbnailFormat::from_tag(tag).zip(size).and_then(
+                        |(format, (width, height))| {
+                            Some((
+                                format,
+                                width.parse().ok()?,
+                                height.parse().ok()?,
+                                String::new(),
+                            ))
+                        },
+                    );
+                }
+                (Some(tag), Some("end")) if ThumbnailFormat::from_tag(tag).is_some() => {
+                    let Some((format, width, height, encoded)) = block.take() else {
+                        continue;
+                    };
+                    let engine = base64::engine::general_purpose::STANDARD;
+                    if let Ok(data) = engine.decode(encoded) {
+                        thumbnails.push(Thumbnail {
+                            width,
+                            height,
+                            format,
+                            data,
+                        });
+                    }
+                }
+                (Some(chunk), None) => {
+                    if let Some((.., encoded)) = block.as_mut() {
+                        encoded.push_str(chunk);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        thumbnails
+    }
+
+    pub fn area(&self) -> u32 {
+        self.width * self.height
+    }
+
+    pub fn decode(&self) -> Result<Image> {
+        match self.format {
+            ThumbnailFormat::Png => Self::decode_png(&self.data),
+            ThumbnailFormat::Qoi => Self::decode_qoi(&self.data),
+            ThumbnailFormat::Jpg => Err(Error::Thumbnail("JPEG is not supported".into())),
+        }
+    }
+
+    fn decode_png(data: &[u8]) -> Result<Image> {
+        let to_error = |e: png::DecodingError| Error::Thumbnail(e.to_string());
+
+        let mut decoder = png::Decoder::new(data);
+        // normalize palettes and 16-bit channels to 8-bit gray/rgb(a)
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(to_error)?;
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(to_error)?;
+        let bytes = &buf[..info.buffer_size()];
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => bytes
+                .chunks_exact(4)
+                .map(|p| [p[0], p[1], p[2], p[3]])
+                .collect(),
+            png::ColorType::Rgb => bytes
+                .chunks_exact(3)
+                .map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => bytes
+                .chunks_exact(2)
+                .map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => bytes.iter().map(|g| [*g, *g, *g, 255]).collect(),
+            png::ColorType::Indexed => {
+                return Err(Error::Thumbnail("unexpanded palette".into()));
+            }
+        };
+
+        Ok(Image {
+            width: info.width,
+            height: info.height,
+            pixels,
+        })
+    }
+
+    fn decode_qoi(data: &[u8]) -> Result<Image> {
+        let (header, bytes) =
+            qoi::decode_to_vec(data).map_err(|e| Error::Thumbnail(e.to_string()))?;
+
+        let pixels = match header.channels {
+            qoi::Channels::Rgba => bytes
+                .chunks_exact(4)
+                .map(|p| [p[0], p[1], p[2], p[3]])
+                .collect(),
+            qoi::Channels::Rgb => bytes
+                .chunks_exact(3)
+                .map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+        };
+
+        Ok(Image {
+            width: header.width,
+            height: header.height,
+            pixels,
+        })
+    }
+}
+
+/// Smallest and largest thumbnail embedded in a file
+#[derive(Debug, Default, Clone)]
+pub struct Thumbnails {
+    pub small: Option<Thumbnail>,
+    pub large: Option<Thumbnail>,
+}
+
+impl Thumbnails {
+    pub fn extract(src: &str) -> Self {
+        let thumbnails = Thumbnail::extract(src);
+
+        Self {
+            small: thumbnails.iter().min_by_key(|t| t.area()).cloned(),
+            large: thumbnails.iter().max_by_key(|t| t.area()).cloned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXELS: [[u8; 4]; 4] = [
+        [255, 0, 0, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 128],
+        [255, 255, 255, 0],
+    ];
+
+    fn png_fixture(color: png::ColorType, bytes: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 2, 2);
+        encoder.set_color(color);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(bytes).unwrap();
+        writer.finish().unwrap();
+        data
+    }
+
+    fn qoi_fixture() -> Vec<u8> {
+        qoi::encode_to_vec(PIXELS.concat(), 2, 2).unwrap()
+    }
+
+    /// Slicer-style comment block, the base64 wrapped every `wrap` characters
+    fn block(tag: &str, size: &str, data: &[u8], wrap: usize) -> String {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(data);
+        let mut block = format!("; {} begin {} {}\n", tag, size, encoded.len());
+        for chunk in encoded.as_bytes().chunks(wrap) {
+            block += &format!("; {}\n", std::str::from_utf8(chunk).unwrap());
+        }
+        block + &format!("; {} end\n;\n", tag)
+    }
+
+    #[test]
+    fn extracts_wrapped_blocks_of_each_format() {
+        let png = png_fixture(png::ColorType::Rgba, &PIXELS.concat());
+        let qoi = qoi_fixture();
+        let jpg = b"\xff\xd8\xff\xe0 not really a jpeg".to_vec();
+        let src = [
+            "; generated by PrusaSlicer\n".to_string(),
+            block("thumbnail", "2x2", &png, 16),
+            block("thumbnail_QOI", "2x2", &qoi, 8),
+            "G28\n".to_string(),
+            block("thumbnail_JPG", "300x200", &jpg, 78),
+            "G1 X10 Y10\n".to_string(),
+        ]
+        .concat();
+
+        let thumbnails = Thumbnail::extract(&src);
+        assert_eq!(thumbnails.len(), 3);
+        let formats: Vec<_> = thumbnails.iter().map(|t| t.format).collect();
+        assert_eq!(
+            formats,
+            [
+                ThumbnailFormat::Png,
+                ThumbnailFormat::Qoi,
+                ThumbnailFormat::Jpg
+            ]
+        );
+        assert_eq!(thumbnails[0].data, png);
+        assert_eq!(thumbnails[1].data, qoi);
+        assert_eq!(thumbnails[2].data, jpg);
+        assert_eq!((thumbnails[2].width, thumbnails[2].height), (300, 200));
+        assert_eq!(thumbnails[2].format.mime_type(), "image/jpeg");
+    }
+
+    #[test]
+    fn skips_malformed_blocks() {
+        let png = png_fixture(png::ColorType::Rgba, &PIXELS.concat());
+        for size in ["16", "16x", "x16", "axb", ""] {
+            let src = block("thumbnail", size, &png, 16);
+            assert!(Thumbnail::extract(&src).is_empty(), "{:?}", size);
+        }
+
+        let unknown = block("thumbnail_BMP", "2x2", &png, 16);
+        assert!(Thumbnail::extract(&unknown).is_empty());
+
+        let not_base64 = "; thumbnail begin 2x2 8\n; !!not*base64\n; thumbnail end\n";
+        assert!(Thumbnail::extract(not_base64).is_empty());
+    }
+
+    #[test]
+    fn blocks_end_at_non_comment_lines() {
+        let png = png_fixture(png::ColorType::Rgba, &PIXELS.concat());
+        let interrupted = block("thumbnail", "2x2", &png, 16);
+        let mut lines: Vec<&str> = interrupted.lines().collect();
+        lines.insert(2, "G1 X1");
+        let src = lines.join("\n") + "\n" + &block("thumbnail_QOI", "2x2", &qoi_fixture(), 16);
+
+        let thumbnails = Thumbnail::extract(&src);
+        assert_eq!(thumbnails.len(), 1);
+        assert_eq!(thumbnails[0].format, ThumbnailFormat::Qoi);
+    }
+
+    #[test]
+    fn decodes_png_and_qoi() {
+        let rgba = Thumbnail {
+            width: 2,
+            height: 2,
+            format: ThumbnailFormat::Png,
+            data: png_fixture(png::ColorType::Rgba, &PIXELS.concat()),
+        };
+        let image = rgba.decode().unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels, PIXELS);
+        assert_eq!(image.pixel(0, 1), [0, 0, 255, 128]);
+
+        // channels missing from the file come out opaque or gray
+        let rgb: Vec<u8> = PIXELS.iter().flat_map(|p| [p[0], p[1], p[2]]).collect();
+        let rgb = Thumbnail {
+            data: png_fixture(png::ColorType::Rgb, &rgb),
+            ..rgba.clone()
+        };
+        assert_eq!(rgb.decode().unwrap().pixel(1, 1), [255, 255, 255, 255]);
+        let gray = Thumbnail {
+            data: png_fixture(png::ColorType::Grayscale, &[0, 64, 128, 255]),
+            ..rgba.clone()
+        };
+        assert_eq!(gray.decode().unwrap().pixel(1, 0), [64, 64, 64, 255]);
+
+        let qoi = Thumbnail {
+            format: ThumbnailFormat::Qoi,
+            data: qoi_fixture(),
+            ..rgba.clone()
+        };
+        assert_eq!(qoi.decode().unwrap().pixels, PIXELS);
+
+        let jpg = Thumbnail {
+            format: ThumbnailFormat::Jpg,
+            ..rgba.clone()
+        };
+        assert!(matches!(jpg.decode(), Err(Error::Thumbnail(_))));
+        let truncated = Thumbnail {
+            data: rgba.data[..20].to_vec(),
+            ..rgba
+        };
+        assert!(matches!(truncated.decode(), Err(Error::Thumbnail(_))));
+    }
+
+    #[test]
+    fn keeps_smallest_and_largest() {
+        let png = png_fixture(png::ColorType::Rgba, &PIXELS.concat());
+        let src = [
+            block("thumbnail", "32x32", &png, 64),
+            block("thumbnail", "16x16", &png, 64),
+            block("thumbnail_QOI", "300x300", &qoi_fixture(), 64),
+            block("thumbnail", "220x124", &png, 64),
+        ]
+        .concat();
+
+        let thumbnails = Thumbnails::extract(&src);
+        let small = thumbnails.small.unwrap();
+        let large = thumbnails.large.unwrap();
+        assert_eq!((small.width, small.format), (16, ThumbnailFormat::Png));
+        assert_eq!((large.width, large.format), (300, ThumbnailFormat::Qoi));
+
+        let none = Thumbnails::extract("G28\nG1 X10\n");
+        assert!(none.small.is_none() && none.large.is_none());
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::Duration;
+
+use super::metric::{Distance, Position};
+use super::simulator::SnapshotEntry;
+use super::snapshot::Transition;
+use super::thermal::{ThermalModel, ThermalSnapshot};
+
+/// Machine state at one instant of a simulated program
+#[derive(Debug, Clone)]
+pub struct TimelineSample {
+    pub time: Duration,
+    /// Zero-based source line of the command executing at `time`
+    pub line: usize,
+    pub position: Position,
+    pub thermal: ThermalSnapshot,
+    pub active_tool: usize,
+    /// Extruder position of the active tool
+    pub extrusion: Distance,
+}
+
+// samples kept before they are thinned out
+const MAX_SAMPLES: usize = 1 << 16;
+
+/// Simulated program indexed by time, for scrubbing and comparing a running
+/// job against its plan. Keeps the machine state at the end of commands, at
+/// most [`MAX_SAMPLES`] of them spread over the print, so memory does not
+/// grow with the program; states in between are interpolated.
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    samples: Vec<TimelineSample>,
+    // least simulated time between kept samples
+    resolution: Duration,
+}
+
+impl Timeline {
+    /// Adds the snapshot of a command on source line `line`, in program order
+    pub fn push<B, T>(&mut self, line: usize, entry: &SnapshotEntry<B, T>)
+    where
+        B: ThermalModel,
+        T: ThermalModel,
+    {
+        let snapshot = entry.snapshot();
+        if self.samples.is_empty() {
+            let (_, thermal) = snapshot.interpolate(0.0);
+            self.samples.push(TimelineSample {
+                time: entry.start_time(),
+                line,
+                position: snapshot.before().position(),
+                thermal,
+                active_tool: snapshot.before().active_tool(),
+                extrusion: *snapshot.before().current_tool().extrusion(),
+            });
+        }
+
+        let (_, thermal) = snapshot.interpolate(1.0);
+        let sample = TimelineSample {
+            time: entry.end_time(),
+            line,
+            position: snapshot.after().position(),
+            thermal,
+            active_tool: snapshot.after().active_tool(),
+            extrusion: *snapshot.after().current_tool().extrusion(),
+        };
+
+        // the last sample stands in for everything since the one before it
+        // until the resolution is reached
+        let len = self.samples.len();
+        match self.samples.get(len.wrapping_sub(2)) {
+            Some(kept) if sample.time - kept.time < self.resolution => {
+                self.samples[len - 1] = sample
+            }
+            _ => self.samples.push(sample),
+        }
+
+        if self.samples.len() > MAX_SAMPLES {
+            self.thin();
+        }
+    }
+
+    /// Drops every other sample, keeping the first and last
+    fn thin(&mut self) {
+        let last = self.samples.len() - 1;
+        let mut i = 0;
+        self.samples.retain(|_| {
+            i += 1;
+            (i - 1) % 2 == 0 || i - 1 == last
+        });
+        self.resolution = (self.resolution * 2).max(self.duration() / (MAX_SAMPLES as u32 / 2));
+    }
+
+    pub fn samples(&self) -> &[TimelineSample] {
+        &self.samples
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.samples
+            .last()
+            .map(|sample| sample.time)
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Index of the first sample after `time`, the end of the command
+    /// running then; past the end it is the last one. The first sample is
+    /// where the program starts, so this is never it.
+    fn index_at(&self, time: Duration) -> Option<usize> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let index = self.samples.partition_point(|sample| sample.time <= time);
+        Some(index.min(self.samples.len() - 1))
+    }
+
+    /// Source line executing at `time`
+    pub fn line_at(&self, time: Duration) -> Option<usize> {
+        self.index_at(time).map(|i| self.samples[i].line)
+    }
+
+    /// When source line `line` starts executing, or the next line that does
+    /// anything for lines without commands
+    pub fn time_at_line(&self, line: usize) -> Option<Duration> {
+        let index =
+            self.samples[1.min(self.samples.len())..].partition_point(|sample| sample.line < line);
+        // the command ending at sample `index + 1` starts at sample `index`
+        self.samples
+            .get(index + 1)
+            .map(|_| self.samples[index].time)
+    }
+
+    pub fn sample(&self, time: Duration) -> Option<TimelineSample> {
+        let index = self.index_at(time)?;
+        let after = &self.samples[index];
+        let before = &self.samples[index.checked_sub(1)?];
+
+        let time = time.min(after.time);
+        let span = after.time - before.time;
+        let tau = if span.is_zero() {
+            1.0
+        } else {
+            (time.saturating_sub(before.time)).as_secs_f32() / span.as_secs_f32()
+        };
+
+        let extrusion =
+            before.extrusion.as_mm() + (after.extrusion.as_mm() - before.extrusion.as_mm()) * tau;
+        Some(TimelineSample {
+            time,
+            line: after.line,
+            position: before.position + (after.position - before.position) * tau,
+            thermal: before.thermal.lerp(&after.thermal, tau),
+            active_tool: after.active_tool,
+            extrusion: Distance::from_mm(extrusion),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::features::profile::MachineProfile;
+    use crate::features::program::GCodeProgram;
+    use crate::features::simulator::GCodeSimulator;
+
+    fn timeline(src: &str) -> Timeline {
+        let profile = MachineProfile::default();
+        let program = GCodeProgram::new(src);
+        let simulator = GCodeSimulator::new(profile.clone());
+        let mut timeline = Timeline::default();
+        simulator.replay(&program, profile.snapshot_builder(), |line, entry| {
+            timeline.push(line, &entry)
+        });
+        timeline
+    }
+
+    #[test]
+    fn maps_time_and_lines_both_ways() {
+        let timeline = timeline("G90\nG1 X0 Y0 F600\n; move\nG1 X100 E10\nG1 X0\n");
+        let second_move = timeline.time_at_line(4).unwrap();
+        assert!(second_move > Duration::ZERO);
+        assert_eq!(timeline.time_at_line(2), timeline.time_at_line(3));
+        assert_eq!(timeline.line_at(second_move), Some(4));
+        assert_eq!(timeline.line_at(second_move / 2), Some(3));
+        assert_eq!(timeline.line_at(timeline.duration() * 2), Some(4));
+        assert!(timeline.time_at_line(5).is_none());
+    }
+
+    #[test]
+    fn samples_interpolate_the_move() {
+        let timeline = timeline("G90\nG1 X0 Y0 F600\nG1 X100 E10\n");
+        let (start, end) = (timeline.time_at_line(2).unwrap(), timeline.duration());
+
+        let first = timeline.sample(start).unwrap();
+        assert_eq!(first.line, 2);
+        assert!(first.position.x().as_mm().abs() < 1e-3);
+
+        // the trapezoid is symmetric, so halfway in time is halfway along
+        let middle = timeline.sample(start + (end - start) / 2).unwrap();
+        assert!((middle.position.x().as_mm() - 50.0).abs() < 0.5);
+        assert!((middle.extrusion.as_mm() - 5.0).abs() < 0.05);
+
+        let last = timeline.sample(end * 2).unwrap();
+        assert_eq!(last.time, end);
+        assert!((last.position.x().as_mm() - 100.0).abs() < 1e-3);
+        assert!((last.extrusion.as_mm() - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn empty_programs_have_no_samples() {
+        let timeline = timeline("; nothing\n");
+        assert_eq!(timeline.duration(), Duration::ZERO);
+        assert!(timeline.sample(Duration::ZERO).is_none());
+        assert!(timeline.line_at(Duration::ZERO).is_none());
+    }
+}
@@ -0,0 +1,446 @@
+use std::fmt;
+
+use gcode::{GCode, Mnemonic, Word};
+
+use crate::prelude::*;
+
+use super::code::GCodeLine;
+use super::format::{parse_lines, Formatter};
+use super::layer::LayerIndex;
+use super::profile::MachineProfile;
+use super::program::cmds;
+use super::statistics::ProgramStatistics;
+
+/// One rewrite of a program, in the one-line-per-source-line shape of
+/// [`parse_lines`]
+pub trait Transform: fmt::Debug {
+    fn apply(&self, lines: Vec<GCodeLine>) -> Vec<GCodeLine>;
+
+    /// What the re-simulated result should show but does not
+    fn check(&self, _before: &ProgramStatistics, _after: &ProgramStatistics) -> Option<String> {
+        None
+    }
+}
+
+/// `gcode` with every argument value passed through `f`
+fn map_arguments(gcode: &GCode, mut f: impl FnMut(char, f32) -> f32) -> GCode {
+    let number = gcode.major_number() as f32 + gcode.minor_number() as f32 / 10.0;
+    let mut mapped = GCode::new(gcode.mnemonic(), number, gcode.span());
+    for arg in gcode.arguments() {
+        let value = f(arg.letter.to_ascii_uppercase(), arg.value);
+        // same number of arguments, so always fits
+        let _ = mapped.push_argument(Word::new(arg.letter, value, arg.span));
+    }
+    mapped
+}
+
+fn map_gcodes(lines: Vec<GCodeLine>, mut f: impl FnMut(&GCode) -> GCode) -> Vec<GCodeLine> {
+    lines
+        .into_iter()
+        .map(|line| match line {
+            GCodeLine::Command { gcodes, comments } => GCodeLine::Command {
+                gcodes: gcodes.iter().map(&mut f).collect(),
+                comments,
+            },
+            GCodeLine::Empty => GCodeLine::Empty,
+        })
+        .collect()
+}
+
+fn is_move(gcode: &GCode) -> bool {
+    gcode.mnemonic() == Mnemonic::General
+        && matches!(
+            gcode.major_number(),
+            cmds::gcode::TRAVEL_MOVE
+                | cmds::gcode::PRINT_MOVE
+                | cmds::gcode::PRINT_ARC_CW
+                | cmds::gcode::PRINT_ARC_CCW
+        )
+}
+
+/// Shifts the print across the bed by offsetting absolute X/Y coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct Offset {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Transform for Offset {
+    fn apply(&self, lines: Vec<GCodeLine>) -> Vec<GCodeLine> {
+        let mut relative = false;
+        map_gcodes(lines, |gcode| {
+            let general = gcode.mnemonic() == Mnemonic::General;
+            match gcode.major_number() {
+                cmds::gcode::ABSOLUTE_POSITIONING if general => relative = false,
+                cmds::gcode::RELATIVE_POSITIONING if general => relative = true,
+                _ => {}
+            }
+
+            // `G92` names absolute positions whatever the mode
+            let set_position = general && gcode.major_number() == cmds::gcode::SET_POSITION;
+            if !(set_position || is_move(gcode) && !relative) {
+                return gcode.clone();
+            }
+            map_arguments(gcode, |letter, value| match letter {
+                'X' => value + self.x,
+                'Y' => value + self.y,
+                _ => value,
+            })
+        })
+    }
+}
+
+/// Multiplies the feedrate of every move
+#[derive(Debug, Clone, Copy)]
+pub struct ScaleFeedrate(pub f32);
+
+impl Transform for ScaleFeedrate {
+    fn apply(&self, lines: Vec<GCodeLine>) -> Vec<GCodeLine> {
+        map_gcodes(lines, |gcode| {
+            if !is_move(gcode) {
+                return gcode.clone();
+            }
+            map_arguments(gcode, |letter, value| match letter {
+                'F' => value * self.0,
+                _ => value,
+            })
+        })
+    }
+
+    fn check(&self, before: &ProgramStatistics, after: &ProgramStatistics) -> Option<String> {
+        let (before, after) = (before.xy_motion.print.time, after.xy_motion.print.time);
+        let slower = after > before;
+        (self.0 > 1.0 && slower || self.0 < 1.0 && after < before)
+            .then(|| format!("print time went from {:?} to {:?}", before, after))
+    }
+}
+
+/// Replaces every non-zero temperature set for the hotends or the bed
+#[derive(Debug, Clone, Copy)]
+pub struct OverrideTemperature {
+    pub bed: bool,
+    pub temp: f32,
+}
+
+impl Transform for OverrideTemperature {
+    fn apply(&self, lines: Vec<GCodeLine>) -> Vec<GCodeLine> {
+        let codes = if self.bed {
+            [cmds::mcode::SET_BED_TEMP, cmds::mcode::WAIT_FOR_BED_TEMP]
+        } else {
+            [
+                cmds::mcode::SET_HOTEND_TEMP,
+                cmds::mcode::WAIT_FOR_HOTEND_TEMP,
+            ]
+        };
+
+        map_gcodes(lines, |gcode| {
+            if gcode.mnemonic() != Mnemonic::Miscellaneous || !codes.contains(&gcode.major_number())
+            {
+                return gcode.clone();
+            }
+            // turning the heater off stays as it is
+            map_arguments(gcode, |letter, value| match letter {
+                'S' | 'R' if value > 0.0 => self.temp,
+                _ => value,
+            })
+        })
+    }
+}
+
+/// Inserts G-code right before a layer starts
+#[derive(Debug, Clone)]
+pub struct InsertAtLayer {
+    /// One-based, like slicer previews
+    pub layer: usize,
+    pub gcode: String,
+}
+
+impl InsertAtLayer {
+    /// Filament change (`M600`) for multi-color prints
+    pub fn color_change(layer: usize) -> Self {
+        Self {
+            layer,
+            gcode: "M600".to_string(),
+        }
+    }
+
+    /// Unconditional stop (`M0`) until resumed on the printer
+    pub fn pause(layer: usize) -> Self {
+        Self {
+            layer,
+            gcode: "M0".to_string(),
+        }
+    }
+}
+
+impl Transform for InsertAtLayer {
+    fn apply(&self, mut lines: Vec<GCodeLine>) -> Vec<GCodeLine> {
+        let layers = LayerIndex::new(&lines);
+        let Some(layer) = self
+            .layer
+            .checked_sub(1)
+            .and_then(|i| layers.layers().get(i))
+        else {
+            return lines;
+        };
+
+        let start = layer.lines.start.min(lines.len());
+        lines.splice(start..start, parse_lines(&self.gcode));
+        lines
+    }
+
+    fn check(&self, _before: &ProgramStatistics, after: &ProgramStatistics) -> Option<String> {
+        (self.layer == 0 || self.layer > after.layers.len()).then(|| {
+            format!(
+                "layer {} does not exist, the program has {}",
+                self.layer,
+                after.layers.len()
+            )
+        })
+    }
+}
+
+/// Parses a transform given as `name=value`:
+/// `offset=X,Y`, `feedrate=FACTOR`, `hotend=TEMP`, `bed=TEMP`,
+/// `color-change=LAYER`, `pause=LAYER` or `insert=LAYER:GCODE`.
+/// Feedrate factors must be positive and temperatures at least zero.
+pub fn parse_transform(spec: &str) -> Result<Box<dyn Transform>> {
+    let invalid = || Error::Transform(spec.to_string());
+    let (name, value) = spec.split_once('=').ok_or_else(invalid)?;
+    let number = |value: &str| {
+        value
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(invalid)
+    };
+    // a factor of zero would stop the machine, there are no negative kelvins
+    let factor =
+        |value: &str| number(value).and_then(|f| (f > 0.0).then_some(f).ok_or_else(invalid));
+    let temp =
+        |value: &str| number(value).and_then(|t| (t >= 0.0).then_some(t).ok_or_else(invalid));
+    let layer = |value: &str| value.trim().parse::<usize>().map_err(|_| invalid());
+
+    Ok(match name.trim() {
+        "offset" => {
+            let (x, y) = value.split_once(',').ok_or_else(invalid)?;
+            Box::new(Offset {
+                x: number(x)?,
+                y: number(y)?,
+            })
+        }
+        "feedrate" => Box::new(ScaleFeedrate(factor(value)?)),
+        "hotend" => Box::new(OverrideTemperature {
+            bed: false,
+            temp: temp(value)?,
+        }),
+        "bed" => Box::new(OverrideTemperature {
+            bed: true,
+            temp: temp(value)?,
+        }),
+        "color-change" => Box::new(InsertAtLayer::color_change(layer(value)?)),
+        "pause" => Box::new(InsertAtLayer::pause(layer(value)?)),
+        "insert" => {
+            let (at, gcode) = value.split_once(':').ok_or_else(invalid)?;
+            Box::new(InsertAtLayer {
+                layer: layer(at)?,
+                gcode: gcode.replace("\\n", "\n"),
+            })
+        }
+        _ => return Err(invalid()),
+    })
+}
+
+/// Statistics of a program before and after transforming it
+#[derive(Debug)]
+pub struct Verification {
+    pub before: ProgramStatistics,
+    pub after: ProgramStatistics,
+    pub issues: Vec<String>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Transforms applied in order
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn then(mut self, transform: impl Transform + 'static) -> Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    pub fn push(&mut self, transform: Box<dyn Transform>) {
+        self.transforms.push(transform);
+    }
+
+    pub fn apply(&self, src: &str) -> String {
+        let lines = self
+            .transforms
+            .iter()
+            .fold(parse_lines(src), |lines, transform| transform.apply(lines));
+
+        Formatter::default().format_lines(&lines)
+    }
+
+    /// Simulates both programs; none of the transforms may change what gets
+    /// printed, only where, how fast or how hot
+    pub fn verify(&self, src: &str, transformed: &str, profile: &MachineProfile) -> Verification {
+        let before = ProgramStatistics::from_source(src, profile);
+        let after = ProgramStatistics::from_source(transformed, profile);
+
+        let mut issues = Vec::new();
+        let close = |a: f64, b: f64| (a - b).abs() <= 1e-3 * a.abs().max(1.0);
+        if !close(before.extrusion.net_mm(), after.extrusion.net_mm()) {
+            issues.push(format!(
+                "extrusion went from {:.1} mm to {:.1} mm",
+                before.extrusion.net_mm(),
+                after.extrusion.net_mm()
+            ));
+        }
+        if !close(
+            before.xy_motion.print.distance_mm,
+            after.xy_motion.print.distance_mm,
+        ) || before.print_moves != after.print_moves
+        {
+            issues.push(format!(
+                "printed path went from {} moves over {:.1} mm to {} over {:.1} mm",
+                before.print_moves,
+                before.xy_motion.print.distance_mm,
+                after.print_moves,
+                after.xy_motion.print.distance_mm
+            ));
+        }
+        if before.layers.len() != after.layers.len() {
+            issues.push(format!(
+                "layers went from {} to {}",
+                before.layers.len(),
+                after.layers.len()
+            ));
+        }
+        issues.extend(
+            self.transforms
+                .iter()
+                .filter_map(|transform| transform.check(&before, &after)),
+        );
+
+        Verification {
+            before,
+            after,
+            issues,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(transform: impl Transform + 'static, src: &str) -> Vec<String> {
+        let out = Pipeline::new().then(transform).apply(src);
+        out.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn offset_shifts_absolute_moves_and_g92() {
+        let src = "G90\nG1 X10 Y10 F1200\nG91\nG1 X1 Y1\nG90\nG92 X0 Y5 E0\nG2 X10 Y5 I2.5 J0\n";
+        assert_eq!(
+            apply(Offset { x: 5.0, y: -2.0 }, src),
+            [
+                "G90",
+                "G1 X15 Y8 F1200",
+                "G91",
+                "G1 X1 Y1",
+                "G90",
+                "G92 X5 Y3 E0",
+                "G2 X15 Y3 I2.5 J0",
+            ]
+        );
+    }
+
+    #[test]
+    fn scale_feedrate_only_touches_moves() {
+        let src = "G1 X10 F1200\nM106 S255\nG0 X0 F6000\n";
+        assert_eq!(
+            apply(ScaleFeedrate(0.5), src),
+            ["G1 X10 F600", "M106 S255", "G0 X0 F3000"]
+        );
+    }
+
+    #[test]
+    fn override_temperature_keeps_heaters_off() {
+        let src = "M104 S200\nM109 S200\nM140 S60\nM104 S0\n";
+        let hotend = OverrideTemperature {
+            bed: false,
+            temp: 215.0,
+        };
+        assert_eq!(
+            apply(hotend, src),
+            ["M104 S215", "M109 S215", "M140 S60", "M104 S0"]
+        );
+        let bed = OverrideTemperature {
+            bed: true,
+            temp: 70.0,
+        };
+        assert_eq!(
+            apply(bed, src),
+            ["M104 S200", "M109 S200", "M140 S70", "M104 S0"]
+        );
+    }
+
+    #[test]
+    fn insert_goes_before_the_layer() {
+        let src = ";LAYER:0\nG1 X10 E1\n;LAYER:1\nG1 X20 E2\n";
+        assert_eq!(
+            apply(InsertAtLayer::color_change(2), src),
+            [";LAYER:0", "G1 X10 E1", "M600", ";LAYER:1", "G1 X20 E2"]
+        );
+        // missing layers leave the program alone and fail verification
+        let pipeline = Pipeline::new().then(InsertAtLayer::pause(3));
+        let out = pipeline.apply(src);
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            src.lines().collect::<Vec<_>>()
+        );
+        assert!(!pipeline
+            .verify(src, &out, &MachineProfile::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for spec in [
+            "feedrate=0",
+            "feedrate=-1",
+            "feedrate=inf",
+            "hotend=-5",
+            "bed=-1",
+            "bed=NaN",
+            "offset=1",
+            "pause=x",
+        ] {
+            assert!(parse_transform(spec).is_err(), "{}", spec);
+        }
+        for spec in [
+            "feedrate=1.5",
+            "hotend=0",
+            "bed=60",
+            "offset=-2,3",
+            "insert=2:M117 hi",
+        ] {
+            assert!(parse_transform(spec).is_ok(), "{}", spec);
+        }
+    }
+}
@@ -1,6 +1,8 @@
 mod error;
 mod prelude;
 
+pub use error::Error;
+
 pub mod features;
 pub mod tui;
 pub mod web;
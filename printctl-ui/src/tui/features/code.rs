@@ -3,28 +3,10 @@ use gcode::GCode;
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text;
 
-use crate::features::code::{ArgRange, GCodeLine};
+use crate::features::code::GCodeLine;
 
 use super::style::{arg_style, comment_style, gutter_style, opcode_style, value_style};
 
-impl From<gcode::Line<'_>> for GCodeLine {
-    fn from(line: gcode::Line<'_>) -> Self {
-        let gcodes: Box<[GCode]> = line.gcodes().into();
-        let comments: Box<[String]> = line
-            .comments()
-            .iter()
-            .map(|c| c.value.to_string())
-            .collect::<Vec<_>>()
-            .into_boxed_slice();
-
-        if gcodes.is_empty() && comments.is_empty() {
-            GCodeLine::Empty
-        } else {
-            GCodeLine::Command { gcodes, comments }
-        }
-    }
-}
-
 fn gcode_spans<'a>(line: &'a [GCode], is_selected: bool) -> Vec<text::Span<'a>> {
     let mut spans = Vec::new();
 
@@ -92,17 +74,15 @@ impl GCodeLine {
     }
 }
 
-impl ArgRange {
-    pub fn to_spans<'a>(&'a self) -> Vec<text::Span<'a>> {
-        let arg = self.argument();
-        vec![
-            text::Span::raw(" ".repeat(8)),
-            text::Span::styled(
-                format!("{} = {}", arg.letter, arg.value),
-                Style::default()
-                    .fg(Color::Cyan)
-                    .add_modifier(Modifier::BOLD),
-            ),
-        ]
-    }
+/// A modal word in effect, for the panel above the program
+pub fn word_spans<'a>(word: &gcode::Word) -> Vec<text::Span<'a>> {
+    vec![
+        text::Span::raw(" ".repeat(8)),
+        text::Span::styled(
+            format!("{} = {}", word.letter, word.value),
+            Style::default()
+                .fg(Color::Cyan)
+                .add_modifier(Modifier::BOLD),
+        ),
+    ]
 }
@@ -1,32 +1,77 @@
 use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
 use ratatui::widgets::ScrollbarState;
 
+use crate::features::profile::MachineProfile;
 use crate::features::program::GCodeProgram;
+use crate::features::progress::{JobProgress, ProgressPlan};
 use crate::features::simulator::GCodeSimulator;
+use crate::features::source::GCodeSource;
+use crate::features::thumbnail::{Image, Thumbnails};
+use crate::features::timeline::{Timeline, TimelineSample};
+
+/// Simulated time skipped per scrub key press
+const SCRUB_STEP: Duration = Duration::from_secs(10);
 
 #[derive(Debug)]
 pub struct GCodeDebugger {
     file_path: PathBuf,
     program: GCodeProgram,
     simulator: GCodeSimulator,
+    // simulated in the background, large files take a while
+    plan: Arc<OnceLock<ProgressPlan>>,
+    timeline: Arc<OnceLock<Timeline>>,
+    thumbnail: Option<Image>,
     scrollbar: ScrollbarState,
 }
 
 impl GCodeDebugger {
     pub fn new(path: &PathBuf) -> Self {
-        let src = std::fs::read_to_string(path).expect("Could not read GCode file");
+        let source = GCodeSource::open(path).expect("Could not read GCode file");
+        let program = GCodeProgram::open(Arc::new(source));
+
+        let plan = Arc::new(OnceLock::new());
+        let timeline = Arc::new(OnceLock::new());
+        let thumbnail = Thumbnails::extract(program.source().as_str())
+            .small
+            .and_then(|thumbnail| thumbnail.decode().ok());
+        {
+            // the clone shares the program's indices, so they are built once
+            let (plan, timeline, program) = (plan.clone(), timeline.clone(), program.clone());
+            std::thread::spawn(move || {
+                let profile = MachineProfile::default();
+                let simulator = GCodeSimulator::new(profile.clone());
+                let mut samples = Timeline::default();
+                let (progress, _) = ProgressPlan::simulate(
+                    &program,
+                    &simulator,
+                    profile.snapshot_builder(),
+                    |line, entry| samples.push(line, entry),
+                );
+                plan.get_or_init(|| progress);
+                timeline.get_or_init(|| samples);
+            });
+        }
 
         Self {
             file_path: path.to_owned(),
-            program: GCodeProgram::new(&src),
+            program,
             simulator: GCodeSimulator::default(),
+            plan,
+            timeline,
+            thumbnail,
             scrollbar: ScrollbarState::default(),
         }
     }
 }
 
 impl GCodeDebugger {
+    pub fn file_path(&self) -> &PathBuf {
+        &self.file_path
+    }
+
     fn file_name(&self) -> &str {
         self.file_path
             .file_name()
@@ -41,13 +86,115 @@ impl GCodeDebugger {
     fn scroll_down(&mut self) {
         self.program.advance();
     }
+
+    fn next_layer(&mut self) {
+        if let Some(line) = self.program.layers().next_start(self.program.cursor()) {
+            self.program.seek(line);
+        }
+    }
+
+    fn previous_layer(&mut self) {
+        if let Some(line) = self.program.layers().previous_start(self.program.cursor()) {
+            self.program.seek(line);
+        }
+    }
+
+    /// Source lines executed once the cursor line has run
+    fn lines_done(&self) -> usize {
+        (self.program.cursor() + 1).min(self.program.len())
+    }
+
+    /// Moves the cursor [`SCRUB_STEP`] of simulated time forward or back
+    fn scrub(&mut self, forward: bool) {
+        let (Some(plan), Some(timeline)) = (self.plan.get(), self.timeline.get()) else {
+            return;
+        };
+        let now = plan.simulated_at(self.lines_done());
+        let target = match forward {
+            true => now + SCRUB_STEP,
+            false => now.saturating_sub(SCRUB_STEP),
+        };
+        let Some(line) = timeline.line_at(target) else {
+            return;
+        };
+
+        // forward stops once the command running at `target` is done, back
+        // right before it, so long commands cannot trap the cursor
+        match forward {
+            true => self.program.seek(line),
+            false => self.program.seek(line.saturating_sub(1)),
+        };
+    }
+
+    /// Simulated machine state once the cursor line has run
+    fn cursor_sample(&self) -> Option<TimelineSample> {
+        let time = self.plan.get()?.simulated_at(self.lines_done());
+        self.timeline.get()?.sample(time)
+    }
+
+    /// Progress as if the program had been streamed up to the cursor
+    fn cursor_progress(&self) -> JobProgress {
+        let lines_done = self.lines_done();
+        match self.plan.get() {
+            Some(plan) => plan.progress(lines_done, plan.simulated_at(lines_done)),
+            None => JobProgress {
+                lines_done,
+                total_lines: self.program.len(),
+                ..Default::default()
+            },
+        }
+    }
 }
 
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
-use ratatui::widgets::{StatefulWidget, Widget};
+use ratatui::text;
+use ratatui::widgets::{Paragraph, StatefulWidget, Widget};
 
+use super::style::{arg_style, value_style};
+use crate::features::progress::format_duration;
 use crate::tui::input::{AppEvent, EventHandler};
 
+fn sample_lines<'a>(sample: &TimelineSample) -> Vec<text::Line<'a>> {
+    let position = &sample.position;
+    let temps = sample
+        .thermal
+        .tool_temps()
+        .iter()
+        .map(|temp| format!("{:.0}°C", temp))
+        .collect::<Vec<_>>()
+        .join(" ");
+    [
+        ("Time", format_duration(sample.time)),
+        (
+            "Toolhead",
+            format!(
+                "X{:.2} Y{:.2} Z{:.2} E{:.2}",
+                position.x().as_mm(),
+                position.y().as_mm(),
+                position.z().as_mm(),
+                sample.extrusion.as_mm()
+            ),
+        ),
+        (
+            "Heaters",
+            format!(
+                "T{} {} bed {:.0}°C",
+                sample.active_tool,
+                temps,
+                sample.thermal.bed_temp()
+            ),
+        ),
+    ]
+    .into_iter()
+    .map(|(label, value)| {
+        text::Line::from(vec![
+            text::Span::styled(format!("{:<9}", label), arg_style(false)),
+            text::Span::styled(value, value_style(false)),
+        ])
+    })
+    .collect()
+}
+
 impl GCodeDebugger {
     fn layout(area: Rect) -> [Rect; 2] {
         let chunks = if area.width < 90 {
@@ -68,13 +215,42 @@ impl GCodeDebugger {
     }
 }
 
+impl GCodeDebugger {
+    fn simulator_layout(&self, area: Rect) -> [Rect; 3] {
+        let thumbnail_height = self
+            .thumbnail
+            .as_ref()
+            .map(|image| image.rows_for_width(area.width).min(area.height / 2))
+            .unwrap_or(0);
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(thumbnail_height),
+                Constraint::Min(1),
+                Constraint::Length(3),
+            ])
+            .split(area);
+
+        [chunks[0], chunks[1], chunks[2]]
+    }
+}
+
 impl Widget for &GCodeDebugger {
     fn render(self, area: Rect, buf: &mut ratatui::prelude::Buffer) {
-        let total_lines = self.program.lines().len();
+        let total_lines = self.program.len();
         let [editor_area, simulator_area] = GCodeDebugger::layout(area);
         let mut scrollbar = self.scrollbar.content_length(total_lines);
 
         self.program.render(editor_area, buf, &mut scrollbar);
+        let [thumbnail_area, progress_area, sample_area] = self.simulator_layout(simulator_area);
+        if let Some(image) = &self.thumbnail {
+            image.render(thumbnail_area, buf);
+        }
+        self.cursor_progress().render(progress_area, buf);
+        if let Some(sample) = self.cursor_sample() {
+            Paragraph::new(sample_lines(&sample)).render(sample_area, buf);
+        }
     }
 }
 
@@ -85,8 +261,10 @@ impl EventHandler for GCodeDebugger {
         match key_event.code {
             KeyCode::Up => self.scroll_up(),
             KeyCode::Down => self.scroll_down(),
-            //KeyCode::Left => self.simulator_mut().stack_mut().rewind(),
-            //KeyCode::Right => self.simulator_mut().stack_mut().advance(),
+            KeyCode::PageUp => self.previous_layer(),
+            KeyCode::PageDown => self.next_layer(),
+            KeyCode::Left => self.scrub(false),
+            KeyCode::Right => self.scrub(true),
             _ => {}
         }
 
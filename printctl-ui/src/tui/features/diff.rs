@@ -0,0 +1,232 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use crate::features::bgcode;
+use crate::features::diff::{DiffLine, Hunk, ProgramDiff};
+use crate::features::profile::MachineProfile;
+use crate::features::program::GCodeProgram;
+
+/// Two programs side by side, removed commands on the left and added ones
+/// on the right
+#[derive(Debug)]
+pub struct GCodeDiffView {
+    paths: [PathBuf; 2],
+    // both programs are simulated in the background, large files take a while
+    diff: Arc<OnceLock<ProgramDiff>>,
+    scroll: usize,
+}
+
+impl GCodeDiffView {
+    pub fn new(before: &Path, after: &Path) -> Self {
+        let paths = [before.to_owned(), after.to_owned()];
+        let diff = Arc::new(OnceLock::new());
+        {
+            let (diff, paths) = (diff.clone(), paths.clone());
+            std::thread::spawn(move || {
+                let [before, after] = paths.map(|path| {
+                    let bytes = std::fs::read(path).expect("Could not read GCode file");
+                    let src = bgcode::to_ascii(&bytes).expect("Could not decode binary GCode file");
+                    GCodeProgram::new(&src)
+                });
+                diff.get_or_init(|| ProgramDiff::new(&before, &after, &MachineProfile::default()));
+            });
+        }
+
+        Self {
+            paths,
+            diff,
+            scroll: 0,
+        }
+    }
+}
+
+/// Rows a hunk takes: its header, then removed and added lines next to
+/// each other
+fn hunk_rows(hunk: &Hunk) -> usize {
+    1 + hunk.removed.len().max(hunk.added.len())
+}
+
+impl GCodeDiffView {
+    fn file_name(&self, i: usize) -> &str {
+        self.paths[i]
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+    }
+
+    /// Row each hunk starts at
+    fn hunk_starts(&self) -> Vec<usize> {
+        let Some(diff) = self.diff.get() else {
+            return Vec::new();
+        };
+        diff.hunks
+            .iter()
+            .scan(0, |row, hunk| {
+                let start = *row;
+                *row += hunk_rows(hunk);
+                Some(start)
+            })
+            .collect()
+    }
+
+    fn scroll_up(&mut self) {
+        self.scroll = self.scroll.saturating_sub(1);
+    }
+
+    fn scroll_down(&mut self) {
+        let rows = self
+            .diff
+            .get()
+            .map_or(0, |diff| diff.hunks.iter().map(hunk_rows).sum());
+        if self.scroll + 1 < rows {
+            self.scroll += 1;
+        }
+    }
+
+    fn next_hunk(&mut self) {
+        if let Some(start) = self.hunk_starts().into_iter().find(|&s| s > self.scroll) {
+            self.scroll = start;
+        }
+    }
+
+    fn previous_hunk(&mut self) {
+        if let Some(start) = self.hunk_starts().into_iter().rfind(|&s| s < self.scroll) {
+            self.scroll = start;
+        }
+    }
+}
+
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::prelude::Buffer;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text;
+use ratatui::widgets::{Block, Borders, Paragraph, Widget};
+
+use super::style::{arg_style, comment_style, gutter_style, value_style};
+
+fn header_line<'a>(hunk: &Hunk, context: &[String]) -> text::Line<'a> {
+    let layer = match hunk.layer {
+        Some(layer) => format!("layer {}", layer + 1),
+        None => "start".to_string(),
+    };
+    let feature = hunk
+        .feature
+        .map(|feature| format!(" · {}", feature))
+        .unwrap_or_default();
+    text::Line::from(vec![
+        text::Span::styled(
+            format!("{}{} ", layer, feature),
+            Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+        ),
+        text::Span::styled(context.join(" "), comment_style(false)),
+    ])
+}
+
+fn diff_line<'a>(line: Option<&DiffLine>, color: Color) -> text::Line<'a> {
+    match line {
+        Some(line) => text::Line::from(vec![
+            text::Span::styled(format!("{:>6} │ ", line.line + 1), gutter_style(false)),
+            text::Span::styled(line.text.clone(), Style::default().fg(color)),
+        ]),
+        None => text::Line::from(text::Span::styled("       │", gutter_style(false))),
+    }
+}
+
+impl GCodeDiffView {
+    fn layout(area: Rect, summary_height: u16) -> [Rect; 3] {
+        let [summary_area, hunks_area] = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(summary_height), Constraint::Min(1)])
+            .areas(area);
+        let [before_area, after_area] = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .areas(hunks_area);
+
+        [summary_area, before_area, after_area]
+    }
+
+    fn summary_lines<'a>(diff: &ProgramDiff) -> Vec<text::Line<'a>> {
+        diff.summary()
+            .into_iter()
+            .map(|[label, before, after, change]| {
+                text::Line::from(vec![
+                    text::Span::styled(format!("{:<14}", label), arg_style(false)),
+                    text::Span::styled(
+                        format!("{:>14}{:>14}  ", before, after),
+                        value_style(false),
+                    ),
+                    text::Span::styled(change, Style::default().fg(Color::Cyan)),
+                ])
+            })
+            .collect()
+    }
+
+    fn sides<'a>(diff: &ProgramDiff) -> [Vec<text::Line<'a>>; 2] {
+        let (mut before, mut after) = (Vec::new(), Vec::new());
+        for hunk in &diff.hunks {
+            before.push(header_line(hunk, &hunk.context_before));
+            after.push(header_line(hunk, &hunk.context_after));
+            for i in 0..hunk_rows(hunk) - 1 {
+                before.push(diff_line(hunk.removed.get(i), Color::Red));
+                after.push(diff_line(hunk.added.get(i), Color::Green));
+            }
+        }
+        [before, after]
+    }
+}
+
+impl Widget for &GCodeDiffView {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let Some(diff) = self.diff.get() else {
+            Paragraph::new(format!(
+                "Comparing {} with {}...",
+                self.file_name(0),
+                self.file_name(1)
+            ))
+            .render(area, buf);
+            return;
+        };
+
+        let summary = GCodeDiffView::summary_lines(diff);
+        let [summary_area, before_area, after_area] =
+            GCodeDiffView::layout(area, summary.len() as u16 + 1);
+        Paragraph::new(summary).render(summary_area, buf);
+
+        let sides = GCodeDiffView::sides(diff);
+        for ((lines, side_area), i) in sides.into_iter().zip([before_area, after_area]).zip(0..) {
+            let lines = match lines.is_empty() {
+                true => vec![text::Line::from("No differences")],
+                false => lines,
+            };
+            Paragraph::new(lines)
+                .block(
+                    Block::default()
+                        .borders(Borders::TOP)
+                        .title(self.file_name(i)),
+                )
+                .scroll((self.scroll as u16, 0))
+                .render(side_area, buf);
+        }
+    }
+}
+
+use crossterm::event::KeyCode;
+
+use crate::tui::input::{AppEvent, EventHandler};
+
+impl EventHandler for GCodeDiffView {
+    fn handle_key_event(&mut self, key_event: &crossterm::event::KeyEvent) -> Option<AppEvent> {
+        match key_event.code {
+            KeyCode::Up => self.scroll_up(),
+            KeyCode::Down => self.scroll_down(),
+            KeyCode::PageUp => self.previous_hunk(),
+            KeyCode::PageDown => self.next_hunk(),
+            _ => {}
+        }
+
+        None
+    }
+}
@@ -1,11 +1,15 @@
 use ratatui_explorer::{FileExplorer, Theme};
 
 use super::debugger::GCodeDebugger;
+use super::diff::GCodeDiffView;
 
 #[derive(Debug)]
 pub struct GCodeEditor {
     file_explorer: FileExplorer,
     debugger: Option<GCodeDebugger>,
+    // picking the file to compare the open one with
+    comparing: bool,
+    diff: Option<GCodeDiffView>,
 }
 
 impl Default for GCodeEditor {
@@ -18,21 +22,50 @@ impl Default for GCodeEditor {
         Self {
             file_explorer,
             debugger: None,
+            comparing: false,
+            diff: None,
         }
     }
 }
 
 impl GCodeEditor {
     fn remove_file(&mut self) {
+        self.close_diff();
         self.debugger.take();
     }
 
+    fn close_diff(&mut self) {
+        self.comparing = false;
+        self.diff.take();
+    }
+
+    fn toggle_diff(&mut self) {
+        if self.comparing || self.diff.is_some() {
+            self.close_diff();
+        } else {
+            self.comparing = self.debugger.is_some();
+        }
+    }
+
+    fn is_gcode(path: &std::path::Path) -> bool {
+        path.extension().is_some_and(|ext| {
+            matches!(ext.to_ascii_lowercase().to_str(), Some("gcode" | "bgcode"))
+        })
+    }
+
     fn select_current_file(&mut self) {
         let path = self.file_explorer.current().path();
-        if let Some(ext) = path.extension() {
-            if ext.to_ascii_lowercase().to_str() == Some("gcode") {
-                self.debugger.replace(GCodeDebugger::new(path));
-            }
+        if Self::is_gcode(path) {
+            self.debugger.replace(GCodeDebugger::new(path));
+        }
+    }
+
+    fn select_diff_file(&mut self) {
+        let path = self.file_explorer.current().path();
+        if let Some(debugger) = self.debugger.as_ref().filter(|_| Self::is_gcode(path)) {
+            self.diff
+                .replace(GCodeDiffView::new(debugger.file_path(), path));
+            self.comparing = false;
         }
     }
 }
@@ -48,6 +81,33 @@ use crate::tui::input::{AppEvent, EventHandler};
 impl Widget for &GCodeEditor {
     fn render(self, area: ratatui::prelude::Rect, buf: &mut ratatui::prelude::Buffer) {
         let layout = StackedLayout::new();
+        if self.comparing {
+            let name = self
+                .debugger
+                .as_ref()
+                .and_then(|debugger| debugger.file_path().file_name())
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            return layout
+                .header(Paragraph::new(format!(
+                    "Select GCode to compare with {}",
+                    name
+                )))
+                .content(&self.file_explorer.widget())
+                .footer(Paragraph::new("[D] Cancel [Q] Quit").alignment(Alignment::Center))
+                .render(area, buf);
+        }
+        if let Some(diff) = &self.diff {
+            return layout
+                .header(Paragraph::new("[D] Close Diff [F] Choose File"))
+                .content(diff)
+                .footer(
+                    Paragraph::new("[PgUp/PgDn] Previous/Next Change [B] Go Back [Q] Quit")
+                        .alignment(Alignment::Center),
+                )
+                .render(area, buf);
+        }
+
         match &self.debugger {
             None => layout
                 .header(Paragraph::new("Select GCode"))
@@ -55,7 +115,7 @@ impl Widget for &GCodeEditor {
                 .footer(Paragraph::new("[B] Go Back [Q] Quit").alignment(Alignment::Center))
                 .render(area, buf),
             Some(debugger) => layout
-                .header(Paragraph::new("[F] Choose File"))
+                .header(Paragraph::new("[F] Choose File [D] Diff"))
                 .content(debugger)
                 .footer(
                     Paragraph::new("[B] Go Back [H] Help [Q] Quit").alignment(Alignment::Center),
@@ -92,9 +152,12 @@ impl EventHandler for GCodeEditor {
     fn handle_key_event(&mut self, key_event: &crossterm::event::KeyEvent) -> Option<AppEvent> {
         match key_event.code {
             KeyCode::Char('F') | KeyCode::Char('f') => self.remove_file(),
+            KeyCode::Char('D') | KeyCode::Char('d') => self.toggle_diff(),
             KeyCode::Enter => {
                 if self.debugger.is_none() {
                     self.select_current_file();
+                } else if self.comparing {
+                    self.select_diff_file();
                 }
             }
             _ => {}
@@ -115,9 +178,12 @@ impl EventHandler for GCodeEditor {
             }
         }
 
+        if let Some(diff) = &mut self.diff {
+            return diff.handle_app_event(app_event, app_emitter);
+        }
         match &mut self.debugger {
-            Some(script) => script.handle_app_event(app_event, app_emitter),
-            None => {
+            Some(script) if !self.comparing => script.handle_app_event(app_event, app_emitter),
+            _ => {
                 self.file_explorer.handle(app_event)?;
                 Ok(())
             }
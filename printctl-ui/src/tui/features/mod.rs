@@ -1,5 +1,8 @@
 pub mod code;
 pub mod debugger;
+pub mod diff;
 pub mod editor;
 pub mod program;
+pub mod progress;
 pub mod style;
+pub mod thumbnail;
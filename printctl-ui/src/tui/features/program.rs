@@ -9,7 +9,8 @@ use ratatui::widgets::{
     Block, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, StatefulWidget, Widget,
 };
 
-use super::style::{arg_style, opcode_style, value_style};
+use super::code::word_spans;
+use super::style::{arg_style, feature_style, opcode_style, value_style};
 
 fn gcode_summary_spans<'a>(lines: &'a [GCode]) -> Vec<text::Span<'a>> {
     let mut spans = Vec::new();
@@ -48,21 +49,29 @@ fn gcode_summary_spans<'a>(lines: &'a [GCode]) -> Vec<text::Span<'a>> {
     spans
 }
 
-impl<'a> Into<text::Text<'a>> for &'a GCodeProgram {
-    fn into(self) -> text::Text<'a> {
-        self.lines()
+impl GCodeProgram {
+    /// `lines` of the program from `first_line` on, the cursor line expanded
+    fn page_text<'a>(&self, lines: &'a [GCodeLine], first_line: usize) -> text::Text<'a> {
+        // feature markers show up once the program is indexed
+        let features = self.is_indexed().then(|| self.features());
+
+        lines
             .iter()
             .enumerate()
             .flat_map(|(i, gcode_line)| {
+                let i = first_line + i;
                 let line_number = i + 1;
                 let is_selected = self.cursor() == i;
 
-                let spans = gcode_line.to_spans(line_number, is_selected);
+                let feature = features.and_then(|features| features.feature_at(i));
+                let marker = if feature.is_some() { "▎" } else { " " };
+                let mut spans = vec![text::Span::styled(marker, feature_style(feature))];
+                spans.extend(gcode_line.to_spans(line_number, is_selected));
                 let mut lines = vec![text::Line::from(spans)];
 
                 if let GCodeLine::Command { gcodes, .. } = gcode_line {
                     if is_selected && !gcodes.is_empty() {
-                        let mut expanded_spans = vec![text::Span::raw(" ".repeat(7))];
+                        let mut expanded_spans = vec![text::Span::raw(" ".repeat(8))];
                         let summary = gcode_summary_spans(gcodes);
 
                         expanded_spans.extend(summary);
@@ -105,22 +114,18 @@ impl StatefulWidget for &GCodeProgram {
     ) {
         let block = Block::default();
         let inner = block.inner(area);
-        let arg_groups = self.current_arg_group();
-        let [arg_area, preview_area] = GCodeProgram::layout(inner, arg_groups.len());
+        let args = self.current_args();
+        let [arg_area, preview_area] = GCodeProgram::layout(inner, args.words().count());
 
         Paragraph::new(
-            arg_groups
-                .into_iter()
-                .map(|arg_range| {
-                    let spans = arg_range.to_spans();
-                    text::Line::from(spans)
-                })
+            args.words()
+                .map(|word| text::Line::from(word_spans(word)))
                 .collect::<Vec<_>>(),
         )
         .block(block)
         .render(arg_area, buf);
 
-        let total_lines = self.lines().len();
+        let total_lines = self.len();
         let page_height = preview_area.height.max(1) as usize;
         let max_scroll = total_lines.saturating_sub(page_height);
 
@@ -129,9 +134,9 @@ impl StatefulWidget for &GCodeProgram {
 
         let mut scrollbar = scroll_state.position(scroll);
 
-        Paragraph::new(self)
-            .scroll((scroll as u16, 0))
-            .render(preview_area, buf);
+        // only the page on screen is parsed
+        let lines = self.lines(scroll..scroll + page_height);
+        Paragraph::new(self.page_text(&lines, scroll)).render(preview_area, buf);
 
         Scrollbar::new(ScrollbarOrientation::VerticalRight)
             .begin_symbol(Some("↑"))
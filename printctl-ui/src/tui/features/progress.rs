@@ -0,0 +1,70 @@
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::prelude::Buffer;
+use ratatui::style::{Color, Style};
+use ratatui::text;
+use ratatui::widgets::{Block, BorderType, Gauge, Paragraph, Widget};
+
+use crate::features::progress::{format_duration, JobProgress};
+
+use super::style::{arg_style, value_style};
+
+fn detail_line<'a>(label: &'a str, value: String) -> text::Line<'a> {
+    text::Line::from(vec![
+        text::Span::styled(format!("{:<9}", label), arg_style(false)),
+        text::Span::styled(value, value_style(false)),
+    ])
+}
+
+impl JobProgress {
+    fn layout(area: Rect) -> [Rect; 2] {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(3), Constraint::Min(1)])
+            .split(area);
+
+        [chunks[0], chunks[1]]
+    }
+}
+
+impl Widget for &JobProgress {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let [gauge_area, details_area] = JobProgress::layout(area);
+        let percent = self.percent_time();
+
+        Gauge::default()
+            .block(
+                Block::bordered()
+                    .title("Progress")
+                    .border_type(BorderType::Rounded),
+            )
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio((percent / 100.0) as f64)
+            .label(format!("{:.1}%", percent))
+            .render(gauge_area, buf);
+
+        Paragraph::new(vec![
+            detail_line(
+                "Lines",
+                format!(
+                    "{}/{} ({:.1}%)",
+                    self.lines_done,
+                    self.total_lines,
+                    self.percent_lines()
+                ),
+            ),
+            detail_line(
+                "Bytes",
+                format!(
+                    "{}/{} ({:.1}%)",
+                    self.bytes_done,
+                    self.total_bytes,
+                    self.percent_bytes()
+                ),
+            ),
+            detail_line("Layer", format!("{}/{}", self.layer, self.layer_count)),
+            detail_line("Elapsed", format_duration(self.elapsed)),
+            detail_line("ETA", format_duration(self.eta)),
+        ])
+        .render(details_area, buf);
+    }
+}
@@ -1,5 +1,7 @@
 use ratatui::style::{Color, Modifier, Style};
 
+use crate::features::feature_type::FeatureType;
+
 pub fn opcode_style(is_selected: bool) -> Style {
     let base = Style::default().fg(Color::Green);
     if is_selected {
@@ -49,3 +51,23 @@ pub fn gutter_style(is_selected: bool) -> Style {
         base.fg(Color::DarkGray)
     }
 }
+
+/// Slicer-preview-like colors for the feature a line prints
+pub fn feature_style(feature: Option<FeatureType>) -> Style {
+    let color = match feature {
+        None => return Style::default(),
+        Some(FeatureType::Skirt | FeatureType::Brim) => Color::Cyan,
+        Some(FeatureType::OuterWall) => Color::LightRed,
+        Some(FeatureType::InnerWall) => Color::Yellow,
+        Some(FeatureType::OverhangWall) => Color::Blue,
+        Some(FeatureType::Infill) => Color::Red,
+        Some(FeatureType::SolidInfill | FeatureType::BottomSurface) => Color::Magenta,
+        Some(FeatureType::TopSurface | FeatureType::Ironing) => Color::LightMagenta,
+        Some(FeatureType::Bridge) => Color::LightBlue,
+        Some(FeatureType::GapFill) => Color::White,
+        Some(FeatureType::Support | FeatureType::SupportInterface) => Color::Green,
+        Some(FeatureType::WipeTower) => Color::LightYellow,
+        Some(FeatureType::Custom | FeatureType::Unknown) => Color::Gray,
+    };
+    Style::default().fg(color)
+}
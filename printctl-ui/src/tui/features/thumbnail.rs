@@ -0,0 +1,56 @@
+use ratatui::layout::Rect;
+use ratatui::prelude::Buffer;
+use ratatui::style::Color;
+use ratatui::widgets::Widget;
+
+use crate::features::thumbnail::Image;
+
+/// Blends a pixel over the terminal's (assumed dark) background
+fn blend([r, g, b, a]: [u8; 4]) -> Color {
+    let scale = |c: u8| (c as u16 * a as u16 / 255) as u8;
+    Color::Rgb(scale(r), scale(g), scale(b))
+}
+
+impl Image {
+    /// Rows needed to draw the image `width` cells wide (each cell holds two pixels vertically)
+    pub fn rows_for_width(&self, width: u16) -> u16 {
+        let cols = (self.width as u16).min(width).max(1);
+        let scaled_height = self.height * cols as u32 / self.width.max(1);
+        scaled_height.div_ceil(2) as u16
+    }
+}
+
+// Half-block rendering: `▀` with the upper pixel as foreground and the lower as background
+impl Widget for &Image {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        if self.width == 0 || self.height == 0 || area.is_empty() {
+            return;
+        }
+
+        // nearest-neighbor scale to fit, preserving aspect ratio
+        let scale = f32::min(
+            area.width as f32 / self.width as f32,
+            (area.height * 2) as f32 / self.height as f32,
+        )
+        .min(1.0);
+        let cols = ((self.width as f32 * scale) as u16).max(1);
+        let rows = ((self.height as f32 * scale / 2.0).ceil() as u16).max(1);
+
+        // center horizontally
+        let x0 = area.x + (area.width - cols) / 2;
+
+        for row in 0..rows.min(area.height) {
+            for col in 0..cols {
+                let src_x = ((col as f32 / scale) as u32).min(self.width - 1);
+                let src_top = (((row * 2) as f32 / scale) as u32).min(self.height - 1);
+                let src_bottom = (((row * 2 + 1) as f32 / scale) as u32).min(self.height - 1);
+
+                if let Some(cell) = buf.cell_mut((x0 + col, area.y + row)) {
+                    cell.set_symbol("▀")
+                        .set_fg(blend(self.pixel(src_x, src_top)))
+                        .set_bg(blend(self.pixel(src_x, src_bottom)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use crate::prelude::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use printctl_ui::features::cost::JobCost;
+use printctl_ui::features::progress::JobProgress;
+
+use super::library::{escape, join, parsed, split, state_dir, unescape};
+use super::models::{Job, JobStatus, LayerAction, LayerCommand};
+
+const EXTENSION: &str = "job";
+
+fn status_to_text(status: &JobStatus) -> &'static str {
+    match status {
+        JobStatus::Queued => "queued",
+        JobStatus::Running => "running",
+        JobStatus::Paused => "paused",
+        JobStatus::Completed => "completed",
+        JobStatus::Failed(_) => "failed",
+    }
+}
+
+/// `<layer>,change`, `<layer>,pause` or `<layer>,gcode,<G-code>`
+fn action_to_text(action: &LayerAction) -> String {
+    match &action.command {
+        LayerCommand::FilamentChange => format!("{},change", action.layer),
+        LayerCommand::Pause => format!("{},pause", action.layer),
+        LayerCommand::Custom(gcode) => format!("{},gcode,{}", action.layer, escape(gcode)),
+    }
+}
+
+fn parse_action(value: &str) -> Option<LayerAction> {
+    let mut parts = value.splitn(3, ',');
+    let layer = parsed(parts.next())?;
+    let command = match (parts.next()?, parts.next()) {
+        ("change", None) => LayerCommand::FilamentChange,
+        ("pause", None) => LayerCommand::Pause,
+        ("gcode", Some(gcode)) => LayerCommand::Custom(unescape(gcode)),
+        _ => return None,
+    };
+    Some(LayerAction { layer, command })
+}
+
+/// Counts, then durations in seconds
+fn progress_to_text(progress: &JobProgress) -> String {
+    let counts = [
+        progress.lines_done,
+        progress.total_lines,
+        progress.bytes_done,
+        progress.total_bytes,
+        progress.layer,
+        progress.layer_count,
+    ];
+    let durations = [
+        progress.simulated_done,
+        progress.simulated_total,
+        progress.elapsed,
+        progress.eta,
+    ];
+    let seconds = durations.map(|duration| duration.as_secs_f64());
+    format!("{},{}", join(&counts), join(&seconds))
+}
+
+fn parse_progress(value: &str) -> Option<JobProgress> {
+    let values = split::<f64>(value).filter(|values| values.len() == 10)?;
+    let count = |i: usize| values[i] as usize;
+    let duration = |i: usize| Duration::try_from_secs_f64(values[i]).ok();
+    Some(JobProgress {
+        lines_done: count(0),
+        total_lines: count(1),
+        bytes_done: count(2),
+        total_bytes: count(3),
+        layer: count(4),
+        layer_count: count(5),
+        simulated_done: duration(6)?,
+        simulated_total: duration(7)?,
+        elapsed: duration(8)?,
+        eta: duration(9)?,
+    })
+}
+
+/// `key=value` lines, a failed job's reason and custom layer G-code escaped
+/// to stay on one
+pub fn to_text(job: &Job) -> String {
+    let mut lines = vec![
+        ("id", job.id.to_string()),
+        ("printer", job.printer_id.to_string()),
+        ("file", job.gcode_file_id.to_string()),
+        ("status", status_to_text(&job.status).to_string()),
+        ("created_at", job.created_at.to_rfc3339()),
+    ];
+    if let JobStatus::Failed(reason) = &job.status {
+        lines.push(("reason", escape(reason)));
+    }
+    lines.extend(job.started_at.map(|at| ("started_at", at.to_rfc3339())));
+    lines.extend(job.finished_at.map(|at| ("finished_at", at.to_rfc3339())));
+    lines.extend(
+        job.progress
+            .as_ref()
+            .map(|progress| ("progress", progress_to_text(progress))),
+    );
+    lines.extend(job.cost.as_ref().map(|cost| {
+        let values = [
+            cost.filament_m,
+            cost.filament_g,
+            cost.filament_cost,
+            cost.energy_kwh,
+            cost.energy_cost,
+        ];
+        ("cost", join(&values))
+    }));
+    lines.extend(
+        job.layer_actions
+            .iter()
+            .map(|action| ("action", action_to_text(action))),
+    );
+
+    lines
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+/// Reads back [`to_text`]; `None` if anything required is missing
+pub fn parse(text: &str) -> Option<Job> {
+    let mut fields = HashMap::new();
+    let mut layer_actions = Vec::new();
+    for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+        match key {
+            "action" => layer_actions.push(parse_action(value)?),
+            _ => {
+                fields.insert(key, value);
+            }
+        }
+    }
+    let field = |key: &str| fields.get(key).copied();
+    let time = |key: &str| -> Option<Option<DateTime<Utc>>> {
+        match field(key) {
+            Some(value) => Some(Some(DateTime::parse_from_rfc3339(value).ok()?.to_utc())),
+            None => Some(None),
+        }
+    };
+
+    let status = match field("status")? {
+        "queued" => JobStatus::Queued,
+        "running" => JobStatus::Running,
+        "paused" => JobStatus::Paused,
+        "completed" => JobStatus::Completed,
+        "failed" => JobStatus::Failed(unescape(field("reason").unwrap_or_default())),
+        _ => return None,
+    };
+    let progress = match field("progress") {
+        Some(value) => Some(parse_progress(value)?),
+        None => None,
+    };
+    let cost = match field("cost") {
+        Some(value) => match split::<f64>(value)?[..] {
+            [filament_m, filament_g, filament_cost, energy_kwh, energy_cost] => Some(JobCost {
+                filament_m,
+                filament_g,
+                filament_cost,
+                energy_kwh,
+                energy_cost,
+            }),
+            _ => return None,
+        },
+        None => None,
+    };
+
+    Some(Job {
+        id: parsed(field("id"))?,
+        printer_id: parsed(field("printer"))?,
+        gcode_file_id: parsed(field("file"))?,
+        status,
+        created_at: time("created_at")??,
+        started_at: time("started_at")?,
+        finished_at: time("finished_at")?,
+        progress,
+        cost,
+        layer_actions,
+    })
+}
+
+/// Job records on disk, one file per job, so later runs can list them and
+/// report on what they cost
+#[derive(Debug, Clone)]
+pub struct JobStore {
+    dir: PathBuf,
+}
+
+impl Default for JobStore {
+    fn default() -> Self {
+        Self::new(state_dir().join("jobs"))
+    }
+}
+
+impl JobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, job_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.{}", job_id, EXTENSION))
+    }
+
+    /// Replaces the job's record, written aside and renamed so a crash
+    /// mid-write keeps the old one
+    pub fn save(&self, job: &Job) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(job.id);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, to_text(job))?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    /// Every job that can be read back, oldest first
+    pub fn load(&self) -> Result<Vec<Job>> {
+        let mut jobs = Vec::new();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(jobs),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != EXTENSION) {
+                continue;
+            }
+            let text = fs::read_to_string(&path)?;
+            jobs.extend(parse(&text));
+        }
+
+        jobs.sort_by_key(|job| job.created_at);
+        Ok(jobs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_survive_reload() {
+        let store = JobStore::new(std::env::temp_dir().join(Uuid::new_v4().to_string()));
+        let created_at = Utc::now();
+        let job = Job {
+            id: Uuid::new_v4(),
+            printer_id: Uuid::new_v4(),
+            gcode_file_id: Uuid::new_v4(),
+            status: JobStatus::Failed("heater\nrunaway".to_string()),
+            created_at,
+            started_at: Some(created_at + chrono::Duration::seconds(5)),
+            finished_at: Some(created_at + chrono::Duration::minutes(42)),
+            progress: Some(JobProgress {
+                lines_done: 1200,
+                total_lines: 5000,
+                bytes_done: 30_000,
+                total_bytes: 120_000,
+                simulated_done: Duration::from_millis(612_250),
+                simulated_total: Duration::from_secs(2520),
+                layer: 7,
+                layer_count: 40,
+                elapsed: Duration::from_secs(640),
+                eta: Duration::from_secs_f64(1995.5),
+            }),
+            cost: Some(JobCost {
+                filament_m: 3.25,
+                filament_g: 9.7,
+                filament_cost: 0.194,
+                energy_kwh: 0.08,
+                energy_cost: 0.024,
+            }),
+            layer_actions: vec![
+                LayerAction {
+                    layer: 2,
+                    command: LayerCommand::FilamentChange,
+                },
+                LayerAction {
+                    layer: 10,
+                    command: LayerCommand::Custom("M117 half,way\nG4 S1".to_string()),
+                },
+            ],
+        };
+        store.save(&job).unwrap();
+
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        let reloaded = &loaded[0];
+        assert_eq!(reloaded.id, job.id);
+        assert_eq!(reloaded.printer_id, job.printer_id);
+        assert_eq!(reloaded.gcode_file_id, job.gcode_file_id);
+        assert!(
+            matches!(&reloaded.status, JobStatus::Failed(reason) if reason == "heater\nrunaway")
+        );
+        assert_eq!(reloaded.created_at, job.created_at);
+        assert_eq!(reloaded.started_at, job.started_at);
+        assert_eq!(reloaded.finished_at, job.finished_at);
+        assert_eq!(reloaded.cost, job.cost);
+        let progress = reloaded.progress.as_ref().unwrap();
+        assert_eq!(
+            progress.to_string(),
+            job.progress.as_ref().unwrap().to_string()
+        );
+        assert_eq!(progress.simulated_done, Duration::from_millis(612_250));
+        assert_eq!(progress.eta, Duration::from_secs_f64(1995.5));
+        assert_eq!(reloaded.layer_actions, job.layer_actions);
+    }
+
+    #[test]
+    fn unreadable_jobs_are_skipped() {
+        assert!(parse("id=nope\n").is_none());
+        assert!(parse(&to_text(&Job {
+            id: Uuid::new_v4(),
+            printer_id: Uuid::new_v4(),
+            gcode_file_id: Uuid::new_v4(),
+            status: JobStatus::Queued,
+            created_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            progress: None,
+            cost: None,
+            layer_actions: Vec::new(),
+        }))
+        .is_some_and(|job| job.finished_at.is_none()
+            && job.progress.is_none()
+            && job.cost.is_none()));
+    }
+}
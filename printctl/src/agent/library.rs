@@ -0,0 +1,222 @@
+use crate::prelude::*;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::DateTime;
+use uuid::Uuid;
+
+use super::models::GcodeFile;
+
+const CONTENT_EXTENSION: &str = "content";
+const META_EXTENSION: &str = "meta";
+
+/// `$PRINTCTL_STATE_DIR`, else the XDG state directory
+pub fn state_dir() -> PathBuf {
+    std::env::var_os("PRINTCTL_STATE_DIR")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("XDG_STATE_HOME").map(|dir| PathBuf::from(dir).join("printctl"))
+        })
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state/printctl"))
+        })
+        .unwrap_or_else(|| std::env::temp_dir().join("printctl"))
+}
+
+/// Escapes `\` and line breaks so a value fits on one `key=value` line
+pub fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+/// Reverses [`escape`]
+pub fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+/// Comma-separated list for a `key=value` line
+pub fn join<T: ToString>(values: &[T]) -> String {
+    values
+        .iter()
+        .map(T::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Reverses [`join`]; `None` if any item fails to parse
+pub fn split<T: std::str::FromStr>(value: &str) -> Option<Vec<T>> {
+    value
+        .split(',')
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse().ok())
+        .collect()
+}
+
+pub fn parsed<T: std::str::FromStr>(value: Option<&str>) -> Option<T> {
+    value?.trim().parse().ok()
+}
+
+/// Uploaded files on disk, each as its raw content next to a `.meta` file
+/// with its name, folder, tags and upload time
+#[derive(Debug, Clone)]
+pub struct FileLibrary {
+    dir: PathBuf,
+}
+
+impl Default for FileLibrary {
+    fn default() -> Self {
+        Self::new(state_dir().join("library"))
+    }
+}
+
+impl FileLibrary {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, id: Uuid, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", id, extension))
+    }
+
+    /// Writes the file's content once and replaces its metadata
+    pub fn save(&self, file: &GcodeFile) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let content = self.path(file.id, CONTENT_EXTENSION);
+        if !content.exists() {
+            fs::write(&content, &file.content)?;
+        }
+
+        let mut meta = vec![
+            ("id", file.id.to_string()),
+            ("name", file.name.to_string_lossy().into_owned()),
+            ("uploaded_at", file.uploaded_at.to_rfc3339()),
+        ];
+        meta.extend(file.folder.clone().map(|folder| ("folder", folder)));
+        meta.extend(file.tags.iter().map(|tag| ("tag", tag.clone())));
+        let text: String = meta
+            .iter()
+            .map(|(key, value)| format!("{}={}\n", key, escape(value)))
+            .collect();
+
+        // written aside and renamed so a crash mid-write keeps the old one
+        let path = self.path(file.id, META_EXTENSION);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, text)?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    pub fn remove(&self, id: Uuid) -> Result<()> {
+        for extension in [META_EXTENSION, CONTENT_EXTENSION] {
+            match fs::remove_file(self.path(id, extension)) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Every file whose content and metadata can be read back
+    pub fn load(&self) -> Result<Vec<GcodeFile>> {
+        let mut files = Vec::new();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(files),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != META_EXTENSION) {
+                continue;
+            }
+            let text = fs::read_to_string(&path)?;
+            files.extend(self.parse(&text));
+        }
+
+        files.sort_by_key(|file| file.uploaded_at);
+        Ok(files)
+    }
+
+    fn parse(&self, text: &str) -> Option<GcodeFile> {
+        let (mut id, mut name, mut uploaded_at, mut folder) = (None, None, None, None);
+        let mut tags = Vec::new();
+        for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+            let value = unescape(value);
+            match key {
+                "id" => id = value.parse::<Uuid>().ok(),
+                "name" => name = Some(OsString::from(value)),
+                "uploaded_at" => uploaded_at = DateTime::parse_from_rfc3339(&value).ok(),
+                "folder" => folder = Some(value),
+                "tag" => tags.push(value),
+                _ => {}
+            }
+        }
+
+        let id = id?;
+        let content = fs::read(self.path(id, CONTENT_EXTENSION)).ok()?;
+        let mut file = GcodeFile::new(&name?, content).ok()?;
+        file.id = id;
+        file.uploaded_at = uploaded_at?.to_utc();
+        file.folder = folder;
+        file.tags = tags.into_iter().collect();
+        Some(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_round_trip() {
+        for value in [
+            "plain",
+            "two\nlines",
+            "back\\slash\\n",
+            "crlf\r\n",
+            "trailing\\",
+        ] {
+            assert!(!escape(value).contains('\n'));
+            assert_eq!(unescape(&escape(value)), value);
+        }
+    }
+
+    #[test]
+    fn files_survive_reload() {
+        let library = FileLibrary::new(std::env::temp_dir().join(Uuid::new_v4().to_string()));
+        let mut file = GcodeFile::new("cube\n.gcode".as_ref(), b"G28\nG1 X10\n".to_vec()).unwrap();
+        file.folder = Some("parts".to_string());
+        file.tags.insert("pla".to_string());
+        library.save(&file).unwrap();
+
+        let loaded = library.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].id, file.id);
+        assert_eq!(loaded[0].name, file.name);
+        assert_eq!(loaded[0].hash, file.hash);
+        assert_eq!(loaded[0].uploaded_at, file.uploaded_at);
+        assert_eq!(loaded[0].folder, file.folder);
+        assert_eq!(loaded[0].tags, file.tags);
+
+        library.remove(file.id).unwrap();
+        assert!(library.load().unwrap().is_empty());
+    }
+}
@@ -1,13 +1,35 @@
+pub mod jobs;
+pub mod library;
 pub mod models;
+pub mod pricing;
+pub mod profiles;
 
 use crate::prelude::*;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
 
-use chrono::Utc;
-use tokio_serial::SerialPortInfo;
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+use tokio_serial::{SerialPortInfo, SerialPortType};
 use uuid::Uuid;
 
-use crate::printer::Printer;
+use printctl_ui::features::cost::{JobCost, Pricing};
+use printctl_ui::features::profile::MachineProfile;
+use printctl_ui::features::resume::{ResumePlan, ResumePoint};
+use printctl_ui::features::statistics::ProgramStatistics;
+
+use crate::printer::calibration::CalibrationCycle;
+use crate::printer::checkpoint::{Checkpoint, CheckpointStore};
+use crate::printer::watchdog::{Heater, WatchdogConfig};
+use crate::printer::{Printer, PrinterEvent};
+use jobs::JobStore;
+use library::FileLibrary;
+use profiles::ProfileStore;
+
+// boards that reset when the port opens take a few seconds to boot
+const READY_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[derive(Default)]
 pub struct PrintAgent {
@@ -16,7 +38,17 @@ pub struct PrintAgent {
     gcode_files: HashMap<Uuid, models::GcodeFile>,
     jobs: HashMap<Uuid, models::Job>,
     job_queue: VecDeque<Uuid>,
+    job_plans: HashMap<Uuid, Arc<models::JobPlan>>,
     job_logs: HashMap<Uuid, Vec<models::JobLogEntry>>,
+    device_profiles: HashMap<Uuid, MachineProfile>,
+    pricing: Pricing,
+    checkpoints: CheckpointStore,
+    // where uploaded files are kept between runs, in memory only if `None`
+    library: Option<FileLibrary>,
+    // where device profiles are kept between runs, in memory only if `None`
+    profiles: Option<ProfileStore>,
+    // where jobs and their progress are kept between runs, in memory only if `None`
+    job_store: Option<JobStore>,
 }
 
 impl PrintAgent {
@@ -27,7 +59,49 @@ impl PrintAgent {
             gcode_files: HashMap::new(),
             jobs: HashMap::new(),
             job_queue: VecDeque::new(),
+            job_plans: HashMap::new(),
             job_logs: HashMap::new(),
+            device_profiles: HashMap::new(),
+            pricing: Pricing::default(),
+            checkpoints: CheckpointStore::default(),
+            library: None,
+            profiles: None,
+            job_store: None,
+        }
+    }
+
+    /// Agent whose uploaded files are stored in and loaded from `library`,
+    /// and device profiles and jobs from the state directory
+    pub fn with_library(name: &str, library: FileLibrary) -> Result<Self> {
+        let mut agent = Self::new(name);
+        for file in library.load()? {
+            agent.gcode_files.insert(file.id, file);
+        }
+        let profiles = ProfileStore::default();
+        agent.device_profiles.extend(profiles.load()?);
+        let job_store = JobStore::default();
+        for job in job_store.load()? {
+            agent.jobs.insert(job.id, job);
+        }
+        agent.library = Some(library);
+        agent.profiles = Some(profiles);
+        agent.job_store = Some(job_store);
+        Ok(agent)
+    }
+
+    /// Writes a file's latest metadata to the library
+    fn persist(&self, file_id: Uuid) -> Result<()> {
+        match (&self.library, self.gcode_files.get(&file_id)) {
+            (Some(library), Some(file)) => library.save(file),
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes a job's latest status and progress to the job store
+    fn persist_job(&self, job_id: Uuid) -> Result<()> {
+        match (&self.job_store, self.jobs.get(&job_id)) {
+            (Some(store), Some(job)) => store.save(job),
+            _ => Ok(()),
         }
     }
 
@@ -45,25 +119,264 @@ impl PrintAgent {
         name: &str,
         port: SerialPortInfo,
         baud: u32,
+        watchdog: WatchdogConfig,
     ) -> Result<()> {
         let port_path = &port.port_name;
-        let printer = Printer::new(port_path, baud, Some(name.to_string())).await?;
+        let printer = Printer::new(port_path, baud, Some(name.to_string()), watchdog).await?;
         self.printers.insert(name.into(), printer);
         Ok(())
     }
 
-    pub fn upload_gcode(&mut self, name: &std::ffi::OsStr, content: Vec<u8>) -> Uuid {
-        let g = models::GcodeFile {
-            id: Uuid::new_v4(),
-            name: name.to_os_string(),
-            content,
+    /// Opens the serial port of `printer_id` and waits until its firmware
+    /// answers
+    pub async fn connect_printer(
+        &mut self,
+        printer_id: Uuid,
+        port: &str,
+        baud: u32,
+        watchdog: WatchdogConfig,
+    ) -> Result<()> {
+        let name = printer_id.to_string();
+        let port = SerialPortInfo {
+            port_name: port.to_string(),
+            port_type: SerialPortType::Unknown,
         };
+        self.start_printer(&name, port, baud, watchdog).await?;
+        self.printers[&name].wait_ready(READY_TIMEOUT).await
+    }
+
+    /// Alerts raised by a connected printer
+    pub fn printer_events(&self, printer_id: Uuid) -> Result<broadcast::Receiver<PrinterEvent>> {
+        self.printers
+            .get(&printer_id.to_string())
+            .map(Printer::subscribe_events)
+            .ok_or(Error::NotConnected)
+    }
+
+    /// Stores a G-code file, returning the existing ID if the same content was uploaded before
+    pub fn upload_gcode(&mut self, name: &std::ffi::OsStr, content: Vec<u8>) -> Result<Uuid> {
+        let hash = models::GcodeFile::content_hash(&content);
+        if let Some(existing) = self.gcode_files.values().find(|g| g.hash == hash) {
+            return Ok(existing.id);
+        }
+
+        let g = models::GcodeFile::new(name, content)?;
         let id = g.id;
         self.gcode_files.insert(id, g);
-        id
+        self.persist(id)?;
+        Ok(id)
+    }
+
+    pub fn list_files(&self) -> std::collections::hash_map::Values<'_, Uuid, models::GcodeFile> {
+        self.gcode_files.values()
+    }
+
+    pub fn get_file(&self, file_id: Uuid) -> Option<&models::GcodeFile> {
+        self.gcode_files.get(&file_id)
+    }
+
+    fn get_file_mut(&mut self, file_id: Uuid) -> Result<&mut models::GcodeFile> {
+        self.gcode_files
+            .get_mut(&file_id)
+            .ok_or(Error::FileNotFound(file_id))
+    }
+
+    pub fn delete_file(&mut self, file_id: Uuid) -> Result<models::GcodeFile> {
+        let in_use = self.jobs.values().any(|job| {
+            job.gcode_file_id == file_id
+                && matches!(
+                    job.status,
+                    models::JobStatus::Queued
+                        | models::JobStatus::Running
+                        | models::JobStatus::Paused
+                )
+        });
+        if in_use {
+            return Err(Error::FileInUse(file_id));
+        }
+
+        let file = self
+            .gcode_files
+            .remove(&file_id)
+            .ok_or(Error::FileNotFound(file_id))?;
+        if let Some(library) = &self.library {
+            library.remove(file_id)?;
+        }
+        Ok(file)
+    }
+
+    pub fn tag_file(&mut self, file_id: Uuid, tags: &[String], remove: bool) -> Result<()> {
+        let file = self.get_file_mut(file_id)?;
+        for tag in tags {
+            if remove {
+                file.tags.remove(tag);
+            } else {
+                file.tags.insert(tag.clone());
+            }
+        }
+        self.persist(file_id)
+    }
+
+    pub fn move_file(&mut self, file_id: Uuid, folder: Option<String>) -> Result<()> {
+        self.get_file_mut(file_id)?.folder = folder;
+        self.persist(file_id)
+    }
+
+    /// Machine limits used when simulating jobs for the printer
+    pub fn device_profile(&self, printer_id: Uuid) -> MachineProfile {
+        self.device_profiles
+            .get(&printer_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set_device_profile(&mut self, printer_id: Uuid, profile: MachineProfile) -> Result<()> {
+        if let Some(profiles) = &self.profiles {
+            profiles.save(printer_id, &profile)?;
+        }
+        self.device_profiles.insert(printer_id, profile);
+        Ok(())
+    }
+
+    /// Runs `cycles` on a started printer and stores the fitted thermal
+    /// models in its device profile
+    pub async fn calibrate_thermal(
+        &mut self,
+        printer_id: Uuid,
+        cycles: &[CalibrationCycle],
+    ) -> Result<MachineProfile> {
+        let printer = self
+            .printers
+            .get(&printer_id.to_string())
+            .ok_or(Error::NotConnected)?;
+
+        let mut profile = self.device_profile(printer_id);
+        for cycle in cycles {
+            let model = printer.calibrate_thermal(cycle).await?;
+            // keep whatever control loop the heater was modelled with
+            let heater = match cycle.heater {
+                Heater::Bed => &mut profile.bed_thermal,
+                Heater::Tool(0) => &mut profile.hotend_thermal,
+                Heater::Tool(idx) => {
+                    let fallback = profile.hotend_thermal.clone();
+                    match profile.tools.get_mut(idx) {
+                        Some(tool) => tool.thermal.get_or_insert(fallback),
+                        None => continue,
+                    }
+                }
+            };
+            *heater = heater.clone().with_plant(model);
+        }
+
+        self.set_device_profile(printer_id, profile.clone())?;
+        Ok(profile)
+    }
+
+    /// Materials and electricity rate costs are billed with
+    pub fn pricing(&self) -> &Pricing {
+        &self.pricing
+    }
+
+    pub fn set_pricing(&mut self, pricing: Pricing) {
+        self.pricing = pricing;
+    }
+
+    /// Simulates what printing a file on `printer_id` would cost
+    pub fn file_cost(&self, file_id: Uuid, printer_id: Option<Uuid>) -> Result<JobCost> {
+        let file = self.get_file(file_id).ok_or(Error::FileNotFound(file_id))?;
+        let profile = printer_id
+            .map(|id| self.device_profile(id))
+            .unwrap_or_default();
+        let stats = ProgramStatistics::from_source(&file.source(), &profile);
+
+        Ok(self
+            .pricing
+            .estimate(&stats, file.metadata.material.as_deref()))
+    }
+
+    /// Costs of jobs completed within `period`, totalled per printer
+    pub fn cost_report(&self, period: Range<DateTime<Utc>>) -> BTreeMap<Uuid, JobCost> {
+        let mut totals = BTreeMap::<Uuid, JobCost>::new();
+        let finished = self.jobs.values().filter(|job| {
+            matches!(job.status, models::JobStatus::Completed)
+                && job.finished_at.is_some_and(|at| period.contains(&at))
+        });
+        for job in finished {
+            if let Some(cost) = &job.cost {
+                *totals.entry(job.printer_id).or_default() += cost;
+            }
+        }
+        totals
+    }
+
+    /// Queues a file on a printer, with `layer_actions` run as the job
+    /// reaches their layers. Jobs with a `resume` point restore the machine
+    /// state up to it and print only the rest.
+    pub fn create_job(
+        &mut self,
+        printer_id: Uuid,
+        gcode_file_id: Uuid,
+        layer_actions: Vec<models::LayerAction>,
+        resume: Option<ResumePoint>,
+    ) -> Result<Uuid> {
+        let profile = self.device_profile(printer_id);
+        let file = self
+            .get_file(gcode_file_id)
+            .ok_or(Error::FileNotFound(gcode_file_id))?;
+        let resume = resume
+            .map(|point| {
+                ResumePlan::new(&file.source(), point, &profile).ok_or(Error::ResumePoint(point))
+            })
+            .transpose()?;
+
+        self.enqueue_job(printer_id, gcode_file_id, layer_actions, resume)
+    }
+
+    /// Queues a job continuing an interrupted one from its checkpoint. The
+    /// file must be the one that job was printing.
+    pub fn recover_job(&mut self, checkpoint: &Checkpoint, gcode_file_id: Uuid) -> Result<Uuid> {
+        let file = self
+            .get_file(gcode_file_id)
+            .ok_or(Error::FileNotFound(gcode_file_id))?;
+        if file.hash != checkpoint.file_hash {
+            return Err(Error::CheckpointMismatch(checkpoint.job_id));
+        }
+
+        let profile = self.device_profile(checkpoint.printer_id);
+        let point = ResumePoint::Line(checkpoint.line);
+        let mut resume =
+            ResumePlan::new(&file.source(), point, &profile).ok_or(Error::ResumePoint(point))?;
+        // restore the state the printer was last known to be in
+        resume.preamble = checkpoint.state.preamble(checkpoint.line);
+
+        self.enqueue_job(
+            checkpoint.printer_id,
+            gcode_file_id,
+            Vec::new(),
+            Some(resume),
+        )
     }
 
-    pub fn create_job(&mut self, printer_id: Uuid, gcode_file_id: Uuid) -> Uuid {
+    fn enqueue_job(
+        &mut self,
+        printer_id: Uuid,
+        gcode_file_id: Uuid,
+        layer_actions: Vec<models::LayerAction>,
+        resume: Option<ResumePlan>,
+    ) -> Result<Uuid> {
+        // simulate up front so progress and ETA are known while streaming
+        let profile = self.device_profile(printer_id);
+        let file = self
+            .get_file(gcode_file_id)
+            .ok_or(Error::FileNotFound(gcode_file_id))?;
+        let plan = Arc::new(models::JobPlan::new(
+            file,
+            &profile,
+            &self.pricing,
+            &layer_actions,
+            resume,
+        )?);
+
         let job = models::Job {
             id: Uuid::new_v4(),
             printer_id,
@@ -72,11 +385,62 @@ impl PrintAgent {
             created_at: Utc::now(),
             started_at: None,
             finished_at: None,
+            progress: Some(plan.progress.progress(0, Duration::ZERO)),
+            cost: Some(plan.cost.clone()),
+            layer_actions,
         };
         let id = job.id;
+        self.job_plans.insert(id, plan);
         self.jobs.insert(id, job);
         self.job_queue.push_back(id);
-        id
+        self.persist_job(id)?;
+        Ok(id)
+    }
+
+    /// Hands queued jobs to their printers and starts them. Jobs of printers
+    /// that are not connected stay queued.
+    pub async fn dispatch_jobs(&mut self) -> Result<()> {
+        let mut waiting = VecDeque::new();
+        let mut queue = std::mem::take(&mut self.job_queue).into_iter();
+
+        while let Some(id) = queue.next() {
+            let (Some(job), Some(plan)) = (self.jobs.get(&id), self.job_plans.get(&id)) else {
+                continue;
+            };
+            let Some(printer) = self.printers.get(&job.printer_id.to_string()) else {
+                waiting.push_back(id);
+                continue;
+            };
+
+            let sent = async {
+                printer.queue_job(job.clone(), plan.clone()).await?;
+                printer.start_next_job().await
+            }
+            .await;
+            if let Err(e) = sent {
+                // keep what was not handed over for the next attempt
+                waiting.extend(queue);
+                self.job_queue = waiting;
+                return Err(e);
+            }
+        }
+
+        self.job_queue = waiting;
+        Ok(())
+    }
+
+    /// Continues the job a pause action holds on `printer_id`
+    pub async fn resume_job(&self, printer_id: Uuid) -> Result<()> {
+        self.printers
+            .get(&printer_id.to_string())
+            .ok_or(Error::NotConnected)?
+            .resume_job()
+            .await
+    }
+
+    /// Saved progress of jobs that were running when the host went down
+    pub fn checkpoints(&self) -> &CheckpointStore {
+        &self.checkpoints
     }
 
     pub fn list_jobs(&self) -> std::collections::hash_map::Values<'_, Uuid, models::Job> {
@@ -87,7 +451,42 @@ impl PrintAgent {
         self.jobs.get(&job_id)
     }
 
+    /// Pull the latest status and progress of running jobs from the printers,
+    /// saving them for `list-jobs` and cost reports of later runs
+    pub async fn refresh_jobs(&mut self) -> Result<()> {
+        let mut refreshed = Vec::new();
+        for printer in self.printers.values() {
+            if let Some(current) = printer.current_job.lock().await.as_ref() {
+                if let Some(job) = self.jobs.get_mut(&current.id) {
+                    *job = current.clone();
+                    refreshed.push(current.id);
+                }
+            }
+        }
+        for id in refreshed {
+            self.persist_job(id)?;
+        }
+        Ok(())
+    }
+
     pub fn get_job_logs(&self, job_id: Uuid) -> Option<&Vec<models::JobLogEntry>> {
         self.job_logs.get(&job_id)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_need_an_uploaded_file() {
+        let mut agent = PrintAgent::new("test");
+        let missing = Uuid::new_v4();
+
+        for resume in [None, Some(ResumePoint::Line(0))] {
+            let created = agent.create_job(Uuid::new_v4(), missing, Vec::new(), resume);
+            assert!(matches!(created, Err(Error::FileNotFound(id)) if id == missing));
+        }
+        assert_eq!(agent.list_jobs().count(), 0);
+    }
+}
@@ -1,17 +1,84 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::str::FromStr;
+
 use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
+use crate::prelude::*;
+
+use printctl_ui::features::bgcode;
+use printctl_ui::features::cost::{JobCost, Pricing};
+use printctl_ui::features::format::parse_lines;
+use printctl_ui::features::layer::LayerIndex;
+use printctl_ui::features::machine::MachineState;
+use printctl_ui::features::metadata::SlicerMetadata;
+use printctl_ui::features::profile::MachineProfile;
+use printctl_ui::features::progress::{JobProgress, ProgressPlan};
+use printctl_ui::features::resume::ResumePlan;
+use printctl_ui::features::thumbnail::Thumbnails;
+
 #[derive(Debug, Clone)]
 pub struct GcodeFile {
     pub id: Uuid,
-    pub name: std::ffi::OsString,
+    pub name: OsString,
     pub content: Vec<u8>,
+    pub hash: String,
+    pub uploaded_at: DateTime<Utc>,
+    pub folder: Option<String>,
+    pub tags: BTreeSet<String>,
+    pub metadata: SlicerMetadata,
+    pub thumbnails: Thumbnails,
+}
+
+impl GcodeFile {
+    /// Accepts ASCII and binary (`.bgcode`) G-code; binary files must decode cleanly
+    pub fn new(name: &OsStr, content: Vec<u8>) -> Result<Self> {
+        let src = bgcode::to_ascii(&content)?;
+        let metadata = SlicerMetadata::parse(&src);
+        let thumbnails = Thumbnails::extract(&src);
+
+        Ok(Self {
+            id: Uuid::new_v4(),
+            name: name.to_os_string(),
+            hash: Self::content_hash(&content),
+            content,
+            uploaded_at: Utc::now(),
+            folder: None,
+            tags: BTreeSet::new(),
+            metadata,
+            thumbnails,
+        })
+    }
+
+    /// ASCII G-code of the file, decoded if it was uploaded as `.bgcode`
+    pub fn source(&self) -> Cow<'_, str> {
+        bgcode::to_ascii(&self.content).expect("content is validated on upload")
+    }
+
+    /// Hex-encoded SHA-256 of the file content, used to deduplicate uploads
+    pub fn content_hash(content: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(content))
+    }
+
+    pub fn path(&self) -> String {
+        let name = self.name.to_string_lossy();
+        match &self.folder {
+            Some(folder) => format!("{}/{}", folder.trim_end_matches('/'), name),
+            None => name.into_owned(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum JobStatus {
     Queued,
     Running,
+    /// Held by a pause action until resumed
+    Paused,
     Completed,
     Failed(String),
 }
@@ -25,6 +92,63 @@ pub struct Job {
     pub created_at: DateTime<Utc>,
     pub started_at: Option<DateTime<Utc>>,
     pub finished_at: Option<DateTime<Utc>>,
+    pub progress: Option<JobProgress>,
+    /// Filament and energy the job is simulated to use
+    pub cost: Option<JobCost>,
+    pub layer_actions: Vec<LayerAction>,
+}
+
+/// What to do when a job reaches a layer
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayerCommand {
+    /// `M600`, the firmware parks and waits for new filament
+    FilamentChange,
+    /// Stop streaming until the job is resumed
+    Pause,
+    /// G-code sent as is, lines separated by `\n`
+    Custom(String),
+}
+
+/// A [`LayerCommand`] run right before layer `layer` (one-based) starts
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerAction {
+    pub layer: usize,
+    pub command: LayerCommand,
+}
+
+/// Parses `LAYER:change`, `LAYER:pause` or `LAYER:GCODE`
+impl FromStr for LayerAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (layer, command) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected LAYER:ACTION, got {:?}", s))?;
+        let layer = layer
+            .trim()
+            .parse()
+            .ok()
+            .filter(|layer| *layer > 0)
+            .ok_or_else(|| format!("invalid layer {:?}", layer))?;
+
+        let command = match command.trim() {
+            "change" => LayerCommand::FilamentChange,
+            "pause" => LayerCommand::Pause,
+            "" => return Err("empty layer action".to_string()),
+            gcode => LayerCommand::Custom(gcode.replace("\\n", "\n")),
+        };
+        Ok(Self { layer, command })
+    }
+}
+
+impl fmt::Display for LayerAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.command {
+            LayerCommand::FilamentChange => write!(f, "layer {}: filament change", self.layer),
+            LayerCommand::Pause => write!(f, "layer {}: pause", self.layer),
+            LayerCommand::Custom(gcode) => write!(f, "layer {}: {:?}", self.layer, gcode),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -33,10 +157,100 @@ pub struct JobLogEntry {
     pub message: String,
 }
 
-// Helper to split G-code file into lines for printer task
-impl Job {
-    pub fn gcode_lines(&self) -> Vec<String> {
-        // For simplicity, you could fetch G-code content from the agent's gcode_files map
-        vec!["G28 ; home all axes".to_string()] // placeholder
+/// G-code lines of a job along with their simulated timeline
+#[derive(Debug)]
+pub struct JobPlan {
+    pub file_name: String,
+    pub file_hash: String,
+    pub lines: Box<[String]>,
+    pub progress: ProgressPlan,
+    pub cost: JobCost,
+    /// Layer actions by the line they run before
+    pub actions: BTreeMap<usize, Vec<LayerCommand>>,
+    /// Line streaming starts at, after sending `preamble`
+    pub start_line: usize,
+    pub preamble: Vec<String>,
+    /// Simulated machine state at `start_line`
+    pub initial_state: MachineState,
+}
+
+impl JobPlan {
+    pub fn new(
+        file: &GcodeFile,
+        profile: &MachineProfile,
+        pricing: &Pricing,
+        layer_actions: &[LayerAction],
+        resume: Option<ResumePlan>,
+    ) -> Result<Self> {
+        let src = file.source();
+        let lines = src.lines().map(str::to_string).collect();
+        let (progress, stats) = ProgressPlan::with_statistics(&src, profile);
+
+        let mut actions = BTreeMap::<usize, Vec<LayerCommand>>::new();
+        if !layer_actions.is_empty() {
+            // parsed one line per source line, so layer starts are source lines
+            let layers = LayerIndex::new(&parse_lines(&src));
+            for action in layer_actions {
+                // layers are one-based, layer 0 never exists
+                let layer = action
+                    .layer
+                    .checked_sub(1)
+                    .and_then(|i| layers.layers().get(i))
+                    .ok_or(Error::LayerNotFound(action.layer, layers.len()))?;
+                actions
+                    .entry(layer.lines.start)
+                    .or_default()
+                    .push(action.command.clone());
+            }
+        }
+
+        Ok(Self {
+            file_name: file.path(),
+            file_hash: file.hash.clone(),
+            lines,
+            progress,
+            cost: pricing.estimate(&stats, file.metadata.material.as_deref()),
+            actions,
+            start_line: resume.as_ref().map_or(0, |plan| plan.line),
+            initial_state: resume.as_ref().map_or_else(
+                || MachineState::from_profile(profile),
+                |plan| plan.state.clone(),
+            ),
+            preamble: resume.map(|plan| plan.preamble).unwrap_or_default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(layer: usize) -> Result<JobPlan> {
+        let src = b";LAYER:0\nG1 Z0.2\nG1 X10 E1\n;LAYER:1\nG1 Z0.4\nG1 X0 E2\n";
+        let file = GcodeFile::new("two_layers.gcode".as_ref(), src.to_vec())?;
+        let action = LayerAction {
+            layer,
+            command: LayerCommand::Pause,
+        };
+        JobPlan::new(
+            &file,
+            &MachineProfile::default(),
+            &Pricing::default(),
+            &[action],
+            None,
+        )
+    }
+
+    #[test]
+    fn layer_actions_must_name_an_existing_layer() {
+        let second = plan(2).unwrap();
+        assert_eq!(second.actions[&3], [LayerCommand::Pause]);
+
+        for layer in [0, 3] {
+            assert!(matches!(
+                plan(layer),
+                Err(Error::LayerNotFound(l, 2)) if l == layer
+            ));
+        }
     }
 }
@@ -0,0 +1,112 @@
+use crate::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use printctl_ui::features::cost::{Material, Pricing};
+
+use super::library::state_dir;
+
+/// Pricing used when no file is given on the command line
+pub fn default_path() -> PathBuf {
+    state_dir().join("pricing.conf")
+}
+
+/// Reads `price_per_kwh=<rate>` and one
+/// `material=<name>,<density g/cm³>,<price per kg>[,<diameter mm>]` line per
+/// material, the first being billed for files that name no known one. Blank
+/// lines and `#` comments are skipped; what the file leaves out keeps its
+/// default.
+pub fn parse(text: &str) -> Result<Pricing> {
+    let mut pricing = Pricing::default();
+    let mut materials = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || Error::Pricing(i + 1, line.to_string());
+        let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+        match key.trim() {
+            "price_per_kwh" => {
+                pricing.price_per_kwh = value.trim().parse().map_err(|_| invalid())?
+            }
+            "material" => {
+                let fields: Vec<&str> = value.split(',').map(str::trim).collect();
+                let number = |i: usize| fields.get(i).and_then(|field| field.parse::<f32>().ok());
+                let (name, density, price) = match (fields.first(), number(1), number(2)) {
+                    (Some(name), Some(density), Some(price)) if !name.is_empty() => {
+                        (name, density, price)
+                    }
+                    _ => return Err(invalid()),
+                };
+                let mut material = Material::new(name, density, price);
+                if fields.len() > 3 {
+                    material.diameter_mm = number(3).ok_or_else(invalid)?;
+                }
+                materials.push(material);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+
+    if !materials.is_empty() {
+        pricing.materials = materials;
+    }
+    Ok(pricing)
+}
+
+/// Pricing from `path`, or from [`default_path`] if it exists, else the
+/// built-in defaults
+pub fn load(path: Option<&Path>) -> Result<Pricing> {
+    let text = match path {
+        Some(path) => fs::read_to_string(path)?,
+        None => match fs::read_to_string(default_path()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Pricing::default()),
+            Err(e) => return Err(e.into()),
+        },
+    };
+    parse(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_materials_and_rate() {
+        let pricing = parse(
+            "# shop prices\n\
+             price_per_kwh = 0.42\n\
+             material=PETG, 1.27, 25\n\
+             material=PLA,1.24,18.5,2.85\n",
+        )
+        .unwrap();
+
+        assert_eq!(pricing.price_per_kwh, 0.42);
+        assert_eq!(pricing.materials.len(), 2);
+        assert_eq!(pricing.material(None).unwrap().name, "PETG");
+        let pla = pricing.material(Some("pla")).unwrap();
+        assert_eq!(pla.price_per_kg, 18.5);
+        assert_eq!(pla.diameter_mm, 2.85);
+    }
+
+    #[test]
+    fn keeps_defaults_left_out() {
+        let pricing = parse("price_per_kwh=0.1\n").unwrap();
+        assert_eq!(pricing.materials, Pricing::default().materials);
+    }
+
+    #[test]
+    fn rejects_malformed_lines() {
+        for text in [
+            "material=PLA,1.24",
+            "price_per_kwh=cheap",
+            "kwh=0.3",
+            "nothing",
+        ] {
+            assert!(matches!(parse(text), Err(Error::Pricing(1, _))), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use crate::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use printctl_ui::features::metric::Position;
+use printctl_ui::features::profile::{MachineProfile, ToolProfile};
+use printctl_ui::features::thermal::{
+    ControlledThermalModel, Controller, HeaterModel, LumpedThermalModel,
+};
+
+use super::library::{escape, join, parsed, split, state_dir, unescape};
+
+const EXTENSION: &str = "profile";
+
+/// `lumped,<plant>` or `controlled,<plant>,<settling>,<controller>`, the
+/// durations in seconds
+fn heater_to_text(model: &HeaterModel) -> String {
+    let plant = model.plant();
+    let mut values = vec![
+        plant.ambient.to_string(),
+        plant.power_w.to_string(),
+        plant.loss_coeff.to_string(),
+        plant.heat_capacity.to_string(),
+    ];
+    let kind = match model {
+        HeaterModel::Lumped(_) => "lumped",
+        HeaterModel::Controlled(model) => {
+            values.extend([
+                model.window.to_string(),
+                model.hysteresis.to_string(),
+                model.residency.as_secs_f32().to_string(),
+                model.step.as_secs_f32().to_string(),
+                model.max_settle.as_secs_f32().to_string(),
+            ]);
+            match model.controller {
+                Controller::BangBang { hysteresis } => {
+                    values.extend(["bang_bang".to_string(), hysteresis.to_string()])
+                }
+                Controller::Pid { kp, ki, kd } => values.extend([
+                    "pid".to_string(),
+                    kp.to_string(),
+                    ki.to_string(),
+                    kd.to_string(),
+                ]),
+            }
+            "controlled"
+        }
+    };
+    format!("{},{}", kind, values.join(","))
+}
+
+/// Reverses [`heater_to_text`]
+fn parse_heater(value: &str) -> Option<HeaterModel> {
+    let values: Vec<&str> = value.split(',').map(str::trim).collect();
+    let number = |i: usize| parsed::<f32>(values.get(i).copied());
+    let seconds = |i: usize| number(i).and_then(|s| Duration::try_from_secs_f32(s).ok());
+
+    let plant = LumpedThermalModel {
+        ambient: number(1)?,
+        power_w: number(2)?,
+        loss_coeff: number(3)?,
+        heat_capacity: number(4)?,
+    };
+    match *values.first()? {
+        "lumped" => Some(HeaterModel::Lumped(plant)),
+        "controlled" => {
+            let controller = match *values.get(10)? {
+                "bang_bang" => Controller::BangBang {
+                    hysteresis: number(11)?,
+                },
+                "pid" => Controller::Pid {
+                    kp: number(11)?,
+                    ki: number(12)?,
+                    kd: number(13)?,
+                },
+                _ => return None,
+            };
+            Some(HeaterModel::Controlled(ControlledThermalModel {
+                plant,
+                controller,
+                window: number(5)?,
+                hysteresis: number(6)?,
+                residency: seconds(7)?,
+                step: seconds(8)?,
+                max_settle: seconds(9)?,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// `key=value` lines, one `tool` line per tool with its offset and
+/// optionally its own heater after it
+pub fn to_text(profile: &MachineProfile) -> String {
+    let limits = &profile.limits;
+    let retraction = &profile.retraction;
+    let mut lines = vec![
+        ("max_acceleration", join(&limits.max_acceleration)),
+        ("max_feedrate", join(&limits.max_feedrate)),
+        ("print_acceleration", limits.print_acceleration.to_string()),
+        (
+            "retract_acceleration",
+            limits.retract_acceleration.to_string(),
+        ),
+        (
+            "travel_acceleration",
+            limits.travel_acceleration.to_string(),
+        ),
+        ("jerk", join(&limits.jerk)),
+        ("min_feedrate", limits.min_feedrate.to_string()),
+        (
+            "min_travel_feedrate",
+            limits.min_travel_feedrate.to_string(),
+        ),
+        ("retract_length", retraction.length.to_string()),
+        ("retract_feedrate", retraction.feedrate.to_string()),
+        ("retract_z_lift", retraction.z_lift.to_string()),
+        ("recover_extra", retraction.recover_extra.to_string()),
+        ("recover_feedrate", retraction.recover_feedrate.to_string()),
+        ("ambient_temp", profile.ambient_temp.to_string()),
+        ("bed_thermal", heater_to_text(&profile.bed_thermal)),
+        ("hotend_thermal", heater_to_text(&profile.hotend_thermal)),
+        ("idle_w", profile.power.idle_w.to_string()),
+        ("motors_w", profile.power.motors_w.to_string()),
+        (
+            "tool_change_time",
+            profile.tool_change_time.as_secs_f32().to_string(),
+        ),
+    ];
+    lines.extend(
+        limits
+            .junction_deviation
+            .map(|deviation| ("junction_deviation", deviation.to_string())),
+    );
+    lines.extend(
+        profile
+            .tool_change_gcode
+            .as_deref()
+            .map(|gcode| ("tool_change_gcode", escape(gcode))),
+    );
+    for tool in &profile.tools {
+        let offset = &tool.offset;
+        let mut value = join(&[offset.x().as_mm(), offset.y().as_mm(), offset.z().as_mm()]);
+        if let Some(thermal) = &tool.thermal {
+            value = format!("{},{}", value, heater_to_text(thermal));
+        }
+        lines.push(("tool", value));
+    }
+
+    lines
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+}
+
+/// Reads back [`to_text`] over the default profile; `None` if a value
+/// can't be parsed
+pub fn parse(text: &str) -> Option<MachineProfile> {
+    let mut profile = MachineProfile::default();
+    profile.limits.junction_deviation = None;
+    let mut tools = Vec::new();
+
+    for (key, value) in text.lines().filter_map(|line| line.split_once('=')) {
+        let number = || parsed::<f32>(Some(value));
+        let axes = || -> Option<[f32; 4]> { split::<f32>(value)?.try_into().ok() };
+        let limits = &mut profile.limits;
+        let retraction = &mut profile.retraction;
+        match key {
+            "max_acceleration" => limits.max_acceleration = axes()?,
+            "max_feedrate" => limits.max_feedrate = axes()?,
+            "print_acceleration" => limits.print_acceleration = number()?,
+            "retract_acceleration" => limits.retract_acceleration = number()?,
+            "travel_acceleration" => limits.travel_acceleration = number()?,
+            "junction_deviation" => limits.junction_deviation = Some(number()?),
+            "jerk" => limits.jerk = axes()?,
+            "min_feedrate" => limits.min_feedrate = number()?,
+            "min_travel_feedrate" => limits.min_travel_feedrate = number()?,
+            "retract_length" => retraction.length = number()?,
+            "retract_feedrate" => retraction.feedrate = number()?,
+            "retract_z_lift" => retraction.z_lift = number()?,
+            "recover_extra" => retraction.recover_extra = number()?,
+            "recover_feedrate" => retraction.recover_feedrate = number()?,
+            "ambient_temp" => profile.ambient_temp = number()?,
+            "bed_thermal" => profile.bed_thermal = parse_heater(value)?,
+            "hotend_thermal" => profile.hotend_thermal = parse_heater(value)?,
+            "idle_w" => profile.power.idle_w = number()?,
+            "motors_w" => profile.power.motors_w = number()?,
+            "tool_change_time" => {
+                profile.tool_change_time = Duration::try_from_secs_f32(number()?).ok()?
+            }
+            "tool_change_gcode" => profile.tool_change_gcode = Some(unescape(value)),
+            "tool" => {
+                let mut values = value.splitn(4, ',');
+                let mut axis = || parsed::<f32>(values.next());
+                let offset = Position::from_mm(axis()?, axis()?, axis()?);
+                let thermal = match values.next() {
+                    Some(heater) => Some(parse_heater(heater)?),
+                    None => None,
+                };
+                tools.push(ToolProfile { offset, thermal });
+            }
+            _ => {}
+        }
+    }
+
+    if !tools.is_empty() {
+        profile.tools = tools;
+    }
+    Some(profile)
+}
+
+/// Machine profiles on disk, one file per printer, so calibrations outlive
+/// the process that ran them
+#[derive(Debug, Clone)]
+pub struct ProfileStore {
+    dir: PathBuf,
+}
+
+impl Default for ProfileStore {
+    fn default() -> Self {
+        Self::new(state_dir().join("profiles"))
+    }
+}
+
+impl ProfileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, printer_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.{}", printer_id, EXTENSION))
+    }
+
+    /// Replaces the printer's profile, written aside and renamed so a crash
+    /// mid-write keeps the old one
+    pub fn save(&self, printer_id: Uuid, profile: &MachineProfile) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(printer_id);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, to_text(profile))?;
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    /// Every profile that can be read back, by printer
+    pub fn load(&self) -> Result<Vec<(Uuid, MachineProfile)>> {
+        let mut profiles = Vec::new();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(profiles),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != EXTENSION) {
+                continue;
+            }
+            let id = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<Uuid>().ok());
+            let text = fs::read_to_string(&path)?;
+            if let (Some(id), Some(profile)) = (id, parse(&text)) {
+                profiles.push((id, profile));
+            }
+        }
+        Ok(profiles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_survive_reload() {
+        let store = ProfileStore::new(std::env::temp_dir().join(Uuid::new_v4().to_string()));
+        let mut profile = MachineProfile::default();
+        profile.limits.max_feedrate = [300.0, 300.0, 12.5, 60.0];
+        profile.limits.junction_deviation = None;
+        profile.retraction.z_lift = 0.4;
+        profile.bed_thermal = HeaterModel::Lumped(LumpedThermalModel {
+            ambient: 21.5,
+            power_w: 220.0,
+            loss_coeff: 1.25,
+            heat_capacity: 900.0,
+        });
+        profile.hotend_thermal = HeaterModel::Controlled(ControlledThermalModel {
+            plant: profile.hotend_thermal.plant().clone(),
+            controller: Controller::Pid {
+                kp: 22.2,
+                ki: 1.08,
+                kd: 114.0,
+            },
+            window: 1.0,
+            hysteresis: 3.0,
+            residency: Duration::from_secs(10),
+            step: Duration::from_millis(50),
+            max_settle: Duration::from_secs(600),
+        });
+        profile.tools = vec![
+            ToolProfile::default(),
+            ToolProfile {
+                offset: Position::from_mm(18.0, -0.5, 0.1),
+                thermal: Some(profile.bed_thermal.clone()),
+            },
+        ];
+        profile.tool_change_gcode = Some("G1 Z5\nT{tool}".to_string());
+
+        let printer_id = Uuid::new_v4();
+        store.save(printer_id, &profile).unwrap();
+        let loaded = store.load().unwrap();
+
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].0, printer_id);
+        let reloaded = &loaded[0].1;
+        assert_eq!(reloaded.limits, profile.limits);
+        assert_eq!(reloaded.retraction, profile.retraction);
+        assert_eq!(reloaded.tools.len(), 2);
+        assert_eq!(reloaded.tool_change_gcode, profile.tool_change_gcode);
+        assert!(matches!(
+            reloaded.hotend_thermal,
+            HeaterModel::Controlled(ControlledThermalModel {
+                controller: Controller::Pid { kp, .. },
+                ..
+            }) if kp == 22.2
+        ));
+        assert_eq!(to_text(reloaded), to_text(&profile));
+    }
+
+    #[test]
+    fn malformed_profiles_are_skipped() {
+        assert!(parse("max_feedrate=1,2,3\n").is_none());
+        assert!(parse("bed_thermal=controlled,20,200,1,900\n").is_none());
+        assert!(parse("retract_length=2.5\n").is_some());
+    }
+}
@@ -1,11 +1,23 @@
-use clap::{Parser, Subcommand};
+use chrono::NaiveDate;
+use clap::{Args, Parser, Subcommand};
 use std::net::IpAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 use uuid::Uuid;
 
+use printctl_ui::features::lint::{Firmware, Rule};
+
+use crate::agent::models::LayerAction;
+use crate::printer::watchdog::WatchdogConfig;
+
 #[derive(Parser)]
 #[command(version, about = "Print Agent Controller")]
 pub struct Cli {
+    /// Materials and electricity rate jobs are billed with, `pricing.conf`
+    /// in the state directory if it exists
+    #[arg(long, global = true, value_name = "FILE", env = "PRINTCTL_PRICING")]
+    pub pricing: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -34,17 +46,281 @@ pub enum Command {
     UploadGcode {
         #[arg(value_name = "FILE")]
         file: PathBuf,
+
+        /// Library folder to store the file in
+        #[arg(short, long)]
+        folder: Option<String>,
+
+        /// Tag to attach to the file (repeatable)
+        #[arg(short, long = "tag")]
+        tags: Vec<String>,
+    },
+
+    /// Convert between ASCII and binary (`.bgcode`) G-code
+    ///
+    /// The output is binary if its extension is `.bgcode`, ASCII otherwise.
+    ConvertGcode {
+        #[arg(value_name = "INPUT")]
+        input: PathBuf,
+
+        #[arg(value_name = "OUTPUT")]
+        output: PathBuf,
+    },
+
+    /// Rewrite a G-code file with a chain of transforms
+    ///
+    /// The result is simulated against the input and only written if
+    /// nothing but what the transforms should change did.
+    Transform {
+        #[arg(value_name = "INPUT")]
+        input: PathBuf,
+
+        #[arg(value_name = "OUTPUT")]
+        output: PathBuf,
+
+        /// Transform to apply, in order: offset=X,Y, feedrate=FACTOR,
+        /// hotend=TEMP, bed=TEMP, color-change=LAYER, pause=LAYER or
+        /// insert=LAYER:GCODE
+        #[arg(short = 't', long = "op", required = true)]
+        ops: Vec<String>,
+
+        /// Write the output even if verification fails
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// Check a G-code file for mistakes
+    ///
+    /// Exits with an error if any rule reports one, so scripts can gate on it.
+    Lint {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Firmware to check commands against: marlin or klipper
+        #[arg(long, default_value = "marlin")]
+        firmware: Firmware,
+
+        /// Rule to skip, e.g. travel-without-retraction
+        #[arg(short = 'A', long = "allow", value_name = "RULE")]
+        allow: Vec<Rule>,
+
+        /// Print the diagnostics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Compare two G-code files by what they print
+    ///
+    /// Both are simulated; commands are compared layer by layer and by
+    /// feature within a layer, ignoring comments and formatting.
+    Diff {
+        #[arg(value_name = "BEFORE")]
+        before: PathBuf,
+
+        #[arg(value_name = "AFTER")]
+        after: PathBuf,
+
+        /// Only show the totals, layers and features, not the commands
+        #[arg(short, long)]
+        stat: bool,
+    },
+
+    /// List uploaded G-code files
+    ListFiles {
+        /// Only show files with this tag
+        #[arg(short, long)]
+        tag: Option<String>,
+
+        /// Only show files in this folder
+        #[arg(short, long)]
+        folder: Option<String>,
+    },
+
+    /// Show G-code file details and slicer metadata
+    ShowFile {
+        #[arg(value_name = "ID")]
+        gcode_id: Uuid,
+    },
+
+    /// Simulate a G-code file and print motion and extrusion statistics
+    Analyze {
+        /// Path to a G-code file or the ID of an uploaded one
+        #[arg(value_name = "FILE|ID")]
+        target: String,
+
+        /// Number of tools (extruders or MMU slots) the printer has
+        #[arg(long, default_value_t = 1)]
+        tools: usize,
+
+        /// Also print time, filament and moves of every layer
+        #[arg(long)]
+        layers: bool,
+    },
+
+    /// Heat and cool a printer's heaters to fit their thermal models
+    ///
+    /// Start with the heaters cold. Each heater is driven to its calibration
+    /// temperature and then left to cool while temperatures are recorded.
+    CalibrateThermal {
+        /// ID the fitted device profile is stored under
+        #[arg(value_name = "PRINTER")]
+        printer_id: Uuid,
+
+        /// Serial port the printer is connected to
+        #[arg(short, long)]
+        port: String,
+
+        #[arg(short, long, default_value_t = 115200)]
+        baud: u32,
+
+        /// Hotend calibration temperature in °C, 0 skips the hotend
+        #[arg(long, default_value_t = 200.0)]
+        hotend_temp: f32,
+
+        /// Rated hotend heater power in W
+        #[arg(long, default_value_t = 40.0)]
+        hotend_power: f32,
+
+        /// Bed calibration temperature in °C, 0 skips the bed
+        #[arg(long, default_value_t = 60.0)]
+        bed_temp: f32,
+
+        /// Rated bed heater power in W
+        #[arg(long, default_value_t = 200.0)]
+        bed_power: f32,
+
+        /// Seconds to record each cool-down for
+        #[arg(long, default_value_t = 120)]
+        cool_time: u64,
     },
 
-    /// Queue a print job
+    /// Delete an uploaded G-code file
+    DeleteFile {
+        #[arg(value_name = "ID")]
+        gcode_id: Uuid,
+    },
+
+    /// Add or remove tags on an uploaded G-code file
+    TagFile {
+        #[arg(value_name = "ID")]
+        gcode_id: Uuid,
+
+        #[arg(value_name = "TAG", required = true)]
+        tags: Vec<String>,
+
+        /// Remove the tags instead of adding them
+        #[arg(short, long)]
+        remove: bool,
+    },
+
+    /// Print an uploaded G-code file
+    ///
+    /// Connects to the printer and streams the job to it, reporting progress
+    /// until the job ends.
     QueueJob {
         #[arg(short, long)]
         printer_id: Uuid,
 
         #[arg(short, long)]
         gcode_id: Uuid,
+
+        /// Serial port the printer is connected to
+        #[arg(long)]
+        port: String,
+
+        #[arg(short, long, default_value_t = 115200)]
+        baud: u32,
+
+        /// Action before a layer starts, one-based: LAYER:change (M600),
+        /// LAYER:pause (held until Enter is pressed) or LAYER:GCODE
+        #[arg(long = "at", value_name = "LAYER:ACTION")]
+        layer_actions: Vec<LayerAction>,
+
+        /// Resume a failed print from this line (one-based)
+        #[arg(long, value_name = "LINE", conflicts_with = "from_z")]
+        from_line: Option<usize>,
+
+        /// Resume a failed print from the first layer at or above this height
+        #[arg(long, value_name = "MM")]
+        from_z: Option<f32>,
+
+        #[command(flatten)]
+        watchdog: WatchdogArgs,
+    },
+
+    /// List jobs interrupted by a crash or power loss, or resume one
+    ///
+    /// Resuming streams the rest of the job to the same printer, after a
+    /// preamble that restores the saved machine state.
+    RecoverJobs {
+        /// Interrupted job to resume
+        #[arg(long, value_name = "JOB", requires_all = ["file", "port"])]
+        resume: Option<Uuid>,
+
+        /// G-code the job was printing
+        #[arg(long, value_name = "FILE")]
+        file: Option<PathBuf>,
+
+        /// Serial port the printer is connected to
+        #[arg(long)]
+        port: Option<String>,
+
+        #[arg(short, long, default_value_t = 115200)]
+        baud: u32,
+
+        #[command(flatten)]
+        watchdog: WatchdogArgs,
     },
 
     /// Show job list
     ListJobs,
+
+    /// Total filament and energy costs of completed jobs per printer
+    CostReport {
+        /// First day of the period
+        #[arg(long)]
+        since: Option<NaiveDate>,
+
+        /// Last day of the period
+        #[arg(long)]
+        until: Option<NaiveDate>,
+    },
+}
+
+/// Limits of the host-side thermal watchdog, which stops the printer when
+/// they are exceeded
+#[derive(Args)]
+#[command(next_help_heading = "Thermal watchdog")]
+pub struct WatchdogArgs {
+    /// Seconds a heater may stay far below target without heating up
+    #[arg(long, value_name = "SECS", default_value_t = WatchdogConfig::default().stall_window.as_secs())]
+    pub stall_window: u64,
+
+    /// Degrees below target that count as far below
+    #[arg(long, value_name = "°C", default_value_t = WatchdogConfig::default().stall_margin)]
+    pub stall_margin: f32,
+
+    /// Smallest rise within the stall window that counts as heating up
+    #[arg(long, value_name = "°C", default_value_t = WatchdogConfig::default().stall_min_rise)]
+    pub stall_min_rise: f32,
+
+    /// Largest drop between two reports while a target is set
+    #[arg(long, value_name = "°C", default_value_t = WatchdogConfig::default().drop_threshold)]
+    pub drop_threshold: f32,
+
+    /// Seconds temperature reports may stop while heaters are on
+    #[arg(long, value_name = "SECS", default_value_t = WatchdogConfig::default().report_timeout.as_secs())]
+    pub report_timeout: u64,
+}
+
+impl From<WatchdogArgs> for WatchdogConfig {
+    fn from(args: WatchdogArgs) -> Self {
+        Self {
+            stall_window: Duration::from_secs(args.stall_window),
+            stall_margin: args.stall_margin,
+            stall_min_rise: args.stall_min_rise,
+            drop_threshold: args.drop_threshold,
+            report_timeout: Duration::from_secs(args.report_timeout),
+        }
+    }
 }
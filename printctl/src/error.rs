@@ -1,3 +1,4 @@
+use crate::printer::watchdog::Heater;
 use crate::printer::PrinterCommand;
 
 #[derive(Debug, thiserror::Error)]
@@ -8,9 +9,45 @@ pub enum Error {
     #[error("serial port error: {0}")]
     Serial(#[from] tokio_serial::Error),
 
+    #[error(transparent)]
+    GCode(#[from] printctl_ui::Error),
+
     #[error("Printer is not connected")]
     NotConnected,
 
+    #[error("G-code file {0} not found")]
+    FileNotFound(uuid::Uuid),
+
+    #[error("G-code file {0} is used by a queued or running job")]
+    FileInUse(uuid::Uuid),
+
+    #[error("Layer {0} not found, the G-code has {1}")]
+    LayerNotFound(usize, usize),
+
+    #[error("Cannot resume from {0}, the G-code does not reach it")]
+    ResumePoint(printctl_ui::features::resume::ResumePoint),
+
+    #[error("No checkpoint saved for job {0}")]
+    CheckpointNotFound(uuid::Uuid),
+
+    #[error("G-code is not the file job {0} was printing")]
+    CheckpointMismatch(uuid::Uuid),
+
+    #[error("Thermal calibration of {0} failed: {1}")]
+    Calibration(Heater, &'static str),
+
+    #[error("Job {0} failed: {1}")]
+    JobFailed(uuid::Uuid, String),
+
+    #[error("Invalid pricing on line {0}: {1:?}")]
+    Pricing(usize, String),
+
+    #[error("Transformed G-code failed verification: {0}")]
+    Verification(String),
+
+    #[error("G-code has {0} lint errors")]
+    Lint(usize),
+
     #[error("Channel send error")]
     Recv(#[from] tokio::sync::oneshot::error::RecvError),
 
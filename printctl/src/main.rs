@@ -8,16 +8,41 @@ mod printer;
 use crate::prelude::*;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> std::process::ExitCode {
+    match run().await {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<()> {
     use clap::Parser;
     use tokio::fs;
 
+    use agent::library::FileLibrary;
     use agent::PrintAgent;
     use cli::{Cli, Command};
+    use printctl_ui::features::bgcode;
+    use printctl_ui::features::diff::ProgramDiff;
+    use printctl_ui::features::lint::{lint, to_json, LintConfig, Severity};
+    use printctl_ui::features::metadata::SlicerMetadata;
+    use printctl_ui::features::profile::MachineProfile;
+    use printctl_ui::features::program::GCodeProgram;
+    use printctl_ui::features::progress::format_duration;
+    use printctl_ui::features::resume::ResumePoint;
+    use printctl_ui::features::source::GCodeSource;
+    use printctl_ui::features::statistics::{ProgramStatistics, MAX_RETRACTIONS_PER_LAYER};
+    use printctl_ui::features::transform::{parse_transform, Pipeline};
+    use printer::calibration::CalibrationCycle;
+    use printer::watchdog::WatchdogConfig;
 
     let cli = Cli::parse();
     let agent_name = hostname::get()?.into_string().unwrap_or("localhost".into());
-    let mut local_agent = PrintAgent::new(&agent_name);
+    let mut local_agent = PrintAgent::with_library(&agent_name, FileLibrary::default())?;
+    local_agent.set_pricing(agent::pricing::load(cli.pricing.as_deref())?);
 
     match cli.command {
         Command::Ui {
@@ -41,28 +66,443 @@ async fn main() -> Result<()> {
             println!("{:#?}", devices);
         }
 
-        Command::UploadGcode { file } => {
+        Command::UploadGcode { file, folder, tags } => {
             let bytes = fs::read(&file).await?;
             let file_name = file.file_name().expect("Could not determine filename");
 
-            let id = local_agent.upload_gcode(file_name, bytes);
+            let id = local_agent.upload_gcode(file_name, bytes)?;
+            local_agent.move_file(id, folder)?;
+            local_agent.tag_file(id, &tags, false)?;
             println!("Uploaded GCODE as ID {}", id);
         }
 
+        Command::ConvertGcode { input, output } => {
+            let bytes = fs::read(&input).await?;
+            let src = bgcode::to_ascii(&bytes)?;
+
+            let converted = if output.extension().is_some_and(|ext| ext == "bgcode") {
+                bgcode::BinaryGCode::from_ascii(&src).encode()
+            } else {
+                src.into_owned().into_bytes()
+            };
+            fs::write(&output, &converted).await?;
+            println!(
+                "Converted {} ({} bytes) to {} ({} bytes)",
+                input.display(),
+                bytes.len(),
+                output.display(),
+                converted.len()
+            );
+        }
+
+        Command::Transform {
+            input,
+            output,
+            ops,
+            force,
+        } => {
+            let mut pipeline = Pipeline::new();
+            for op in &ops {
+                pipeline.push(parse_transform(op)?);
+            }
+
+            let bytes = fs::read(&input).await?;
+            let src = bgcode::to_ascii(&bytes)?;
+            let transformed = pipeline.apply(&src);
+
+            let check = pipeline.verify(&src, &transformed, &MachineProfile::default());
+            for issue in &check.issues {
+                eprintln!("warning: {}", issue);
+            }
+            if !check.is_ok() && !force {
+                return Err(Error::Verification(check.issues.join("; ")));
+            }
+
+            fs::write(&output, &transformed).await?;
+            println!(
+                "Transformed {} to {}, print time {} -> {}",
+                input.display(),
+                output.display(),
+                format_duration(check.before.total_time),
+                format_duration(check.after.total_time)
+            );
+        }
+
+        Command::Lint {
+            file,
+            firmware,
+            allow,
+            json,
+        } => {
+            let bytes = fs::read(&file).await?;
+            let src = bgcode::to_ascii(&bytes)?;
+            let mut config = LintConfig {
+                firmware,
+                ..Default::default()
+            };
+            for rule in &allow {
+                config.rules.remove(rule);
+            }
+
+            let program = GCodeProgram::new(&src);
+            let diagnostics = lint(&program, &MachineProfile::default(), &config);
+            if json {
+                println!("{}", to_json(&diagnostics));
+            } else {
+                for diagnostic in &diagnostics {
+                    println!("{}: {}", file.display(), diagnostic);
+                }
+            }
+
+            let errors = diagnostics
+                .iter()
+                .filter(|d| d.severity() == Severity::Error)
+                .count();
+            if errors > 0 {
+                return Err(Error::Lint(errors));
+            }
+        }
+
+        Command::Diff {
+            before,
+            after,
+            stat,
+        } => {
+            let mut programs = Vec::new();
+            for path in [&before, &after] {
+                let bytes = fs::read(path).await?;
+                programs.push(GCodeProgram::new(&bgcode::to_ascii(&bytes)?));
+            }
+
+            let diff = ProgramDiff::new(&programs[0], &programs[1], &MachineProfile::default());
+            println!("--- {}\n+++ {}", before.display(), after.display());
+            print!("{}", diff);
+            if diff.is_empty() {
+                println!("No differences in commands");
+            }
+            if !stat {
+                for hunk in &diff.hunks {
+                    print!("{}", hunk);
+                }
+            }
+        }
+
+        Command::ListFiles { tag, folder } => {
+            let files = local_agent.list_files().filter(|g| {
+                tag.as_ref().is_none_or(|tag| g.tags.contains(tag))
+                    && folder.as_ref().is_none_or(|f| g.folder.as_ref() == Some(f))
+            });
+
+            for g in files {
+                println!(
+                    "{}  {}  {} bytes  {}  [{}]",
+                    g.id,
+                    g.path(),
+                    g.content.len(),
+                    g.uploaded_at,
+                    g.tags.iter().cloned().collect::<Vec<_>>().join(", ")
+                );
+            }
+        }
+
+        Command::ShowFile { gcode_id } => {
+            let g = local_agent
+                .get_file(gcode_id)
+                .ok_or(Error::FileNotFound(gcode_id))?;
+            let meta = &g.metadata;
+
+            println!("ID:          {}", g.id);
+            println!("Path:        {}", g.path());
+            println!("Size:        {} bytes", g.content.len());
+            println!("SHA-256:     {}", g.hash);
+            println!("Uploaded:    {}", g.uploaded_at);
+            println!(
+                "Tags:        {}",
+                g.tags.iter().cloned().collect::<Vec<_>>().join(", ")
+            );
+            println!("Slicer:      {:?}", meta.slicer);
+            println!(
+                "Est. time:   {:?}",
+                meta.estimated_time.map(format_duration)
+            );
+            println!("Filament:    {:?} mm", meta.filament_length_mm);
+            println!("Weight:      {:?} g", meta.filament_weight_g);
+            println!("Layer:       {:?} mm", meta.layer_height_mm);
+            println!("Material:    {:?}", meta.material);
+            println!("Cost:        {}", local_agent.file_cost(gcode_id, None)?);
+
+            for (label, thumbnail) in [
+                ("small", &g.thumbnails.small),
+                ("large", &g.thumbnails.large),
+            ] {
+                if let Some(t) = thumbnail {
+                    println!(
+                        "Thumbnail:   {} {}x{} {}",
+                        label,
+                        t.width,
+                        t.height,
+                        t.format.mime_type()
+                    );
+                }
+            }
+        }
+
+        Command::Analyze {
+            target,
+            tools,
+            layers,
+        } => {
+            let uploaded = target.parse().ok().and_then(|id| local_agent.get_file(id));
+
+            // large files are mapped and simulated in chunks
+            let source = match uploaded {
+                Some(file) => GCodeSource::new(file.source().into_owned()),
+                None => GCodeSource::open(&target)?,
+            };
+            let program = GCodeProgram::open(std::sync::Arc::new(source));
+
+            let stats =
+                ProgramStatistics::from_program(&program, &MachineProfile::with_tools(tools));
+            println!("{}", stats);
+            let material = SlicerMetadata::parse(program.source().as_str()).material;
+            let cost = local_agent.pricing().estimate(&stats, material.as_deref());
+            println!("Cost:         {}", cost);
+
+            for (i, layer) in stats.excessive_retractions(MAX_RETRACTIONS_PER_LAYER) {
+                eprintln!(
+                    "warning: layer {} retracts {} times",
+                    i + 1,
+                    layer.retractions
+                );
+            }
+
+            if layers {
+                println!();
+                for (i, layer) in stats.layers.iter().enumerate() {
+                    println!("{:>5} {}", i + 1, layer);
+                }
+            }
+        }
+
+        Command::CalibrateThermal {
+            printer_id,
+            port,
+            baud,
+            hotend_temp,
+            hotend_power,
+            bed_temp,
+            bed_power,
+            cool_time,
+        } => {
+            let cool_time = std::time::Duration::from_secs(cool_time);
+            let mut cycles = Vec::new();
+            if hotend_temp > 0.0 {
+                cycles.push(CalibrationCycle::hotend(
+                    0,
+                    hotend_temp,
+                    hotend_power,
+                    cool_time,
+                ));
+            }
+            if bed_temp > 0.0 {
+                cycles.push(CalibrationCycle::bed(bed_temp, bed_power, cool_time));
+            }
+
+            local_agent
+                .connect_printer(printer_id, &port, baud, WatchdogConfig::default())
+                .await?;
+
+            let profile = local_agent.calibrate_thermal(printer_id, &cycles).await?;
+            for (label, model, calibrated) in [
+                ("Hotend", profile.hotend_thermal.plant(), hotend_temp > 0.0),
+                ("Bed", profile.bed_thermal.plant(), bed_temp > 0.0),
+            ] {
+                if calibrated {
+                    println!(
+                        "{:<8}{:.1} W, loss {:.3} W/K, capacity {:.1} J/K, ambient {:.1}°C",
+                        label, model.power_w, model.loss_coeff, model.heat_capacity, model.ambient
+                    );
+                }
+            }
+        }
+
+        Command::DeleteFile { gcode_id } => {
+            let g = local_agent.delete_file(gcode_id)?;
+            println!("Deleted {}", g.path());
+        }
+
+        Command::TagFile {
+            gcode_id,
+            tags,
+            remove,
+        } => {
+            local_agent.tag_file(gcode_id, &tags, remove)?;
+            println!("Updated tags of {}", gcode_id);
+        }
+
         Command::QueueJob {
             printer_id,
             gcode_id,
+            port,
+            baud,
+            layer_actions,
+            from_line,
+            from_z,
+            watchdog,
         } => {
-            let id = local_agent.create_job(printer_id, gcode_id);
+            let resume = match (from_line, from_z) {
+                (Some(line), _) => Some(ResumePoint::Line(line.saturating_sub(1))),
+                (None, Some(z)) => Some(ResumePoint::Z(z)),
+                (None, None) => None,
+            };
+            let id = local_agent.create_job(printer_id, gcode_id, layer_actions, resume)?;
             println!("Queued job {}", id);
+
+            local_agent
+                .connect_printer(printer_id, &port, baud, watchdog.into())
+                .await?;
+            local_agent.dispatch_jobs().await?;
+            follow_job(&mut local_agent, id).await?;
+        }
+
+        Command::RecoverJobs {
+            resume,
+            file,
+            port,
+            baud,
+            watchdog,
+        } => {
+            let checkpoints = local_agent.checkpoints().clone();
+            let (Some(job_id), Some(file), Some(port)) = (resume, file, port) else {
+                let interrupted = checkpoints.list().await?;
+                if interrupted.is_empty() {
+                    println!("No interrupted jobs");
+                }
+                for checkpoint in interrupted {
+                    println!(
+                        "{}  {}  line {}  Z {:.2} mm  saved {}",
+                        checkpoint.job_id,
+                        checkpoint.file_name,
+                        checkpoint.line + 1,
+                        checkpoint.state.z,
+                        checkpoint.saved_at
+                    );
+                    println!(
+                        "    resume with: printctl recover-jobs --resume {} --file {} --port PORT",
+                        checkpoint.job_id, checkpoint.file_name
+                    );
+                }
+                return Ok(());
+            };
+
+            let checkpoint = checkpoints.load(job_id).await?;
+            let bytes = fs::read(&file).await?;
+            let file_name = file.file_name().expect("Could not determine filename");
+            let gcode_id = local_agent.upload_gcode(file_name, bytes)?;
+
+            let id = local_agent.recover_job(&checkpoint, gcode_id)?;
+            // the new job saves checkpoints of its own
+            checkpoints.remove(job_id).await?;
+            println!(
+                "Queued job {} resuming {} from line {}",
+                id,
+                job_id,
+                checkpoint.line + 1
+            );
+
+            local_agent
+                .connect_printer(checkpoint.printer_id, &port, baud, watchdog.into())
+                .await?;
+            local_agent.dispatch_jobs().await?;
+            follow_job(&mut local_agent, id).await?;
         }
 
         Command::ListJobs => {
+            local_agent.refresh_jobs().await?;
             for job in local_agent.list_jobs() {
                 println!("{:?}", job);
+                if let Some(progress) = &job.progress {
+                    println!("    {}", progress);
+                }
+                if let Some(cost) = &job.cost {
+                    println!("    {}", cost);
+                }
+                for action in &job.layer_actions {
+                    println!("    {}", action);
+                }
+            }
+        }
+
+        Command::CostReport { since, until } => {
+            let day = |date: chrono::NaiveDate| date.and_time(chrono::NaiveTime::MIN).and_utc();
+            let start = since
+                .map(day)
+                .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC);
+            let end = until
+                .and_then(|date| date.succ_opt())
+                .map(day)
+                .unwrap_or(chrono::DateTime::<chrono::Utc>::MAX_UTC);
+
+            local_agent.refresh_jobs().await?;
+            let report = local_agent.cost_report(start..end);
+            if report.is_empty() {
+                println!("No completed jobs in this period");
+            }
+            for (printer_id, cost) in report {
+                println!("{}  {}", printer_id, cost);
             }
         }
     }
 
     Ok(())
 }
+
+/// Reports the progress and thermal alerts of a dispatched job until it
+/// ends, resuming it from pause actions when Enter is pressed
+async fn follow_job(agent: &mut agent::PrintAgent, job_id: uuid::Uuid) -> Result<()> {
+    use agent::models::JobStatus;
+    use tokio::io::AsyncBufReadExt;
+
+    let Some(printer_id) = agent.get_job(job_id).map(|job| job.printer_id) else {
+        return Ok(());
+    };
+    let mut events = agent.printer_events(printer_id)?;
+    let mut stdin = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut tick = tokio::time::interval(std::time::Duration::from_secs(5));
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {}
+            Ok(printer::PrinterEvent::ThermalAlert(fault)) = events.recv() => {
+                eprintln!("Thermal alert: {}, printer stopped", fault);
+                // the worker has failed the job by now
+                tick.reset_immediately();
+                continue;
+            }
+        }
+        agent.refresh_jobs().await?;
+        let Some(job) = agent.get_job(job_id) else {
+            return Ok(());
+        };
+
+        match &job.status {
+            JobStatus::Queued => {}
+            JobStatus::Running => {
+                if let Some(progress) = &job.progress {
+                    println!("{}", progress);
+                }
+            }
+            JobStatus::Paused => {
+                println!("Job {} paused, press Enter to resume", job_id);
+                stdin.next_line().await?;
+                agent.resume_job(printer_id).await?;
+                // give the printer a full period to pick the job back up
+                tick.reset();
+            }
+            JobStatus::Completed => {
+                println!("Job {} completed", job_id);
+                return Ok(());
+            }
+            JobStatus::Failed(reason) => return Err(Error::JobFailed(job_id, reason.clone())),
+        }
+    }
+}
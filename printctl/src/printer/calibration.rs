@@ -0,0 +1,147 @@
+use crate::prelude::*;
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
+
+use printctl_ui::features::thermal::{LumpedThermalModel, ThermalSample};
+
+use super::state::PrinterState;
+use super::watchdog::Heater;
+use super::Printer;
+
+// firmware regulation kicks in short of the target, those samples are not
+// taken at full power
+const REGULATION_MARGIN: f32 = 10.0;
+
+/// Heat/cool cycle run on one heater to fit its thermal model
+#[derive(Debug, Clone)]
+pub struct CalibrationCycle {
+    pub heater: Heater,
+    /// °C to heat towards
+    pub target: f32,
+    /// Rated heater power, W
+    pub power_w: f32,
+    /// How long to record the cool-down for
+    pub cool_time: Duration,
+    /// How long heating may take before giving up
+    pub heat_timeout: Duration,
+}
+
+impl CalibrationCycle {
+    pub fn hotend(tool: usize, target: f32, power_w: f32, cool_time: Duration) -> Self {
+        Self {
+            heater: Heater::Tool(tool),
+            target,
+            power_w,
+            cool_time,
+            heat_timeout: Duration::from_secs(5 * 60),
+        }
+    }
+
+    pub fn bed(target: f32, power_w: f32, cool_time: Duration) -> Self {
+        Self {
+            heater: Heater::Bed,
+            target,
+            power_w,
+            cool_time,
+            heat_timeout: Duration::from_secs(15 * 60),
+        }
+    }
+
+    fn set_temp(&self, temp: f32) -> Vec<u8> {
+        match self.heater {
+            Heater::Bed => format!("M140 S{:.0}\n", temp),
+            Heater::Tool(idx) => format!("M104 T{} S{:.0}\n", idx, temp),
+        }
+        .into_bytes()
+    }
+}
+
+/// Temperature reports of one heater, parsed the same way the worker does
+struct TempReports {
+    lines: broadcast::Receiver<String>,
+    state: PrinterState,
+    heater: Heater,
+}
+
+impl TempReports {
+    async fn next(&mut self) -> Result<f32> {
+        loop {
+            let line = match self.lines.recv().await {
+                Ok(line) => line,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return Err(Error::NotConnected),
+            };
+
+            let reported = self.state.last_temp_report;
+            self.state.update_from_line(&line);
+            if self.state.last_temp_report != reported {
+                if let Some(temp) = self.state.temperature(self.heater) {
+                    return Ok(temp);
+                }
+            }
+        }
+    }
+}
+
+impl Printer {
+    /// Heats a heater at full power, lets it cool down and fits a
+    /// [`LumpedThermalModel`] to the recorded temperatures. The heater should
+    /// start out cold, its first reading is taken as ambient.
+    pub async fn calibrate_thermal(&self, cycle: &CalibrationCycle) -> Result<LumpedThermalModel> {
+        let mut reports = TempReports {
+            lines: self.subscribe(),
+            state: PrinterState::default(),
+            heater: cycle.heater,
+        };
+
+        // have the firmware report temperatures every second
+        self.write(b"M155 S1\n".to_vec()).await?;
+        let ambient = reports.next().await?;
+
+        let start = Instant::now();
+        let mut samples = vec![ThermalSample {
+            time: Duration::ZERO,
+            temp: ambient,
+            heating: true,
+        }];
+
+        self.write(cycle.set_temp(cycle.target)).await?;
+        let heated = tokio::time::timeout(cycle.heat_timeout, async {
+            loop {
+                let temp = reports.next().await?;
+                if temp >= cycle.target - REGULATION_MARGIN {
+                    return Ok::<_, Error>(());
+                }
+                samples.push(ThermalSample {
+                    time: start.elapsed(),
+                    temp,
+                    heating: true,
+                });
+            }
+        })
+        .await;
+
+        // never leave the heater on, whatever happened while heating
+        self.write(cycle.set_temp(0.0)).await?;
+        match heated {
+            Ok(result) => result?,
+            Err(_) => return Err(Error::Calibration(cycle.heater, "target not reached")),
+        }
+
+        let cooling = Instant::now();
+        while cooling.elapsed() < cycle.cool_time {
+            let temp = reports.next().await?;
+            samples.push(ThermalSample {
+                time: start.elapsed(),
+                temp,
+                heating: false,
+            });
+        }
+
+        LumpedThermalModel::fit(ambient, cycle.power_w, &samples).ok_or(Error::Calibration(
+            cycle.heater,
+            "no model fits the recording",
+        ))
+    }
+}
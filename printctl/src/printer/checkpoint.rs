@@ -0,0 +1,224 @@
+use crate::prelude::*;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use tokio::fs;
+use uuid::Uuid;
+
+use printctl_ui::features::resume::RestoreState;
+
+use crate::agent::library::{escape, join, parsed, split, state_dir, unescape};
+
+const EXTENSION: &str = "checkpoint";
+
+/// Last line of a streamed job the printer acknowledged and the simulated
+/// machine state after it, saved so the job survives a crash of the host
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub job_id: Uuid,
+    pub printer_id: Uuid,
+    pub file_name: String,
+    /// SHA-256 of the G-code, to tell the file apart when it is uploaded again
+    pub file_hash: String,
+    /// Zero-based source line to continue from
+    pub line: usize,
+    pub state: RestoreState,
+    pub saved_at: DateTime<Utc>,
+}
+
+impl Checkpoint {
+    /// `key=value` lines, the file name escaped to stay on one
+    pub fn to_text(&self) -> String {
+        let state = &self.state;
+        [
+            ("job", self.job_id.to_string()),
+            ("printer", self.printer_id.to_string()),
+            ("file", escape(&self.file_name)),
+            ("hash", self.file_hash.clone()),
+            ("line", self.line.to_string()),
+            ("saved_at", self.saved_at.to_rfc3339()),
+            ("x", state.x.to_string()),
+            ("y", state.y.to_string()),
+            ("z", state.z.to_string()),
+            ("e", state.e.to_string()),
+            ("feedrate", state.feedrate_mm_per_min.to_string()),
+            ("tool", state.active_tool.to_string()),
+            ("tool_temps", join(&state.tool_temps)),
+            ("bed_temp", state.bed_temp.to_string()),
+            ("fan", state.fan_speed.to_string()),
+            ("aux_fans", join(&state.aux_fan_speeds)),
+            ("relative", state.relative.to_string()),
+            ("relative_e", state.relative_e.to_string()),
+            ("inches", state.inches.to_string()),
+        ]
+        .iter()
+        .map(|(key, value)| format!("{}={}\n", key, value))
+        .collect()
+    }
+
+    /// Reads back [`Checkpoint::to_text`]; `None` if anything is missing
+    pub fn parse(text: &str) -> Option<Self> {
+        let fields = text
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .collect::<std::collections::HashMap<_, _>>();
+        let field = |key: &str| fields.get(key).copied();
+
+        Some(Self {
+            job_id: parsed(field("job"))?,
+            printer_id: parsed(field("printer"))?,
+            file_name: unescape(field("file")?),
+            file_hash: field("hash")?.to_string(),
+            line: parsed(field("line"))?,
+            saved_at: DateTime::parse_from_rfc3339(field("saved_at")?)
+                .ok()?
+                .to_utc(),
+            state: RestoreState {
+                x: parsed(field("x"))?,
+                y: parsed(field("y"))?,
+                z: parsed(field("z"))?,
+                e: parsed(field("e"))?,
+                feedrate_mm_per_min: parsed(field("feedrate"))?,
+                active_tool: parsed(field("tool"))?,
+                tool_temps: split(field("tool_temps")?)?,
+                bed_temp: parsed(field("bed_temp"))?,
+                fan_speed: parsed(field("fan"))?,
+                aux_fan_speeds: split(field("aux_fans")?)?,
+                relative: parsed(field("relative"))?,
+                relative_e: parsed(field("relative_e"))?,
+                inches: parsed(field("inches"))?,
+            },
+        })
+    }
+}
+
+/// Directory holding one checkpoint file per interrupted or running job
+#[derive(Debug, Clone)]
+pub struct CheckpointStore {
+    dir: PathBuf,
+}
+
+impl Default for CheckpointStore {
+    fn default() -> Self {
+        Self::new(state_dir().join("checkpoints"))
+    }
+}
+
+impl CheckpointStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, job_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.{}", job_id, EXTENSION))
+    }
+
+    /// Replaces the job's checkpoint; written aside and renamed so a crash
+    /// mid-write leaves the previous one intact
+    pub async fn save(&self, checkpoint: &Checkpoint) -> Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let path = self.path(checkpoint.job_id);
+        let partial = path.with_extension("partial");
+        fs::write(&partial, checkpoint.to_text()).await?;
+        fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    pub async fn load(&self, job_id: Uuid) -> Result<Checkpoint> {
+        let text = fs::read_to_string(self.path(job_id))
+            .await
+            .map_err(|_| Error::CheckpointNotFound(job_id))?;
+        Checkpoint::parse(&text).ok_or(Error::CheckpointNotFound(job_id))
+    }
+
+    pub async fn remove(&self, job_id: Uuid) -> Result<()> {
+        match fs::remove_file(self.path(job_id)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every readable checkpoint, oldest first
+    pub async fn list(&self) -> Result<Vec<Checkpoint>> {
+        let mut checkpoints = Vec::new();
+        let mut entries = match fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(checkpoints),
+            Err(e) => return Err(e.into()),
+        };
+
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != EXTENSION) {
+                continue;
+            }
+            let text = fs::read_to_string(&path).await?;
+            checkpoints.extend(Checkpoint::parse(&text));
+        }
+
+        checkpoints.sort_by_key(|checkpoint| checkpoint.saved_at);
+        Ok(checkpoints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkpoint(file_name: &str) -> Checkpoint {
+        Checkpoint {
+            job_id: Uuid::new_v4(),
+            printer_id: Uuid::new_v4(),
+            file_name: file_name.to_string(),
+            file_hash: "ab12".to_string(),
+            line: 1234,
+            state: RestoreState {
+                x: 10.5,
+                y: -2.25,
+                z: 3.2,
+                e: 1024.125,
+                feedrate_mm_per_min: 1800.0,
+                active_tool: 1,
+                tool_temps: vec![0.0, 215.0],
+                bed_temp: 60.0,
+                fan_speed: 255,
+                aux_fan_speeds: vec![128],
+                relative: false,
+                relative_e: true,
+                inches: false,
+            },
+            saved_at: DateTime::parse_from_rfc3339("2026-01-02T03:04:05.678Z")
+                .unwrap()
+                .to_utc(),
+        }
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let saved = checkpoint("parts/cube.gcode");
+        assert_eq!(Checkpoint::parse(&saved.to_text()), Some(saved));
+
+        let mut empty = checkpoint("empty.gcode");
+        empty.state.tool_temps.clear();
+        empty.state.aux_fan_speeds.clear();
+        assert_eq!(Checkpoint::parse(&empty.to_text()), Some(empty));
+    }
+
+    #[test]
+    fn file_name_cannot_break_the_format() {
+        let saved = checkpoint("cube\nline=7\n.gcode");
+        let text = saved.to_text();
+
+        assert_eq!(text.lines().count(), 19);
+        assert_eq!(Checkpoint::parse(&text), Some(saved));
+    }
+
+    #[test]
+    fn incomplete_text_is_rejected() {
+        let text = checkpoint("cube.gcode").to_text();
+        let truncated: String = text.lines().take(5).map(|l| format!("{}\n", l)).collect();
+
+        assert_eq!(Checkpoint::parse(&truncated), None);
+        assert_eq!(Checkpoint::parse(""), None);
+    }
+}
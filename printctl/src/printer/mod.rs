@@ -1,21 +1,83 @@
+pub mod calibration;
+pub mod checkpoint;
 pub mod state;
+pub mod stream;
+pub mod watchdog;
 
 use crate::prelude::*;
+use std::time::{Duration, Instant};
 use std::{collections::VecDeque, sync::Arc};
 
+use chrono::Utc;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
 use tokio_serial::SerialPortBuilderExt;
 
 use crate::agent::models;
+use checkpoint::CheckpointStore;
 use state::PrinterState;
+use stream::{JobStream, StreamStep};
+use watchdog::{ThermalFault, ThermalWatchdog, WatchdogConfig};
+
+// how often the running job's checkpoint is saved
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+// temperatures are polled with M105 when the firmware has not reported for
+// this long, e.g. because it lacks M155 auto-reporting
+const TEMP_POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+// a poll still unanswered after this long is sent again, its answer may
+// still arrive late
+const TEMP_POLL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// M105 polls the worker sent and the firmware has not answered yet. A poll
+/// is repeated when the previous one times out, so several can be in flight.
+#[derive(Debug, Default)]
+struct TempPolls {
+    outstanding: usize,
+    last_sent: Option<Instant>,
+}
+
+impl TempPolls {
+    fn due(&self, now: Instant) -> bool {
+        self.outstanding == 0
+            || self
+                .last_sent
+                .is_none_or(|sent| now.duration_since(sent) >= TEMP_POLL_TIMEOUT)
+    }
+
+    fn sent(&mut self, now: Instant) {
+        self.outstanding += 1;
+        self.last_sent = Some(now);
+    }
+
+    /// Takes `line` as the answer to a poll if it is a temperature report
+    /// and one is outstanding. Reports beyond that answer an `M105` of the
+    /// job and ack its line like a plain `ok`.
+    fn answered(&mut self, line: &str) -> bool {
+        let report = line.starts_with("ok") && line.contains("T:");
+        if !report || self.outstanding == 0 {
+            return false;
+        }
+        self.outstanding -= 1;
+        true
+    }
+}
 
 #[derive(Debug)]
 pub enum PrinterCommand {
     Write(Vec<u8>, oneshot::Sender<Result<()>>),
     ReadLine(oneshot::Sender<Result<String>>),
-    QueueJob(models::Job),
+    QueueJob(Box<QueuedJob>),
     StartNextJob,
+    ResumeJob,
+}
+
+pub type QueuedJob = (models::Job, Arc<models::JobPlan>);
+
+#[derive(Debug, Clone)]
+pub enum PrinterEvent {
+    ThermalAlert(ThermalFault),
 }
 
 #[derive(Debug)]
@@ -27,7 +89,10 @@ pub struct Printer {
     pub state: Arc<Mutex<PrinterState>>,
 
     // queued print jobs
-    pub job_queue: Arc<Mutex<VecDeque<models::Job>>>,
+    pub job_queue: Arc<Mutex<VecDeque<QueuedJob>>>,
+
+    // job currently being printed
+    pub current_job: Arc<Mutex<Option<models::Job>>>,
 
     // command channel TO worker
     cmd_tx: mpsc::Sender<PrinterCommand>,
@@ -35,12 +100,23 @@ pub struct Printer {
     // raw serial lines FROM worker
     pub serial_rx: broadcast::Sender<String>,
 
+    // alerts raised by the worker
+    events: broadcast::Sender<PrinterEvent>,
+
     // serial connection (owned only by worker)
     connection: Arc<Mutex<Option<tokio_serial::SerialStream>>>,
+
+    // where the running job's progress is saved for recovery
+    checkpoints: CheckpointStore,
 }
 
 impl Printer {
-    pub async fn new(path: &str, baud: u32, tag: Option<String>) -> Result<Self> {
+    pub async fn new(
+        path: &str,
+        baud: u32,
+        tag: Option<String>,
+        watchdog: WatchdogConfig,
+    ) -> Result<Self> {
         let serial = tokio_serial::new(path, baud).open_native_async()?;
 
         // mpsc command channel to worker
@@ -49,19 +125,25 @@ impl Printer {
         // broadcast for serial lines (observers subscribe)
         let (serial_tx, _) = broadcast::channel(256);
 
+        // broadcast for printer events (alerts, etc)
+        let (events, _) = broadcast::channel(16);
+
         let printer = Self {
             tag,
             port_path: path.to_string(),
 
             state: Arc::new(Mutex::new(PrinterState::default())),
             job_queue: Arc::new(Mutex::new(VecDeque::new())),
+            current_job: Arc::new(Mutex::new(None)),
             connection: Arc::new(Mutex::new(Some(serial))),
 
             cmd_tx,
             serial_rx: serial_tx.clone(),
+            events,
+            checkpoints: CheckpointStore::default(),
         };
 
-        printer.spawn_worker(cmd_rx, serial_tx);
+        printer.spawn_worker(cmd_rx, serial_tx, ThermalWatchdog::new(watchdog));
 
         Ok(printer)
     }
@@ -70,14 +152,22 @@ impl Printer {
         &self,
         mut cmd_rx: mpsc::Receiver<PrinterCommand>,
         serial_tx: broadcast::Sender<String>,
+        mut watchdog: ThermalWatchdog,
     ) {
         let connection = self.connection.clone();
         let state = self.state.clone();
         let job_queue = self.job_queue.clone();
+        let current_job = self.current_job.clone();
+        let events = self.events.clone();
+        let checkpoints = self.checkpoints.clone();
 
         tokio::spawn(async move {
             let mut buf = [0u8; 1024];
             let mut line_buf = Vec::new();
+            let mut watchdog_tick = tokio::time::interval(Duration::from_secs(1));
+            let mut checkpoint_tick = tokio::time::interval(CHECKPOINT_INTERVAL);
+            let mut stream: Option<JobStream> = None;
+            let mut polls = TempPolls::default();
 
             loop {
                 let mut guard = connection.lock().await;
@@ -101,9 +191,30 @@ impl Printer {
                                 let _ = serial_tx.send(line.clone());
 
                                 // update printer state machine
-                                {
+                                let fault = {
                                     let mut st = state.lock().await;
                                     st.update_from_line(&line);
+                                    watchdog.check(&st, Instant::now())
+                                };
+
+                                if let Some(fault) = fault {
+                                    stream.take();
+                                    Self::emergency_stop(serial, &current_job, &events, fault).await;
+                                } else if polls.answered(&line) {
+                                    // answer to our own poll, not to a job line
+                                } else if line.starts_with("ok") {
+                                    // printer accepted the last job line -> send the next one
+                                    if let Some(active) = stream.as_mut() {
+                                        active.ack();
+                                        Self::stream_next(serial, &mut stream, &current_job, &checkpoints).await;
+
+                                        let completed = current_job.lock().await.as_ref().is_some_and(|job| {
+                                            matches!(job.status, models::JobStatus::Completed)
+                                        });
+                                        if completed {
+                                            Self::start_next(serial, &mut stream, &job_queue, &current_job, &checkpoints).await;
+                                        }
+                                    }
                                 }
 
                                 line_buf.clear();
@@ -113,6 +224,39 @@ impl Printer {
                         }
                     }
 
+                    // THERMAL WATCHDOG (catches reports that stop arriving)
+                    _ = watchdog_tick.tick() => {
+                        let (fault, stale) = {
+                            let st = state.lock().await;
+                            let stale = st
+                                .last_temp_report
+                                .is_none_or(|at| at.elapsed() >= TEMP_POLL_INTERVAL);
+                            (watchdog.check(&st, Instant::now()), stale)
+                        };
+                        if let Some(fault) = fault {
+                            stream.take();
+                            Self::emergency_stop(serial, &current_job, &events, fault).await;
+                            continue;
+                        }
+
+                        if stale && polls.due(Instant::now()) {
+                            if let Err(e) = serial.write_all(b"M105\n").await {
+                                eprintln!("Could not poll temperatures: {}", e);
+                            }
+                            polls.sent(Instant::now());
+                        }
+                    }
+
+                    // CHECKPOINT of the running job, for recovery after a crash
+                    _ = checkpoint_tick.tick() => {
+                        let (Some(active), Some(job)) = (stream.as_ref(), current_job.lock().await.clone()) else {
+                            continue;
+                        };
+                        if let Err(e) = checkpoints.save(&active.checkpoint(&job)).await {
+                            eprintln!("Could not save checkpoint of job {}: {}", job.id, e);
+                        }
+                    }
+
                     // COMMAND HANDLING
                     Some(cmd) = cmd_rx.recv() => {
                         match cmd {
@@ -142,14 +286,28 @@ impl Printer {
                                 }
                             }
 
-                            PrinterCommand::QueueJob(job) => {
-                                job_queue.lock().await.push_back(job);
+                            PrinterCommand::QueueJob(queued) => {
+                                job_queue.lock().await.push_back(*queued);
                             }
 
                             PrinterCommand::StartNextJob => {
-                                if let Some(job) = job_queue.lock().await.pop_front() {
-                                    println!("Starting job: {:?}", job);
+                                if stream.is_some() {
+                                    continue;
+                                }
+
+                                watchdog.reset();
+                                Self::start_next(serial, &mut stream, &job_queue, &current_job, &checkpoints).await;
+                            }
+
+                            PrinterCommand::ResumeJob => {
+                                if !stream.as_mut().is_some_and(JobStream::resume) {
+                                    continue;
+                                }
+
+                                if let Some(job) = current_job.lock().await.as_mut() {
+                                    job.status = models::JobStatus::Running;
                                 }
+                                Self::stream_next(serial, &mut stream, &current_job, &checkpoints).await;
                             }
                         }
                     }
@@ -157,6 +315,102 @@ impl Printer {
             }
         });
     }
+
+    /// Takes the next queued job and sends its first line
+    async fn start_next(
+        serial: &mut tokio_serial::SerialStream,
+        stream: &mut Option<JobStream>,
+        job_queue: &Mutex<VecDeque<QueuedJob>>,
+        current_job: &Mutex<Option<models::Job>>,
+        checkpoints: &CheckpointStore,
+    ) {
+        let Some((mut job, plan)) = job_queue.lock().await.pop_front() else {
+            return;
+        };
+        println!("Starting job: {}", job.id);
+        job.status = models::JobStatus::Running;
+        job.started_at = Some(Utc::now());
+        current_job.lock().await.replace(job);
+
+        stream.replace(JobStream::new(plan));
+        Self::stream_next(serial, stream, current_job, checkpoints).await;
+    }
+
+    /// Sends the next line of the running job, completing it when none are
+    /// left and marking it paused while a pause action holds it
+    async fn stream_next(
+        serial: &mut tokio_serial::SerialStream,
+        stream: &mut Option<JobStream>,
+        current_job: &Mutex<Option<models::Job>>,
+        checkpoints: &CheckpointStore,
+    ) {
+        let Some(active) = stream.as_mut() else {
+            return;
+        };
+
+        let command = active.next_command();
+        let mut current = current_job.lock().await;
+        let Some(job) = current.as_mut() else {
+            return;
+        };
+        job.progress = Some(active.progress());
+
+        let command = match command {
+            StreamStep::Send(command) => command,
+            StreamStep::Hold => {
+                job.status = models::JobStatus::Paused;
+                return;
+            }
+            StreamStep::Done => {
+                job.status = models::JobStatus::Completed;
+                job.finished_at = Some(Utc::now());
+                stream.take();
+                if let Err(e) = checkpoints.remove(job.id).await {
+                    eprintln!("Could not remove checkpoint of job {}: {}", job.id, e);
+                }
+                return;
+            }
+        };
+
+        let res = async {
+            serial.write_all(command.as_bytes()).await?;
+            serial.flush().await
+        }
+        .await;
+
+        if let Err(e) = res {
+            job.status = models::JobStatus::Failed(e.to_string());
+            job.finished_at = Some(Utc::now());
+            stream.take();
+        }
+    }
+
+    /// Halts the printer (M112), fails the running job and raises an alert
+    async fn emergency_stop(
+        serial: &mut tokio_serial::SerialStream,
+        current_job: &Mutex<Option<models::Job>>,
+        events: &broadcast::Sender<PrinterEvent>,
+        fault: ThermalFault,
+    ) {
+        eprintln!("Thermal watchdog tripped: {}", fault);
+
+        if let Err(e) = serial.write_all(b"M112\n").await {
+            eprintln!("Could not send emergency stop: {}", e);
+        }
+        let _ = serial.flush().await;
+
+        if let Some(job) = current_job.lock().await.as_mut() {
+            if matches!(
+                job.status,
+                models::JobStatus::Running | models::JobStatus::Paused
+            ) {
+                job.status = models::JobStatus::Failed(fault.to_string());
+                job.finished_at = Some(Utc::now());
+            }
+        }
+
+        let _ = events.send(PrinterEvent::ThermalAlert(fault));
+    }
 }
 
 impl Printer {
@@ -172,9 +426,35 @@ impl Printer {
         rx.await?
     }
 
-    pub async fn queue_job(&self, job: models::Job) -> Result<()> {
+    /// Polls the firmware until it answers with `ok` or `timeout` passes.
+    /// The probe turns on temperature auto-reporting (`M155`) where the
+    /// firmware has it, the worker polls `M105` otherwise.
+    pub async fn wait_ready(&self, timeout: Duration) -> Result<()> {
+        let mut lines = self.subscribe();
+        let deadline = Instant::now() + timeout;
+
+        while Instant::now() < deadline {
+            self.write(b"M155 S1\n".to_vec()).await?;
+            let answered = tokio::time::timeout(Duration::from_secs(1), async {
+                while let Ok(line) = lines.recv().await {
+                    if line.starts_with("ok") {
+                        return true;
+                    }
+                }
+                false
+            })
+            .await;
+            if answered == Ok(true) {
+                return Ok(());
+            }
+        }
+
+        Err(Error::NotConnected)
+    }
+
+    pub async fn queue_job(&self, job: models::Job, plan: Arc<models::JobPlan>) -> Result<()> {
         self.cmd_tx
-            .send(PrinterCommand::QueueJob(job))
+            .send(PrinterCommand::QueueJob(Box::new((job, plan))))
             .await
             .map_err(|e| Error::IO(std::io::Error::new(std::io::ErrorKind::BrokenPipe, e)))
     }
@@ -186,8 +466,59 @@ impl Printer {
             .map_err(|e| Error::IO(std::io::Error::new(std::io::ErrorKind::BrokenPipe, e)))
     }
 
+    /// Continues a job held by a pause action
+    pub async fn resume_job(&self) -> Result<()> {
+        self.cmd_tx
+            .send(PrinterCommand::ResumeJob)
+            .await
+            .map_err(|e| Error::IO(std::io::Error::new(std::io::ErrorKind::BrokenPipe, e)))
+    }
+
     /// Get a live stream of raw lines from the printer
     pub fn subscribe(&self) -> broadcast::Receiver<String> {
         self.serial_rx.subscribe()
     }
+
+    /// Get a live stream of alerts raised by the printer worker
+    pub fn subscribe_events(&self) -> broadcast::Receiver<PrinterEvent> {
+        self.events.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPORT: &str = "ok T:200.0 /200.0 B:60.0 /60.0";
+
+    #[test]
+    fn temperature_reports_answer_polls_first() {
+        let mut polls = TempPolls::default();
+        assert!(!polls.answered(REPORT));
+
+        let start = Instant::now();
+        polls.sent(start);
+        assert!(!polls.answered("ok"));
+        assert!(polls.answered(REPORT));
+        // a second report answers a job line of its own
+        assert!(!polls.answered(REPORT));
+    }
+
+    #[test]
+    fn repolls_after_a_timeout_are_each_answered() {
+        let mut polls = TempPolls::default();
+        let start = Instant::now();
+        assert!(polls.due(start));
+        polls.sent(start);
+        assert!(!polls.due(start + TEMP_POLL_TIMEOUT / 2));
+
+        // the first answer was only late, both arrive after the second poll
+        let later = start + TEMP_POLL_TIMEOUT;
+        assert!(polls.due(later));
+        polls.sent(later);
+        assert!(polls.answered(REPORT));
+        assert!(polls.answered(REPORT));
+        assert!(!polls.answered(REPORT));
+        assert!(polls.due(later));
+    }
 }
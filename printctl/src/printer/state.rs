@@ -1,4 +1,7 @@
 use std::collections::HashMap;
+use std::time::Instant;
+
+use super::watchdog::Heater;
 
 #[derive(Debug, Clone)]
 pub struct ToolState {
@@ -32,6 +35,7 @@ pub struct PrinterState {
     pub fan_speed: u8,
     pub ready: bool,
     pub last_error: Option<String>,
+    pub last_temp_report: Option<Instant>,
 }
 
 impl Default for PrinterState {
@@ -42,6 +46,7 @@ impl Default for PrinterState {
             fan_speed: 0,
             ready: false,
             last_error: None,
+            last_temp_report: None,
         }
     }
 }
@@ -59,10 +64,9 @@ impl PrinterState {
             return;
         }
 
-        // OK = printer ready
+        // OK = printer ready (M105 replies arrive as `ok T:...`, so keep parsing)
         if raw.starts_with("ok") {
             self.ready = true;
-            return;
         }
 
         // M105 Temperature Report
@@ -87,7 +91,25 @@ impl PrinterState {
         }
     }
 
+    /// Returns true if any heater has a non-zero target
+    pub fn heaters_on(&self) -> bool {
+        self.bed.target > 0.0 || self.tools.values().any(|tool| tool.target > 0.0)
+    }
+
+    /// Last reported temperature of `heater`
+    pub fn temperature(&self, heater: Heater) -> Option<f32> {
+        match heater {
+            Heater::Bed => self.last_temp_report.map(|_| self.bed.temp),
+            Heater::Tool(idx) => self.tools.get(&idx).map(|tool| tool.temp),
+        }
+    }
+
     fn parse_temperature_report(&mut self, raw: &str) {
+        // Marlin separates current and target with a space (`T:200.0 /210.0`)
+        // so rejoin those pairs before splitting into parts
+        let raw = raw.replace(" /", "/");
+        let mut reported = false;
+
         for part in raw.split_whitespace() {
             // Hotends: T: / T0: / T1:
             if part.starts_with('T') && part.contains(':') && !part.starts_with("T:") {
@@ -101,6 +123,7 @@ impl PrinterState {
                     let entry = self.tools.entry(tool_idx).or_default();
                     entry.temp = cur;
                     entry.target = tgt;
+                    reported = true;
                 }
             }
 
@@ -111,6 +134,7 @@ impl PrinterState {
                     let entry = self.tools.entry(0).or_default();
                     entry.temp = cur;
                     entry.target = tgt;
+                    reported = true;
                 }
             }
 
@@ -120,9 +144,14 @@ impl PrinterState {
                 if let Some((cur, tgt)) = parse_temp_pair(temps) {
                     self.bed.temp = cur;
                     self.bed.target = tgt;
+                    reported = true;
                 }
             }
         }
+
+        if reported {
+            self.last_temp_report = Some(Instant::now());
+        }
     }
 
     fn parse_position_report(&mut self, raw: &str) {
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Instant;
+
+use chrono::Utc;
+
+use printctl_ui::features::code::GCodeLine;
+use printctl_ui::features::format::parse_lines;
+use printctl_ui::features::machine::MachineState;
+use printctl_ui::features::progress::JobProgress;
+use printctl_ui::features::resume::RestoreState;
+
+use super::checkpoint::Checkpoint;
+use crate::agent::models::{Job, JobPlan, LayerCommand};
+
+/// What the running job wants sent next
+#[derive(Debug, PartialEq)]
+pub enum StreamStep {
+    Send(String),
+    /// Paused, nothing is sent until [`JobStream::resume`]
+    Hold,
+    Done,
+}
+
+/// Line-by-line streaming state of the running job.
+///
+/// Each command is sent only after the printer acknowledged the previous one
+/// with `ok`, so `acked` always reflects what the printer has accepted.
+#[derive(Debug)]
+pub struct JobStream {
+    plan: Arc<JobPlan>,
+    // index of the next source line to look at
+    sent: usize,
+    // number of source lines the printer has accepted
+    acked: usize,
+    started: Instant,
+    // layer action commands due before line `sent`; `None` pauses
+    injected: VecDeque<Option<String>>,
+    // last line whose layer actions were queued
+    injected_at: Option<usize>,
+    paused: bool,
+    // simulated state after the last accepted line
+    machine: MachineState,
+}
+
+impl JobStream {
+    /// Starts at the plan's start line, restoring the printer with its
+    /// preamble first when resuming
+    pub fn new(plan: Arc<JobPlan>) -> Self {
+        let injected = plan
+            .preamble
+            .iter()
+            .filter_map(|line| Self::strip(line))
+            .map(Some)
+            .collect();
+
+        Self {
+            sent: plan.start_line,
+            acked: plan.start_line,
+            started: Instant::now(),
+            injected,
+            injected_at: None,
+            paused: false,
+            machine: plan.initial_state.clone(),
+            plan,
+        }
+    }
+
+    /// Next command to send, skipping blank and comment-only lines and
+    /// running layer actions before the line they are attached to
+    pub fn next_command(&mut self) -> StreamStep {
+        if self.paused {
+            return StreamStep::Hold;
+        }
+
+        loop {
+            if self.injected_at != Some(self.sent) {
+                self.injected_at = Some(self.sent);
+                if let Some(commands) = self.plan.actions.get(&self.sent) {
+                    self.injected.extend(commands.iter().flat_map(Self::expand));
+                }
+            }
+            match self.injected.pop_front() {
+                Some(Some(command)) => return StreamStep::Send(command),
+                Some(None) => {
+                    self.paused = true;
+                    return StreamStep::Hold;
+                }
+                None => {}
+            }
+
+            let Some(line) = self.plan.lines.get(self.sent) else {
+                return StreamStep::Done;
+            };
+            self.sent += 1;
+
+            match Self::strip(line) {
+                Some(command) => return StreamStep::Send(command),
+                None => self.ack(),
+            }
+        }
+    }
+
+    /// The printer accepted the last command sent
+    pub fn ack(&mut self) {
+        for line in &self.plan.lines[self.acked..self.sent] {
+            for line in parse_lines(line) {
+                if let GCodeLine::Command { gcodes, .. } = line {
+                    for gcode in gcodes.iter() {
+                        self.machine = self.machine.execute(gcode).0;
+                    }
+                }
+            }
+        }
+        self.acked = self.sent;
+    }
+
+    /// Lets a paused job continue, returns whether it was paused
+    pub fn resume(&mut self) -> bool {
+        std::mem::replace(&mut self.paused, false)
+    }
+
+    /// Where `job` could be resumed from if the host went down now
+    pub fn checkpoint(&self, job: &Job) -> Checkpoint {
+        Checkpoint {
+            job_id: job.id,
+            printer_id: job.printer_id,
+            file_name: self.plan.file_name.clone(),
+            file_hash: self.plan.file_hash.clone(),
+            line: self.acked,
+            state: RestoreState::from(&self.machine),
+            saved_at: Utc::now(),
+        }
+    }
+
+    pub fn progress(&self) -> JobProgress {
+        self.plan
+            .progress
+            .progress(self.acked, self.started.elapsed())
+    }
+
+    fn strip(line: &str) -> Option<String> {
+        let command = line.split(';').next().unwrap_or_default().trim();
+        (!command.is_empty()).then(|| format!("{}\n", command))
+    }
+
+    fn expand(command: &LayerCommand) -> Vec<Option<String>> {
+        match command {
+            LayerCommand::FilamentChange => vec![Some("M600\n".to_string())],
+            LayerCommand::Pause => vec![None],
+            LayerCommand::Custom(gcode) => {
+                gcode.lines().filter_map(Self::strip).map(Some).collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use printctl_ui::features::cost::Pricing;
+    use printctl_ui::features::profile::MachineProfile;
+
+    use crate::agent::models::{GcodeFile, LayerAction};
+
+    const SRC: &str = "G28\n;LAYER:0\nG1 Z0.2 F600\nG1 X10 E1\n\
+                       ;LAYER:1\nG1 Z0.4\nG1 X0 E2\n\
+                       ;LAYER:2\nG1 Z0.6 ; next\nG1 X10 E3\n";
+
+    fn stream(actions: &[&str]) -> JobStream {
+        let file = GcodeFile::new("layers.gcode".as_ref(), SRC.as_bytes().to_vec()).unwrap();
+        let actions: Vec<LayerAction> = actions.iter().map(|a| a.parse().unwrap()).collect();
+        let plan = JobPlan::new(
+            &file,
+            &MachineProfile::default(),
+            &Pricing::default(),
+            &actions,
+            None,
+        )
+        .unwrap();
+        JobStream::new(Arc::new(plan))
+    }
+
+    /// Commands sent until the stream holds or ends, acknowledging each
+    fn drain(stream: &mut JobStream) -> (Vec<String>, StreamStep) {
+        let mut sent = Vec::new();
+        loop {
+            match stream.next_command() {
+                StreamStep::Send(command) => {
+                    sent.push(command.trim_end().to_string());
+                    stream.ack();
+                }
+                step => return (sent, step),
+            }
+        }
+    }
+
+    #[test]
+    fn streams_commands_without_comments() {
+        let (sent, step) = drain(&mut stream(&[]));
+
+        assert_eq!(step, StreamStep::Done);
+        assert_eq!(
+            sent,
+            [
+                "G28",
+                "G1 Z0.2 F600",
+                "G1 X10 E1",
+                "G1 Z0.4",
+                "G1 X0 E2",
+                "G1 Z0.6",
+                "G1 X10 E3"
+            ]
+        );
+    }
+
+    #[test]
+    fn actions_run_at_layer_boundaries() {
+        let mut stream = stream(&["2:change", "3:pause", "3:M117 layer 3\\nG4 P0"]);
+
+        let (sent, step) = drain(&mut stream);
+        assert_eq!(step, StreamStep::Hold);
+        assert_eq!(
+            sent,
+            [
+                "G28",
+                "G1 Z0.2 F600",
+                "G1 X10 E1",
+                "M600",
+                "G1 Z0.4",
+                "G1 X0 E2"
+            ]
+        );
+
+        // held until resumed, then the rest of layer 3's actions run first
+        assert_eq!(stream.next_command(), StreamStep::Hold);
+        assert!(stream.resume());
+        let (sent, step) = drain(&mut stream);
+        assert_eq!(step, StreamStep::Done);
+        assert_eq!(sent, ["M117 layer 3", "G4 P0", "G1 Z0.6", "G1 X10 E3"]);
+        assert!(!stream.resume());
+    }
+}
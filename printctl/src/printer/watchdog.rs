@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::state::PrinterState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Heater {
+    Bed,
+    Tool(usize),
+}
+
+impl std::fmt::Display for Heater {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Heater::Bed => write!(f, "bed"),
+            Heater::Tool(idx) => write!(f, "tool {}", idx),
+        }
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum ThermalFault {
+    #[error("{heater} stalled at {temp:.1}°C (target {target:.1}°C) for more than {window:?}")]
+    StalledHeating {
+        heater: Heater,
+        temp: f32,
+        target: f32,
+        window: Duration,
+    },
+
+    #[error("{heater} dropped from {from:.1}°C to {to:.1}°C while targeting {target:.1}°C")]
+    TemperatureDrop {
+        heater: Heater,
+        from: f32,
+        to: f32,
+        target: f32,
+    },
+
+    #[error("no temperature report for {silence:?} while heaters are on")]
+    ReportsStopped { silence: Duration },
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    /// How long a heater may stay far below target without making progress
+    pub stall_window: Duration,
+    /// Degrees below target that count as "far below"
+    pub stall_margin: f32,
+    /// Minimum rise within `stall_window` that counts as heating progress
+    pub stall_min_rise: f32,
+    /// Largest drop allowed between two consecutive reports while a target is set
+    pub drop_threshold: f32,
+    /// How long reports may stop arriving while any heater is on
+    pub report_timeout: Duration,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            stall_window: Duration::from_secs(60),
+            stall_margin: 10.0,
+            stall_min_rise: 2.0,
+            drop_threshold: 10.0,
+            report_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct HeaterWatch {
+    temp: f32,
+    target: f32,
+    progress_temp: f32,
+    progress_at: Instant,
+}
+
+impl HeaterWatch {
+    fn new(temp: f32, target: f32, now: Instant) -> Self {
+        Self {
+            temp,
+            target,
+            progress_temp: temp,
+            progress_at: now,
+        }
+    }
+}
+
+/// Host-side safety net for boards with firmware thermal protection disabled.
+///
+/// Once a fault is reported the watchdog latches and stays silent until the
+/// heaters are reported off or the next job starts, since the printer has to
+/// be reset after an emergency stop anyway.
+#[derive(Debug)]
+pub struct ThermalWatchdog {
+    config: WatchdogConfig,
+    heaters: HashMap<Heater, HeaterWatch>,
+    latched: bool,
+}
+
+impl ThermalWatchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self {
+            config,
+            heaters: HashMap::new(),
+            latched: false,
+        }
+    }
+
+    pub fn check(&mut self, state: &PrinterState, now: Instant) -> Option<ThermalFault> {
+        if self.latched {
+            if state.heaters_on() {
+                return None;
+            }
+            self.reset();
+        }
+
+        let fault = self.check_reports(state, now).or_else(|| {
+            let bed = std::iter::once((Heater::Bed, state.bed.temp, state.bed.target));
+            let tools = state
+                .tools
+                .iter()
+                .map(|(idx, tool)| (Heater::Tool(*idx), tool.temp, tool.target));
+
+            bed.chain(tools)
+                .collect::<Vec<_>>()
+                .into_iter()
+                .find_map(|(heater, temp, target)| self.check_heater(heater, temp, target, now))
+        });
+
+        self.latched = fault.is_some();
+        fault
+    }
+
+    /// Forgets any fault and heater history
+    pub fn reset(&mut self) {
+        self.latched = false;
+        self.heaters.clear();
+    }
+
+    fn check_reports(&self, state: &PrinterState, now: Instant) -> Option<ThermalFault> {
+        if !state.heaters_on() {
+            return None;
+        }
+
+        let silence = now.duration_since(state.last_temp_report?);
+        (silence > self.config.report_timeout).then_some(ThermalFault::ReportsStopped { silence })
+    }
+
+    fn check_heater(
+        &mut self,
+        heater: Heater,
+        temp: f32,
+        target: f32,
+        now: Instant,
+    ) -> Option<ThermalFault> {
+        let config = &self.config;
+        let watch = self
+            .heaters
+            .entry(heater)
+            .or_insert_with(|| HeaterWatch::new(temp, target, now));
+
+        // heater off or target changed -> start watching from scratch
+        if target <= 0.0 || target != watch.target {
+            *watch = HeaterWatch::new(temp, target, now);
+            return None;
+        }
+
+        let previous = std::mem::replace(&mut watch.temp, temp);
+        if previous - temp > config.drop_threshold {
+            return Some(ThermalFault::TemperatureDrop {
+                heater,
+                from: previous,
+                to: temp,
+                target,
+            });
+        }
+
+        // close to target or still climbing -> heater is making progress
+        if temp >= target - config.stall_margin
+            || temp >= watch.progress_temp + config.stall_min_rise
+        {
+            watch.progress_temp = temp;
+            watch.progress_at = now;
+            return None;
+        }
+
+        (now.duration_since(watch.progress_at) > config.stall_window).then_some(
+            ThermalFault::StalledHeating {
+                heater,
+                temp,
+                target,
+                window: config.stall_window,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(line: &str) -> PrinterState {
+        let mut state = PrinterState::default();
+        state.update_from_line(line);
+        state
+    }
+
+    fn report_at(line: &str, at: Instant) -> PrinterState {
+        let mut state = report(line);
+        state.last_temp_report = Some(at);
+        state
+    }
+
+    #[test]
+    fn heater_stuck_below_target_stalls() {
+        let mut watchdog = ThermalWatchdog::new(WatchdogConfig::default());
+        let start = Instant::now();
+        let state = report("ok T:25.0 /200.0 B:25.0 /0.0");
+
+        assert!(watchdog.check(&state, start).is_none());
+        // slowly climbing counts as progress
+        let later = start + Duration::from_secs(50);
+        let climbing = report_at("ok T:30.0 /200.0 B:25.0 /0.0", later);
+        assert!(watchdog.check(&climbing, later).is_none());
+
+        let stuck_at = later + Duration::from_secs(61);
+        let stuck = report_at("ok T:30.0 /200.0 B:25.0 /0.0", stuck_at);
+        let fault = watchdog.check(&stuck, stuck_at);
+        assert!(matches!(
+            fault,
+            Some(ThermalFault::StalledHeating { heater: Heater::Tool(0), target, .. }) if target == 200.0
+        ));
+    }
+
+    #[test]
+    fn heater_at_target_does_not_stall() {
+        let mut watchdog = ThermalWatchdog::new(WatchdogConfig::default());
+        let start = Instant::now();
+        let state = report("ok T:195.0 /200.0 B:60.0 /60.0");
+
+        assert!(watchdog.check(&state, start).is_none());
+        assert!(watchdog
+            .check(&state, start + Duration::from_secs(5))
+            .is_none());
+    }
+
+    #[test]
+    fn sudden_drop_while_heating_faults() {
+        let mut watchdog = ThermalWatchdog::new(WatchdogConfig::default());
+        let now = Instant::now();
+
+        assert!(watchdog
+            .check(&report("ok T:200.0 /200.0 B:60.0 /60.0"), now)
+            .is_none());
+        let fault = watchdog.check(&report("ok T:200.0 /200.0 B:45.0 /60.0"), now);
+        assert!(matches!(
+            fault,
+            Some(ThermalFault::TemperatureDrop { heater: Heater::Bed, from, to, .. })
+                if from == 60.0 && to == 45.0
+        ));
+    }
+
+    #[test]
+    fn silent_firmware_with_heaters_on_faults() {
+        let mut watchdog = ThermalWatchdog::new(WatchdogConfig::default());
+        let now = Instant::now();
+        let state = report("ok T:200.0 /200.0 B:60.0 /60.0");
+
+        assert!(watchdog.check(&state, now).is_none());
+        let fault = watchdog.check(&state, now + Duration::from_secs(11));
+        assert!(matches!(fault, Some(ThermalFault::ReportsStopped { .. })));
+
+        // nothing to protect once the heaters are off
+        let mut watchdog = ThermalWatchdog::new(WatchdogConfig::default());
+        let off = report("ok T:30.0 /0.0 B:30.0 /0.0");
+        assert!(watchdog
+            .check(&off, now + Duration::from_secs(60))
+            .is_none());
+    }
+
+    #[test]
+    fn latch_clears_once_heaters_are_off() {
+        let mut watchdog = ThermalWatchdog::new(WatchdogConfig::default());
+        let now = Instant::now();
+        let heating = report("ok T:200.0 /200.0 B:60.0 /60.0");
+        let late = now + Duration::from_secs(11);
+
+        assert!(watchdog.check(&heating, now).is_none());
+        assert!(watchdog.check(&heating, late).is_some());
+        // latched while the heaters stay on
+        assert!(watchdog.check(&heating, late).is_none());
+
+        assert!(watchdog
+            .check(&report("ok T:30.0 /0.0 B:30.0 /0.0"), late)
+            .is_none());
+        assert!(watchdog.check(&heating, late).is_some());
+
+        watchdog.reset();
+        assert!(watchdog.check(&heating, now).is_none());
+    }
+}